@@ -48,8 +48,9 @@ pub struct IoStreams {
     /// The reader to key event queue. This is the same reader as that in
     /// shell. Apps can take this reader to directly access keyboard events.
     key_event_reader: Arc<Mutex<Option<KeyEventQueueReader>>>,
-    /// Points to the terminal.
-    terminal: Arc<Mutex<Terminal>>
+    /// Points to the terminal. This is `None` for headless streams, e.g., those whose
+    /// input and output are bridged to a remote console over the network.
+    terminal: Option<Arc<Mutex<Terminal>>>
 }
 
 /// Applications set the flags in this structure to inform the parent shell to
@@ -80,7 +81,22 @@ impl IoStreams {
             stdout,
             stderr,
             key_event_reader,
-            terminal
+            terminal: Some(terminal)
+        }
+    }
+
+    /// Create a new `IoStreams` that is not bound to any terminal instance.
+    /// Applications running with these streams can only perform IO through stdio,
+    /// and `get_my_terminal()` will return `None` for them.
+    pub fn new_headless(stdin: StdioReader, stdout: StdioWriter,
+                        stderr: StdioWriter,
+                        key_event_reader: Arc<Mutex<Option<KeyEventQueueReader>>>) -> IoStreams {
+        IoStreams {
+            stdin,
+            stdout,
+            stderr,
+            key_event_reader,
+            terminal: None
         }
     }
 }
@@ -129,11 +145,12 @@ mod shared_maps {
 
 
 /// An application can call this function to get the terminal to which it should print.
+/// Returns `None` if the application is running with headless streams.
 pub fn get_my_terminal() -> Option<Arc<Mutex<Terminal>>> {
     task::get_my_current_task_id()
         .and_then(|id| shared_maps::lock_stream_map()
             .get(&id)
-            .map(|property| property.terminal.clone())
+            .and_then(|property| property.terminal.clone())
         )
 }
 
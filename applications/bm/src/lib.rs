@@ -344,7 +344,6 @@ fn do_spawn_inner(overhead_ct: u64, th: usize, nr: usize, _child_core: u8) -> Re
 	        .spawn()?;
 
 	    child.join()?;
-	    child.take_exit_value().ok_or("Couldn't retrieve exit value")?;
 	    end_hpet = hpet.get_counter();
 		delta_hpet += end_hpet - start_hpet - overhead_ct;		
 	}
@@ -432,9 +431,6 @@ fn do_ctx_inner(th: usize, nr: usize, child_core: u8) -> Result<u64, &'static st
 		taskref3.join()?;
 		taskref4.join()?;
 
-		taskref3.take_exit_value().ok_or("could not retrieve exit value")?;
-		taskref4.take_exit_value().ok_or("could not retrieve exit value")?;
-
 
 	overhead_end_hpet = hpet.get_counter();
 
@@ -453,9 +449,6 @@ fn do_ctx_inner(th: usize, nr: usize, child_core: u8) -> Result<u64, &'static st
 		taskref1.join()?;
 		taskref2.join()?;
 
-		taskref1.take_exit_value().ok_or("could not retrieve exit value")?;
-		taskref2.take_exit_value().ok_or("could not retrieve exit value")?;
-
     end_hpet = hpet.get_counter();
 
     let delta_overhead = overhead_end_hpet - start_hpet;
@@ -609,7 +602,6 @@ fn do_ipc_rendezvous_inner(th: usize, nr: usize, child_core: Option<u8>) -> Resu
 		}
 		
 		taskref3.join()?;
-		taskref3.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let overhead = hpet.get_counter();
 
@@ -635,7 +627,6 @@ fn do_ipc_rendezvous_inner(th: usize, nr: usize, child_core: Option<u8>) -> Resu
 		rendezvous_task_receiver((sender2, receiver1));
 
 		taskref1.join()?;
-		taskref1.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let end = hpet.get_counter();
 
@@ -676,7 +667,6 @@ fn do_ipc_rendezvous_inner_cycles(th: usize, nr: usize, child_core: Option<u8>)
 		}
 		
 		taskref3.join()?;
-		taskref3.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let overhead = counter.diff();
 	counter.start()?;
@@ -703,7 +693,6 @@ fn do_ipc_rendezvous_inner_cycles(th: usize, nr: usize, child_core: Option<u8>)
 		rendezvous_task_receiver((sender2, receiver1));
 
 		taskref1.join()?;
-		taskref1.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let end = counter.end()?;
 
@@ -815,7 +804,6 @@ fn do_ipc_async_inner(th: usize, nr: usize, child_core: Option<u8>, blocking: bo
 		}
 		
 		taskref3.join()?;
-		taskref3.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let overhead = hpet.get_counter();
 
@@ -844,7 +832,6 @@ fn do_ipc_async_inner(th: usize, nr: usize, child_core: Option<u8>, blocking: bo
 		receiver_task((sender2, receiver1));
 
 		taskref1.join()?;
-		taskref1.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let end = hpet.get_counter();
 
@@ -891,7 +878,6 @@ fn do_ipc_async_inner_cycles(th: usize, nr: usize, child_core: Option<u8>, block
 		}
 		
 		taskref3.join()?;
-		taskref3.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let overhead = counter.diff();
 	counter.start()?;
@@ -921,7 +907,6 @@ fn do_ipc_async_inner_cycles(th: usize, nr: usize, child_core: Option<u8>, block
 		receiver_task((sender2, receiver1));
 
 		taskref1.join()?;
-		taskref1.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let end = counter.end()?;
 
@@ -1052,7 +1037,6 @@ fn do_ipc_simple_inner(th: usize, nr: usize, child_core: Option<u8>) -> Result<u
 		}
 		
 		taskref3.join()?;
-		taskref3.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let overhead = hpet.get_counter();
 
@@ -1078,7 +1062,6 @@ fn do_ipc_simple_inner(th: usize, nr: usize, child_core: Option<u8>) -> Result<u
 		simple_task_receiver((sender2, receiver1));
 
 		taskref1.join()?;
-		taskref1.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let end = hpet.get_counter();
 
@@ -1119,7 +1102,6 @@ fn do_ipc_simple_inner_cycles(th: usize, nr: usize, child_core: Option<u8>) -> R
 		}
 		
 		taskref3.join()?;
-		taskref3.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let overhead = counter.diff();
 	counter.start()?;
@@ -1146,7 +1128,6 @@ fn do_ipc_simple_inner_cycles(th: usize, nr: usize, child_core: Option<u8>) -> R
 		simple_task_receiver((sender2, receiver1));
 
 		taskref1.join()?;
-		taskref1.take_exit_value().ok_or("could not retrieve exit value")?;
 
 	let end = counter.end()?;
 		
@@ -98,13 +98,10 @@ fn test_multiple(iterations: usize) -> Result<(), &'static str> {
     info!("test_multiple(): Finished spawning the sender and receiver tasks");
     t2.unblock(); t1.unblock();
 
-    t1.join()?;
-    t2.join()?;
+    let t1_result = t1.join()?;
+    let t2_result = t2.join()?;
     info!("test_multiple(): Joined the sender and receiver tasks.");
-    let _t1_exit = t1.take_exit_value();
-    let _t2_exit = t2.take_exit_value();
-    
-    Ok(())
+    t1_result.and(t2_result)
 }
 
 
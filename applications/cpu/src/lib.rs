@@ -37,7 +37,7 @@ pub fn main(args: Vec<String>) -> isize {
 
         println!("\n{} (apic: {}, proc: {})", core_type, apic_id, processor); 
         
-        if let Some(runqueue) = runqueue::get_runqueue(apic_id).map(|rq| rq.read()) {
+        if let Some(runqueue) = runqueue::get_tasks(apic_id) {
             let mut runqueue_contents = String::new();
             for task_ref in runqueue.iter() {
                 let task = task_ref.lock();
@@ -48,6 +48,11 @@ pub fn main(args: Vec<String>) -> isize {
                 ));
             }
             println!("RunQueue:\n{}", runqueue_contents);
+            if let Some(load) = runqueue::get_load(apic_id) {
+                println!("Load: {} runnable of {} tasks, {} migrated in, {} migrated out\n", 
+                    load.runnable, load.tasks, load.migrated_in, load.migrated_out
+                );
+            }
         }
         
         else {
@@ -55,6 +60,8 @@ pub fn main(args: Vec<String>) -> isize {
             return -1;
         }
     }
+
+    println!("Total task migrations between cores: {}", runqueue::total_migrations());
     
     0
 }
@@ -62,7 +69,7 @@ pub fn main(args: Vec<String>) -> isize {
 fn print_usage(opts: Options) -> isize {
     let mut brief = format!("Usage: cpu \n \n");
 
-    brief.push_str("For each core, prints apic id, processor id, whether it is the bootstrap processor (the first processor to boot up), which tasks that is currently running on that core and which tasks are present in that core's runqueue");
+    brief.push_str("For each core, prints apic id, processor id, whether it is the bootstrap processor (the first processor to boot up), which tasks that is currently running on that core, which tasks are present in that core's runqueue, and that core's load and the number of tasks migrated to and from it");
 
     println!("{} \n", opts.usage(&brief));
 
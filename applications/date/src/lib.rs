@@ -1,19 +1,90 @@
+//! Prints the current date and time, and optionally synchronizes it with a time server.
+//!
+//! Usage:
+//! * `date`: prints the wall-clock time and the raw time of the CMOS RTC.
+//! * `date -n [-s SERVER[:PORT]] [-w]`: corrects the wall clock using an SNTP time server,
+//!    and optionally writes the corrected time back into the RTC.
+
 #![no_std]
 // #![feature(plugin)]
 // #![plugin(application_main_fn)]
 
 
-extern crate alloc;
+#[macro_use] extern crate alloc;
 #[macro_use] extern crate terminal_print;
+extern crate getopts;
 extern crate rtc;
+extern crate wall_clock;
+extern crate sntp_client;
+extern crate smoltcp;
 
+use core::str::FromStr;
 use alloc::vec::Vec;
 use alloc::string::String;
+use getopts::{Matches, Options};
+use smoltcp::wire::IpEndpoint;
+use sntp_client::SntpConfig;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("n", "ntp", "synchronize the wall clock with an SNTP time server");
+    opts.optopt("s", "server", "the time server to use with -n (default: 10.0.2.2:123)", "SERVER[:PORT]");
+    opts.optflag("w", "write-rtc", "with -n, also write the corrected time into the CMOS RTC");
 
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
 
-pub fn main(_args: Vec<String>) -> isize {
-    let now = rtc::read_rtc();
-    println!("{}", now);
+    if matches.opt_present("h") {
+        print_usage(&opts);
+        return 0;
+    }
 
-    0
+    match rmain(&matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
 }
+
+
+fn rmain(matches: &Matches) -> Result<(), String> {
+    if matches.opt_present("n") {
+        let mut config = SntpConfig::default();
+        if let Some(server) = matches.opt_str("s") {
+            config.server = IpEndpoint::from_str(&server)
+                .map_err(|_e| format!("couldn't parse server address {:?}", server))?;
+            if config.server.port == 0 {
+                config.server.port = sntp_client::NTP_PORT;
+            }
+        }
+        let sample = sntp_client::synchronize(&config, matches.opt_present("w"))?;
+        println!("Adjusted clock by {} us using {} (stratum {}, round trip {} us)",
+            sample.offset_micros, config.server, sample.stratum, sample.round_trip_micros
+        );
+    } else if matches.opt_present("s") || matches.opt_present("w") {
+        return Err(format!("-s and -w can only be used with -n"));
+    }
+
+    let now = wall_clock::now()?;
+    println!("{}{}", now, if wall_clock::is_synchronized() { "" } else { " (not synchronized)" });
+    println!("{}", rtc::read_rtc());
+    Ok(())
+}
+
+
+fn print_usage(opts: &Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: date [OPTION]...
+Prints the current date and time, optionally synchronizing it with an SNTP time server first.";
@@ -56,14 +56,14 @@ pub fn do_threadtest() -> Result<(), &'static str> {
         }  
 
         for i in 0..nthreads {
-            threads[i].join()?;
+            threads[i].task().join()?;
         }
 
         let end = hpet.get_counter() - hpet_overhead;
 
         // Don't want this to be part of the timing measurement
         for thread in threads {
-            thread.take_exit_value();
+            let _ = thread.join();
         }
 
         let diff = hpet_2_us(end - start);
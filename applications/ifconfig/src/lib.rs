@@ -0,0 +1,232 @@
+//! Displays and configures the network interfaces, similar to `ifconfig` or `ip` on Linux.
+//!
+//! Usage:
+//! * `ifconfig [IFACE]`: shows the MAC address, IP addresses, routes and traffic counters of all (or one) interfaces.
+//! * `ifconfig IFACE addr add|del ADDR/PREFIX`: assigns or removes an IP address.
+//! * `ifconfig IFACE route add|del DEST/PREFIX|default [GATEWAY]`: adds or removes a route.
+//! * `ifconfig IFACE hw MAC`: sets the interface's Ethernet address.
+//! * `ifconfig -s`: lists the active sockets, similar to `netstat`.
+//!
+//! Interfaces are named `lo` for the loopback interface, and `eth0`, `eth1`, etc for the others,
+//! in the order they were added to the list of `NETWORK_INTERFACES`.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+extern crate getopts;
+extern crate network_manager;
+extern crate smoltcp_helper;
+extern crate smoltcp;
+
+use core::str::FromStr;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use getopts::Options;
+use network_manager::{NetworkInterfaceRef, NETWORK_INTERFACES};
+use smoltcp::{
+    iface::Route,
+    wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv4Cidr, Ipv6Address, Ipv6Cidr},
+};
+use smoltcp_helper::SocketProtocol;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optflag("s", "sockets", "list the active sockets instead of the interfaces");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&opts);
+        return 0;
+    }
+
+    let result = if matches.opt_present("s") {
+        print_sockets();
+        Ok(())
+    } else {
+        let free: Vec<&str> = matches.free.iter().map(|s| s.as_str()).collect();
+        match free.as_slice() {
+            [] => {
+                for (name, iface) in named_interfaces() {
+                    print_interface(&name, &iface);
+                }
+                Ok(())
+            }
+            [name] => find_interface(name).map(|iface| print_interface(name, &iface)),
+            [name, "addr", action, cidr] => find_interface(name).and_then(|iface| change_addr(&iface, action, cidr)),
+            [name, "route", action, dest] => find_interface(name).and_then(|iface| change_route(&iface, action, dest, None)),
+            [name, "route", action, dest, gateway] => find_interface(name).and_then(|iface| change_route(&iface, action, dest, Some(gateway))),
+            [name, "hw", mac] => find_interface(name).and_then(|iface| set_mac(&iface, mac)),
+            _ => {
+                print_usage(&opts);
+                return -1;
+            }
+        }
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+/// Returns each network interface along with its name.
+fn named_interfaces() -> Vec<(String, NetworkInterfaceRef)> {
+    let mut eth_index = 0;
+    NETWORK_INTERFACES.lock().iter().map(|iface| {
+        let name = if iface.lock().is_loopback() {
+            String::from("lo")
+        } else {
+            eth_index += 1;
+            format!("eth{}", eth_index - 1)
+        };
+        (name, iface.clone())
+    }).collect()
+}
+
+fn find_interface(name: &str) -> Result<NetworkInterfaceRef, String> {
+    named_interfaces().into_iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, iface)| iface)
+        .ok_or_else(|| format!("no network interface named {:?}", name))
+}
+
+
+fn print_interface(name: &str, iface: &NetworkInterfaceRef) {
+    let mut iface = iface.lock();
+    let mut out = String::new();
+    out.push_str(&format!("{}: {}\n", name, if iface.is_loopback() { "loopback" } else { "ethernet" }));
+    out.push_str(&format!("    ether {}\n", iface.ethernet_addr()));
+    for cidr in iface.ip_addrs() {
+        let family = match cidr.address() {
+            IpAddress::Ipv6(_) => "inet6",
+            _ => "inet",
+        };
+        out.push_str(&format!("    {} {}\n", family, cidr));
+    }
+    iface.routes_mut().update(|routes| {
+        for (dest, route) in routes.iter() {
+            let dest = if dest.prefix_len() == 0 { "default".to_string() } else { dest.to_string() };
+            out.push_str(&format!("    route {} via {}\n", dest, route.via_router));
+        }
+    });
+    let stats = iface.stats();
+    out.push_str(&format!("    RX packets {}  bytes {}  errors {}\n", stats.rx_packets, stats.rx_bytes, stats.rx_errors));
+    out.push_str(&format!("    TX packets {}  bytes {}  errors {}\n", stats.tx_packets, stats.tx_bytes, stats.tx_errors));
+    println!("{}", out);
+}
+
+
+fn change_addr(iface: &NetworkInterfaceRef, action: &str, cidr: &str) -> Result<(), String> {
+    let cidr = IpCidr::from_str(cidr).map_err(|_e| format!("couldn't parse address {:?}, expected ADDR/PREFIX", cidr))?;
+    let mut iface = iface.lock();
+    match action {
+        "add" => iface.add_ip_addr(cidr)?,
+        "del" => iface.remove_ip_addr(cidr)?,
+        _ => return Err(format!("unknown action {:?}, expected \"add\" or \"del\"", action)),
+    }
+    Ok(())
+}
+
+
+fn change_route(iface: &NetworkInterfaceRef, action: &str, dest: &str, gateway: Option<&str>) -> Result<(), String> {
+    let gateway = match gateway {
+        Some(g) => Some(IpAddress::from_str(g).map_err(|_e| format!("couldn't parse gateway address {:?}", g))?),
+        None => None,
+    };
+    // The default route's destination depends on the address family of the gateway.
+    let dest = if dest == "default" {
+        match gateway {
+            Some(IpAddress::Ipv6(_)) => IpCidr::Ipv6(Ipv6Cidr::new(Ipv6Address::UNSPECIFIED, 0)),
+            _ => IpCidr::Ipv4(Ipv4Cidr::new(Ipv4Address::UNSPECIFIED, 0)),
+        }
+    } else {
+        IpCidr::from_str(dest).map_err(|_e| format!("couldn't parse destination {:?}, expected ADDR/PREFIX or \"default\"", dest))?
+    };
+
+    let mut result = Ok(());
+    iface.lock().routes_mut().update(|routes| {
+        result = match (action, gateway) {
+            ("add", Some(IpAddress::Ipv4(gateway))) => routes.insert(dest, Route::new_ipv4_gateway(gateway))
+                .map(|_old| ())
+                .map_err(|_e| format!("the routing table is full")),
+            ("add", Some(IpAddress::Ipv6(gateway))) => routes.insert(dest, Route::new_ipv6_gateway(gateway))
+                .map(|_old| ())
+                .map_err(|_e| format!("the routing table is full")),
+            ("add", _) => Err(format!("adding a route requires a gateway address")),
+            ("del", _) => routes.remove(&dest)
+                .map(|_old| ())
+                .ok_or_else(|| format!("there is no route to {}", dest)),
+            _ => Err(format!("unknown action {:?}, expected \"add\" or \"del\"", action)),
+        };
+    });
+    result
+}
+
+
+fn set_mac(iface: &NetworkInterfaceRef, mac: &str) -> Result<(), String> {
+    let mac = EthernetAddress::from_str(mac).map_err(|_e| format!("couldn't parse Ethernet address {:?}", mac))?;
+    if !mac.is_unicast() {
+        return Err(format!("{} is not a unicast Ethernet address", mac));
+    }
+    iface.lock().set_ethernet_addr(mac);
+    Ok(())
+}
+
+
+/// Prints the sockets that are being polled by any task, similar to `netstat`.
+fn print_sockets() {
+    let sockets = match smoltcp_helper::snapshot_sockets() {
+        Ok(sockets) => sockets,
+        Err(e) => {
+            println!("Error: couldn't take a snapshot of the active sockets: {}", e);
+            return;
+        }
+    };
+    let mut out = format!("{0:<6}  {1:<28}  {2:<28}  {3:<12}  {4}\n", "PROTO", "LOCAL", "REMOTE", "STATE", "TASK");
+    for socket in &sockets {
+        let protocol = match socket.protocol {
+            SocketProtocol::Tcp  => "tcp",
+            SocketProtocol::Udp  => "udp",
+            SocketProtocol::Icmp => "icmp",
+            SocketProtocol::Raw  => "raw",
+        };
+        out.push_str(&format!("{0:<6}  {1:<28}  {2:<28}  {3:<12}  {4}\n",
+            protocol,
+            socket.local_endpoint.map(|e| e.to_string()).unwrap_or_else(|| String::from("*")),
+            socket.remote_endpoint.map(|e| e.to_string()).unwrap_or_else(|| String::from("*")),
+            socket.state.as_ref().map(|s| s.as_str()).unwrap_or("-"),
+            socket.task_id,
+        ));
+    }
+    print!("{}", out);
+    println!("Total number of sockets: {}", sockets.len());
+}
+
+
+fn print_usage(opts: &Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: ifconfig [IFACE]
+       ifconfig IFACE addr add|del ADDR/PREFIX
+       ifconfig IFACE route add|del DEST/PREFIX|default [GATEWAY]
+       ifconfig IFACE hw MAC
+       ifconfig -s
+Displays and configures network interfaces, or lists active sockets.";
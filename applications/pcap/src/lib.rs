@@ -0,0 +1,215 @@
+//! Captures Ethernet frames sent and received by the network devices into pcap files,
+//! which can be copied out of Theseus and opened in Wireshark.
+//!
+//! Usage:
+//! * `pcap start [OPTIONS]`: starts a capture, either into an in-memory ring or directly into a file (`-w`).
+//! * `pcap status`: shows the statistics of the running capture.
+//! * `pcap stop [-o FILE] [-x]`: stops the capture and saves the ring's frames into a pcap file,
+//!    and/or prints them as a hexdump that the host's `text2pcap` tool can convert back into a pcap file.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate memfs;
+extern crate packet_capture;
+extern crate smoltcp;
+
+use core::str::FromStr;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use getopts::{Matches, Options};
+use fs_node::FileRef;
+use memfs::MemFile;
+use packet_capture::{CaptureConfig, CaptureFilter, CaptureResult, CaptureSink, Direction};
+use smoltcp::wire::{EthernetProtocol, IpAddress, IpProtocol};
+
+
+/// The default name of the file into which the ring's frames are saved upon `pcap stop`.
+const DEFAULT_OUTPUT_FILE: &'static str = "capture.pcap";
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("w", "write", "start: write frames directly into FILE as they are captured", "FILE");
+    opts.optopt("n", "frames", "start: the number of frames kept in the in-memory ring (default: 1024)", "N");
+    opts.optopt("s", "snaplen", "start: the maximum number of bytes captured from each frame (default: 1518)", "BYTES");
+    opts.optopt("d", "direction", "start: only capture frames in this direction, either \"rx\" or \"tx\"", "DIR");
+    opts.optopt("p", "protocol", "start: only capture this protocol: arp, ipv4, ipv6, tcp, udp, icmp or icmpv6", "PROTO");
+    opts.optopt("", "host", "start: only capture IP packets from or to this address", "IP");
+    opts.optopt("", "port", "start: only capture TCP/UDP segments from or to this port", "PORT");
+    opts.optopt("o", "output", "stop: save the captured frames into FILE (default: capture.pcap)", "FILE");
+    opts.optflag("x", "hexdump", "stop: print the captured frames as a hexdump for the host's `text2pcap -t \"%s.\"` tool");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() != 1 {
+        print_usage(&opts);
+        return 0;
+    }
+
+    let result = match matches.free[0].as_str() {
+        "start"  => start(&matches),
+        "stop"   => stop(&matches),
+        "status" => status(),
+        other => Err(format!("unknown command {:?}", other)),
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn start(matches: &Matches) -> Result<(), String> {
+    let mut config = CaptureConfig::default();
+    config.filter = parse_filter(matches)?;
+
+    if let Some(s) = matches.opt_str("s") {
+        config.snaplen = s.parse::<usize>().map_err(|_e| format!("couldn't parse snaplen {:?}", s))?;
+    }
+    if let Some(file_name) = matches.opt_str("w") {
+        config.sink = CaptureSink::File(create_file(&file_name)?);
+        println!("Capturing frames into file {:?}", file_name);
+    } else {
+        let max_frames = match matches.opt_str("n") {
+            Some(n) => n.parse::<usize>().map_err(|_e| format!("couldn't parse number of frames {:?}", n))?,
+            None => packet_capture::DEFAULT_RING_FRAMES,
+        };
+        config.sink = CaptureSink::Ring { max_frames };
+        println!("Capturing up to {} frames into memory", max_frames);
+    }
+
+    packet_capture::start(config)?;
+    Ok(())
+}
+
+
+fn stop(matches: &Matches) -> Result<(), String> {
+    let result = packet_capture::stop().ok_or("no capture is running")?;
+    print_stats(&result.stats);
+
+    if result.frames.is_empty() {
+        return Ok(());
+    }
+    if matches.opt_present("x") {
+        print_hexdump(&result);
+        if !matches.opt_present("o") {
+            return Ok(());
+        }
+    }
+
+    let file_name = matches.opt_str("o").unwrap_or_else(|| DEFAULT_OUTPUT_FILE.to_string());
+    let file = create_file(&file_name)?;
+    let size = result.write_to_file(&file)?;
+    println!("Saved {} frames ({} bytes) into {:?}", result.frames.len(), size, file.lock().get_absolute_path());
+    Ok(())
+}
+
+
+fn status() -> Result<(), String> {
+    match packet_capture::stats() {
+        Some(stats) => print_stats(&stats),
+        None => println!("No capture is running."),
+    }
+    Ok(())
+}
+
+
+fn print_stats(stats: &packet_capture::CaptureStats) {
+    println!("{} frames captured, {} frames filtered out, {} frames dropped",
+        stats.captured, stats.filtered_out, stats.dropped
+    );
+}
+
+
+/// Prints each frame as a hexdump preceded by its timestamp,
+/// which `text2pcap -t "%s."` can convert into a pcap file on the host.
+fn print_hexdump(result: &CaptureResult) {
+    for frame in &result.frames {
+        println!("{}.{:06}", frame.timestamp_micros / 1_000_000, frame.timestamp_micros % 1_000_000);
+        for (i, chunk) in frame.data.chunks(16).enumerate() {
+            let mut line = format!("{:06x}", i * 16);
+            for byte in chunk {
+                line.push_str(&format!(" {:02x}", byte));
+            }
+            println!("{}", line);
+        }
+        println!("");
+    }
+}
+
+
+/// Creates a new file with the given name in the current working directory.
+fn create_file(file_name: &str) -> Result<FileRef, String> {
+    let curr_dir = task::get_my_current_task()
+        .map(|t| t.get_env().lock().working_dir.clone())
+        .ok_or_else(|| format!("couldn't get my current working directory"))?;
+    if curr_dir.lock().get(file_name).is_some() {
+        return Err(format!("{:?} already exists", file_name));
+    }
+    MemFile::new(file_name.to_string(), &curr_dir).map_err(|e| e.to_string())
+}
+
+
+fn parse_filter(matches: &Matches) -> Result<CaptureFilter, String> {
+    let mut filter = CaptureFilter::default();
+
+    if let Some(dir) = matches.opt_str("d") {
+        filter.direction = Some(match dir.as_str() {
+            "rx" => Direction::Received,
+            "tx" => Direction::Transmitted,
+            _ => return Err(format!("invalid direction {:?}, must be \"rx\" or \"tx\"", dir)),
+        });
+    }
+
+    if let Some(proto) = matches.opt_str("p") {
+        let (ethertype, ip_protocol) = match proto.as_str() {
+            "arp"    => (Some(EthernetProtocol::Arp), None),
+            "ipv4"   => (Some(EthernetProtocol::Ipv4), None),
+            "ipv6"   => (Some(EthernetProtocol::Ipv6), None),
+            "tcp"    => (None, Some(IpProtocol::Tcp)),
+            "udp"    => (None, Some(IpProtocol::Udp)),
+            "icmp"   => (Some(EthernetProtocol::Ipv4), Some(IpProtocol::Icmp)),
+            "icmpv6" => (Some(EthernetProtocol::Ipv6), Some(IpProtocol::Icmpv6)),
+            _ => return Err(format!("unsupported protocol {:?}", proto)),
+        };
+        filter.ethertype = ethertype;
+        filter.ip_protocol = ip_protocol;
+    }
+
+    if let Some(host) = matches.opt_str("host") {
+        filter.host = Some(IpAddress::from_str(&host).map_err(|_e| format!("invalid IP address {:?}", host))?);
+    }
+
+    if let Some(port) = matches.opt_str("port") {
+        filter.port = Some(port.parse::<u16>().map_err(|_e| format!("couldn't parse port {:?}", port))?);
+    }
+
+    Ok(filter)
+}
+
+
+fn print_usage(opts: &Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: pcap start|stop|status [OPTION]...
+Captures Ethernet frames from the network devices into a pcap file.";
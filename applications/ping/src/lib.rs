@@ -1,4 +1,4 @@
-//! This application pings a specific IPv4 address and gets ping statistics.
+//! This application pings a specific IPv4 or IPv6 address and gets ping statistics.
 //! Important: QEMU does not support the ICMP protocol by default so it's important to 
 //! run this command: sudo sh -c "echo \"0 2147483647\" > /proc/sys/net/ipv4/ping_group_range"
 //! in the environment prior to running this application
@@ -21,16 +21,17 @@ extern crate getopts;
 
 use getopts::{Matches, Options};
 use core::str::FromStr;
+use core::fmt::Debug;
 use hashbrown::HashMap;
 use alloc::vec::Vec;        
 use alloc::string::String;
 use hpet::get_hpet;
 use smoltcp::{
     socket::{SocketSet, IcmpSocket, IcmpSocketBuffer, IcmpPacketMetadata, IcmpEndpoint},
-    wire::{IpAddress, Icmpv4Repr, Icmpv4Packet},
+    wire::{IpAddress, Icmpv4Repr, Icmpv4Packet, Icmpv6Repr, Icmpv6Packet},
     phy::{ChecksumCapabilities},
 };
-use network_manager::{NetworkInterfaceRef, NETWORK_INTERFACES};
+use network_manager::NetworkInterfaceRef;
 use byteorder::{ByteOrder, NetworkEndian};
 use smoltcp_helper::{millis_since, poll_iface};
 
@@ -140,33 +141,108 @@ pub fn rmain(matches: &Matches, _opts: Options, address: IpAddress) -> Result<()
     }
 }
 
-/// Used to gain access to the ethernet interface
-fn get_default_iface() -> Result<NetworkInterfaceRef, String> {
-    NETWORK_INTERFACES.lock()
-        .iter()
+/// Used to gain access to the network interface that can reach the given `address`,
+/// e.g., the loopback interface for `127.0.0.1`.
+fn get_iface_for(address: IpAddress) -> Result<NetworkInterfaceRef, String> {
+    network_manager::route_iface(address)
+        .ok_or_else(|| format!("no network interface can reach {}", address))
+}
+
+/// Returns the address of the given interface that is the source of the ICMPv6 packets it sends.
+/// 
+/// An ICMP socket can't be bound to a specific address, so smoltcp always sends from the interface's first IPv6 address,
+/// which must therefore also be used to compute the ICMPv6 checksum.
+fn local_ipv6_addr(iface: &NetworkInterfaceRef) -> Option<IpAddress> {
+    iface.lock().ip_addrs().iter()
+        .filter_map(|cidr| match cidr.address() {
+            IpAddress::Ipv6(addr) => Some(IpAddress::Ipv6(addr)),
+            _ => None,
+        })
         .next()
-        .cloned()
-        .ok_or_else(|| format!("no network interfaces available"))
+}
+
+/// Prints the fields of a sent or received ICMP echo packet, if `verbose` output was requested.
+fn print_packet_details(buffer_len: usize, checksum: u16, echo_ident: u16, msg_type: &dyn Debug, sent: bool) {
+    let (expected_checksum, expected_msg_type) = if sent { ("0", "echo_request") } else { ("above 0", "echo_reply") };
+    println!("buffer length: {}", buffer_len);
+    println!("checking checksum of packet, should be {}: {:?}", expected_checksum, checksum);
+    println!("checking echo_ident of packet, should be a value: {:?}", echo_ident);
+    println!("checking msg_type of packet, should be an {}: {:?}", expected_msg_type, msg_type);
+}
+
+/// Sends an echo request with the given `ident`, `seq_no` and `data` through the given `socket`,
+/// as an ICMPv6 packet if `remote_addr` is an IPv6 address and as an ICMPv4 packet otherwise.
+/// 
+/// The ICMPv6 checksum covers the source address, so `local_addr` must be the address the packet is sent from.
+fn send_echo_request(socket: &mut IcmpSocket, remote_addr: IpAddress, local_addr: IpAddress, ident: u16, seq_no: u16,
+    data: &[u8], checksum_caps: &ChecksumCapabilities, verbose: bool) -> Result<(), &'static str> {
+
+    if let IpAddress::Ipv6(_) = remote_addr {
+        let icmp_repr = Icmpv6Repr::EchoRequest { ident, seq_no, data };
+        let icmp_payload = socket.send(icmp_repr.buffer_len(), remote_addr).map_err(|_e| "the icmp socket cannot send")?;
+        let mut icmp_packet = Icmpv6Packet::new_unchecked(icmp_payload);
+        icmp_repr.emit(&local_addr, &remote_addr, &mut icmp_packet, checksum_caps); //turns or "emits" the raw network stack into an icmpv6 packet
+        if verbose {
+            print_packet_details(icmp_repr.buffer_len(), icmp_packet.checksum(), icmp_packet.echo_ident(), &icmp_packet.msg_type(), true);
+        }
+    } else {
+        let icmp_repr = Icmpv4Repr::EchoRequest { ident, seq_no, data };
+        let icmp_payload = socket.send(icmp_repr.buffer_len(), remote_addr).map_err(|_e| "the icmp socket cannot send")?;
+        let mut icmp_packet = Icmpv4Packet::new_unchecked(icmp_payload);
+        icmp_repr.emit(&mut icmp_packet, checksum_caps); //turns or "emits" the raw network stack into an icmpv4 packet
+        if verbose {
+            print_packet_details(icmp_repr.buffer_len(), icmp_packet.checksum(), icmp_packet.echo_ident(), &icmp_packet.msg_type(), true);
+        }
+    }
+    Ok(())
+}
+
+/// Parses the given received ICMP `payload` from `remote_addr` and returns the sequence number and data
+/// if it is an echo reply, or `None` if it is another ICMP message.
+fn parse_echo_reply<'a>(payload: &'a [u8], remote_addr: IpAddress, local_addr: IpAddress,
+    checksum_caps: &ChecksumCapabilities, verbose: bool) -> Result<Option<(u16, &'a [u8])>, String> {
+
+    if let IpAddress::Ipv6(_) = remote_addr {
+        let icmp_packet = Icmpv6Packet::new_checked(payload).map_err(|e| format!("{}", e))?;
+        // Turns or "parses" the ICMPv6 packet into a raw level representation
+        let icmp_repr = Icmpv6Repr::parse(&remote_addr, &local_addr, &icmp_packet, checksum_caps).map_err(|e| format!("{}", e))?;
+        if verbose {
+            print_packet_details(icmp_repr.buffer_len(), icmp_packet.checksum(), icmp_packet.echo_ident(), &icmp_packet.msg_type(), false);
+        }
+        match icmp_repr {
+            Icmpv6Repr::EchoReply { seq_no, data, .. } => Ok(Some((seq_no, data))),
+            _ => Ok(None),
+        }
+    } else {
+        let icmp_packet = Icmpv4Packet::new_checked(payload).map_err(|e| format!("{}", e))?;
+        // Turns or "parses" the ICMPv4 packet into a raw level representation
+        let icmp_repr = Icmpv4Repr::parse(&icmp_packet, checksum_caps).map_err(|e| format!("{}", e))?;
+        if verbose {
+            print_packet_details(icmp_repr.buffer_len(), icmp_packet.checksum(), icmp_packet.echo_ident(), &icmp_packet.msg_type(), false);
+        }
+        match icmp_repr {
+            Icmpv4Repr::EchoReply { seq_no, data, .. } => Ok(Some((seq_no, data))),
+            _ => Ok(None),
+        }
+    }
 }
 
 // Retrieves the echo reply contained in the receive buffer and prints data pertaining to the packet
 fn get_icmp_pong (waiting_queue: &mut HashMap<u16, u64>, times: &mut Vec<u64>, total_time: &mut u64, 
-    repr: Icmpv4Repr, received: &mut u16, remote_addr: IpAddress, timestamp: u64)  {
+    seq_no: u16, data: &[u8], received: &mut u16, remote_addr: IpAddress, timestamp: u64)  {
     
-    if let Icmpv4Repr::EchoReply { seq_no, data, ..} = repr {
-        if let Some(_) = waiting_queue.get(&seq_no) {
-            let packet_timestamp_ms = NetworkEndian::read_i64(data) as u64;
-            
-            println!("{} bytes from {}: icmp_seq={}, time={}ms",
-                        data.len(), remote_addr, seq_no,
-                        timestamp - packet_timestamp_ms);
-            
-            waiting_queue.remove(&seq_no);
-            *received += 1;
-            times.push((timestamp - packet_timestamp_ms) as u64);
-            *total_time += timestamp - packet_timestamp_ms;
-        }
-    } 
+    if let Some(_) = waiting_queue.get(&seq_no) {
+        let packet_timestamp_ms = NetworkEndian::read_i64(data) as u64;
+        
+        println!("{} bytes from {}: icmp_seq={}, time={}ms",
+                    data.len(), remote_addr, seq_no,
+                    timestamp - packet_timestamp_ms);
+        
+        waiting_queue.remove(&seq_no);
+        *received += 1;
+        times.push((timestamp - packet_timestamp_ms) as u64);
+        *total_time += timestamp - packet_timestamp_ms;
+    }
 }
 
 fn ping(address: IpAddress, count: usize, interval: u64, timeout: u64, verbose: bool, buffer_size: usize) {
@@ -184,13 +260,22 @@ fn ping(address: IpAddress, count: usize, interval: u64, timeout: u64, verbose:
     let icmp_tx_buffer = IcmpSocketBuffer::new(vec![IcmpPacketMetadata::EMPTY], vec![0; 256]);
     let icmp_socket = IcmpSocket::new(icmp_rx_buffer, icmp_tx_buffer);
     
-    // Get the default ethernet interface to ping with
-    let iface_result = get_default_iface();
+    // Get the network interface to ping with
+    let iface_result = get_iface_for(remote_addr);
     let iface = match iface_result {
         Ok(network) => network,
         Err(err) => return println!("couldn't initialize the network: {}", err),
     };
 
+    // ICMPv6 checksums cover the source address, so an IPv6 ping needs to know which address it is sent from.
+    let local_addr = match remote_addr {
+        IpAddress::Ipv6(_) => match local_ipv6_addr(&iface) {
+            Some(addr) => addr,
+            None => return println!("the network interface has no IPv6 address to reach {} from", remote_addr),
+        },
+        _ => IpAddress::Unspecified,
+    };
+
 
     let mut sockets = SocketSet::new(vec![]);
     let icmp_handle = sockets.add(icmp_socket);
@@ -249,25 +334,8 @@ fn ping(address: IpAddress, count: usize, interval: u64, timeout: u64, verbose:
                 
                 NetworkEndian::write_i64(&mut echo_payload, timestamp as i64);
 
-                let icmp_repr = Icmpv4Repr::EchoRequest{
-                        ident: ident,
-                        seq_no: seq_no,
-                        data: &echo_payload
-                    };
-
-                let icmp_payload = match socket.send(icmp_repr.buffer_len(), remote_addr) {
-                    Ok(payload) => payload,
-                    Err(_err) => return println!("the icmp socket cannot send"),
-                };
-
-                let mut icmp_packet = Icmpv4Packet::new_unchecked(icmp_payload);
-                
-                icmp_repr.emit(&mut icmp_packet, &checksum_caps); //turns or "emits" the raw network stack into an icmpv4 packet,
-                if verbose {
-                    println!("buffer length: {}", icmp_repr.buffer_len());
-                    println!("checking checksum of packet, should be 0: {:?}", icmp_packet.checksum());
-                    println!("checking echo_ident of packet, should be a value: {:?}", icmp_packet.echo_ident());
-                    println!("checking msg_type of packet, should be an echo_request: {:?}", icmp_packet.msg_type());
+                if let Err(err) = send_echo_request(&mut socket, remote_addr, local_addr, ident, seq_no, &echo_payload, &checksum_caps, verbose) {
+                    return println!("{}", err);
                 }
             
             // Insert the sequence number into the waiting que along with the timestamp after an echo
@@ -285,22 +353,12 @@ fn ping(address: IpAddress, count: usize, interval: u64, timeout: u64, verbose:
                     Ok((packet_buff,end_point)) => (packet_buff, end_point),
                     Err(err) => return println!("err: {} the receive buffer is empty", err), 
                 }; 
-                let icmp_packet = match Icmpv4Packet::new_checked(&payload) {
-                    Ok(packet) => packet,
-                    Err(err) => return println!("err: {}", err),
-                }; 
-                // Turns or "parses" the ICMPv4 packet into a raw level representation
-                let icmp_repr = match Icmpv4Repr::parse(&icmp_packet, &checksum_caps) {
-                    Ok(repr) => repr,
+                match parse_echo_reply(&payload, remote_addr, local_addr, &checksum_caps, verbose) {
+                    Ok(Some((seq_no, data))) => {
+                        get_icmp_pong(&mut waiting_queue, &mut times, &mut total_time, seq_no, data, &mut received, remote_addr, timestamp);
+                    }
+                    Ok(None) => { }
                     Err(err) => return println!("err: {}", err),
-                }; 
-                
-                get_icmp_pong(&mut waiting_queue, &mut times, &mut total_time, icmp_repr, &mut received, remote_addr, timestamp);
-                if verbose {
-                    println!("buffer length: {}", icmp_repr.buffer_len());
-                    println!("checking checksum of packet, should be above 0: {:?}", icmp_packet.checksum());
-                    println!("checking echo_ident of packet, should be a value: {:?}", icmp_packet.echo_ident());
-                    println!("checking msg_type of packet, should be an echo_reply: {:?}", icmp_packet.msg_type());                
                 }
             }
             
@@ -359,7 +417,7 @@ fn ping(address: IpAddress, count: usize, interval: u64, timeout: u64, verbose:
 fn print_usage(opts: &Options) -> isize {
     let mut brief = format!("Usage: ping DESTINATION \n \n");
 
-    brief.push_str("pings an IPv4 address and returns ping statistics");
+    brief.push_str("pings an IPv4 or IPv6 address and returns ping statistics");
 
     println!("{} \n", opts.usage(&brief));
 
@@ -9,6 +9,7 @@ extern crate scheduler;
 use getopts::Options;
 use alloc::vec::Vec;
 use alloc::string::String;
+use core::time::Duration;
 use task::{TASKLIST, RunState};
 
 pub fn main(args: Vec<String>) -> isize {
@@ -33,12 +34,7 @@ pub fn main(args: Vec<String>) -> isize {
         println!("{0:<5}  {1}", "ID", "NAME");
     }
     else {
-        #[cfg(priority_scheduler)] {
-            println!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5:<10}  {6}", "ID", "RUNSTATE", "CPU", "PIN", "TYPE", "PRIORITY", "NAME");
-        }
-        #[cfg(not(priority_scheduler))] {
-            println!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5}", "ID", "RUNSTATE", "CPU", "PIN", "TYPE", "NAME");
-        }
+        println!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5:<10}  {6:<10}  {7:<10}  {8:<8}  {9}", "ID", "RUNSTATE", "CPU", "PIN", "TYPE", "PRIORITY", "TIME(ms)", "WAIT(ms)", "SWITCHES", "NAME");
     }
 
     // Print all tasks
@@ -51,6 +47,9 @@ pub fn main(args: Vec<String>) -> isize {
         let cpu;
         let pinned; 
         let task_type;
+        let run_time;
+        let wait_time;
+        let switches;
         // only hold the task's lock for a short time
         {
             let task = taskref.lock();
@@ -59,6 +58,7 @@ pub fn main(args: Vec<String>) -> isize {
                 RunState::Initing    => "Initing",
                 RunState::Runnable   => "Runnable",
                 RunState::Blocked    => "Blocked",
+                RunState::Stopped    => "Stopped",
                 RunState::Exited(_)  => "Exited",
                 RunState::Reaped     => "Reaped",
             };
@@ -67,25 +67,20 @@ pub fn main(args: Vec<String>) -> isize {
             task_type = if task.is_an_idle_task {"I"}
                 else if task.is_application() {"A"}
                 else {" "} ;
+            run_time = format_millis(task.cpu_times.run_time());
+            wait_time = format_millis(task.cpu_times.wait_time());
+            switches = task.cpu_times.context_switches();
         }    
         if matches.opt_present("b") {
             task_string.push_str(&format!("{0:<5}  {1}\n", id, name));
         }
         else {
-
-            #[cfg(priority_scheduler)] {
-                let priority = scheduler::get_priority(&taskref).map(|priority| format!("{}", priority)).unwrap_or_else(|| String::from("-"));
-                task_string.push_str(
-                    &format!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5:<10}  {6}\n", 
-                    id, runstate, cpu, pinned, task_type, priority, name)
-                );
-            }
-            #[cfg(not(priority_scheduler))] {
-                task_string.push_str(
-                    &format!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5}\n", 
-                    id, runstate, cpu, pinned, task_type, name)
-                );
-            }
+            // the priority is only known for tasks on cores whose scheduler policy uses priorities
+            let priority = scheduler::get_priority(&taskref).map(|priority| format!("{}", priority)).unwrap_or_else(|| String::from("-"));
+            task_string.push_str(
+                &format!("{0:<5}  {1:<10}  {2:<4}  {3:<4}  {4:<5}  {5:<10}  {6:<10}  {7:<10}  {8:<8}  {9}\n", 
+                id, runstate, cpu, pinned, task_type, priority, run_time, wait_time, switches, name)
+            );
         }
     }
     print!("{}", task_string);
@@ -94,6 +89,11 @@ pub fn main(args: Vec<String>) -> isize {
     0
 }
 
+/// Formats the given time as whole milliseconds, or "-" if it's unknown.
+fn format_millis(time: Option<Duration>) -> String {
+    time.map(|t| format!("{}", t.as_millis())).unwrap_or_else(|| String::from("-"))
+}
+
 fn print_usage(opts: Options) -> isize {
     let mut brief = format!("Usage: ps [options] \n \n");
 
@@ -101,6 +101,10 @@ fn print_usage(opts: Options) -> isize {
     brief.push_str("CPU is the cpu core the task is currently running on. \n");
     brief.push_str("PIN is the core the task is pinned on, if any. \n");
     brief.push_str("RUNSATE is runnability status of this task, i.e. whether it's allowed to be scheduled in. \n");
+    brief.push_str("PRIORITY is the priority of the task, if its core uses the priority scheduler policy. \n");
+    brief.push_str("TIME is the total time the task has spent running, in milliseconds. \n");
+    brief.push_str("WAIT is the total time the task has spent runnable but waiting to run, in milliseconds. \n");
+    brief.push_str("SWITCHES is the number of times the task has been switched to. \n");
     brief.push_str("ID is the unique id of task. \n");
     brief.push_str("NAME is the simple name of the task");
 
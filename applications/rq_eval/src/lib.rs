@@ -130,9 +130,8 @@ fn run_whole(num_tasks: usize) -> Result<(), &'static str> {
         tasks.push(taskref);
     }
 
-    for t in &tasks {
+    for t in tasks {
         t.join()?;
-        let _ = t.take_exit_value();
     }
 
     let end = hpet.get_counter();
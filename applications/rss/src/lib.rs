@@ -0,0 +1,176 @@
+//! Shows the queues of the multi-queue igb NIC and steers received packets between them.
+//!
+//! Usage:
+//! * `rss`: shows each queue's core, interrupt and frame counts, the RSS redirection table and the ethertype filters.
+//! * `rss steer ENTRY[-END] QUEUE`: points one (or a range of) RSS redirection table entries at a queue.
+//! * `rss ethertype add ETHERTYPE QUEUE`: steers all packets with the given ethertype, e.g., `0x88F7`, to a queue.
+//! * `rss ethertype del INDEX`: removes an ethertype filter.
+//! * `rss irq QUEUE APIC_ID`: steers a queue's interrupt to another core.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+extern crate getopts;
+extern crate igb;
+
+use alloc::{
+    string::String,
+    vec::Vec,
+};
+use getopts::Options;
+use igb::{IgbNic, IGB_RETA_ENTRIES};
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&opts);
+        return 0;
+    }
+
+    let nic_ref = match igb::get_igb_nic() {
+        Some(nic) => nic,
+        None => {
+            println!("Error: no igb NIC was found");
+            return -1;
+        }
+    };
+    let mut nic = nic_ref.lock();
+
+    let free: Vec<&str> = matches.free.iter().map(|s| s.as_str()).collect();
+    let result = match free.as_slice() {
+        [] => {
+            print_status(&nic);
+            Ok(())
+        }
+        ["steer", entries, queue] => steer(&mut nic, entries, queue),
+        ["ethertype", "add", ethertype, queue] => add_ethertype_filter(&mut nic, ethertype, queue),
+        ["ethertype", "del", index] => parse_number(index)
+            .and_then(|i| nic.remove_ethertype_filter(i).map_err(String::from)),
+        ["irq", queue, apic_id] => parse_number(queue).and_then(|q| {
+            let apic_id = parse_number(apic_id)?;
+            nic.steer_queue_interrupt(q, apic_id as u8).map_err(String::from)
+        }),
+        _ => {
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    match result {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn print_status(nic: &IgbNic) {
+    println!("{:<6} {:<6} {:<6} {:>12} {:>12} {:>10} {:>12}", "QUEUE", "CORE", "IRQ", "INTERRUPTS", "RX FRAMES", "PENDING", "TX FRAMES");
+    for q in nic.queue_stats() {
+        println!("{:<6} {:<6} {:<6} {:>12} {:>12} {:>10} {:>12}",
+            q.id, q.cpu_id, q.interrupt_num, q.interrupts, q.rx_frames, q.pending_frames, q.tx_frames
+        );
+    }
+
+    // Summarize the redirection table as the entries that point to each queue.
+    let table = nic.redirection_table();
+    println!("\nRSS redirection table ({} entries):", IGB_RETA_ENTRIES);
+    for qid in 0..nic.num_queues() {
+        let entries: Vec<usize> = (0..IGB_RETA_ENTRIES).filter(|&i| table[i] as usize == qid).collect();
+        println!("  queue {}: {} entries {}", qid, entries.len(), format_ranges(&entries));
+    }
+
+    let filters = nic.ethertype_filters();
+    if !filters.is_empty() {
+        println!("\nEthertype filters:");
+        for (index, ethertype, queue) in filters {
+            println!("  [{}] ethertype {:#06X} -> queue {}", index, ethertype, queue);
+        }
+    }
+}
+
+
+/// Formats a sorted list of numbers as ranges, e.g., "0-3, 8, 10-11".
+fn format_ranges(numbers: &[usize]) -> String {
+    let mut ranges: Vec<String> = Vec::new();
+    let mut i = 0;
+    while i < numbers.len() {
+        let start = numbers[i];
+        while i + 1 < numbers.len() && numbers[i + 1] == numbers[i] + 1 {
+            i += 1;
+        }
+        if numbers[i] == start {
+            ranges.push(format!("{}", start));
+        } else {
+            ranges.push(format!("{}-{}", start, numbers[i]));
+        }
+        i += 1;
+    }
+    ranges.join(", ")
+}
+
+
+fn steer(nic: &mut IgbNic, entries: &str, queue: &str) -> Result<(), String> {
+    let (start, end) = match entries.find('-') {
+        Some(i) => (parse_number(&entries[..i])?, parse_number(&entries[i + 1 ..])?),
+        None => {
+            let entry = parse_number(entries)?;
+            (entry, entry)
+        }
+    };
+    if start > end {
+        return Err(format!("invalid range of entries {:?}", entries));
+    }
+    let queue = parse_number(queue)? as u8;
+    for entry in start ..= end {
+        nic.set_redirection_entry(entry, queue)?;
+    }
+    println!("Steered {} redirection table entries to queue {}", end - start + 1, queue);
+    Ok(())
+}
+
+
+fn add_ethertype_filter(nic: &mut IgbNic, ethertype: &str, queue: &str) -> Result<(), String> {
+    let ethertype = parse_number(ethertype)?;
+    if ethertype > 0xFFFF {
+        return Err(format!("ethertype {:#X} doesn't fit into 16 bits", ethertype));
+    }
+    let queue = parse_number(queue)? as u8;
+    let index = nic.add_ethertype_filter(ethertype as u16, queue)?;
+    println!("Added ethertype filter [{}]: ethertype {:#06X} -> queue {}", index, ethertype, queue);
+    Ok(())
+}
+
+
+/// Parses a decimal number, or a hexadecimal number starting with "0x".
+fn parse_number(s: &str) -> Result<usize, String> {
+    let result = if s.starts_with("0x") || s.starts_with("0X") {
+        usize::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse::<usize>()
+    };
+    result.map_err(|_e| format!("couldn't parse number {:?}", s))
+}
+
+
+fn print_usage(opts: &Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: rss [steer ENTRY[-END] QUEUE | ethertype add ETHERTYPE QUEUE | ethertype del INDEX | irq QUEUE APIC_ID]
+Shows the receive queues of the igb NIC, and steers received packets or interrupts between queues and cores.";
@@ -0,0 +1,90 @@
+//! An application that reports and changes the scheduler policy used on each core.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate getopts;
+extern crate scheduler;
+extern crate scheduler_policy;
+
+use getopts::Options;
+use alloc::vec::Vec;
+use alloc::string::String;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("c", "core", "change the policy of only the given core (APIC ID)", "CORE");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let policy_name = match matches.free.len() {
+        0 => {
+            if matches.opt_present("c") {
+                println!("A policy must be given when a core is specified");
+                return -1;
+            }
+            print_policies();
+            return 0;
+        }
+        1 => &matches.free[0],
+        _ => {
+            println!("Only one policy can be given");
+            return -1;
+        }
+    };
+
+    let cores = match matches.opt_str("c").map(|c| c.parse::<u8>()) {
+        None => scheduler_policy::cores(),
+        Some(Ok(core)) => vec![core],
+        Some(Err(_)) => {
+            println!("Invalid core, must be an APIC ID");
+            return -1;
+        }
+    };
+
+    for core in cores {
+        match scheduler::set_policy(core, policy_name) {
+            Ok(_) => println!("Core {} now uses the {} policy", core, policy_name),
+            Err(e) => {
+                println!("Failed to switch core {} to the {} policy: {}", core, policy_name, e);
+                return -1;
+            }
+        }
+    }
+    0
+}
+
+/// Prints the policy that is active on each core, and all policies that can be activated.
+fn print_policies() {
+    println!("{0:<6}  {1}", "CORE", "POLICY");
+    let mut cores = scheduler_policy::cores();
+    cores.sort();
+    for core in cores {
+        println!("{0:<6}  {1}", core, scheduler::get_policy(core).unwrap_or("-"));
+    }
+    println!("\nAvailable policies: {}", scheduler::policy_names().join(", "));
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: sched [options] [POLICY] \n \n");
+
+    brief.push_str("Without a POLICY, shows the scheduler policy that is active on each core. \n");
+    brief.push_str("With a POLICY, switches all cores (or only the given core) to that policy. \n");
+    brief.push_str("Tasks on a switched core keep running, but lose their policy-specific settings, e.g., priorities.");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
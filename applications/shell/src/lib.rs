@@ -10,12 +10,10 @@ extern crate spin;
 extern crate dfqueue;
 extern crate spawn;
 extern crate task;
-extern crate runqueue;
 extern crate event_types; 
 extern crate window_manager;
 extern crate path;
 extern crate root;
-extern crate scheduler;
 extern crate stdio;
 extern crate core_io;
 extern crate app_io;
@@ -24,6 +22,9 @@ extern crate terminal_print;
 extern crate print;
 extern crate environment;
 extern crate libterm;
+extern crate select;
+extern crate task_group;
+extern crate wait_queue;
 
 #[macro_use] extern crate alloc;
 #[macro_use] extern crate log;
@@ -42,11 +43,14 @@ use environment::Environment;
 use core::mem;
 use alloc::collections::BTreeMap;
 use stdio::{Stdio, KeyEventQueue, KeyEventQueueReader, KeyEventQueueWriter,
-            StdioReader, StdioWriter};
+            StdioReader, StdioWriter, Listener};
 use core_io::Write;
 use core::ops::Deref;
 use app_io::{IoStreams, IoControlFlags};
 use fs_node::FileOrDir;
+use select::Select;
+use task_group::TaskGroup;
+use wait_queue::WaitQueue;
 
 /// The status of a job.
 #[derive(PartialEq)]
@@ -54,10 +58,22 @@ enum JobStatus {
     /// Normal state. All the tasks in this job are either running or exited.
     Running,
     /// The job is suspended (but not killed), e.g. upon ctrl-Z.
-    /// All the tasks in this job are either blocked or exited.
+    /// All the tasks in this job are either suspended or exited.
     Stopped
 }
 
+/// An event that needs the shell's attention, as obtained by `Shell::wait_for_event()`.
+enum ShellEvent {
+    /// A running application has printed output that hasn't been displayed yet.
+    AppOutput,
+    /// A task of a job has exited or stopped.
+    TaskStateChanged,
+    /// An event from the terminal's window, e.g., a keypress or a resize.
+    Window(Event),
+    /// A key event that hasn't been taken by an application.
+    Key(KeyEvent),
+}
+
 /// This structure is used by shell to track its spawned applications. Each successfully
 /// evaluated command line will create a `Job`. Each job contains one or more tasks.
 /// Tasks are stored in `tasks` in the same sequence as in the command line.
@@ -70,8 +86,12 @@ struct Job {
     /// A copy of the task ids. Mainly for performance optimization. Task ids are stored
     /// in the same sequence as in `tasks`.
     task_ids: Vec<usize>,
+    /// The group of all tasks in this job, through which the job is stopped, continued or killed as a whole.
+    group: Arc<TaskGroup>,
     /// Status of the job.
     status: JobStatus,
+    /// Ids of the tasks in this job that have exited and whose exit value has already been handled.
+    exited_task_ids: Vec<usize>,
     /// The stdio queues between the running application and the shell, or between
     /// running applications if pipe is used. Assume there are N tasks, counting from 0
     /// to (N-1). `pipe_queues[0]` is the input for the 0-th task. `pipe_queues[N]` is the
@@ -166,7 +186,22 @@ struct Shell {
     /// The terminal's current environment
     env: Arc<Mutex<Environment>>,
     /// the terminal that is bind with the shell instance
-    terminal: Arc<Mutex<Terminal>>
+    terminal: Arc<Mutex<Terminal>>,
+    /// Notified whenever an application writes to the print queue or to the stdout or stderr queues of a job.
+    output_wait_queue: Arc<WaitQueue>,
+    /// Notified whenever a task of a job exits or is suspended.
+    task_wait_queue: Arc<WaitQueue>,
+    /// Notified whenever a key event is pushed into the key event queue.
+    key_event_wait_queue: Arc<WaitQueue>,
+    /// Notified whenever an event is sent to the terminal's window.
+    window_event_wait_queue: Arc<WaitQueue>
+}
+
+/// Returns a function that notifies the given `wait_queue` when invoked,
+/// which is used as a listener for the shell's event sources.
+fn notifier(wait_queue: &Arc<WaitQueue>) -> Listener {
+    let wait_queue = Arc::clone(wait_queue);
+    Arc::new(move || { wait_queue.notify_one(); })
 }
 
 impl Shell {
@@ -179,21 +214,25 @@ impl Shell {
         let terminal_print_dfq: DFQueue<Event>  = DFQueue::new();
         let print_consumer = terminal_print_dfq.into_consumer();
         let print_producer = print_consumer.obtain_producer();
+        let output_wait_queue = Arc::new(WaitQueue::new());
 
-        let key_event_queue: KeyEventQueue = KeyEventQueue::new();
+        let key_event_wait_queue = Arc::new(WaitQueue::new());
+        let key_event_queue: KeyEventQueue = KeyEventQueue::with_listener(notifier(&key_event_wait_queue));
         let key_event_producer = key_event_queue.get_writer();
         let key_event_consumer = key_event_queue.get_reader();
 
         // Sets up the kernel to print to this terminal instance.
         // Note that if this has already been previously set by an existing shell,
         // this function call will do nothing. 
-        print::set_default_print_output(print_producer.obtain_producer());
+        print::set_default_print_output(print_producer.obtain_producer(), Arc::clone(&output_wait_queue));
 
         let env = Environment {
             working_dir: Arc::clone(root::get_root()), 
         };
 
-        let terminal = Arc::new(Mutex::new(Terminal::new()?));
+        let terminal = Terminal::new()?;
+        let window_event_wait_queue = Arc::clone(terminal.window.event_wait_queue());
+        let terminal = Arc::new(Mutex::new(terminal));
 
         Ok(Shell {
             jobs: BTreeMap::new(),
@@ -209,7 +248,11 @@ impl Shell {
             print_consumer,
             print_producer,
             env: Arc::new(Mutex::new(env)),
-            terminal
+            terminal,
+            output_wait_queue,
+            task_wait_queue: Arc::new(WaitQueue::new()),
+            key_event_wait_queue,
+            window_event_wait_queue
         })
     }
 
@@ -399,8 +442,8 @@ impl Shell {
         // Ctrl+C signals the shell to exit the job
         if keyevent.modifiers.is_control() && keyevent.keycode == Keycode::C {
             if let Some(ref fg_job_num) = self.fg_job_num {
-                let task_refs = match self.jobs.get(fg_job_num) {
-                    Some(job) => job.tasks.clone(), 
+                let group = match self.jobs.get(fg_job_num) {
+                    Some(job) => job.group.clone(), 
                     None => {
                         self.clear_cmdline(true)?;
                         self.input_buffer.clear();
@@ -414,29 +457,11 @@ impl Shell {
                 app_io::lock_and_execute(&move |_flags_guard: MutexGuard<BTreeMap<usize, IoControlFlags>>,
                                                 _streamss_guard: MutexGuard<BTreeMap<usize, IoStreams>>| {
 
-                    // Kill all tasks in the job.
-                    for task_ref in &task_refs {
-                        if task_ref.lock().has_exited() { continue; }
-                        match task_ref.kill(KillReason::Requested) {
-                            Ok(_) => {
-                                if let Err(e) = runqueue::remove_task_from_all(&task_ref) {
-                                    error!("Killed task but could not remove it from runqueue: {}", e);
-                                }
-                            }
-                            Err(e) => error!("Could not kill task, error: {}", e),
-                        }
-
-                        // Here we must wait for the running application to quit before releasing the lock,
-                        // because the previous `kill` method will NOT stop the application immediately.
-                        // We must circumvent the situation where the application is killed while holding the
-                        // lock. We wait for the application to finish its last time slice. It will then be
-                        // removed from the run queue. We can thereafter release the lock.
-                        loop {
-                            scheduler::schedule(); // yield the CPU
-                            if !task_ref.lock().is_running() {
-                                break;
-                            }
-                        }
+                    // Kill all tasks in the job at once. Killing the group waits for all of its tasks
+                    // to finish their last time slice, so none of them is killed while holding the lock
+                    // and we can thereafter release the lock.
+                    if let Err(e) = group.kill(KillReason::Requested) {
+                        error!("Could not kill all tasks of the job, error: {}", e);
                     }
                 });
                 self.terminal.lock().print_to_terminal("^C\n".to_string());
@@ -455,8 +480,8 @@ impl Shell {
             // Do nothing if we have no running foreground job.
 
             if let Some(ref fg_job_num) = self.fg_job_num {
-                let task_refs = match self.jobs.get(fg_job_num) {
-                    Some(job) => job.tasks.clone(), 
+                let group = match self.jobs.get(fg_job_num) {
+                    Some(job) => job.group.clone(), 
                     None => {
                         return Ok(());
                     }
@@ -466,22 +491,11 @@ impl Shell {
                 app_io::lock_and_execute(&move |_flags_guard: MutexGuard<BTreeMap<usize, IoControlFlags>>,
                                                 _streams_guard: MutexGuard<BTreeMap<usize, IoStreams>>| {
                     
-                    // Stop all tasks in the job.
-                    for task_ref in &task_refs {
-                        if task_ref.lock().has_exited() { continue; }
-                        task_ref.block();
-
-                        // Here we must wait for the running application to stop before releasing the lock,
-                        // because the previous `block` method will NOT stop the application immediately.
-                        // We must circumvent the situation where the application is stopped while holding the
-                        // lock. We wait for the application to finish its last time slice. It will then be
-                        // truly blocked. We can thereafter release the lock.
-                        loop {
-                            scheduler::schedule(); // yield the CPU
-                            if !task_ref.lock().is_running() {
-                                break;
-                            }
-                        }
+                    // Stop all tasks in the job at once. Suspending the group waits for all of its tasks
+                    // to finish their last time slice, so none of them is stopped while holding the lock
+                    // and we can thereafter release the lock.
+                    if let Err(e) = group.suspend() {
+                        error!("Could not stop all tasks of the job, error: {}", e);
                     }
                 });
             }
@@ -683,7 +697,7 @@ impl Shell {
         taskref.set_env(self.env.clone()); // Set environment variable of application to the same as terminal task
 
         // Gets the task id so we can reference this task if we need to kill it with Ctrl+C
-        return Ok(taskref);
+        return Ok(taskref.task().clone());
     }
 
     /// Evaluate the command line. It creates a sequence of jobs, which forms a chain of applications that
@@ -741,8 +755,8 @@ impl Shell {
                 let mut previous_queue_reader = first_stdio_queue.get_reader();
                 pipe_queues.push(first_stdio_queue);
                 for task_id in &task_ids {
-                    let stdio_queue_for_stdin_and_stdout = Stdio::new();
-                    let stdio_queue_for_stderr = Stdio::new();
+                    let stdio_queue_for_stdin_and_stdout = Stdio::with_listener(notifier(&self.output_wait_queue));
+                    let stdio_queue_for_stderr = Stdio::with_listener(notifier(&self.output_wait_queue));
                     let streams = IoStreams::new(
                         previous_queue_reader,
                         stdio_queue_for_stdin_and_stdout.get_writer(),
@@ -757,7 +771,11 @@ impl Shell {
                     pipe_queues.push(stdio_queue_for_stdin_and_stdout);
 
                     // Insert print event producer to `terminal_print` to support legacy output.
-                    if let Err(msg) = terminal_print::add_child(*task_id, self.print_producer.obtain_producer()) {
+                    if let Err(msg) = terminal_print::add_child_with_wait_queue(
+                        *task_id,
+                        self.print_producer.obtain_producer(),
+                        Arc::clone(&self.output_wait_queue),
+                    ) {
                         self.terminal.lock().print_to_terminal(format!("{}\n", msg).to_string());
                         return Err(msg);
                     }
@@ -765,10 +783,14 @@ impl Shell {
 
                 let job_stdout_reader = previous_queue_reader;
 
+                let group = Arc::new(TaskGroup::new(task_refs.clone()));
+
                 let new_job = Job {
                     tasks: task_refs,
                     task_ids,
+                    group,
                     status: JobStatus::Running,
+                    exited_task_ids: Vec::new(),
                     pipe_queues,
                     stderr_queues,
                     stdin_writer: job_stdin_writer,
@@ -776,8 +798,10 @@ impl Shell {
                     cmd: self.cmdline.clone()
                 };
 
-                // All IO streams have been set up for the new tasks. Safe to unblock them now.
+                // All IO streams have been set up for the new tasks. Safe to unblock them now,
+                // once the shell will be notified when they exit or are suspended.
                 for task_ref in &new_job.tasks {
+                    task_ref.set_state_change_handler(notifier(&self.task_wait_queue));
                     task_ref.unblock();
                 }
 
@@ -1064,6 +1088,7 @@ impl Shell {
                 if task_ref.lock().has_exited() { // a task has exited
                     let exited_task_id = task_ref.lock().id;
                     if let Some(exit_val) = task_ref.take_exit_value() {
+                        job.exited_task_ids.push(exited_task_id);
                         match exit_val {
                             ExitValue::Completed(exit_status) => {
                                 // here: the task ran to completion successfully, so it has an exit value.
@@ -1126,7 +1151,7 @@ impl Shell {
                         }
                     }
 
-                } else if !task_ref.lock().is_runnable() && job.status != JobStatus::Stopped { // task has just stopped
+                } else if task_ref.lock().is_suspended() && job.status != JobStatus::Stopped { // task has just stopped
 
                     // One task in this job is stopped, but the status of the Job has not been set to
                     // `Stopped`. Let's set it now.
@@ -1271,50 +1296,89 @@ impl Shell {
         need_refresh
     }
 
-    /// This main loop is the core component of the shell's event-driven architecture. The shell receives events
-    /// from two queues
+    /// Blocks until any of the shell's event sources is ready and returns the corresponding event.
+    ///
+    /// The sources are checked in order of priority: output from applications is always printed first,
+    /// then exited or stopped tasks are handled, and only then are window and key events handled.
+    /// Each source notifies its own wait queue, so the shell sleeps until one of them has an event.
+    fn wait_for_event(&self) -> Result<ShellEvent, &'static str> {
+        let print_consumer = &self.print_consumer;
+        let jobs = &self.jobs;
+        let terminal = &self.terminal;
+        let key_event_consumer = &self.key_event_consumer;
+
+        let mut select = Select::new();
+        select
+            .wait_queue(&self.output_wait_queue, move || {
+                let has_legacy_output = print_consumer.peek().is_some();
+                let has_output = has_legacy_output || jobs.values().any(|job| {
+                    job.stdout_reader.lock().remaining_bytes() > 0
+                        || job.stderr_queues.iter().any(|stderr| stderr.get_reader().lock().remaining_bytes() > 0)
+                });
+                if has_output { Some(ShellEvent::AppOutput) } else { None }
+            })
+            .wait_queue(&self.task_wait_queue, move || {
+                let has_changed = jobs.values().any(|job| job.tasks.iter().any(|task_ref| {
+                    let task = task_ref.lock();
+                    if task.has_exited() {
+                        !job.exited_task_ids.contains(&task.id)
+                    } else {
+                        task.is_suspended() && job.status != JobStatus::Stopped
+                    }
+                }));
+                if has_changed { Some(ShellEvent::TaskStateChanged) } else { None }
+            })
+            .wait_queue(&self.window_event_wait_queue, move || terminal.lock().get_event().map(ShellEvent::Window))
+            .wait_queue(&self.key_event_wait_queue, move || {
+                let locked_consumer = key_event_consumer.lock();
+                // the key event queue may currently be taken by an application
+                locked_consumer.as_ref().and_then(|consumer| consumer.read_one()).map(ShellEvent::Key)
+            });
+
+        select.wait().map_err(|_e| {
+            error!("shell: failed to wait for events: {:?}", _e);
+            "shell: failed to wait for events"
+        })
+    }
+
+    /// This main loop is the core component of the shell's event-driven architecture. The shell blocks until
+    /// it receives an event from one of the following sources, in order of priority:
+    /// 
+    /// 1) The output of applications, i.e., the print queue that handles print events from legacy applications
+    ///    and the stdout and stderr queues of jobs.
     /// 
-    /// 1) The print queue handles print events from applications. The producer to this queue
-    ///    is any EXTERNAL application that prints to the terminal.
+    /// 2) The state of the tasks of each job, which is handled by `task_handler` when a task exits or stops.
     /// 
-    /// 2) The input queue (provided by the window manager when the temrinal request a window) gives key events
+    /// 3) The input queue (provided by the window manager when the temrinal request a window) gives key events
     ///    and resize event to the application.
     /// 
-    /// The print queue is handled first inside the loop iteration, which means that all print events in the print
-    /// queue will always be printed to the text display before input events or any other managerial functions are handled. 
+    /// 4) The key event queue, which holds key events that have not been taken by an application.
+    /// 
+    /// Application output is handled first, which means that all output will always be printed to the text display
+    /// before input events or any other managerial functions are handled. 
     /// This allows for clean appending to the scrollback buffer and prevents interleaving of text.
     fn start(mut self) -> Result<(), &'static str> {
-        let mut need_refresh = false;
-        let mut need_prompt = false;
         self.redisplay_prompt();
         self.terminal.lock().refresh_display()?;
 
         loop {
-            // If there is anything from running applications to be printed, it printed on the screen and then
-            // return true, so that the loop continues, otherwise nothing happens and we keep on going with the
-            // loop body. We do so to ensure that printing is handled before keypresses.
-            if self.check_and_print_app_output() {
-                need_refresh = true;
-                continue;
-            }
+            let mut need_refresh = false;
+            let mut need_prompt = false;
 
-            // Handles the cleanup of any application task that has finished running, returns whether we need
-            // a new prompt or need to refresh the screen.
-            let (need_refresh_on_task_event, need_prompt_on_task_event) = self.task_handler()?;
+            match self.wait_for_event()? {
+                ShellEvent::AppOutput => {
+                    need_refresh = self.check_and_print_app_output();
+                }
 
-            // Print prompt or refresh the screen based on needs.
-            if need_prompt || need_prompt_on_task_event {
-                self.redisplay_prompt();
-                need_prompt = false;
-            }
+                // Handles the cleanup of any application task that has finished running, returns whether we need
+                // a new prompt or need to refresh the screen.
+                ShellEvent::TaskStateChanged => {
+                    let (need_refresh_on_task_event, need_prompt_on_task_event) = self.task_handler()?;
+                    need_refresh = need_refresh_on_task_event;
+                    need_prompt = need_prompt_on_task_event;
+                }
 
-            // Handle all available events from the terminal's (its window's) event queue.
-            while let Some(ev) = {
-                // this weird syntax ensures the terminal lock is dropped before entering the loop body
-                let mut locked_terminal = self.terminal.lock();
-                locked_terminal.get_event()
-            } {
-                match ev {
+                ShellEvent::Window(ev) => match ev {
                     // Returns from the main loop.
                     Event::ExitEvent => {
                         trace!("exited terminal");
@@ -1334,10 +1398,21 @@ impl Shell {
                     _unhandled => { 
                         // trace!("Shell is ignoring unhandled event: {:?}", _unhandled);
                     }
-                };
-            }          
-            if need_refresh || need_refresh_on_task_event {
-                // update if there are outputs from applications
+                },
+
+                ShellEvent::Key(key_event) => {
+                    if let Err(e) = self.handle_key_event(key_event) {
+                        error!("{}", e);
+                    }
+                    if key_event.action == KeyAction::Pressed { need_refresh = true; }
+                }
+            }
+
+            // Print prompt or refresh the screen based on needs.
+            if need_prompt {
+                self.redisplay_prompt();
+            }
+            if need_refresh {
                 self.terminal.lock().refresh_display()?;
             }
 
@@ -1346,33 +1421,11 @@ impl Shell {
                 term.window.is_active()
             };
             
+            // The shell only wakes up upon events, so the cursor is kept visible rather than blinking.
             if is_active {
-                self.terminal.lock().display_cursor()?;
-            }
-
-            // handle inputs
-            need_refresh = false;
-            loop {
-                let locked_consumer = self.key_event_consumer.lock();
-                if let Some(ref key_event_consumer) = locked_consumer.deref() {
-                    if let Some(key_event) = key_event_consumer.read_one() {
-                        mem::drop(locked_consumer); // drop the lock so that we can invoke the method on the next line
-                        if let Err(e) = self.handle_key_event(key_event) {
-                            error!("{}", e);
-                        }
-                        if key_event.action == KeyAction::Pressed { need_refresh = true; }
-                    } else { // currently the key event queue is empty, break the loop
-                        break;
-                    }
-                } else { // currently the key event queue is taken by an application
-                    break;
-                }
-            }
-            if need_refresh {
-                // update if there are inputs
-                self.terminal.lock().refresh_display()?;
-            } else {
-                scheduler::schedule(); // yield the CPU if nothing to do
+                let mut terminal = self.terminal.lock();
+                terminal.cursor.reset();
+                terminal.display_cursor()?;
             }
         }
     }
@@ -1434,12 +1487,10 @@ impl Shell {
             let job_num = args[0].chars().skip(1).collect::<String>();
             if let Ok(job_num) = job_num.parse::<isize>() {
                 if let Some(job) = self.jobs.get_mut(&job_num) {
-                    for task_ref in &job.tasks {
-                        if !task_ref.lock().has_exited() {
-                            task_ref.unblock();
-                        }
-                        job.status = JobStatus::Running;
+                    if let Err(e) = job.group.resume() {
+                        error!("Could not continue all tasks of job {}, error: {}", job_num, e);
                     }
+                    job.status = JobStatus::Running;
                     self.clear_cmdline(false)?;
                     self.redisplay_prompt();
                     return Ok(());
@@ -1467,12 +1518,10 @@ impl Shell {
             if let Ok(job_num) = job_num.parse::<isize>() {
                 if let Some(job) = self.jobs.get_mut(&job_num) {
                     self.fg_job_num = Some(job_num);
-                    for task_ref in &job.tasks {
-                        if !task_ref.lock().has_exited() {
-                            task_ref.unblock();
-                        }
-                        job.status = JobStatus::Running;
+                    if let Err(e) = job.group.resume() {
+                        error!("Could not continue all tasks of job {}, error: {}", job_num, e);
                     }
+                    job.status = JobStatus::Running;
                     return Ok(());
                 }
                 self.terminal.lock().print_to_terminal(format!("No job number {} found!\n", job_num).to_string());
@@ -0,0 +1,333 @@
+//! A remote console service that allows a headless or remote machine to be driven interactively over the network.
+//!
+//! `telnetd` listens for TCP connections on the given port (23 by default)
+//! and spawns a new shell session task for each accepted connection.
+//! The session's input and output are bridged to the TCP socket through a pair of `stdio` queues,
+//! and the applications started from that session receive headless `app_io::IoStreams`.
+//!
+//! Basic telnet option negotiation is performed, so that both a standard `telnet` client
+//! and a raw TCP client like `netcat` can be used, e.g.:
+//! `telnet <theseus_ip> 23` or `nc <theseus_ip> 23`.
+//! When running in QEMU with user-mode networking, the port must be forwarded to the host first.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate terminal_print;
+extern crate spin;
+extern crate getopts;
+extern crate core_io;
+extern crate dfqueue;
+extern crate event_types;
+extern crate stdio;
+extern crate app_io;
+extern crate spawn;
+extern crate task;
+extern crate scheduler;
+extern crate environment;
+extern crate root;
+extern crate path;
+extern crate hpet;
+extern crate smoltcp;
+#[macro_use] extern crate smoltcp_helper;
+
+mod telnet;
+mod session;
+
+
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core_io::Write;
+use getopts::Options;
+use hpet::get_hpet;
+use smoltcp::socket::{SocketSet, SocketHandle, TcpSocket, TcpSocketBuffer};
+use stdio::{Stdio, StdioReader, StdioWriter};
+use task::{TaskRef, KillReason};
+use smoltcp_helper::{get_default_iface, poll_iface, millis_since};
+use telnet::TelnetParser;
+
+
+/// The well-known telnet port.
+const DEFAULT_PORT: u16 = 23;
+/// The default number of clients that can be connected simultaneously.
+const DEFAULT_MAX_CONNECTIONS: usize = 4;
+/// The size of each TCP socket's receive and transmit buffers.
+const SOCKET_BUFFER_SIZE: usize = 4096;
+/// How long a disconnected client's session task may take to exit on its own before it is killed.
+const SESSION_EXIT_TIMEOUT_MILLIS: u64 = 1000;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("p", "port", "the TCP port to listen on (default: 23)", "PORT");
+    opts.optopt("c", "connections", "the maximum number of simultaneous connections (default: 4)", "N");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&opts);
+        return 0;
+    }
+
+    let port = match matches.opt_str("p").map(|p| p.parse::<u16>()) {
+        None => DEFAULT_PORT,
+        Some(Ok(p)) => p,
+        Some(Err(_e)) => {
+            println!("couldn't parse port");
+            return -1;
+        }
+    };
+    let max_connections = match matches.opt_str("c").map(|c| c.parse::<usize>()) {
+        None => DEFAULT_MAX_CONNECTIONS,
+        Some(Ok(c)) if c > 0 => c,
+        _ => {
+            println!("couldn't parse number of connections");
+            return -1;
+        }
+    };
+
+    println!("telnetd: listening on TCP port {} for up to {} connections.", port, max_connections);
+    match rmain(port, max_connections) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("telnetd: error: {}", e);
+            -1
+        }
+    }
+}
+
+
+/// The state of one TCP socket that accepts connections and its currently-connected session, if any.
+struct Connection {
+    /// The handle of this connection's TCP socket.
+    handle: SocketHandle,
+    /// The state of the connected client, if any.
+    client: Option<Client>,
+}
+
+/// A connected remote client whose input and output are bridged to a session task.
+struct Client {
+    /// The task running the shell session for this client.
+    session_task: TaskRef,
+    /// The writer to the session's input queue.
+    input_writer: StdioWriter,
+    /// The reader of the session's output queue.
+    output_reader: StdioReader,
+    /// Filters telnet commands out of the received byte stream.
+    parser: TelnetParser,
+    /// Encoded bytes that have yet to be accepted by the TCP socket's transmit buffer.
+    pending_tx: Vec<u8>,
+}
+
+/// A session task whose client has disconnected, which is given some time to exit on its own
+/// (and clean up the job it's running) before it is killed.
+struct EndedSession {
+    task: TaskRef,
+    /// The HPET tick count at which the session's input was closed.
+    ended_at: u64,
+}
+
+
+/// The network task loop, which accepts connections and shuttles bytes between sockets and sessions.
+/// Since `smoltcp` sockets on one interface must all be polled together,
+/// a single task services every connection, while each session runs in its own task.
+/// 
+/// An error on one connection only closes that connection; the other connections keep being serviced.
+fn rmain(port: u16, max_connections: usize) -> Result<(), &'static str> {
+    let iface = get_default_iface()?;
+    let startup_time = hpet_ticks!();
+
+    let mut sockets = SocketSet::new(Vec::with_capacity(max_connections));
+    let mut connections: Vec<Connection> = (0 .. max_connections).map(|_| {
+        let tcp_rx_buffer = TcpSocketBuffer::new(vec![0; SOCKET_BUFFER_SIZE]);
+        let tcp_tx_buffer = TcpSocketBuffer::new(vec![0; SOCKET_BUFFER_SIZE]);
+        Connection {
+            handle: sockets.add(TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer)),
+            client: None,
+        }
+    }).collect();
+    let mut ended_sessions: Vec<EndedSession> = Vec::new();
+
+    loop {
+        let packet_io_occurred = poll_iface(&iface, &mut sockets, startup_time)?;
+
+        let mut data_transferred = false;
+        for conn in connections.iter_mut() {
+            match service_connection(&mut sockets, conn, port, &mut ended_sessions) {
+                Ok(transferred) => data_transferred |= transferred,
+                Err(e) => {
+                    error!("telnetd: closing connection after error: {}", e);
+                    close_connection(&mut sockets, conn, &mut ended_sessions);
+                }
+            }
+        }
+        reap_ended_sessions(&mut ended_sessions);
+
+        if !packet_io_occurred && !data_transferred {
+            scheduler::schedule(); // yield the CPU if nothing to do
+        }
+    }
+}
+
+
+/// Services one connection: re-listens on a closed socket, starts a session for a newly-accepted client,
+/// and moves data between the socket and the client's session.
+/// Returns true if any data was transferred.
+fn service_connection(
+    sockets: &mut SocketSet,
+    conn: &mut Connection,
+    port: u16,
+    ended_sessions: &mut Vec<EndedSession>,
+) -> Result<bool, &'static str> {
+    let mut socket = sockets.get::<TcpSocket>(conn.handle);
+
+    if !socket.is_open() {
+        if let Some(client) = conn.client.take() {
+            ended_sessions.push(end_session(client));
+        }
+        socket.listen(port).map_err(|_e| {
+            error!("telnetd: failed to listen on port {}: {:?}", port, _e);
+            "failed to listen on TCP socket"
+        })?;
+        return Ok(false);
+    }
+
+    // A new client has connected to this listening socket.
+    if conn.client.is_none() && socket.is_active() && socket.may_send() {
+        let remote = socket.remote_endpoint().to_string();
+        info!("telnetd: accepted connection from {}", remote);
+        let input = Stdio::new();
+        let output = Stdio::new();
+        let session_task = spawn::new_task_builder(session::session_entry, (input.get_reader(), output.get_writer(), remote.clone()))
+            .name(format!("telnetd_session_{}", remote))
+            .spawn()?
+            .task()
+            .clone();
+        conn.client = Some(Client {
+            session_task,
+            input_writer: input.get_writer(),
+            output_reader: output.get_reader(),
+            parser: TelnetParser::new(),
+            pending_tx: telnet::INITIAL_NEGOTIATION.to_vec(),
+        });
+    }
+
+    let client = match conn.client {
+        Some(ref mut c) => c,
+        None => return Ok(false),
+    };
+    let mut data_transferred = false;
+
+    // Receive bytes from the remote client and pass them to the session.
+    if socket.can_recv() {
+        let mut data = Vec::new();
+        let mut replies = Vec::new();
+        {
+            let parser = &mut client.parser;
+            socket.recv(|buf| {
+                parser.process(buf, &mut data, &mut replies);
+                (buf.len(), ())
+            }).map_err(|_e| "failed to receive from TCP socket")?;
+        }
+        client.pending_tx.extend_from_slice(&replies);
+        if !data.is_empty() && client.input_writer.lock().write_all(&data).is_err() {
+            warn!("telnetd: session for {} is no longer accepting input", socket.remote_endpoint());
+        }
+        data_transferred = true;
+    }
+
+    // Send the session's output to the remote client.
+    if client.pending_tx.is_empty() {
+        let mut buf: [u8; 256] = [0; 256];
+        let cnt = client.output_reader.lock().try_read(&mut buf).map_err(|_e| "failed to read session output")?;
+        telnet::encode(&buf[..cnt], &mut client.pending_tx);
+    }
+    if !client.pending_tx.is_empty() && socket.can_send() {
+        let sent = socket.send_slice(&client.pending_tx).map_err(|_e| "failed to send on TCP socket")?;
+        client.pending_tx.drain(.. sent);
+        data_transferred = true;
+    }
+
+    // The remote client closed its end of the connection, so end its session.
+    if !socket.may_recv() {
+        client.input_writer.lock().set_eof();
+    }
+
+    // The session has ended and all of its output has been sent, so close the connection.
+    // The session's output must be fully drained first, even if its task has already exited,
+    // otherwise its last words (e.g., the exit message) would be lost.
+    let session_ended = {
+        let locked_output = client.output_reader.lock();
+        locked_output.remaining_bytes() == 0
+            && (locked_output.is_eof() || client.session_task.lock().has_exited())
+    };
+    if session_ended && client.pending_tx.is_empty() {
+        // Closing a TCP socket still transmits the data in its transmit buffer before the FIN.
+        socket.close();
+        if let Some(client) = conn.client.take() {
+            ended_sessions.push(end_session(client));
+        }
+    }
+
+    Ok(data_transferred)
+}
+
+/// Aborts the given connection after an error occurred while servicing it, and ends its client's session, if any.
+/// The socket will listen for a new client the next time the connection is serviced.
+fn close_connection(sockets: &mut SocketSet, conn: &mut Connection, ended_sessions: &mut Vec<EndedSession>) {
+    sockets.get::<TcpSocket>(conn.handle).abort();
+    if let Some(client) = conn.client.take() {
+        ended_sessions.push(end_session(client));
+    }
+}
+
+
+/// Closes the input of the given client's session, which tells it to kill its running job and exit.
+/// 
+/// The session task is detached, such that it's reaped as soon as it exits;
+/// it's killed by `reap_ended_sessions()` if it doesn't exit on its own in time.
+fn end_session(client: Client) -> EndedSession {
+    client.input_writer.lock().set_eof();
+    client.session_task.detach();
+    EndedSession {
+        task: client.session_task,
+        ended_at: get_hpet().as_ref().map_or(0, |hpet| hpet.get_counter()),
+    }
+}
+
+/// Forgets the ended sessions whose tasks have exited, and kills the ones that haven't exited in time.
+/// If the elapsed time can't be measured, a session that hasn't exited is killed right away.
+fn reap_ended_sessions(ended_sessions: &mut Vec<EndedSession>) {
+    let mut i = 0;
+    while i < ended_sessions.len() {
+        let exited = ended_sessions[i].task.lock().has_exited();
+        let timed_out = millis_since(ended_sessions[i].ended_at).map_or(true, |ms| ms >= SESSION_EXIT_TIMEOUT_MILLIS);
+        if !exited && !timed_out {
+            i += 1;
+            continue;
+        }
+        let ended = ended_sessions.swap_remove(i);
+        if !exited {
+            warn!("telnetd: killing session task {:?}, which didn't exit after its client disconnected", ended.task.lock().name);
+            // The task may have exited in the meantime, in which case it has already been reaped.
+            let _ = ended.task.kill(KillReason::Requested);
+        }
+    }
+}
+
+
+fn print_usage(opts: &Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: telnetd [OPTION]...
+Listens for telnet (or raw TCP) connections and starts a remote shell session for each one.";
@@ -0,0 +1,435 @@
+//! A simple line-oriented shell session that runs on behalf of one remote client.
+//!
+//! Unlike the `shell` application, a session is not bound to any `libterm::Terminal`.
+//! All of its input and output flow through a pair of `stdio` queues,
+//! the other ends of which are serviced by the network task that owns the client's TCP socket.
+
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::mem;
+use core::ops::Deref;
+use core_io::Write;
+use spin::Mutex;
+use dfqueue::{DFQueue, DFQueueConsumer, DFQueueProducer};
+use event_types::Event;
+use environment::Environment;
+use path::Path;
+use stdio::{Stdio, StdioReader, StdioWriter, KeyEventQueue, KeyEventQueueReader};
+use task::{TaskRef, ExitValue, KillReason};
+use app_io::IoStreams;
+use telnet::{ASCII_ETX, ASCII_EOT, ASCII_DEL};
+
+
+/// The arguments given to a new session task:
+/// the reader of the client's input queue, the writer of the client's output queue,
+/// and a description of the remote endpoint.
+pub type SessionArgs = (StdioReader, StdioWriter, String);
+
+/// The entry point of a session task, which is spawned once per client connection.
+///
+/// The session ends when the client's input stream reaches EOF or the user types `exit`,
+/// at which point the EOF flag of the output queue is set to tell the network task to close the connection.
+pub fn session_entry((input, output, remote): SessionArgs) -> Result<(), &'static str> {
+    let output_on_exit = output.clone();
+    let res = Session::new(input, output, remote).start();
+    if let Err(e) = res {
+        error!("telnetd: session exited with error: {}", e);
+    }
+    output_on_exit.lock().set_eof();
+    res
+}
+
+
+/// A job started from the command line of a session, which may consist of multiple piped tasks.
+/// This mirrors the `Job` used in the `shell` application, but there is only ever one (foreground) job.
+struct Job {
+    /// The tasks in this job, in the same order as they appear in the command line.
+    tasks: Vec<TaskRef>,
+    /// The ids of the tasks in this job, in the same order as `tasks`.
+    task_ids: Vec<usize>,
+    /// Whether each task in this job has already been observed to exit.
+    exited: Vec<bool>,
+    /// The queues chaining the tasks together. `pipe_queues[0]` is the job's stdin
+    /// and `pipe_queues[N]` is the output from the last task back to the session.
+    pipe_queues: Vec<Stdio>,
+    /// The stderr queues of each task.
+    stderr_queues: Vec<Stdio>,
+    /// The writer to the job's stdin, i.e., `pipe_queues[0]`.
+    stdin_writer: StdioWriter,
+    /// The reader of the job's stdout, i.e., `pipe_queues[N]`.
+    stdout_reader: StdioReader,
+}
+
+
+struct Session {
+    /// Bytes typed by the remote client.
+    input: StdioReader,
+    /// Bytes to be sent to the remote client.
+    output: StdioWriter,
+    /// The command line being typed, before `enter` is received.
+    cmdline: String,
+    /// The currently-running job, if any.
+    job: Option<Job>,
+    /// The environment shared by this session and the applications it spawns.
+    env: Arc<Mutex<Environment>>,
+    /// Remote clients cannot produce key events, so this key event queue is never written to;
+    /// it exists only because applications expect one to exist.
+    key_event_consumer: Arc<Mutex<Option<KeyEventQueueReader>>>,
+    /// The consumer of the legacy `terminal_print` output of this session's applications.
+    print_consumer: DFQueueConsumer<Event>,
+    /// The producer of the legacy `terminal_print` output of this session's applications.
+    print_producer: DFQueueProducer<Event>,
+    /// Whether the user has requested to end the session.
+    exit_requested: bool,
+}
+
+impl Session {
+    fn new(input: StdioReader, output: StdioWriter, remote: String) -> Session {
+        let print_dfq: DFQueue<Event> = DFQueue::new();
+        let print_consumer = print_dfq.into_consumer();
+        let print_producer = print_consumer.obtain_producer();
+        let key_event_queue = KeyEventQueue::new();
+
+        let env = Environment {
+            working_dir: Arc::clone(root::get_root()),
+        };
+
+        let mut session = Session {
+            input,
+            output,
+            cmdline: String::new(),
+            job: None,
+            env: Arc::new(Mutex::new(env)),
+            key_event_consumer: Arc::new(Mutex::new(Some(key_event_queue.get_reader()))),
+            print_consumer,
+            print_producer,
+            exit_requested: false,
+        };
+        session.print(&format!("Theseus remote console, connected from {}.\nType \"exit\" to disconnect.\n", remote));
+        session
+    }
+
+    /// Writes the given string to the remote client.
+    fn print(&mut self, s: &str) {
+        if let Err(_e) = self.output.lock().write_all(s.as_bytes()) {
+            error!("telnetd: failed to write to session output: {:?}", _e);
+        }
+    }
+
+    fn display_prompt(&mut self) {
+        let prompt = format!("{}: ", self.env.lock().working_dir.lock().get_absolute_path());
+        self.print(&prompt);
+    }
+
+    /// The main loop of the session, which alternates between handling input from the remote client
+    /// and servicing the currently-running job, if any.
+    fn start(mut self) -> Result<(), &'static str> {
+        let mut buf: [u8; 256] = [0; 256];
+        self.display_prompt();
+
+        loop {
+            let (cnt, eof) = {
+                let mut locked_input = self.input.lock();
+                let cnt = locked_input.try_read(&mut buf).map_err(|_e| "failed to read session input")?;
+                (cnt, locked_input.is_eof())
+            };
+            if cnt > 0 {
+                self.handle_input(&buf[..cnt])?;
+            } else if eof {
+                // The client disconnected.
+                self.exit_requested = true;
+            }
+
+            let job_output_occurred = self.service_job()?;
+
+            if self.exit_requested {
+                break;
+            }
+            if cnt == 0 && !job_output_occurred {
+                scheduler::schedule(); // yield the CPU if nothing to do
+            }
+        }
+
+        // Wait for the running job to be killed so that its stdio queues are cleaned up.
+        self.kill_job();
+        while self.job.is_some() {
+            self.service_job()?;
+            scheduler::schedule();
+        }
+        Ok(())
+    }
+
+    /// Handles bytes received from the remote client, which have already been stripped of telnet commands.
+    fn handle_input(&mut self, bytes: &[u8]) -> Result<(), &'static str> {
+        // Bytes destined for the running job's stdin.
+        let mut job_input = Vec::new();
+
+        for &byte in bytes {
+            if self.job.is_some() {
+                match byte {
+                    ASCII_ETX => {
+                        self.print("^C\n");
+                        self.kill_job();
+                    }
+                    ASCII_EOT => {
+                        if let Some(ref job) = self.job {
+                            job.stdin_writer.lock().set_eof();
+                        }
+                    }
+                    _ => job_input.push(byte),
+                }
+                continue;
+            }
+
+            match byte {
+                b'\n' => {
+                    let cmdline = mem::replace(&mut self.cmdline, String::new());
+                    self.eval_cmdline(cmdline.trim());
+                    if self.job.is_none() && !self.exit_requested {
+                        self.display_prompt();
+                    }
+                }
+                ASCII_ETX => {
+                    self.cmdline.clear();
+                    self.print("^C\n");
+                    self.display_prompt();
+                }
+                ASCII_EOT if self.cmdline.is_empty() => {
+                    self.exit_requested = true;
+                }
+                ASCII_DEL | 0x08 => {
+                    self.cmdline.pop();
+                }
+                _ if (byte as char).is_ascii_graphic() || byte == b' ' || byte == b'\t' => {
+                    self.cmdline.push(byte as char);
+                }
+                _ => { } // ignore other control characters
+            }
+        }
+
+        if !job_input.is_empty() {
+            if let Some(ref job) = self.job {
+                if job.stdin_writer.lock().write_all(&job_input).is_err() {
+                    warn!("telnetd: dropped {} bytes of input to a job whose stdin was closed", job_input.len());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Evaluates a complete command line, either as a session-internal command or by starting a new job.
+    fn eval_cmdline(&mut self, cmdline: &str) {
+        match cmdline {
+            "" => { }
+            "exit" | "logout" => self.exit_requested = true,
+            _ => {
+                if let Err(e) = self.build_new_job(cmdline) {
+                    self.print(&format!("{}\n", e));
+                }
+            }
+        }
+    }
+
+    /// Spawns a single application task, initially blocked.
+    fn create_single_task(&mut self, cmd: &str, args: Vec<String>) -> Result<TaskRef, String> {
+        let namespace_dir = task::get_my_current_task()
+            .map(|t| t.get_namespace().dir().clone())
+            .ok_or_else(|| format!("Failed to find directory of application executables."))?;
+        let cmd_crate_name = format!("{}-", cmd);
+        let mut matching_apps = namespace_dir.get_files_starting_with(&cmd_crate_name).into_iter();
+        let app_file = matching_apps.next();
+        let second_match = matching_apps.next(); // return an error if there are multiple matching apps
+        let app_path = app_file.xor(second_match)
+            .map(|f| Path::new(f.lock().get_absolute_path()))
+            .ok_or_else(|| format!("{:?} command not found.", cmd))?;
+
+        let taskref = spawn::new_application_task_builder(app_path, None)
+            .and_then(|builder| builder.argument(args).block().spawn())
+            .map_err(|e| format!("Failed to spawn new task to run command. Error: {}.", e))?;
+
+        taskref.set_env(self.env.clone());
+        Ok(taskref.task().clone())
+    }
+
+    /// Starts a new job from the given command line, connecting its stdio queues to this session.
+    fn build_new_job(&mut self, cmdline: &str) -> Result<(), String> {
+        let mut tasks = Vec::new();
+        for single_task_cmd in cmdline.split('|') {
+            let mut args: Vec<String> = single_task_cmd.split_whitespace().map(|s| s.to_string()).collect();
+            if args.is_empty() {
+                continue;
+            }
+            let command = args.remove(0);
+            match self.create_single_task(&command, args) {
+                Ok(task_ref) => tasks.push(task_ref),
+                Err(e) => {
+                    // Once we run into an error, we must kill all previously spawned tasks in this command line.
+                    for task_ref in tasks {
+                        if let Err(kill_error) = task_ref.kill(KillReason::Requested) {
+                            error!("{}", kill_error);
+                        }
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        let task_ids: Vec<usize> = tasks.iter().map(|t| t.lock().id).collect();
+        let mut pipe_queues = Vec::new();
+        let mut stderr_queues = Vec::new();
+
+        // Chain the queues together just as the `shell` does, but with headless streams.
+        let first_stdio_queue = Stdio::new();
+        let stdin_writer = first_stdio_queue.get_writer();
+        let mut previous_queue_reader = first_stdio_queue.get_reader();
+        pipe_queues.push(first_stdio_queue);
+        for task_id in &task_ids {
+            let stdio_queue_for_stdin_and_stdout = Stdio::new();
+            let stdio_queue_for_stderr = Stdio::new();
+            let streams = IoStreams::new_headless(
+                previous_queue_reader,
+                stdio_queue_for_stdin_and_stdout.get_writer(),
+                stdio_queue_for_stderr.get_writer(),
+                self.key_event_consumer.clone(),
+            );
+            app_io::insert_child_streams(*task_id, streams);
+
+            previous_queue_reader = stdio_queue_for_stdin_and_stdout.get_reader();
+            stderr_queues.push(stdio_queue_for_stderr);
+            pipe_queues.push(stdio_queue_for_stdin_and_stdout);
+
+            // Support legacy output from applications that use `terminal_print`.
+            terminal_print::add_child(*task_id, self.print_producer.obtain_producer()).map_err(|e| e.to_string())?;
+        }
+
+        for task_ref in &tasks {
+            task_ref.unblock();
+        }
+
+        self.job = Some(Job {
+            exited: vec![false; tasks.len()],
+            tasks,
+            task_ids,
+            pipe_queues,
+            stderr_queues,
+            stdin_writer,
+            stdout_reader: previous_queue_reader,
+        });
+        Ok(())
+    }
+
+    /// Forwards the output of the running job to the remote client and cleans up the job once all of its tasks exit.
+    /// Returns true if any output was forwarded.
+    fn service_job(&mut self) -> Result<bool, &'static str> {
+        let mut output_occurred = self.forward_job_output();
+
+        let all_exited = {
+            let job = match self.job {
+                Some(ref mut job) => job,
+                None => return Ok(output_occurred),
+            };
+            let mut messages = Vec::new();
+            for (i, task_ref) in job.tasks.iter().enumerate() {
+                if job.exited[i] || !task_ref.lock().has_exited() {
+                    continue;
+                }
+                job.exited[i] = true;
+                let task_id = job.task_ids[i];
+                match task_ref.take_exit_value() {
+                    Some(ExitValue::Completed(exit_status)) => {
+                        if let Some(val) = exit_status.downcast_ref::<isize>() {
+                            if *val < 0 {
+                                messages.push(format!("task [{}] returned error value {:?}\n", task_id, val));
+                            }
+                        }
+                    }
+                    // We have already printed "^C" upon the kill request.
+                    Some(ExitValue::Killed(KillReason::Requested)) | None => { }
+                    Some(ExitValue::Killed(kill_reason)) => {
+                        messages.push(format!("task [{}] was killed because {:?}\n", task_id, kill_reason));
+                    }
+                }
+                terminal_print::remove_child(task_id)?;
+
+                // Close this task's stdin, stderr, and stdout, in that order.
+                job.pipe_queues[i].get_writer().lock().set_eof();
+                job.stderr_queues[i].get_writer().lock().set_eof();
+                job.pipe_queues[i + 1].get_writer().lock().set_eof();
+            }
+            for msg in messages {
+                self.print(&msg);
+                output_occurred = true;
+            }
+            self.job.as_ref().map(|j| j.exited.iter().all(|e| *e)).unwrap_or(true)
+        };
+
+        if all_exited {
+            // Print all remaining output from the exited tasks before removing their queues.
+            self.forward_job_output();
+            if let Some(job) = self.job.take() {
+                for task_id in job.task_ids {
+                    app_io::remove_child_streams(&task_id);
+                }
+            }
+            if !self.exit_requested {
+                self.display_prompt();
+            }
+            output_occurred = true;
+        }
+        Ok(output_occurred)
+    }
+
+    /// Copies all pending stdout, stderr and legacy print output from the running job to the remote client.
+    fn forward_job_output(&mut self) -> bool {
+        let mut output = Vec::new();
+
+        while let Some(print_event) = self.print_consumer.peek() {
+            if let &Event::OutputEvent(ref s) = print_event.deref() {
+                output.extend_from_slice(s.as_bytes());
+            }
+            print_event.mark_completed();
+        }
+
+        if let Some(ref job) = self.job {
+            let mut buf: [u8; 256] = [0; 256];
+            loop {
+                match job.stdout_reader.lock().try_read(&mut buf) {
+                    Ok(cnt) if cnt > 0 => output.extend_from_slice(&buf[..cnt]),
+                    _ => break,
+                }
+            }
+            for stderr in &job.stderr_queues {
+                let stderr = stderr.get_reader();
+                loop {
+                    match stderr.lock().try_read(&mut buf) {
+                        Ok(cnt) if cnt > 0 => output.extend_from_slice(&buf[..cnt]),
+                        _ => break,
+                    }
+                }
+            }
+        }
+
+        if output.is_empty() {
+            return false;
+        }
+        if let Err(_e) = self.output.lock().write_all(&output) {
+            error!("telnetd: failed to write job output to session output: {:?}", _e);
+        }
+        true
+    }
+
+    /// Kills all tasks in the running job, if any. The job will be cleaned up in the next `service_job()`.
+    fn kill_job(&mut self) {
+        if let Some(ref job) = self.job {
+            for (task_ref, exited) in job.tasks.iter().zip(job.exited.iter()) {
+                if *exited || task_ref.lock().has_exited() {
+                    continue;
+                }
+                if let Err(e) = task_ref.kill(KillReason::Requested) {
+                    error!("telnetd: failed to kill task {:?}: {}", task_ref.lock().name, e);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,161 @@
+//! A minimal implementation of the telnet protocol (RFC 854) and its option negotiation (RFC 855).
+//!
+//! We only support enough of the protocol for a standard `telnet` client to work in its default line mode:
+//! all options requested by the client are refused, except for Suppress Go Ahead (RFC 858).
+//! Clients that do not speak telnet at all, e.g., `netcat`, never send `IAC` sequences
+//! and thus their byte streams pass through unmodified (besides newline translation).
+
+use alloc::vec::Vec;
+
+/// Interpret As Command: the escape byte that starts every telnet command sequence.
+pub const IAC:  u8 = 255;
+const DONT:     u8 = 254;
+const DO:       u8 = 253;
+const WONT:     u8 = 252;
+const WILL:     u8 = 251;
+/// Subnegotiation begin.
+const SB:       u8 = 250;
+/// Erase Character.
+const EC:       u8 = 247;
+/// Are You There.
+const AYT:      u8 = 246;
+/// Interrupt Process.
+const IP:       u8 = 244;
+/// Subnegotiation end.
+const SE:       u8 = 240;
+/// End of File (RFC 1184), sent by some clients upon Ctrl + D.
+const EOF:      u8 = 236;
+
+/// The Suppress Go Ahead option, the only one we agree to enable.
+const OPT_SUPPRESS_GO_AHEAD: u8 = 3;
+
+/// The ASCII control character that interrupts the foreground job, i.e., Ctrl + C.
+pub const ASCII_ETX: u8 = 0x03;
+/// The ASCII control character that ends the input stream, i.e., Ctrl + D.
+pub const ASCII_EOT: u8 = 0x04;
+/// The ASCII control character that erases the previous character.
+pub const ASCII_DEL: u8 = 0x7F;
+
+/// The negotiation that the server sends upon accepting a new connection.
+/// We offer to suppress go-aheads and ask the client to do the same, which is harmless for a line-mode client.
+pub const INITIAL_NEGOTIATION: [u8; 6] = [
+    IAC, WILL, OPT_SUPPRESS_GO_AHEAD,
+    IAC, DO,   OPT_SUPPRESS_GO_AHEAD,
+];
+
+/// The states of the receive-side parser.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Ordinary data bytes.
+    Data,
+    /// The previous data byte was a carriage return.
+    CarriageReturn,
+    /// The previous byte was an `IAC`.
+    Command,
+    /// The previous bytes were `IAC` followed by the enclosed `WILL`, `WONT`, `DO` or `DONT` verb.
+    Negotiation(u8),
+    /// Inside of a subnegotiation, which we ignore entirely.
+    Subnegotiation,
+    /// Inside of a subnegotiation, and the previous byte was an `IAC`.
+    SubnegotiationCommand,
+}
+
+/// A stateful filter that separates telnet commands from the data stream received from a client.
+pub struct TelnetParser {
+    state: State,
+}
+
+impl TelnetParser {
+    /// Creates a new parser that expects ordinary data first.
+    pub fn new() -> TelnetParser {
+        TelnetParser {
+            state: State::Data,
+        }
+    }
+
+    /// Processes the bytes received from the client.
+    ///
+    /// Data bytes are appended to `data`, with line endings (`CR LF`, `CR NUL`, or bare `LF`) translated to `\n`
+    /// and the telnet editing/interrupt commands translated to their ASCII control character equivalents.
+    /// Any replies that must be sent back to the client are appended to `replies`.
+    pub fn process(&mut self, input: &[u8], data: &mut Vec<u8>, replies: &mut Vec<u8>) {
+        for &byte in input {
+            self.state = match self.state {
+                State::Data | State::CarriageReturn => {
+                    let prev_was_cr = self.state == State::CarriageReturn;
+                    match byte {
+                        IAC  => State::Command,
+                        b'\r' => {
+                            data.push(b'\n');
+                            State::CarriageReturn
+                        }
+                        // A `LF` or `NUL` after a `CR` completes a newline that we already emitted.
+                        b'\n' | 0 if prev_was_cr => State::Data,
+                        _ => {
+                            data.push(byte);
+                            State::Data
+                        }
+                    }
+                }
+                State::Command => match byte {
+                    // An escaped 0xFF data byte
+                    IAC => {
+                        data.push(IAC);
+                        State::Data
+                    }
+                    WILL | WONT | DO | DONT => State::Negotiation(byte),
+                    SB  => State::Subnegotiation,
+                    IP  => { data.push(ASCII_ETX); State::Data }
+                    EOF => { data.push(ASCII_EOT); State::Data }
+                    EC  => { data.push(ASCII_DEL); State::Data }
+                    AYT => {
+                        replies.extend_from_slice(b"\r\n[yes]\r\n");
+                        State::Data
+                    }
+                    // Erase Line, NOP, Data Mark, Break, Go Ahead, Abort Output and others are ignored.
+                    _ => State::Data,
+                }
+                State::Negotiation(verb) => {
+                    negotiate(verb, byte, replies);
+                    State::Data
+                }
+                State::Subnegotiation => match byte {
+                    IAC => State::SubnegotiationCommand,
+                    _   => State::Subnegotiation,
+                }
+                State::SubnegotiationCommand => match byte {
+                    SE => State::Data,
+                    _  => State::Subnegotiation,
+                }
+            };
+        }
+    }
+}
+
+/// Determines the reply to a client's `verb option` request.
+///
+/// To avoid negotiation loops (RFC 854), we only reply to requests that would change an option's state,
+/// i.e., `WILL`/`DO` requests, since all options other than Suppress Go Ahead are always disabled.
+fn negotiate(verb: u8, option: u8, replies: &mut Vec<u8>) {
+    let reply = match (verb, option) {
+        // We already offered (and requested) Suppress Go Ahead in our initial negotiation.
+        (WILL, OPT_SUPPRESS_GO_AHEAD) | (DO, OPT_SUPPRESS_GO_AHEAD) => return,
+        (WILL, _) => DONT,
+        (DO, _)   => WONT,
+        // The client is refusing or disabling an option, which we never depend on.
+        _ => return,
+    };
+    replies.extend_from_slice(&[IAC, reply, option]);
+}
+
+/// Encodes the given data bytes for transmission to a client in the telnet network virtual terminal format,
+/// appending them to `out`. Newlines are sent as `CR LF`, and data bytes equal to `IAC` are escaped.
+pub fn encode(data: &[u8], out: &mut Vec<u8>) {
+    for &byte in data {
+        match byte {
+            b'\n' => out.extend_from_slice(b"\r\n"),
+            IAC  => out.extend_from_slice(&[IAC, IAC]),
+            _    => out.push(byte),
+        }
+    }
+}
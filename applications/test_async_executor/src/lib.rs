@@ -0,0 +1,179 @@
+//! Tests the async executor by running timer and channel futures on it.
+//!
+//! The futures are written by hand rather than with `async fn`,
+//! because this crate (like the rest of Theseus) uses the 2015 edition.
+//! The name of a single test can be given as an argument, otherwise all tests are run.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spawn;
+extern crate sleep;
+extern crate async_channel;
+extern crate async_executor;
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+use alloc::{
+    vec::Vec,
+    string::String,
+    sync::Arc,
+};
+use sleep::Instant;
+use async_channel::{Receiver, Sender};
+use async_executor::{Executor, channel::{self, ReceiveOwned}, timer};
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let res = match args.get(0).map(|s| &**s) {
+        Some("timer")   => test_timer(),
+        Some("channel") => test_channel(),
+        None            => test_timer().and_then(|_| test_channel()),
+        Some(_)         => Err("unknown test, must be one of: timer, channel"),
+    };
+    match res {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+/// Blocks until all futures spawned onto the given executor have completed.
+fn wait_for_executor(executor: &Executor) -> Result<(), &'static str> {
+    while executor.pending() > 0 {
+        sleep::sleep(Duration::from_millis(1))?;
+    }
+    Ok(())
+}
+
+
+const NUM_TIMERS: u64 = 10;
+
+/// Runs several sleep futures at once and checks that none of them completes early.
+fn test_timer() -> Result<(), &'static str> {
+    let executor = Executor::new(String::from("test_async_timer"))?;
+    let completed = Arc::new(AtomicUsize::new(0));
+    let early = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+    for i in 1..=NUM_TIMERS {
+        executor.spawn(TimedSleep {
+            sleep: timer::sleep(Duration::from_millis(10 * i)),
+            completed: completed.clone(),
+            early: early.clone(),
+        });
+    }
+    wait_for_executor(&executor)?;
+    let elapsed = start.elapsed();
+
+    warn!("Async timers: {} completed in {:?}, {} early", completed.load(Ordering::SeqCst), elapsed, early.load(Ordering::SeqCst));
+    if completed.load(Ordering::SeqCst) != NUM_TIMERS as usize
+        || early.load(Ordering::SeqCst) != 0
+        || elapsed < Duration::from_millis(10 * NUM_TIMERS)
+    {
+        return Err("async timer test failed");
+    }
+    warn!("Async timer test passed.");
+    Ok(())
+}
+
+/// Wraps a sleep future to record whether it completed before its deadline.
+struct TimedSleep {
+    sleep: timer::Sleep,
+    completed: Arc<AtomicUsize>,
+    early: Arc<AtomicUsize>,
+}
+
+impl Future for TimedSleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        match Pin::new(&mut this.sleep).poll(cx) {
+            Poll::Ready(()) => {
+                if Instant::now() < this.sleep.deadline() {
+                    this.early.fetch_add(1, Ordering::SeqCst);
+                }
+                this.completed.fetch_add(1, Ordering::SeqCst);
+                Poll::Ready(())
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+
+const NUM_MESSAGES: usize = 1000;
+
+/// Sends messages from a regular task to a future that sums them up, until the sender disconnects.
+fn test_channel() -> Result<(), &'static str> {
+    let executor = Executor::new(String::from("test_async_channel"))?;
+    let (sender, receiver) = async_channel::new_channel::<usize>(8);
+    let receiver = Arc::new(receiver);
+    let sum = Arc::new(AtomicUsize::new(0));
+    executor.spawn(SumMessages {
+        receiver,
+        receive: None,
+        sum: sum.clone(),
+    });
+
+    let sender_task = spawn::new_task_builder(send_messages, sender)
+        .name(String::from("test_async_channel_sender"))
+        .spawn()?;
+    sender_task.join()??;
+    wait_for_executor(&executor)?;
+
+    let expected_sum = NUM_MESSAGES * (NUM_MESSAGES + 1) / 2;
+    let sum = sum.load(Ordering::SeqCst);
+    warn!("Async channel sum of received messages: {} (expected {})", sum, expected_sum);
+    if sum != expected_sum {
+        return Err("async channel test failed");
+    }
+    warn!("Async channel test passed.");
+    Ok(())
+}
+
+fn send_messages(sender: Sender<usize>) -> Result<(), &'static str> {
+    for msg in 1..=NUM_MESSAGES {
+        sender.send(msg).map_err(|_| "failed to send message")?;
+    }
+    Ok(())
+}
+
+/// Receives messages until the channel is disconnected, adding them to `sum`.
+struct SumMessages {
+    receiver: Arc<Receiver<usize>>,
+    receive: Option<ReceiveOwned<usize>>,
+    sum: Arc<AtomicUsize>,
+}
+
+impl Future for SumMessages {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        loop {
+            let result = {
+                let receiver = &this.receiver;
+                let receive = this.receive.get_or_insert_with(|| channel::receive_owned(receiver.clone()));
+                match Pin::new(receive).poll(cx) {
+                    Poll::Ready(result) => result,
+                    Poll::Pending => return Poll::Pending,
+                }
+            };
+            this.receive = None;
+            match result {
+                Ok(msg) => { this.sum.fetch_add(msg, Ordering::SeqCst); }
+                Err(_) => return Poll::Ready(()),
+            }
+        }
+    }
+}
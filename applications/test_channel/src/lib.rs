@@ -184,8 +184,8 @@ fn rendezvous_test_oneshot() -> Result<(), &'static str> {
     warn!("rendezvous_test_oneshot(): Finished spawning the sender and receiver tasks");
     t2.unblock(); t1.unblock();
 
-    t1.join()?;
-    t2.join()?;
+    t1.join()??;
+    t2.join()??;
     warn!("rendezvous_test_oneshot(): Joined the sender and receiver tasks.");
     
     Ok(())
@@ -215,9 +215,10 @@ fn rendezvous_test_multiple(send_count: usize, receive_count: usize, send_panic:
     warn!("rendezvous_test_multiple(): Finished spawning the sender and receiver tasks");
     t2.unblock(); t1.unblock();
 
-    t1.join()?;
-    t2.join()?;
-    warn!("rendezvous_test_multiple(): Joined the sender and receiver tasks.");
+    // The sender or receiver task may have been told to panic, so their results are only reported.
+    let t1_result = t1.join();
+    let t2_result = t2.join();
+    warn!("rendezvous_test_multiple(): Joined the sender and receiver tasks. Sender: {:?}, receiver: {:?}", t1_result, t2_result);
     
     Ok(())
 }
@@ -308,8 +309,8 @@ fn asynchronous_test_oneshot() -> Result<(), &'static str> {
     warn!("asynchronous_test_oneshot(): Finished spawning the sender and receiver tasks");
     t2.unblock(); t1.unblock();
 
-    t1.join()?;
-    t2.join()?;
+    t1.join()??;
+    t2.join()??;
     warn!("asynchronous_test_oneshot(): Joined the sender and receiver tasks.");
     
     Ok(())
@@ -337,9 +338,10 @@ fn asynchronous_test_multiple(send_count: usize, receive_count: usize, send_pani
     warn!("asynchronous_test_multiple(): Finished spawning the sender and receiver tasks");
     t2.unblock(); t1.unblock();
 
-    t1.join()?;
-    t2.join()?;
-    warn!("asynchronous_test_multiple(): Joined the sender and receiver tasks.");
+    // The sender or receiver task may have been told to panic, so their results are only reported.
+    let t1_result = t1.join();
+    let t2_result = t2.join();
+    warn!("asynchronous_test_multiple(): Joined the sender and receiver tasks. Sender: {:?}, receiver: {:?}", t1_result, t2_result);
     
     Ok(())
 }
@@ -0,0 +1,128 @@
+//! Tests heap quotas by spawning tasks that allocate within and beyond their quota.
+//!
+//! The name of a single test can be given as an argument, otherwise all tests are run.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spawn;
+extern crate task;
+extern crate heap;
+
+use alloc::{
+    vec::Vec,
+    string::String,
+    sync::Arc,
+};
+use task::KillReason;
+use spawn::JoinError;
+use heap::HeapQuota;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let res = match args.get(0).map(|s| &**s) {
+        Some("within")   => test_within_quota(),
+        Some("exceeded") => test_quota_exceeded(),
+        Some("shared")   => test_shared_quota(),
+        None             => test_within_quota().and_then(|_| test_quota_exceeded()).and_then(|_| test_shared_quota()),
+        Some(_)          => Err("unknown test, must be one of: within, exceeded, shared"),
+    };
+    match res {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+const QUOTA: usize = 64 * 1024;
+const CHUNK: usize = 1024;
+
+/// Allocates `num_chunks` chunks of memory and keeps them all alive until it returns their total size.
+fn allocate_chunks(num_chunks: usize) -> usize {
+    let mut chunks: Vec<Vec<u8>> = Vec::with_capacity(num_chunks);
+    for i in 0..num_chunks {
+        chunks.push(vec![i as u8; CHUNK]);
+    }
+    chunks.iter().map(|c| c.len()).sum()
+}
+
+
+/// A task that allocates less than its quota should run to completion as usual.
+fn test_within_quota() -> Result<(), &'static str> {
+    let quota = Arc::new(HeapQuota::new(QUOTA));
+    let allocated = spawn::new_task_builder(allocate_chunks, QUOTA / CHUNK / 2)
+        .name(String::from("test_heap_quota_within"))
+        .heap_quota(quota.clone())
+        .spawn()?
+        .join()
+        .map_err(|_| "task within its heap quota was killed")?;
+
+    warn!("Task allocated {} bytes within its quota; {} bytes are still charged to the quota.", allocated, quota.used());
+    if quota.used() > quota.limit() {
+        return Err("heap quota was overcharged");
+    }
+    warn!("Heap quota within test passed.");
+    Ok(())
+}
+
+/// A task that allocates more than its quota should be killed, while this task keeps running.
+fn test_quota_exceeded() -> Result<(), &'static str> {
+    let quota = Arc::new(HeapQuota::new(QUOTA));
+    let result = spawn::new_task_builder(allocate_chunks, QUOTA / CHUNK * 2)
+        .name(String::from("test_heap_quota_exceeded"))
+        .heap_quota(quota.clone())
+        .spawn()?
+        .join();
+
+    match result {
+        Err(JoinError::Killed(KillReason::HeapQuotaExceeded { requested, limit })) => {
+            warn!("Task was killed for exceeding its heap quota of {} bytes when allocating {} bytes.", limit, requested);
+        }
+        Err(other) => {
+            error!("Couldn't join task: {}", other);
+            return Err("task was killed for a reason other than exceeding its heap quota");
+        }
+        Ok(allocated) => {
+            error!("Task allocated {} bytes despite its heap quota of {} bytes.", allocated, QUOTA);
+            return Err("task was not stopped from exceeding its heap quota");
+        }
+    }
+    if quota.used() > quota.limit() {
+        return Err("heap quota was overcharged");
+    }
+    warn!("Heap quota exceeded test passed.");
+    Ok(())
+}
+
+/// Tasks that share a quota are limited by their combined allocations.
+fn test_shared_quota() -> Result<(), &'static str> {
+    let quota = Arc::new(HeapQuota::new(QUOTA));
+    let handles = (0..4).map(|i| 
+        spawn::new_task_builder(allocate_chunks, QUOTA / CHUNK / 2)
+            .name(format!("test_heap_quota_shared_{}", i))
+            .heap_quota(quota.clone())
+            .spawn()
+    ).collect::<Result<Vec<_>, _>>()?;
+
+    let mut killed = 0;
+    for handle in handles {
+        match handle.join() {
+            Ok(_) => { }
+            Err(JoinError::Killed(KillReason::HeapQuotaExceeded { .. })) => killed += 1,
+            Err(_) => return Err("task sharing a heap quota was killed for an unexpected reason"),
+        }
+    }
+
+    // The tasks run concurrently, so which of them exceed the quota depends on how they were scheduled,
+    // but the quota must never be exceeded.
+    warn!("{} of 4 tasks sharing a heap quota were killed for exceeding it.", killed);
+    if quota.used() > quota.limit() {
+        return Err("shared heap quota was overcharged");
+    }
+    warn!("Heap quota shared test passed.");
+    Ok(())
+}
@@ -59,9 +59,9 @@ fn test_contention() -> Result<(), &'static str> {
 
     t3.unblock(); t2.unblock(); t1.unblock();
 
-    t1.join()?;
-    t2.join()?;
-    t3.join()?;
+    t1.join()??;
+    t2.join()??;
+    t3.join()??;
     warn!("Joined the 3 tasks. Final value of shared_lock: {:?}", shared_lock);
     
     Ok(())
@@ -115,9 +115,9 @@ fn test_lockstep() -> Result<(), &'static str> {
 
     t3.unblock(); t2.unblock(); t1.unblock();
 
-    t1.join()?;
-    t2.join()?;
-    t3.join()?;
+    t1.join()??;
+    t2.join()??;
+    t3.join()??;
     warn!("Joined the 3 tasks. Final value of shared_lock: {:?}", shared_lock);
     
     Ok(())
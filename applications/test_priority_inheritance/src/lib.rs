@@ -0,0 +1,205 @@
+//! Tests that priority inheritance in `MutexSleep` prevents priority inversion.
+//!
+//! A low-priority task holds a lock that a high-priority task wants,
+//! while medium-priority tasks that don't use the lock compete for the same core.
+//! Without priority inheritance, the medium-priority tasks would get most of the CPU time
+//! and finish before the low-priority task releases the lock, delaying the high-priority task.
+//! With priority inheritance, the low-priority task runs at the high priority until it releases the lock.
+//!
+//! With the `-c` option, the high-priority task instead waits for a lock held by a middle task,
+//! which in turn waits for the lock held by the low-priority task, to test chained inheritance.
+//!
+//! All tasks are pinned to the current core, which must use the priority scheduler policy.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+extern crate task;
+extern crate spawn;
+extern crate scheduler;
+extern crate mutex_sleep;
+extern crate apic;
+
+use core::sync::atomic::{Ordering, AtomicBool, AtomicUsize};
+use alloc::{
+    vec::Vec,
+    string::String,
+    sync::Arc,
+};
+use spawn::JoinHandle;
+use mutex_sleep::MutexSleep;
+
+
+const LOW_PRIORITY: u8 = 1;
+const MIDDLE_PRIORITY: u8 = 10;
+const MEDIUM_PRIORITY: u8 = 20;
+const HIGH_PRIORITY: u8 = 40;
+const NUM_MEDIUM_TASKS: usize = 2;
+
+/// The number of iterations the low-priority task spins for while holding the lock.
+const LOW_WORK: usize = 2_000_000;
+/// The number of iterations each medium-priority task spins for, which is much more than the low-priority task.
+const MEDIUM_WORK: usize = 10 * LOW_WORK;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let chained = args.iter().any(|a| a == "-c");
+    match run(chained) {
+        Ok(true) => {
+            println!("PASSED: the lock holder inherited the high priority");
+            0
+        }
+        Ok(false) => {
+            println!("FAILED: the high-priority task suffered from priority inversion");
+            -1
+        }
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+/// The state shared among all tasks in the test.
+struct Shared {
+    /// The lock held by the low-priority task.
+    lock_a: MutexSleep<()>,
+    /// The lock held by the middle task in the chained test.
+    lock_b: MutexSleep<()>,
+    low_has_lock: AtomicBool,
+    middle_has_lock: AtomicBool,
+    /// The highest priority the low-priority task observed for itself while holding the lock.
+    low_max_priority: AtomicUsize,
+    /// The number of medium-priority tasks that have finished.
+    mediums_done: AtomicUsize,
+    /// The number of medium-priority tasks that had finished when the high-priority task got its lock.
+    mediums_done_when_high_locked: AtomicUsize,
+}
+
+fn run(chained: bool) -> Result<bool, &'static str> {
+    let my_cpu = apic::get_my_apic_id();
+    if scheduler::get_policy(my_cpu) != Some("priority") {
+        return Err("this test requires the priority scheduler policy on the current core, see the `sched` command");
+    }
+
+    let shared = Arc::new(Shared {
+        lock_a: MutexSleep::new(()),
+        lock_b: MutexSleep::new(()),
+        low_has_lock: AtomicBool::new(false),
+        middle_has_lock: AtomicBool::new(false),
+        low_max_priority: AtomicUsize::new(0),
+        mediums_done: AtomicUsize::new(0),
+        mediums_done_when_high_locked: AtomicUsize::new(0),
+    });
+
+    let spawn_blocked = |func: fn(Arc<Shared>) -> Result<(), &'static str>, name: &str, priority: u8| -> Result<JoinHandle<Result<(), &'static str>>, &'static str> {
+        let task = spawn::new_task_builder(func, shared.clone())
+            .name(String::from(name))
+            .pin_on_core(my_cpu)
+            .block()
+            .spawn()?;
+        scheduler::set_priority(&task, priority)?;
+        Ok(task)
+    };
+
+    let low = spawn_blocked(low_task, "pi_low", LOW_PRIORITY)?;
+    let middle = if chained {
+        Some(spawn_blocked(middle_task, "pi_middle", MIDDLE_PRIORITY)?)
+    } else {
+        None
+    };
+    let mut mediums = Vec::with_capacity(NUM_MEDIUM_TASKS);
+    for i in 0..NUM_MEDIUM_TASKS {
+        mediums.push(spawn_blocked(medium_task, &format!("pi_medium_{}", i), MEDIUM_PRIORITY)?);
+    }
+    let high = spawn_blocked(if chained { high_task_chained } else { high_task }, "pi_high", HIGH_PRIORITY)?;
+
+    // Start the tasks in order, such that each lock is held before another task waits for it.
+    low.unblock();
+    while !shared.low_has_lock.load(Ordering::SeqCst) {
+        scheduler::schedule();
+    }
+    if let Some(ref middle) = middle {
+        middle.unblock();
+        while !shared.middle_has_lock.load(Ordering::SeqCst) {
+            scheduler::schedule();
+        }
+    }
+    for medium in &mediums {
+        medium.unblock();
+    }
+    high.unblock();
+
+    let low_task_ref = low.task().clone();
+    low.join()??;
+    if let Some(middle) = middle {
+        middle.join()??;
+    }
+    for medium in mediums {
+        medium.join()??;
+    }
+    high.join()??;
+
+    let low_max_priority = shared.low_max_priority.load(Ordering::SeqCst);
+    let mediums_done = shared.mediums_done_when_high_locked.load(Ordering::SeqCst);
+    println!("Highest priority of the low-priority task while holding the lock: {} (originally {})", low_max_priority, LOW_PRIORITY);
+    println!("Medium-priority tasks finished before the high-priority task got the lock: {} of {}", mediums_done, NUM_MEDIUM_TASKS);
+    println!("Final priority of the low-priority task: {:?}", scheduler::get_priority(&low_task_ref));
+
+    Ok(low_max_priority == HIGH_PRIORITY as usize && mediums_done == 0)
+}
+
+
+/// Spins for the given number of iterations without blocking.
+fn spin_for(iterations: usize, progress: &AtomicUsize) {
+    for _ in 0..iterations {
+        progress.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn low_task(shared: Arc<Shared>) -> Result<(), &'static str> {
+    let curr_task = task::get_my_current_task().ok_or("couldn't get current task")?.clone();
+    let _locked = shared.lock_a.lock()?;
+    shared.low_has_lock.store(true, Ordering::SeqCst);
+
+    let progress = AtomicUsize::new(0);
+    let chunks = 100;
+    for _ in 0..chunks {
+        spin_for(LOW_WORK / chunks, &progress);
+        // only this task updates the highest observed priority
+        if let Some(priority) = scheduler::get_priority(&curr_task) {
+            if priority as usize > shared.low_max_priority.load(Ordering::SeqCst) {
+                shared.low_max_priority.store(priority as usize, Ordering::SeqCst);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn middle_task(shared: Arc<Shared>) -> Result<(), &'static str> {
+    let _locked_b = shared.lock_b.lock()?;
+    shared.middle_has_lock.store(true, Ordering::SeqCst);
+    let _locked_a = shared.lock_a.lock()?;
+    Ok(())
+}
+
+fn medium_task(shared: Arc<Shared>) -> Result<(), &'static str> {
+    let progress = AtomicUsize::new(0);
+    spin_for(MEDIUM_WORK, &progress);
+    shared.mediums_done.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+fn high_task(shared: Arc<Shared>) -> Result<(), &'static str> {
+    let _locked = shared.lock_a.lock()?;
+    shared.mediums_done_when_high_locked.store(shared.mediums_done.load(Ordering::SeqCst), Ordering::SeqCst);
+    Ok(())
+}
+
+fn high_task_chained(shared: Arc<Shared>) -> Result<(), &'static str> {
+    let _locked = shared.lock_b.lock()?;
+    shared.mediums_done_when_high_locked.store(shared.mediums_done.load(Ordering::SeqCst), Ordering::SeqCst);
+    Ok(())
+}
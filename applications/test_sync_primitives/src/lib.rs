@@ -0,0 +1,324 @@
+//! Stress tests for the sleeping synchronization primitives:
+//! `RwLockSleep`, `Semaphore`, `Condvar` (together with `MutexSleep`), and `Barrier`.
+//!
+//! Each test spawns several tasks that hammer on one primitive at the same time
+//! and check that its guarantees are never violated.
+//! The name of a single test can be given as an argument, otherwise all tests are run.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spawn;
+extern crate scheduler;
+extern crate mutex_sleep;
+extern crate rwlock_sleep;
+extern crate semaphore;
+extern crate condvar;
+extern crate barrier;
+
+use core::sync::atomic::{Ordering, AtomicUsize};
+use alloc::{
+    collections::VecDeque,
+    vec::Vec,
+    string::String,
+    sync::Arc,
+};
+use mutex_sleep::MutexSleep;
+use rwlock_sleep::RwLockSleep;
+use semaphore::Semaphore;
+use condvar::Condvar;
+use barrier::Barrier;
+
+
+const ITERATIONS: usize = 1000;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let res = match args.get(0).map(|s| &**s) {
+        Some("rwlock")    => test_rwlock(),
+        Some("semaphore") => test_semaphore(),
+        Some("condvar")   => test_condvar(),
+        Some("barrier")   => test_barrier(),
+        None              => test_rwlock()
+                                .and_then(|_| test_semaphore())
+                                .and_then(|_| test_condvar())
+                                .and_then(|_| test_barrier()),
+        Some(_)           => Err("unknown test, must be one of: rwlock, semaphore, condvar, barrier"),
+    };
+    match res {
+        Ok(_) => 0,
+        Err(e) => {
+            error!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+/// Spawns `count` tasks that all run the given `func` with a clone of the given `arg`,
+/// and then waits for all of them to finish.
+fn run_tasks<A>(name: &str, count: usize, func: fn(A) -> Result<(), &'static str>, arg: A) -> Result<(), &'static str>
+    where A: Clone + Send + 'static
+{
+    let tasks = (0..count)
+        .map(|i| {
+            spawn::new_task_builder(func, arg.clone())
+                .name(format!("{}_{}", name, i))
+                .spawn()
+        })
+        .collect::<Result<Vec<_>, &'static str>>()?;
+    for t in tasks {
+        t.join()??;
+    }
+    Ok(())
+}
+
+/// Returns an error if any of the tasks in a test reported a failure.
+fn check_failures(test: &str, failures: &AtomicUsize) -> Result<(), &'static str> {
+    match failures.load(Ordering::SeqCst) {
+        0 => {
+            warn!("{} test passed.", test);
+            Ok(())
+        }
+        n => {
+            error!("{} test had {} failures.", test, n);
+            Err("test failed")
+        }
+    }
+}
+
+
+struct RwLockTest {
+    lock: RwLockSleep<usize>,
+    /// The number of tasks currently holding the lock for reading.
+    readers: AtomicUsize,
+    /// The number of tasks currently holding the lock for writing.
+    writers: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+/// Several writers increment a shared counter while many readers check that it doesn't change while they hold the lock.
+fn test_rwlock() -> Result<(), &'static str> {
+    const NUM_READERS: usize = 6;
+    const NUM_WRITERS: usize = 3;
+    let test = Arc::new(RwLockTest {
+        lock: RwLockSleep::new(0),
+        readers: AtomicUsize::new(0),
+        writers: AtomicUsize::new(0),
+        failures: AtomicUsize::new(0),
+    });
+
+    let readers = {
+        let test = test.clone();
+        spawn::new_task_builder(move |_: ()| run_tasks("rwlock_reader", NUM_READERS, rwlock_reader, test), ())
+            .name(String::from("rwlock_readers"))
+            .spawn()?
+    };
+    run_tasks("rwlock_writer", NUM_WRITERS, rwlock_writer, test.clone())?;
+    readers.join()??;
+
+    let final_value = *test.lock.read()?;
+    warn!("RwLockSleep final value: {} (expected {})", final_value, NUM_WRITERS * ITERATIONS);
+    if final_value != NUM_WRITERS * ITERATIONS {
+        test.failures.fetch_add(1, Ordering::SeqCst);
+    }
+    check_failures("RwLockSleep", &test.failures)
+}
+
+fn rwlock_reader(test: Arc<RwLockTest>) -> Result<(), &'static str> {
+    for _i in 0..ITERATIONS {
+        let locked = test.lock.read()?;
+        test.readers.fetch_add(1, Ordering::SeqCst);
+        let value = *locked;
+        scheduler::schedule(); // give writers a chance to (incorrectly) modify the value
+        if test.writers.load(Ordering::SeqCst) != 0 || *locked != value {
+            error!("RwLockSleep: a writer held the lock at the same time as a reader");
+            test.failures.fetch_add(1, Ordering::SeqCst);
+        }
+        test.readers.fetch_sub(1, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+fn rwlock_writer(test: Arc<RwLockTest>) -> Result<(), &'static str> {
+    for _i in 0..ITERATIONS {
+        let mut locked = test.lock.write()?;
+        if test.writers.fetch_add(1, Ordering::SeqCst) != 0 || test.readers.load(Ordering::SeqCst) != 0 {
+            error!("RwLockSleep: a writer held the lock at the same time as another reader or writer");
+            test.failures.fetch_add(1, Ordering::SeqCst);
+        }
+        *locked += 1;
+        scheduler::schedule();
+        test.writers.fetch_sub(1, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+
+struct SemaphoreTest {
+    semaphore: Semaphore,
+    /// The number of tasks currently holding a resource of the semaphore.
+    holders: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+const SEMAPHORE_RESOURCES: usize = 2;
+
+/// Many tasks repeatedly acquire a semaphore with few resources and check that it's never oversubscribed.
+fn test_semaphore() -> Result<(), &'static str> {
+    const NUM_TASKS: usize = 6;
+    let test = Arc::new(SemaphoreTest {
+        semaphore: Semaphore::new(SEMAPHORE_RESOURCES),
+        holders: AtomicUsize::new(0),
+        failures: AtomicUsize::new(0),
+    });
+
+    run_tasks("semaphore", NUM_TASKS, semaphore_task, test.clone())?;
+
+    warn!("Semaphore final count: {} (expected {})", test.semaphore.count(), SEMAPHORE_RESOURCES);
+    if test.semaphore.count() != SEMAPHORE_RESOURCES {
+        test.failures.fetch_add(1, Ordering::SeqCst);
+    }
+    check_failures("Semaphore", &test.failures)
+}
+
+fn semaphore_task(test: Arc<SemaphoreTest>) -> Result<(), &'static str> {
+    for _i in 0..ITERATIONS {
+        let _access = test.semaphore.access().map_err(|_| "failed to acquire semaphore")?;
+        let holders = test.holders.fetch_add(1, Ordering::SeqCst) + 1;
+        if holders > SEMAPHORE_RESOURCES {
+            error!("Semaphore: {} tasks held a resource at the same time", holders);
+            test.failures.fetch_add(1, Ordering::SeqCst);
+        }
+        scheduler::schedule();
+        test.holders.fetch_sub(1, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+
+struct CondvarTest {
+    queue: MutexSleep<VecDeque<usize>>,
+    /// Notified when an item is added to the queue.
+    not_empty: Condvar,
+    /// Notified when an item is removed from the queue.
+    not_full: Condvar,
+    sum: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+const CONDVAR_QUEUE_CAPACITY: usize = 4;
+
+/// Producers and consumers exchange items through a bounded queue, waiting on condition variables when it's full or empty.
+fn test_condvar() -> Result<(), &'static str> {
+    const NUM_PRODUCERS: usize = 3;
+    const NUM_CONSUMERS: usize = 3;
+    let test = Arc::new(CondvarTest {
+        queue: MutexSleep::new(VecDeque::new()),
+        not_empty: Condvar::new(),
+        not_full: Condvar::new(),
+        sum: AtomicUsize::new(0),
+        failures: AtomicUsize::new(0),
+    });
+
+    let consumers = {
+        let test = test.clone();
+        spawn::new_task_builder(move |_: ()| run_tasks("condvar_consumer", NUM_CONSUMERS, condvar_consumer, test), ())
+            .name(String::from("condvar_consumers"))
+            .spawn()?
+    };
+    run_tasks("condvar_producer", NUM_PRODUCERS, condvar_producer, test.clone())?;
+    consumers.join()??;
+
+    // Each producer produces the items 1 through ITERATIONS, and each consumer consumes as many items.
+    let expected_sum = NUM_PRODUCERS * ITERATIONS * (ITERATIONS + 1) / 2;
+    let sum = test.sum.load(Ordering::SeqCst);
+    warn!("Condvar sum of consumed items: {} (expected {})", sum, expected_sum);
+    if sum != expected_sum {
+        test.failures.fetch_add(1, Ordering::SeqCst);
+    }
+    check_failures("Condvar", &test.failures)
+}
+
+fn condvar_producer(test: Arc<CondvarTest>) -> Result<(), &'static str> {
+    for item in 1..=ITERATIONS {
+        let locked = test.queue.lock()?;
+        let mut locked = test.not_full.wait_while(locked, |queue| queue.len() >= CONDVAR_QUEUE_CAPACITY)?;
+        locked.push_back(item);
+        if locked.len() > CONDVAR_QUEUE_CAPACITY {
+            error!("Condvar: producer woke up while the queue was full");
+            test.failures.fetch_add(1, Ordering::SeqCst);
+        }
+        test.not_empty.notify_one();
+    }
+    Ok(())
+}
+
+fn condvar_consumer(test: Arc<CondvarTest>) -> Result<(), &'static str> {
+    for _i in 0..ITERATIONS {
+        let locked = test.queue.lock()?;
+        let mut locked = test.not_empty.wait_while(locked, |queue| queue.is_empty())?;
+        match locked.pop_front() {
+            Some(item) => { test.sum.fetch_add(item, Ordering::SeqCst); }
+            None => {
+                error!("Condvar: consumer woke up while the queue was empty");
+                test.failures.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        test.not_full.notify_one();
+    }
+    Ok(())
+}
+
+
+struct BarrierTest {
+    barrier: Barrier,
+    /// The number of tasks that have reached the barrier in all rounds so far.
+    arrived: AtomicUsize,
+    /// The number of tasks that were the leader of a round.
+    leaders: AtomicUsize,
+    failures: AtomicUsize,
+}
+
+const BARRIER_TASKS: usize = 5;
+const BARRIER_ROUNDS: usize = 200;
+
+/// Several tasks repeatedly meet at a barrier and check that none of them got past it early.
+fn test_barrier() -> Result<(), &'static str> {
+    let test = Arc::new(BarrierTest {
+        barrier: Barrier::new(BARRIER_TASKS),
+        arrived: AtomicUsize::new(0),
+        leaders: AtomicUsize::new(0),
+        failures: AtomicUsize::new(0),
+    });
+
+    run_tasks("barrier", BARRIER_TASKS, barrier_task, test.clone())?;
+
+    // Each round consists of two waits on the barrier, see `barrier_task()`.
+    let leaders = test.leaders.load(Ordering::SeqCst);
+    warn!("Barrier leaders: {} (expected {})", leaders, 2 * BARRIER_ROUNDS);
+    if leaders != 2 * BARRIER_ROUNDS {
+        test.failures.fetch_add(1, Ordering::SeqCst);
+    }
+    check_failures("Barrier", &test.failures)
+}
+
+fn barrier_task(test: Arc<BarrierTest>) -> Result<(), &'static str> {
+    for round in 0..BARRIER_ROUNDS {
+        test.arrived.fetch_add(1, Ordering::SeqCst);
+        if test.barrier.wait().map_err(|_| "failed to wait on barrier")?.is_leader() {
+            test.leaders.fetch_add(1, Ordering::SeqCst);
+        }
+        let arrived = test.arrived.load(Ordering::SeqCst);
+        if arrived != (round + 1) * BARRIER_TASKS {
+            error!("Barrier: a task got past the barrier when {} of {} tasks had arrived", arrived, (round + 1) * BARRIER_TASKS);
+            test.failures.fetch_add(1, Ordering::SeqCst);
+        }
+        // Wait again such that no task starts the next round while others are still checking this one.
+        if test.barrier.wait().map_err(|_| "failed to wait on barrier")?.is_leader() {
+            test.leaders.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+    Ok(())
+}
@@ -57,7 +57,7 @@ fn rmain() -> Result<(), &'static str> {
             warn!("DeezNutz:  testing spurious wakeup on task {:?}", tref);
             tref.unblock();
         }, 
-        t1.clone(),
+        t1.task().clone(),
         )
         .name(String::from("deeznutz"))
         .pin_on_core(my_cpu)
@@ -74,8 +74,8 @@ fn rmain() -> Result<(), &'static str> {
     t2.unblock();
 
 
-    t1.join()?;
-    t2.join()?;
+    t1.join()??;
+    t2.join()??;
     t3.join()?;
     warn!("Joined the 3 tasks");
     
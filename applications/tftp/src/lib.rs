@@ -0,0 +1,127 @@
+//! Downloads files from and uploads files to a TFTP server.
+//!
+//! Usage:
+//! * `tftp get SERVER[:PORT] REMOTE_FILE [LOCAL_FILE]`: downloads a file into the current directory.
+//! * `tftp put SERVER[:PORT] LOCAL_FILE [REMOTE_FILE]`: uploads a file to the server.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+extern crate getopts;
+extern crate task;
+extern crate fs_node;
+extern crate memfs;
+extern crate path;
+extern crate smoltcp_helper;
+extern crate tftp_client;
+extern crate smoltcp;
+
+use core::str::FromStr;
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use getopts::{Matches, Options};
+use fs_node::{DirRef, FileOrDir};
+use memfs::MemFile;
+use path::Path;
+use smoltcp::wire::IpEndpoint;
+use tftp_client::TftpConfig;
+
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("b", "blksize", "the block size to request from the server (default: 1468)", "BYTES");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{}", _f);
+            print_usage(&opts);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") || matches.free.len() < 3 || matches.free.len() > 4 {
+        print_usage(&opts);
+        return 0;
+    }
+
+    match rmain(&matches) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+
+fn rmain(matches: &Matches) -> Result<(), String> {
+    let mut config = TftpConfig::default();
+    if let Some(b) = matches.opt_str("b") {
+        config.block_size = b.parse::<u16>().map_err(|_e| format!("couldn't parse block size {:?}", b))?;
+    }
+
+    let mut server = IpEndpoint::from_str(&matches.free[1])
+        .map_err(|_e| format!("couldn't parse server address {:?}", matches.free[1]))?;
+    if server.port == 0 {
+        server.port = tftp_client::DEFAULT_SERVER_PORT;
+    }
+    let iface = smoltcp_helper::get_iface_for(server.addr)?;
+    let curr_dir = task::get_my_current_task()
+        .map(|t| t.get_env().lock().working_dir.clone())
+        .ok_or_else(|| format!("couldn't get my current working directory"))?;
+
+    match matches.free[0].as_str() {
+        "get" => {
+            let remote_file = &matches.free[2];
+            let local_file = matches.free.get(3).cloned()
+                .unwrap_or_else(|| Path::new(remote_file.clone()).basename().to_string());
+            if curr_dir.lock().get(&local_file).is_some() {
+                return Err(format!("{:?} already exists", local_file));
+            }
+            let content = tftp_client::get(&iface, server, remote_file, &config)?;
+            write_file(&curr_dir, local_file.clone(), &content)?;
+            println!("Downloaded {:?} ({} bytes) into {:?}", remote_file, content.len(), local_file);
+        }
+        "put" => {
+            let local_file = &matches.free[2];
+            let remote_file = matches.free.get(3).unwrap_or(local_file);
+            let content = read_file(&curr_dir, local_file)?;
+            tftp_client::put(&iface, server, remote_file, &content, &config)?;
+            println!("Uploaded {:?} ({} bytes) as {:?}", local_file, content.len(), remote_file);
+        }
+        other => return Err(format!("unknown command {:?}", other)),
+    }
+    Ok(())
+}
+
+
+fn read_file(dir: &DirRef, path: &str) -> Result<Vec<u8>, String> {
+    let file = match Path::new(path.to_string()).get(dir) {
+        Some(FileOrDir::File(f)) => f,
+        _ => return Err(format!("couldn't find file {:?}", path)),
+    };
+    let mut content: Vec<u8> = vec![0; file.lock().size()];
+    let _bytes_read = file.lock().read(&mut content, 0)?;
+    Ok(content)
+}
+
+
+fn write_file(dir: &DirRef, name: String, content: &[u8]) -> Result<(), String> {
+    let file = MemFile::new(name, dir)?;
+    file.lock().write(content, 0)?;
+    Ok(())
+}
+
+
+fn print_usage(opts: &Options) {
+    println!("{}", opts.usage(USAGE));
+}
+
+const USAGE: &'static str = "Usage: tftp [OPTION]... get SERVER[:PORT] REMOTE_FILE [LOCAL_FILE]
+       tftp [OPTION]... put SERVER[:PORT] LOCAL_FILE [REMOTE_FILE]
+Downloads a file from or uploads a file to a TFTP server.";
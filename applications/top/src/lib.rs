@@ -0,0 +1,198 @@
+//! An application that periodically shows how much CPU time each task and each core has used.
+//!
+//! The utilization of each core is derived from the run time of that core's idle task,
+//! since the idle task only runs when no other task on that core is runnable.
+
+#![no_std]
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate terminal_print;
+
+extern crate task;
+extern crate tsc;
+extern crate sleep;
+extern crate getopts;
+
+use core::time::Duration;
+use getopts::Options;
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    vec::Vec,
+};
+use task::TASKLIST;
+
+/// The default time between two refreshes, in milliseconds.
+const DEFAULT_DELAY_MS: u64 = 1000;
+
+pub fn main(args: Vec<String>) -> isize {
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this help menu");
+    opts.optopt("d", "delay", "the time between refreshes, in milliseconds (default 1000)", "MS");
+    opts.optopt("i", "iterations", "the number of refreshes after which to exit (default: run until killed)", "COUNT");
+    opts.optopt("n", "num", "show only the given number of busiest tasks", "NUM");
+
+    let matches = match opts.parse(&args) {
+        Ok(m) => m,
+        Err(_f) => {
+            println!("{} \n", _f);
+            return -1;
+        }
+    };
+
+    if matches.opt_present("h") {
+        return print_usage(opts);
+    }
+
+    let delay = match matches.opt_str("d").map(|d| d.parse::<u64>()) {
+        None => DEFAULT_DELAY_MS,
+        Some(Ok(d)) if d > 0 => d,
+        _ => {
+            println!("Invalid delay, must be a positive number of milliseconds");
+            return -1;
+        }
+    };
+    let iterations = match matches.opt_str("i").map(|i| i.parse::<usize>()) {
+        None => None,
+        Some(Ok(i)) => Some(i),
+        Some(Err(_)) => {
+            println!("Invalid number of iterations");
+            return -1;
+        }
+    };
+    let max_tasks = match matches.opt_str("n").map(|n| n.parse::<usize>()) {
+        None => None,
+        Some(Ok(n)) => Some(n),
+        Some(Err(_)) => {
+            println!("Invalid number of tasks");
+            return -1;
+        }
+    };
+
+    match run(Duration::from_millis(delay), iterations, max_tasks) {
+        Ok(_) => 0,
+        Err(e) => {
+            println!("Error: {}", e);
+            -1
+        }
+    }
+}
+
+/// The CPU time accounting of one task at one point in time.
+struct TaskSample {
+    name: String,
+    /// The core the task is running on, or otherwise the core it's pinned to.
+    core: Option<u8>,
+    is_idle: bool,
+    run_ticks: u64,
+    wait_ticks: u64,
+    switches: u64,
+}
+
+/// Samples the CPU time accounting of all tasks, keyed by task ID.
+fn sample_tasks() -> BTreeMap<usize, TaskSample> {
+    let mut samples = BTreeMap::new();
+    for (id, taskref) in TASKLIST.lock().iter() {
+        let task = taskref.lock();
+        samples.insert(*id, TaskSample {
+            name: task.name.clone(),
+            core: task.running_on_cpu.or(task.pinned_core),
+            is_idle: task.is_an_idle_task,
+            run_ticks: task.cpu_times.run_ticks(),
+            wait_ticks: task.cpu_times.wait_ticks(),
+            switches: task.cpu_times.context_switches(),
+        });
+    }
+    samples
+}
+
+/// Formats the given fraction `part / whole` as a percentage with one decimal place.
+fn percent(part: u64, whole: u64) -> String {
+    if whole == 0 {
+        return String::from("-");
+    }
+    let permille = (part as u128 * 1000 / whole as u128) as u64;
+    format!("{}.{}%", permille / 10, permille % 10)
+}
+
+fn run(delay: Duration, iterations: Option<usize>, max_tasks: Option<usize>) -> Result<(), &'static str> {
+    let mut prev_samples = sample_tasks();
+    let mut prev_ticks = tsc::tsc_ticks().into();
+    let mut refreshes = 0;
+
+    while iterations.map_or(true, |i| refreshes < i) {
+        sleep::sleep(delay)?;
+        let samples = sample_tasks();
+        let now_ticks = tsc::tsc_ticks().into();
+        let elapsed = now_ticks.saturating_sub(prev_ticks);
+
+        // The change in each task's accounting since the previous refresh.
+        // Tasks that were spawned since then are measured from zero.
+        let mut deltas: Vec<(usize, &TaskSample, u64, u64, u64)> = samples.iter().map(|(id, s)| {
+            let (run, wait, switches) = prev_samples.get(id)
+                .map(|p| (p.run_ticks, p.wait_ticks, p.switches))
+                .unwrap_or((0, 0, 0));
+            (
+                *id, s,
+                s.run_ticks.saturating_sub(run),
+                s.wait_ticks.saturating_sub(wait),
+                s.switches.saturating_sub(switches),
+            )
+        }).collect();
+
+        let mut output = String::new();
+
+        // Per-core utilization, based on each core's idle task.
+        output.push_str(&format!("{0:<6}  {1:<8}  {2:<8}\n", "CORE", "BUSY", "IDLE"));
+        for &(_id, s, run, _, _) in deltas.iter().filter(|d| d.1.is_idle) {
+            let idle = core::cmp::min(run, elapsed);
+            output.push_str(&format!("{0:<6}  {1:<8}  {2:<8}\n",
+                s.core.map(|c| format!("{}", c)).unwrap_or_else(|| String::from("-")),
+                percent(elapsed - idle, elapsed),
+                percent(idle, elapsed),
+            ));
+        }
+        output.push_str("\n");
+
+        // Per-task utilization, busiest tasks first.
+        deltas.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        output.push_str(&format!("{0:<5}  {1:<4}  {2:<7}  {3:<7}  {4:<8}  {5:<10}  {6}\n",
+            "ID", "CORE", "CPU", "WAIT", "SWITCHES", "TIME(ms)", "NAME"
+        ));
+        for &(id, s, run, wait, switches) in deltas.iter().take(max_tasks.unwrap_or(usize::max_value())) {
+            let total_time = task::ticks_to_duration(s.run_ticks)
+                .map(|t| format!("{}", t.as_millis()))
+                .unwrap_or_else(|| String::from("-"));
+            output.push_str(&format!("{0:<5}  {1:<4}  {2:<7}  {3:<7}  {4:<8}  {5:<10}  {6}\n",
+                id,
+                s.core.map(|c| format!("{}", c)).unwrap_or_else(|| String::from("-")),
+                percent(run, elapsed),
+                percent(wait, elapsed),
+                switches,
+                total_time,
+                s.name,
+            ));
+        }
+        output.push_str(&format!("Total number of tasks: {}\n", samples.len()));
+        println!("{}", output);
+
+        prev_samples = samples;
+        prev_ticks = now_ticks;
+        refreshes += 1;
+    }
+    Ok(())
+}
+
+fn print_usage(opts: Options) -> isize {
+    let mut brief = format!("Usage: top [options] \n \n");
+
+    brief.push_str("Shows the CPU utilization of each core and each task since the previous refresh. \n");
+    brief.push_str("BUSY and IDLE are the share of time a core spent running tasks or its idle task. \n");
+    brief.push_str("CPU is the share of time the task spent running. \n");
+    brief.push_str("WAIT is the share of time the task spent runnable but waiting to run. \n");
+    brief.push_str("SWITCHES is the number of times the task was switched to. \n");
+    brief.push_str("TIME is the total time the task has spent running, in milliseconds.");
+
+    println!("{} \n", opts.usage(&brief));
+
+    0
+}
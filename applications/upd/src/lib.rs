@@ -1,6 +1,9 @@
 //! This application offers a front-end for communicating with
 //! Theseus's update server to download updated crate object files,
 //! apply live updates to evolve Theseus, traverse update history, etc.
+//! 
+//! Every downloaded crate object file and diff file must match the update build's signed manifest,
+//! which is checked again right before an update is applied.
 
 
 #![no_std]
@@ -48,8 +51,8 @@ use crate_swap::{
 use memfs::MemFile;
 use path::Path;
 use vfs_node::VFSDirectory;
-use fs_node::{FileOrDir, DirRef};
-use ota_update_client::DIFF_FILE_NAME;
+use fs_node::{FileOrDir, FileRef, DirRef};
+use ota_update_client::{DIFF_FILE_NAME, MANIFEST_FILE_NAME, MANIFEST_SIGNATURE_FILE_NAME, Manifest};
 
 
 
@@ -171,22 +174,41 @@ fn download(remote_endpoint: IpEndpoint, update_build: &str, crate_list: Option<
     println!("Downloading crates...");
     let crate_list = if crate_list == Some(&[]) { None } else { crate_list };
 
-    let mut diff_file_lines: Option<Vec<String>> = None;
+    // First, obtain the update build's signed manifest, which every other downloaded file must match.
+    let manifest_content = ota_update_client::download_update_file(&iface, remote_endpoint, update_build, MANIFEST_FILE_NAME)
+        .map_err(|e| format!("failed to download manifest file for {}, error: {}", update_build, e))?;
+    let signature = ota_update_client::download_update_file(&iface, remote_endpoint, update_build, MANIFEST_SIGNATURE_FILE_NAME)
+        .map_err(|e| format!("failed to download manifest signature file for {}, error: {}", update_build, e))?;
+    let manifest = ota_update_client::verify_manifest(&manifest_content, &signature)
+        .map_err(|e| format!("failed to verify manifest for {}, error: {}", update_build, e))?;
+    manifest.verify_build(update_build)
+        .map_err(|e| format!("refusing to download {}: {}", update_build, e))?;
+
+    let mut diff_file_content: Option<Vec<u8>> = None;
 
     let crates = if let Some(crate_list) = crate_list {
         let crate_set = crate_list.iter().cloned().collect::<BTreeSet<String>>();
         ota_update_client::download_crates(&iface, remote_endpoint, update_build, crate_set).map_err(|e| e.to_string())?
     } else {
-        let diff_lines = ota_update_client::download_diff(&iface, remote_endpoint, update_build)
+        let diff_content = ota_update_client::download_update_file(&iface, remote_endpoint, update_build, DIFF_FILE_NAME)
             .map_err(|e| format!("failed to download diff file for {}, error: {}", update_build, e))?;
-        let diff = ota_update_client::parse_diff_lines(&diff_lines).map_err(|e| e.to_string())?;
+        manifest.verify_file(DIFF_FILE_NAME, &diff_content)?;
+        let diff = ota_update_client::as_lines(&diff_content)
+            .and_then(|diff_lines| ota_update_client::parse_diff_lines(&diff_lines))
+            .map_err(|e| e.to_string())?;
 
         // download all of the new crates
         let new_crates_to_download: BTreeSet<String> = diff.pairs.iter().map(|(_old, new)| new.clone()).collect();
         let crates = ota_update_client::download_crates(&iface, remote_endpoint, update_build, new_crates_to_download).map_err(|e| e.to_string())?;
-        diff_file_lines = Some(diff_lines);
+        diff_file_content = Some(diff_content);
         crates
     };
+
+    // Ensure that every crate matches the manifest before saving any of them.
+    for df in crates.iter() {
+        manifest.verify_file(Path::new(df.name.clone()).basename(), df.content.as_result_err_str()?)
+            .map_err(|e| format!("refusing to save crate {:?}: {}", df.name, e))?;
+    }
     
     // save each new crate to a file 
     let curr_dir = task::get_my_current_task().map(|t| t.get_env().lock().working_dir.clone()).ok_or_else(|| format!("couldn't get my current working directory"))?;
@@ -202,11 +224,17 @@ fn download(remote_endpoint: IpEndpoint, update_build: &str, crate_list: Option<
     }
 
     // if downloaded, save the diff file into the base directory
-    if let Some(diffs) = diff_file_lines {
+    if let Some(diff_content) = diff_file_content {
         let cfile = MemFile::new(String::from(DIFF_FILE_NAME), &new_namespace_dir)?;
-        cfile.lock().write(diffs.join("\n").as_bytes(), 0)?;
+        cfile.lock().write(&diff_content, 0)?;
     }
 
+    // save the manifest and its signature such that they can be verified again when the update is applied
+    let cfile = MemFile::new(String::from(MANIFEST_FILE_NAME), &new_namespace_dir)?;
+    cfile.lock().write(&manifest_content, 0)?;
+    let cfile = MemFile::new(String::from(MANIFEST_SIGNATURE_FILE_NAME), &new_namespace_dir)?;
+    cfile.lock().write(&signature, 0)?;
+
     Ok(())
 }
 
@@ -224,12 +252,18 @@ fn apply(base_dir_path: &Path) -> Result<(), String> {
         Some(FileOrDir::Dir(d)) => NamespaceDir::new(d),
         _ => return Err(format!("cannot find an update base directory at path {}", base_dir_path)),
     };
-    let diff_file = match new_namespace_dir.lock().get(DIFF_FILE_NAME) { 
-        Some(FileOrDir::File(f)) => f,
-        _ => return Err(format!("cannot find diff file expected at {}/{}", base_dir_path, DIFF_FILE_NAME)),
+
+    // The update files may have been modified since they were downloaded, 
+    // so we verify the signed manifest again, and then verify every file against it.
+    let manifest = {
+        let manifest_content = read_file(&new_namespace_dir, MANIFEST_FILE_NAME, base_dir_path)?;
+        let signature = read_file(&new_namespace_dir, MANIFEST_SIGNATURE_FILE_NAME, base_dir_path)?;
+        ota_update_client::verify_manifest(&manifest_content, &signature)
+            .map_err(|e| format!("failed to verify update manifest, error: {}", e))?
     };
-    let mut diff_content: Vec<u8> = alloc::vec::from_elem(0, diff_file.lock().size()); 
-    let _bytes_read = diff_file.lock().read(&mut diff_content, 0)?;
+    println!("Applying update build {:?}", manifest.build_name());
+    let diff_content = read_file(&new_namespace_dir, DIFF_FILE_NAME, base_dir_path)?;
+    manifest.verify_file(DIFF_FILE_NAME, &diff_content)?;
     let diffs = ota_update_client::as_lines(&diff_content).map_err(|e| e.to_string())
         .and_then(|diff_lines| ota_update_client::parse_diff_lines(&diff_lines).map_err(|e| e.to_string()))?;
 
@@ -264,6 +298,7 @@ fn apply(base_dir_path: &Path) -> Result<(), String> {
         let new_crate_file = new_namespace_dir.get_crate_object_file(&new_crate_module_file_name).ok_or_else(|| 
             format!("cannot find new crate file {:?} in new namespace dir {}", new_crate_module_file_name, base_dir_path)
         )?;
+        verify_crate_file(&manifest, &new_crate_module_file_name, &new_crate_file)?;
 
         let swap_req = SwapRequest::new(
             old_crate_name.as_deref(),
@@ -290,6 +325,27 @@ fn apply(base_dir_path: &Path) -> Result<(), String> {
 }
 
 
+/// Reads the entire contents of the file with the given name in the given update base directory.
+fn read_file(dir: &NamespaceDir, file_name: &str, base_dir_path: &Path) -> Result<Vec<u8>, String> {
+    let file = match dir.lock().get(file_name) { 
+        Some(FileOrDir::File(f)) => f,
+        _ => return Err(format!("cannot find file expected at {}/{}", base_dir_path, file_name)),
+    };
+    let mut content: Vec<u8> = alloc::vec::from_elem(0, file.lock().size()); 
+    let _bytes_read = file.lock().read(&mut content, 0)?;
+    Ok(content)
+}
+
+
+/// Ensures that the given crate object file matches the hash of `crate_module_file_name` in the signed `manifest`.
+fn verify_crate_file(manifest: &Manifest, crate_module_file_name: &str, file: &FileRef) -> Result<(), String> {
+    let mut content: Vec<u8> = alloc::vec::from_elem(0, file.lock().size()); 
+    let _bytes_read = file.lock().read(&mut content, 0)?;
+    manifest.verify_file(crate_module_file_name, &content)
+        .map_err(|e| format!("refusing to apply crate {:?}: {}", crate_module_file_name, e))
+}
+
+
 fn get_my_current_namespace() -> Arc<CrateNamespace> {
     task::get_my_current_task().map(|t| t.get_namespace()).unwrap_or_else(|| 
         mod_mgmt::get_initial_kernel_namespace().expect("BUG: initial kernel namespace wasn't initialized").clone()
@@ -350,4 +406,5 @@ Runs the given update-related COMMAND. Choices include:
         
     apply BASE_DIR
         Applies the evolutionary update specified by the diff file 
-        in the given BASE_DIR, which contains the new crate object files to be used.";
+        in the given BASE_DIR, which contains the new crate object files to be used.
+        The diff file and all new crate object files must match the signed manifest in BASE_DIR.";
@@ -10,6 +10,7 @@ const CFG_PREFIX: &'static str = "cargo:rustc-cfg=";
 // const MIRROR_LOG_TO_VGA: &'static str = "mirror_log_to_vga";
 // const SIMD_PERSONALITY: &'static str = "simd_personality";
 // const PRIORITY_SCHEDULER: &'static str = "priority_scheduler";
+// const REALTIME_SCHEDULER: &'static str = "realtime_scheduler";
 
 fn main() {
     println!("cargo:rerun-if-env-changed=THESEUS_CONFIG");
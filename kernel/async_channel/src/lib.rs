@@ -15,6 +15,7 @@ extern crate alloc;
 #[cfg(trace_channel)] #[macro_use] extern crate log;
 #[cfg(trace_channel)] #[macro_use] extern crate debugit;
 extern crate wait_queue;
+extern crate sleep;
 extern crate mpmc;
 extern crate atomic;
 
@@ -24,9 +25,11 @@ extern crate hpet;
 extern crate task;
 
 use core::sync::atomic::Ordering;
+use core::time::Duration;
 use alloc::sync::Arc;
 use mpmc::Queue as MpmcQueue;
-use wait_queue::WaitQueue;
+use wait_queue::{WaitQueue, WaitError};
+use sleep::Instant;
 use atomic::Atomic;
 
 
@@ -78,6 +81,8 @@ pub enum ChannelError {
     ChannelFull,
     /// Occurs when one end of channel is dropped
     ChannelDisconnected,
+    /// Occurs when a `*_timeout` or `*_deadline` operation didn't complete in time
+    Timeout,
     /// Occurs when an error occur in `WaitQueue`
     WaitError(wait_queue::WaitError)
 }
@@ -121,6 +126,28 @@ impl <T: Send> Sender<T> {
     /// Returns `Ok(())` if the message was sent successfully,
     /// otherwise returns an error of `ChannelError` type. 
     pub fn send(&self, msg: T) -> Result<(), ChannelError> {
+        self.send_internal(msg, None).map_err(|(_msg, channel_error)| channel_error)
+    }
+
+    /// Similar to [`send`](#method.send), but gives up and returns `ChannelError::Timeout`
+    /// if no buffer space became available within the given `timeout`.
+    ///
+    /// If the message couldn't be sent, it is returned to the caller along with the `ChannelError`.
+    pub fn send_timeout(&self, msg: T, timeout: Duration) -> Result<(), (T, ChannelError)> {
+        self.send_internal(msg, Some(Instant::now() + timeout))
+    }
+
+    /// Similar to [`send`](#method.send), but gives up and returns `ChannelError::Timeout`
+    /// if no buffer space became available by the given `deadline`.
+    ///
+    /// If the message couldn't be sent, it is returned to the caller along with the `ChannelError`.
+    pub fn send_deadline(&self, msg: T, deadline: Instant) -> Result<(), (T, ChannelError)> {
+        self.send_internal(msg, Some(deadline))
+    }
+
+    /// The internal routine for sending a message, which blocks until the optional `deadline`.
+    /// If the message couldn't be sent, it is returned along with the error.
+    fn send_internal(&self, msg: T, deadline: Option<Instant>) -> Result<(), (T, ChannelError)> {
         #[cfg(trace_channel)]
         trace!("async_channel: sending msg: {:?}", debugit!(msg));
         // Fast path: attempt to send the message, assuming the buffer isn't full
@@ -130,7 +157,7 @@ impl <T: Send> Sender<T> {
             // if unsunccessful check whether it fails due to any other reason than channel being full
             Err((returned_msg, channel_error)) => {
                 if channel_error != ChannelError::ChannelFull {
-                    return Err(channel_error);
+                    return Err((returned_msg, channel_error));
                 }
                 returned_msg
             },
@@ -149,8 +176,15 @@ impl <T: Send> Sender<T> {
         // because it will notify the receivers which can cause deadlock.
         // Therefore, we need to perform the nofity action outside of this closure after it returns.
         let mut closure = || {
+            if self.channel.is_disconnected() {
+                 // trace!("Receiver Endpoint is dropped");
+                 // Here the receiver end has dropped. 
+                 // So we don't wait anymore in the waitqueue, and keep the message to return it to the caller.
+                 return Some(Err(ChannelError::ChannelDisconnected));
+            }
+
             let owned_msg = msg.take();
-            let result = owned_msg.and_then(|m| match self.channel.queue.push(m) {
+            owned_msg.and_then(|m| match self.channel.queue.push(m) {
                 Ok(()) => {
                     // trace!("Sending in closure");
                     // We wrap the result in Some() since `wait_until` progresses only when `Some` is returned.
@@ -163,24 +197,19 @@ impl <T: Send> Sender<T> {
                     msg = Some(returned_msg);
                     None
                 }
-            });
-
-            if self.channel.is_disconnected() {
-                 // trace!("Receiver Endpoint is dropped");
-                 // Here the receiver end has dropped. 
-                 // So we don't wait anymore in the waitqueue
-                 Some(Err(ChannelError::ChannelDisconnected))
-            } else {
-                result
-            }
-            
+            })
         };
 
         // When `wait_until_mut` returns it can be either a successful send marked as  Ok(Ok()), 
         // Error in the condition (channel disconnection) marked as Ok(Err()),
         // or the wait_until runs into error (Err()) 
-        let res =  match self.channel.waiting_senders.wait_until_mut(&mut closure) {
+        let wait_result = match deadline {
+            Some(d) => self.channel.waiting_senders.wait_until_mut_deadline(&mut closure, d),
+            None => self.channel.waiting_senders.wait_until_mut(&mut closure),
+        };
+        let res = match wait_result {
             Ok(r) => r,
+            Err(WaitError::Timeout) => Err(ChannelError::Timeout),
             Err(wait_error) => Err(ChannelError::WaitError(wait_error)),
         };
 
@@ -188,11 +217,15 @@ impl <T: Send> Sender<T> {
 
         // If we successfully sent a message, we need to notify any waiting receivers.
         // As stated above, to avoid deadlock, this must be done here rather than in the above closure.
-        if res.is_ok() {
-            // trace!("successful send() is notifying receivers.");
-            self.channel.waiting_receivers.notify_one();
+        match res {
+            Ok(()) => {
+                // trace!("successful send() is notifying receivers.");
+                self.channel.waiting_receivers.notify_one();
+                Ok(())
+            }
+            // The message is only taken out of `msg` once it has been sent, so it is still there upon an error.
+            Err(channel_error) => Err((msg.take().expect("BUG: async_channel: unsent message was lost"), channel_error)),
         }
-        res
     }
 
     /// Tries to send the message, only succeeding if buffer space is available.
@@ -257,6 +290,23 @@ impl <T: Send> Receiver<T> {
     /// 
     /// Returns the message if it was received properly, otherwise returns an error of `ChannelError` type.
     pub fn receive(&self) -> Result<T, ChannelError> {
+        self.receive_internal(None)
+    }
+
+    /// Similar to [`receive`](#method.receive), but gives up and returns `ChannelError::Timeout`
+    /// if no message was received within the given `timeout`.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, ChannelError> {
+        self.receive_internal(Some(Instant::now() + timeout))
+    }
+
+    /// Similar to [`receive`](#method.receive), but gives up and returns `ChannelError::Timeout`
+    /// if no message was received by the given `deadline`.
+    pub fn receive_deadline(&self, deadline: Instant) -> Result<T, ChannelError> {
+        self.receive_internal(Some(deadline))
+    }
+
+    /// The internal routine for receiving a message, which blocks until the optional `deadline`.
+    fn receive_internal(&self, deadline: Option<Instant>) -> Result<T, ChannelError> {
         // trace!("async_channel: receive() entry");
         // Fast path: attempt to receive a message, assuming the buffer isn't empty
         // The code progresses beyond this match only if try_receive fails due to
@@ -294,9 +344,14 @@ impl <T: Send> Receiver<T> {
         // When wait returns it can be either a successful receiver marked as  Ok(Ok(msg)), 
         // Error in wait condition marked as Ok(Err(error)),
         // or the wait_until runs into error (Err()) 
-        let res =  match self.channel.waiting_receivers.wait_until(& closure) {
+        let wait_result = match deadline {
+            Some(d) => self.channel.waiting_receivers.wait_until_deadline(& closure, d),
+            None => self.channel.waiting_receivers.wait_until(& closure),
+        };
+        let res = match wait_result {
             Ok(Ok(x)) => Ok(x),
             Ok(Err(error)) => Err(error),
+            Err(WaitError::Timeout) => Err(ChannelError::Timeout),
             Err(wait_error) => Err(ChannelError::WaitError(wait_error)),
         };
 
@@ -346,6 +401,12 @@ impl <T: Send> Receiver<T> {
     pub fn is_disconnected(&self) -> bool {
         self.channel.is_disconnected()
     }
+
+    /// Returns the `WaitQueue` that is notified when a message is sent or the sender disconnects,
+    /// which allows a task to wait on this and other receivers at once, e.g., using the `select` crate.
+    pub fn receive_wait_queue(&self) -> &WaitQueue {
+        &self.channel.waiting_receivers
+    }
 }
 
 
@@ -0,0 +1,91 @@
+//! Futures that receive a message from an `async_channel::Receiver`.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use alloc::sync::Arc;
+use async_channel::{Receiver, ChannelError};
+use WakerRegistration;
+
+
+/// Returns a future that receives the next message from the given `receiver`,
+/// which is the async version of `Receiver::receive()`.
+pub fn receive<T: Send>(receiver: &Receiver<T>) -> Receive<T> {
+    Receive {
+        receiver,
+        registration: WakerRegistration::new(),
+    }
+}
+
+/// Similar to [`receive`](fn.receive.html), but the returned future owns a reference to the `receiver`,
+/// such that it can be stored alongside the receiver, e.g., in a hand-written future.
+pub fn receive_owned<T: Send>(receiver: Arc<Receiver<T>>) -> ReceiveOwned<T> {
+    ReceiveOwned {
+        receiver,
+        registration: WakerRegistration::new(),
+    }
+}
+
+/// A future that completes once a message has been received from a channel, see [`receive`](fn.receive.html).
+pub struct Receive<'r, T: Send + 'r> {
+    receiver: &'r Receiver<T>,
+    registration: WakerRegistration,
+}
+
+impl<'r, T: Send> Future for Receive<'r, T> {
+    type Output = Result<T, ChannelError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        poll_receive(this.receiver, &mut this.registration, cx)
+    }
+}
+
+impl<'r, T: Send> Drop for Receive<'r, T> {
+    fn drop(&mut self) {
+        self.registration.unregister(self.receiver.receive_wait_queue());
+    }
+}
+
+/// A future that completes once a message has been received from a channel, see [`receive_owned`](fn.receive_owned.html).
+pub struct ReceiveOwned<T: Send> {
+    receiver: Arc<Receiver<T>>,
+    registration: WakerRegistration,
+}
+
+impl<T: Send> Future for ReceiveOwned<T> {
+    type Output = Result<T, ChannelError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        poll_receive(&this.receiver, &mut this.registration, cx)
+    }
+}
+
+impl<T: Send> Drop for ReceiveOwned<T> {
+    fn drop(&mut self) {
+        self.registration.unregister(self.receiver.receive_wait_queue());
+    }
+}
+
+/// Polls for a message from the given `receiver` on behalf of a future with the given waker `registration`.
+fn poll_receive<T: Send>(receiver: &Receiver<T>, registration: &mut WakerRegistration, cx: &mut Context) -> Poll<Result<T, ChannelError>> {
+    match receiver.try_receive() {
+        Err(ChannelError::ChannelEmpty) => { }
+        result => {
+            registration.unregister(receiver.receive_wait_queue());
+            return Poll::Ready(result);
+        }
+    }
+    // Register before checking again, such that a message sent in between isn't missed.
+    registration.register(receiver.receive_wait_queue(), cx.waker());
+    match receiver.try_receive() {
+        Err(ChannelError::ChannelEmpty) => Poll::Pending,
+        result => {
+            registration.unregister(receiver.receive_wait_queue());
+            Poll::Ready(result)
+        }
+    }
+}
@@ -0,0 +1,315 @@
+//! An executor that runs `Future`s on a dedicated `Task`, which lets I/O-bound services be written with `async fn`.
+//!
+//! The executor's `Task` sleeps on a `WaitQueue` until one of its futures is woken up,
+//! at which point that future's `Waker` puts it back on the executor's ready queue
+//! and unblocks the executor's `Task` to poll it again.
+//! Wakers may run in interrupt context, so waking up a future never allocates or frees memory:
+//! the ready queue always has room for every spawned future,
+//! and the executor keeps every future alive until it's the only one referring to it.
+//!
+//! This crate also provides leaf futures that wait on existing Theseus event sources:
+//! * [`channel::receive`](channel/fn.receive.html) and [`channel::receive_owned`](channel/fn.receive_owned.html)
+//!   receive a message from an `async_channel::Receiver`,
+//! * [`timer::sleep`](timer/fn.sleep.html) completes after a given duration,
+//! * [`nic::receive_frame`](nic/fn.receive_frame.html) completes once a NIC has received a frame.
+//!
+//! Futures can be written by hand or with `async fn` and `async` blocks,
+//! the latter of which require the crate that uses them to be built with the 2018 edition.
+//!
+//! # Example
+//! ```ignore
+//! let executor = Executor::new(String::from("net_executor"))?;
+//! executor.spawn(async move {
+//!     while let Ok(msg) = channel::receive(&receiver).await {
+//!         timer::sleep(Duration::from_millis(10)).await;
+//!         handle(msg);
+//!     }
+//! });
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate irq_safety;
+extern crate kernel_config;
+extern crate spawn;
+extern crate wait_queue;
+extern crate sleep;
+extern crate async_channel;
+extern crate nic_buffers;
+extern crate network_interface_card;
+
+pub mod channel;
+pub mod timer;
+pub mod nic;
+
+use core::{
+    fmt,
+    future::Future,
+    mem::ManuallyDrop,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker},
+};
+use alloc::{
+    boxed::Box,
+    collections::VecDeque,
+    string::String,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::Mutex;
+use irq_safety::MutexIrqSafe;
+use wait_queue::WaitQueue;
+
+
+/// A future that has been spawned onto an executor, boxed such that futures of different types can be stored together.
+type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A handle to an executor that runs futures on its own dedicated `Task`.
+///
+/// The handle can be cloned to spawn futures from multiple places.
+/// The executor's `Task` exits once all handles have been dropped and all of its futures have completed.
+pub struct Executor {
+    inner: Arc<ExecutorInner>,
+}
+
+struct ExecutorInner {
+    /// The futures that have been woken up and must be polled again.
+    ///
+    /// This is locked within the waitqueue's wait condition, during which interrupts are disabled,
+    /// and by wakers that run in interrupt context, e.g., timer callbacks.
+    ready: MutexIrqSafe<ReadyQueue>,
+    /// All spawned futures, which are only dropped by the executor's `Task`
+    /// once no `Waker` refers to them anymore, such that they're never freed in interrupt context.
+    spawned: Mutex<Vec<Arc<FutureTask>>>,
+    /// The executor's `Task` waits on this queue until a future is ready to be polled.
+    wait_queue: WaitQueue,
+    /// The number of spawned futures that haven't completed yet.
+    pending: AtomicUsize,
+    /// The number of `Executor` handles that currently exist.
+    handles: AtomicUsize,
+    name: String,
+}
+
+/// The futures that have been woken up, whose capacity is reserved in advance
+/// such that waking up a future never allocates memory.
+struct ReadyQueue {
+    futures: VecDeque<Arc<FutureTask>>,
+    /// The number of futures that may be on this queue at once:
+    /// every future that hasn't completed, plus every completed future that was woken up right before completing.
+    slots: usize,
+}
+
+/// A spawned future together with the state needed to wake it up.
+struct FutureTask {
+    /// The future itself, which is taken out once it has completed.
+    /// This is only ever locked by the executor's `Task` while polling the future.
+    future: Mutex<Option<BoxFuture>>,
+    /// The executor that this future was spawned onto.
+    executor: Weak<ExecutorInner>,
+    /// Whether this future is already on the executor's ready queue,
+    /// such that waking it up multiple times before it's polled only queues it once.
+    /// This stays set once the future has completed, such that it's never queued again.
+    queued: AtomicBool,
+}
+
+impl Executor {
+    /// Creates a new executor and spawns the `Task` with the given `name` that runs its futures.
+    pub fn new(name: String) -> Result<Executor, &'static str> {
+        let inner = Arc::new(ExecutorInner {
+            ready: MutexIrqSafe::new(ReadyQueue { futures: VecDeque::new(), slots: 0 }),
+            spawned: Mutex::new(Vec::new()),
+            wait_queue: WaitQueue::new(),
+            pending: AtomicUsize::new(0),
+            handles: AtomicUsize::new(1),
+            name: name.clone(),
+        });
+        spawn::new_task_builder(executor_loop, inner.clone())
+            .name(name)
+            .spawn()?
+            .detach();
+        Ok(Executor { inner })
+    }
+
+    /// Spawns the given `future` onto this executor, which will poll it until it completes.
+    pub fn spawn<F>(&self, future: F) where F: Future<Output = ()> + Send + 'static {
+        let task = Arc::new(FutureTask {
+            future: Mutex::new(Some(Box::pin(future))),
+            executor: Arc::downgrade(&self.inner),
+            queued: AtomicBool::new(false),
+        });
+        {
+            let mut ready = self.inner.ready.lock();
+            ready.slots += 1;
+            let additional = ready.slots.saturating_sub(ready.futures.len());
+            ready.futures.reserve(additional);
+        }
+        self.inner.spawned.lock().push(task.clone());
+        self.inner.pending.fetch_add(1, Ordering::SeqCst);
+        // Every future is polled at least once, which is when it registers its waker with whatever it's waiting on.
+        FutureTask::wake(&task);
+    }
+
+    /// Returns the number of futures spawned onto this executor that haven't completed yet.
+    pub fn pending(&self) -> usize {
+        self.inner.pending.load(Ordering::SeqCst)
+    }
+}
+
+impl Clone for Executor {
+    fn clone(&self) -> Executor {
+        self.inner.handles.fetch_add(1, Ordering::SeqCst);
+        Executor { inner: self.inner.clone() }
+    }
+}
+
+impl Drop for Executor {
+    fn drop(&mut self) {
+        if self.inner.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            // Wake up the executor's task such that it can exit if it has no more pending futures.
+            self.inner.wait_queue.notify_one();
+        }
+    }
+}
+
+impl fmt::Debug for Executor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Executor {{ name: {:?}, pending: {} }}", self.inner.name, self.pending())
+    }
+}
+
+
+/// The entry point of an executor's `Task`, which polls futures as they become ready.
+fn executor_loop(inner: Arc<ExecutorInner>) -> Result<(), &'static str> {
+    loop {
+        let next = inner.wait_queue.wait_until(&|| {
+            if let Some(task) = inner.ready.lock().futures.pop_front() {
+                Some(Some(task))
+            } else if inner.handles.load(Ordering::SeqCst) == 0 && inner.pending.load(Ordering::SeqCst) == 0 {
+                Some(None)
+            } else {
+                None
+            }
+        }).map_err(|e| {
+            error!("async_executor {:?}: failed to wait for ready futures: {:?}", inner.name, e);
+            "async_executor: failed to wait for ready futures"
+        })?;
+
+        let task = match next {
+            Some(task) => task,
+            None => return Ok(()),
+        };
+
+        let mut slot = task.future.lock();
+        if slot.is_none() {
+            // The future was woken up right before it completed, see below.
+            inner.ready.lock().slots -= 1;
+            continue;
+        }
+        // Clear the flag before polling, such that a wakeup that occurs during the poll queues the future again.
+        task.queued.store(false, Ordering::SeqCst);
+        let completed = {
+            let waker = FutureTask::waker(task.clone());
+            let mut cx = Context::from_waker(&waker);
+            slot.as_mut().map_or(false, |future| future.as_mut().poll(&mut cx).is_ready())
+        };
+        if !completed {
+            continue;
+        }
+        *slot = None;
+        drop(slot);
+        // Keep the future from being queued again. If it's already queued, its slot is released once it's popped.
+        if !task.queued.swap(true, Ordering::SeqCst) {
+            inner.ready.lock().slots -= 1;
+        }
+        inner.pending.fetch_sub(1, Ordering::SeqCst);
+        drop(task);
+        // Drop the completed futures that no `Waker` refers to anymore.
+        inner.spawned.lock().retain(|t| Arc::strong_count(t) > 1 || t.future.lock().is_some());
+    }
+}
+
+
+impl FutureTask {
+    /// Puts this future on its executor's ready queue, unless it's already there,
+    /// and unblocks the executor's `Task`.
+    ///
+    /// This may be invoked in interrupt context, so it must not block.
+    fn wake(task: &Arc<FutureTask>) {
+        if task.queued.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some(executor) = task.executor.upgrade() {
+            // This never reallocates, because `Executor::spawn()` reserved a slot for this future.
+            executor.ready.lock().futures.push_back(task.clone());
+            executor.wait_queue.notify_one();
+        }
+    }
+
+    /// Creates a `Waker` that wakes up the given future.
+    fn waker(task: Arc<FutureTask>) -> Waker {
+        unsafe { Waker::from_raw(RawWaker::new(Arc::into_raw(task) as *const (), &WAKER_VTABLE)) }
+    }
+}
+
+/// The functions that implement a `Waker` for a `FutureTask`,
+/// whose data pointer is a strong reference to the `FutureTask` obtained from `Arc::into_raw()`.
+static WAKER_VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake_by_ref, waker_drop);
+
+unsafe fn waker_clone(ptr: *const ()) -> RawWaker {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const FutureTask));
+    let clone: Arc<FutureTask> = Arc::clone(&task);
+    RawWaker::new(Arc::into_raw(clone) as *const (), &WAKER_VTABLE)
+}
+
+unsafe fn waker_wake(ptr: *const ()) {
+    let task = Arc::from_raw(ptr as *const FutureTask);
+    FutureTask::wake(&task);
+}
+
+unsafe fn waker_wake_by_ref(ptr: *const ()) {
+    let task = ManuallyDrop::new(Arc::from_raw(ptr as *const FutureTask));
+    FutureTask::wake(&task);
+}
+
+unsafe fn waker_drop(ptr: *const ()) {
+    drop(Arc::from_raw(ptr as *const FutureTask));
+}
+
+
+/// Tracks the registration of a future's `Waker` on a `WaitQueue`,
+/// such that the future can re-register when it's polled with a different waker
+/// and unregister once it's done waiting or dropped.
+struct WakerRegistration {
+    waker: Option<Waker>,
+}
+
+impl WakerRegistration {
+    fn new() -> WakerRegistration {
+        WakerRegistration { waker: None }
+    }
+
+    /// Registers the given `waker` on the given `queue`, replacing a previously registered waker.
+    ///
+    /// The waker is registered again even if it's unchanged, because a notification removes it from the queue.
+    fn register(&mut self, queue: &WaitQueue, waker: &Waker) {
+        match self.waker {
+            Some(ref old) if old.will_wake(waker) => { }
+            _ => {
+                self.unregister(queue);
+                self.waker = Some(waker.clone());
+            }
+        }
+        queue.register_waker(waker);
+    }
+
+    /// Removes the registered waker, if any, from the given `queue`.
+    fn unregister(&mut self, queue: &WaitQueue) {
+        if let Some(old) = self.waker.take() {
+            queue.unregister_waker(&old);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! A future that completes once a network interface card has received a frame.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use irq_safety::MutexIrqSafe;
+use kernel_config::time::CONFIG_TIMESLICE_PERIOD_MICROSECONDS;
+use nic_buffers::ReceivedFrame;
+use network_interface_card::NetworkInterfaceCard;
+use timer::{self, Sleep};
+use WakerRegistration;
+
+
+/// Returns a future that completes with the next frame received by the given `nic`.
+///
+/// If the NIC provides a [`receive_wait_queue`](../../network_interface_card/trait.NetworkInterfaceCard.html#method.receive_wait_queue),
+/// the future is woken up when frames are received.
+/// Otherwise, the NIC is polled again every timeslice until it has received a frame.
+pub fn receive_frame<N>(nic: &'static MutexIrqSafe<N>) -> ReceiveFrame<N>
+    where N: NetworkInterfaceCard + ?Sized + 'static
+{
+    ReceiveFrame {
+        nic,
+        registration: WakerRegistration::new(),
+        poll_timer: None,
+    }
+}
+
+/// A future that completes once a NIC has received a frame, see [`receive_frame`](fn.receive_frame.html).
+pub struct ReceiveFrame<N: NetworkInterfaceCard + ?Sized + 'static> {
+    nic: &'static MutexIrqSafe<N>,
+    registration: WakerRegistration,
+    /// Used to poll NICs that don't notify a waitqueue when frames are received.
+    poll_timer: Option<Sleep>,
+}
+
+impl<N: NetworkInterfaceCard + ?Sized + 'static> ReceiveFrame<N> {
+    fn unregister(&mut self) {
+        if let Some(wq) = self.nic.lock().receive_wait_queue() {
+            self.registration.unregister(wq);
+        }
+        self.poll_timer = None;
+    }
+}
+
+impl<N: NetworkInterfaceCard + ?Sized + 'static> Future for ReceiveFrame<N> {
+    type Output = Result<ReceivedFrame, &'static str>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let wait_queue = {
+            let mut nic = this.nic.lock();
+            if let Some(frame) = nic.get_received_frame() {
+                drop(nic);
+                this.unregister();
+                return Poll::Ready(Ok(frame));
+            }
+            nic.receive_wait_queue()
+        };
+
+        match wait_queue {
+            Some(wq) => {
+                // Register before checking again, such that a frame received in between isn't missed.
+                this.registration.register(wq, cx.waker());
+                let frame = this.nic.lock().get_received_frame();
+                if let Some(frame) = frame {
+                    this.unregister();
+                    return Poll::Ready(Ok(frame));
+                }
+            }
+            None => {
+                let frame = {
+                    let mut nic = this.nic.lock();
+                    if let Err(e) = nic.poll_receive() {
+                        return Poll::Ready(Err(e));
+                    }
+                    nic.get_received_frame()
+                };
+                if let Some(frame) = frame {
+                    this.unregister();
+                    return Poll::Ready(Ok(frame));
+                }
+                // Poll the NIC again after one timeslice.
+                let mut poll_timer = timer::sleep(Duration::from_micros(CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u64));
+                // A new timer is always pending, so this only arms it with the current waker.
+                let _ = Pin::new(&mut poll_timer).poll(cx);
+                this.poll_timer = Some(poll_timer);
+            }
+        }
+        Poll::Pending
+    }
+}
+
+impl<N: NetworkInterfaceCard + ?Sized + 'static> Drop for ReceiveFrame<N> {
+    fn drop(&mut self) {
+        self.unregister();
+    }
+}
@@ -0,0 +1,71 @@
+//! Futures that complete after a given duration or at a given deadline, backed by the `sleep` crate's timers.
+
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+use sleep::{Instant, TimerHandle, add_oneshot_timer};
+
+
+/// Returns a future that completes once the given `duration` has elapsed,
+/// which is the async version of `sleep::sleep()`.
+pub fn sleep(duration: Duration) -> Sleep {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Returns a future that completes at the given `deadline`.
+pub fn sleep_until(deadline: Instant) -> Sleep {
+    Sleep { deadline, timer: None }
+}
+
+/// A future that completes at a given deadline, see [`sleep`](fn.sleep.html).
+pub struct Sleep {
+    deadline: Instant,
+    /// The timer that wakes up this future, along with the waker that it wakes up.
+    timer: Option<(TimerHandle, Waker)>,
+}
+
+impl Sleep {
+    /// Returns the instant at which this future completes.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    fn cancel_timer(&mut self) {
+        if let Some((handle, _waker)) = self.timer.take() {
+            handle.cancel();
+        }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = self.get_mut();
+        if Instant::now() >= this.deadline {
+            this.cancel_timer();
+            return Poll::Ready(());
+        }
+        let rearm = match this.timer {
+            Some((_, ref waker)) => !waker.will_wake(cx.waker()),
+            None => true,
+        };
+        if rearm {
+            this.cancel_timer();
+            let waker = cx.waker().clone();
+            // The callback runs in interrupt context, which is fine because waking up a future doesn't block.
+            let handle = add_oneshot_timer(this.deadline, move || waker.wake_by_ref());
+            this.timer = Some((handle, cx.waker().clone()));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        self.cancel_timer();
+    }
+}
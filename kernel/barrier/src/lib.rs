@@ -0,0 +1,83 @@
+//! A barrier that lets multiple tasks wait until all of them have reached the same point.
+
+#![no_std]
+
+extern crate irq_safety;
+extern crate wait_queue;
+
+use core::fmt;
+use irq_safety::MutexIrqSafe;
+use wait_queue::{WaitQueue, WaitError};
+
+
+/// A barrier that puts each `Task` that reaches it to sleep until a given number of `Task`s have reached it,
+/// at which point all of them are woken up and continue.
+///
+/// A barrier can be reused: once all `Task`s have been released, the next ones that reach it wait again.
+///
+/// This can be shared across multiple `Task`s by wrapping it in an `Arc`.
+pub struct Barrier {
+    queue: WaitQueue,
+    /// This is locked within the waitqueue's wait condition, during which interrupts are disabled,
+    /// so it must not be held by a `Task` that could be preempted.
+    state: MutexIrqSafe<BarrierState>,
+    num_tasks: usize,
+}
+
+struct BarrierState {
+    /// The number of `Task`s that have reached the barrier in the current generation.
+    count: usize,
+    /// Incremented every time the barrier releases all of its waiting `Task`s.
+    generation: usize,
+}
+
+/// The result of waiting on a `Barrier`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BarrierWaitResult(bool);
+
+impl BarrierWaitResult {
+    /// Returns `true` if the `Task` that received this result was the last one to reach the barrier,
+    /// i.e., the one that released all others. Exactly one `Task` per generation is the leader.
+    pub fn is_leader(&self) -> bool {
+        self.0
+    }
+}
+
+impl Barrier {
+    /// Creates a new barrier that releases the waiting `Task`s once `num_tasks` of them have reached it.
+    ///
+    /// A barrier for zero or one `Task`s never blocks.
+    pub fn new(num_tasks: usize) -> Barrier {
+        Barrier {
+            queue: WaitQueue::new(),
+            state: MutexIrqSafe::new(BarrierState { count: 0, generation: 0 }),
+            num_tasks,
+        }
+    }
+
+    /// Blocks until all `num_tasks` `Task`s have reached this barrier.
+    pub fn wait(&self) -> Result<BarrierWaitResult, WaitError> {
+        let generation = {
+            let mut state = self.state.lock();
+            state.count += 1;
+            if state.count >= self.num_tasks {
+                state.count = 0;
+                state.generation = state.generation.wrapping_add(1);
+                drop(state);
+                self.queue.notify_all();
+                return Ok(BarrierWaitResult(true));
+            }
+            state.generation
+        };
+        // The generation changes once the last `Task` reaches the barrier,
+        // which may even happen before this `Task` starts waiting.
+        self.queue.wait_until(&|| if self.state.lock().generation != generation { Some(()) } else { None })?;
+        Ok(BarrierWaitResult(false))
+    }
+}
+
+impl fmt::Debug for Barrier {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Barrier {{ num_tasks: {} }}", self.num_tasks)
+    }
+}
@@ -45,7 +45,9 @@ extern crate exceptions_full;
 extern crate network_manager;
 extern crate window_manager;
 extern crate multiple_heaps;
+extern crate load_balancer;
 #[cfg(simd_personality)] extern crate simd_personality;
+#[cfg(log_to_udp)] extern crate udp_logger;
 
 
 
@@ -122,6 +124,12 @@ pub fn init(
     multiple_heaps::switch_to_multiple_heaps()?;
     info!("Initialized per-core heaps");
 
+    // start buffering log records to be streamed over UDP once the network device is initialized below
+    #[cfg(log_to_udp)]
+    {
+        udp_logger::init(udp_logger::DEFAULT_DESTINATION)?;
+    }
+
     // initialize window manager.
     let (key_producer, mouse_producer) = window_manager::init()?;
 
@@ -129,6 +137,9 @@ pub fn init(
     device_manager::init(key_producer, mouse_producer)?;
     task_fs::init()?;
 
+    // start periodically balancing the load across all cores, now that they have all been booted up
+    load_balancer::init()?;
+
 
     // Before we start running applications, we need to unmap the identity-mapped section of the kernel's page tables, at PML4[0].
     // Unmap the kernel's original identity mapping (including multiboot2 boot_info) to clear the way for userspace mappings, 
@@ -0,0 +1,112 @@
+//! A condition variable that is used together with a `MutexSleep`.
+
+#![no_std]
+
+extern crate wait_queue;
+extern crate mutex_sleep;
+extern crate sleep;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use wait_queue::{WaitQueue, WaitError};
+use mutex_sleep::MutexSleepGuard;
+use sleep::Instant;
+
+
+/// A condition variable that puts a `Task` to sleep until another `Task` notifies it,
+/// while atomically releasing the `MutexSleep` that protects the shared state being waited on.
+///
+/// Like condition variables in general, a waiting `Task` may wake up spuriously,
+/// so callers should check their condition in a loop, or use [`wait_while`](#method.wait_while).
+///
+/// This can be shared across multiple `Task`s by wrapping it in an `Arc`.
+pub struct Condvar {
+    queue: WaitQueue,
+    /// Incremented upon every notification, such that a `Task` that starts waiting
+    /// can tell whether it was notified in between releasing the lock and going to sleep.
+    notifications: AtomicUsize,
+}
+
+impl Condvar {
+    /// Creates a new condition variable that no `Task` is waiting on.
+    pub fn new() -> Condvar {
+        Condvar {
+            queue: WaitQueue::new(),
+            notifications: AtomicUsize::new(0),
+        }
+    }
+
+    /// Releases the lock held by the given `guard` and blocks until this condition variable is notified,
+    /// after which the lock is re-acquired and a new guard for it is returned.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexSleepGuard<'a, T>) -> Result<MutexSleepGuard<'a, T>, &'static str> {
+        let (guard, _timed_out) = self.wait_internal(guard, None)?;
+        Ok(guard)
+    }
+
+    /// Similar to [`wait`](#method.wait), but gives up waiting if this condition variable
+    /// hasn't been notified within the given `timeout`.
+    ///
+    /// The lock is re-acquired in either case. The returned bool is `true` if the wait timed out.
+    pub fn wait_timeout<'a, T: ?Sized>(&self, guard: MutexSleepGuard<'a, T>, timeout: Duration) -> Result<(MutexSleepGuard<'a, T>, bool), &'static str> {
+        self.wait_internal(guard, Some(Instant::now() + timeout))
+    }
+
+    /// Blocks until the given `condition` returns `false` for the data protected by the given `guard`,
+    /// waiting on this condition variable as long as it returns `true`.
+    pub fn wait_while<'a, T: ?Sized, F>(&self, mut guard: MutexSleepGuard<'a, T>, mut condition: F) -> Result<MutexSleepGuard<'a, T>, &'static str>
+        where F: FnMut(&mut T) -> bool
+    {
+        while condition(&mut *guard) {
+            guard = self.wait(guard)?;
+        }
+        Ok(guard)
+    }
+
+    /// Wakes up one `Task` that is waiting on this condition variable.
+    /// Returns `true` if a `Task` was woken up.
+    pub fn notify_one(&self) -> bool {
+        self.notifications.fetch_add(1, Ordering::SeqCst);
+        self.queue.notify_one()
+    }
+
+    /// Wakes up all `Task`s that are waiting on this condition variable.
+    /// Returns the number of `Task`s that were woken up.
+    pub fn notify_all(&self) -> usize {
+        self.notifications.fetch_add(1, Ordering::SeqCst);
+        self.queue.notify_all()
+    }
+
+    /// The internal routine for waiting on this condition variable, optionally until the given `deadline`.
+    fn wait_internal<'a, T: ?Sized>(&self, guard: MutexSleepGuard<'a, T>, deadline: Option<Instant>) -> Result<(MutexSleepGuard<'a, T>, bool), &'static str> {
+        let mutex = MutexSleepGuard::mutex(&guard);
+        // This must be read before the lock is released, such that a notification
+        // that occurs right after releasing it is not missed.
+        let notifications = self.notifications.load(Ordering::SeqCst);
+        drop(guard);
+
+        let notified = || if self.notifications.load(Ordering::SeqCst) != notifications { Some(()) } else { None };
+        let result = match deadline {
+            Some(deadline) => self.queue.wait_until_deadline(&notified, deadline),
+            None => self.queue.wait_until(&notified),
+        };
+        let timed_out = match result {
+            Ok(()) => false,
+            Err(WaitError::Timeout) => true,
+            Err(_) => return Err("failed to add current task to waitqueue"),
+        };
+        Ok((mutex.lock()?, timed_out))
+    }
+}
+
+impl fmt::Debug for Condvar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Condvar {{ .. }}")
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Condvar {
+        Condvar::new()
+    }
+}
@@ -455,14 +455,16 @@ impl LoadedCrate {
                     new_rodata_pages_locked.as_ref().and_then(|rp| rp.address_at_offset(new_sec_mapped_pages_offset)),
                 ),
                 SectionType::Data |
-                SectionType::Bss => (
+                SectionType::Bss |
+                SectionType::TlsData |
+                SectionType::TlsBss => (
                     new_data_pages_ref.clone().ok_or_else(|| "BUG: missing data pages in newly-copied crate")?,
                     new_data_pages_locked.as_ref().and_then(|dp| dp.address_at_offset(new_sec_mapped_pages_offset)),
                 ),
             };
             let new_sec_virt_addr = new_sec_virt_addr.ok_or_else(|| "BUG: couldn't get virt_addr for new section")?;
 
-            let new_sec = Arc::new(LoadedSection::with_dependencies(
+            let mut new_sec = LoadedSection::with_dependencies(
                 old_sec.typ,                            // section type is the same
                 old_sec.name.clone(),                   // name is the same
                 new_sec_mapped_pages_ref,               // mapped_pages is different, points to the new duplicated one
@@ -474,9 +476,10 @@ impl LoadedCrate {
                 old_sec_inner.sections_i_depend_on.clone(),   // dependencies are the same, but relocations need to be re-written
                 Vec::new(),                             // no sections can possibly depend on this one, since we just created it
                 old_sec_inner.internal_dependencies.clone()   // internal dependencies are the same, but relocations need to be re-written
-            ));
+            );
+            new_sec.tls_offset = old_sec.tls_offset;    // TLS offset is the same, so the copied crate uses the same thread-local variables
 
-            new_sections.insert(*shndx, new_sec);
+            new_sections.insert(*shndx, Arc::new(new_sec));
         }
 
 
@@ -491,7 +494,9 @@ impl LoadedCrate {
                 SectionType::GccExceptTable | 
                 SectionType::EhFrame => new_rodata_pages_locked.as_mut().ok_or_else(|| "BUG: missing rodata pages in newly-copied crate")?,
                 SectionType::Data |
-                SectionType::Bss     => new_data_pages_locked.as_mut().ok_or_else(|| "BUG: missing data pages in newly-copied crate")?,
+                SectionType::Bss |
+                SectionType::TlsData |
+                SectionType::TlsBss  => new_data_pages_locked.as_mut().ok_or_else(|| "BUG: missing data pages in newly-copied crate")?,
             };
             let new_sec_mapped_pages_offset = new_sec.mapped_pages_offset;
 
@@ -507,7 +512,7 @@ impl LoadedCrate {
                         strong_dep.relocation, 
                         new_sec_mapped_pages, 
                         new_sec_mapped_pages_offset,
-                        source_sec.start_address(),
+                        source_sec.relocation_address(&strong_dep.relocation),
                         true
                     )?;
 
@@ -533,10 +538,10 @@ impl LoadedCrate {
                 // to ensure that we don't cause deadlock by trying to lock the same section twice.
                 let source_sec_vaddr = if Arc::ptr_eq(source_sec, new_sec) {
                     // here: the source_sec and new_sec are the same, so just use the already-locked new_sec
-                    new_sec.start_address()
+                    new_sec.relocation_address(&internal_dep.relocation)
                 } else {
                     // here: the source_sec and new_sec are different, so we can go ahead and safely lock the source_sec
-                    source_sec.start_address()
+                    source_sec.relocation_address(&internal_dep.relocation)
                 };
                 write_relocation(
                     internal_dep.relocation, 
@@ -595,6 +600,13 @@ pub enum SectionType {
     /// Some documentation here: <https://gcc.gnu.org/wiki/Dwarf2EHNewbiesHowto>
     /// 
     EhFrame,
+    /// A ".tdata" section holds the initial contents of thread-local variables.
+    /// The loaded section itself is only a template that is copied into each new task's TLS area;
+    /// code accesses its own task's copy relative to the thread pointer (the FS base).
+    TlsData,
+    /// A ".tbss" section holds zero-initialized thread-local variables,
+    /// which, like `TlsData`, are instantiated in each new task's TLS area.
+    TlsBss,
 }
 impl SectionType {
     /// Returns `true` if `Data` or `Bss`, otherwise `false`.
@@ -604,6 +616,14 @@ impl SectionType {
             _ => false,
         }
     }
+
+    /// Returns `true` if `TlsData` or `TlsBss`, otherwise `false`.
+    pub fn is_tls(&self) -> bool {
+        match self {
+            Self::TlsData | Self::TlsBss => true,
+            _ => false,
+        }
+    }
 }
 
 /// The parts of a `LoadedSection` that may be mutable, i.e., 
@@ -657,6 +677,9 @@ pub struct LoadedSection {
     pub address_range: Range<VirtualAddress>, 
     /// The `LoadedCrate` object that contains/owns this section
     pub parent_crate: WeakCrateRef,
+    /// For TLS sections only, the offset of this section below the thread pointer
+    /// in every task's TLS area, i.e., this section's copy starts at `thread_pointer - tls_offset`.
+    pub tls_offset: Option<usize>,
     /// The inner contents of a section that could possibly change
     /// after the section was initially loaded and linked. 
     pub inner: RwLock<LoadedSectionInner>,
@@ -712,6 +735,7 @@ impl LoadedSection {
             address_range: virt_addr .. (virt_addr + size),
             global,
             parent_crate,
+            tls_offset: None,
             inner: RwLock::new(LoadedSectionInner {
                 sections_i_depend_on,
                 sections_dependent_on_me,
@@ -726,6 +750,18 @@ impl LoadedSection {
         self.address_range.start
     }
 
+    /// Returns the address of this section that the given relocation should be calculated from.
+    /// 
+    /// This is the section's starting address, except for thread-pointer-relative relocations
+    /// against a TLS section, for which it's the (negative) offset of the section from the thread pointer.
+    pub fn relocation_address(&self, relocation: &RelocationEntry) -> VirtualAddress {
+        match (relocation.typ, self.tls_offset) {
+            (R_X86_64_TPOFF32, Some(tls_offset)) |
+            (R_X86_64_TPOFF64, Some(tls_offset)) => VirtualAddress::new_canonical(0usize.wrapping_sub(tls_offset)),
+            _ => self.start_address(),
+        }
+    }
+
     /// Returns the size in bytes of this section.
     pub fn size(&self) -> usize {
         self.address_range.end.value() - self.address_range.start.value()
@@ -916,6 +952,8 @@ impl InternalDependency {
 /// * `target_sec_mapped_pages`: the `MappedPages` that covers the target section, i.e., the section where the relocation data will be written to.
 /// * `target_sec_mapped_pages_offset`: the offset into `target_sec_mapped_pages` where the target section is located.
 /// * `source_sec_vaddr`: the `VirtualAddress` of the source section of the relocation, i.e., the section that the `target_sec` depends on and "points" to.
+///    This should be obtained from [`LoadedSection::relocation_address()`](struct.LoadedSection.html#method.relocation_address),
+///    which handles thread-local sections.
 /// * `verbose_log`: whether to output verbose logging information about this relocation action.
 pub fn write_relocation(
     relocation_entry: RelocationEntry,
@@ -956,6 +994,20 @@ pub fn write_relocation(
             if verbose_log { trace!("                    target_ptr: {:#X}, source_val: {:#X} (from source_sec_vaddr {:#X})", target_ref as *mut _ as usize, source_val, source_sec_vaddr); }
             *target_ref = source_val as u64;
         }
+        // For thread-local variables, the `source_sec_vaddr` is already the (negative) offset from the thread pointer,
+        // as given by `LoadedSection::relocation_address()`.
+        R_X86_64_TPOFF32 => {
+            let target_ref: &mut u32 = target_sec_mapped_pages.as_type_mut(target_offset)?;
+            let source_val = source_sec_vaddr.value().wrapping_add(relocation_entry.addend);
+            if verbose_log { trace!("                    target_ptr: {:#X}, source_val: {:#X} (from TLS offset {:#X})", target_ref as *mut _ as usize, source_val, source_sec_vaddr); }
+            *target_ref = source_val as u32;
+        }
+        R_X86_64_TPOFF64 => {
+            let target_ref: &mut u64 = target_sec_mapped_pages.as_type_mut(target_offset)?;
+            let source_val = source_sec_vaddr.value().wrapping_add(relocation_entry.addend);
+            if verbose_log { trace!("                    target_ptr: {:#X}, source_val: {:#X} (from TLS offset {:#X})", target_ref as *mut _ as usize, source_val, source_sec_vaddr); }
+            *target_ref = source_val as u64;
+        }
         // R_X86_64_GOTPCREL => { 
         //     unimplemented!(); // if we stop using the large code model, we need to create a Global Offset Table
         // }
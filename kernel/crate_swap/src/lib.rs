@@ -440,7 +440,7 @@ pub fn swap_crates(
                             relocation_entry, 
                             &mut target_sec_mapped_pages, 
                             target_sec.mapped_pages_offset, 
-                            new_source_sec.start_address(), 
+                            new_source_sec.relocation_address(&relocation_entry), 
                             verbose_log
                         )?;
 
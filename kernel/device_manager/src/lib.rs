@@ -4,6 +4,7 @@
 #[macro_use] extern crate log;
 extern crate event_types;
 extern crate e1000;
+extern crate igb;
 extern crate memory;
 extern crate apic;
 extern crate acpi;
@@ -13,6 +14,8 @@ extern crate mouse;
 extern crate storage_manager;
 extern crate network_manager;
 extern crate ethernet_smoltcp_device;
+extern crate loopback;
+extern crate ipv6_autoconf;
 extern crate mpmc;
 
 
@@ -83,7 +86,28 @@ pub fn init(key_producer: Queue<Event>, mouse_producer: Queue<Event>) -> Result<
                 info!("e1000 PCI device found at: {:?}", dev.location);
                 let e1000_nic_ref = e1000::E1000Nic::init(dev)?;
                 let e1000_interface = EthernetNetworkInterface::new_ipv4_interface(e1000_nic_ref, DEFAULT_LOCAL_IP, &DEFAULT_GATEWAY_IP)?;
-                add_to_network_interfaces(e1000_interface);
+                let e1000_iface_ref = add_to_network_interfaces(e1000_interface);
+                // IPv6 is configured in the background alongside the static IPv4 address.
+                if let Err(e) = ipv6_autoconf::start(e1000_iface_ref) {
+                    error!("Failed to start IPv6 autoconfiguration for the e1000 interface: {}", e);
+                }
+                continue;
+            }
+            if dev.vendor_id == igb::INTEL_VEND && dev.device_id == igb::IGB_DEV {
+                info!("igb PCI device found at: {:?}", dev.location);
+                // only one igb device is supported, so any further ones are skipped rather than failing device initialization
+                let igb_nic_ref = match igb::IgbNic::init(dev) {
+                    Ok(nic_ref) => nic_ref,
+                    Err(e) => {
+                        error!("Failed to initialize igb device at {:?}: {}", dev.location, e);
+                        continue;
+                    }
+                };
+                let igb_interface = EthernetNetworkInterface::new_ipv4_interface(igb_nic_ref, DEFAULT_LOCAL_IP, &DEFAULT_GATEWAY_IP)?;
+                let igb_iface_ref = add_to_network_interfaces(igb_interface);
+                if let Err(e) = ipv6_autoconf::start(igb_iface_ref) {
+                    error!("Failed to start IPv6 autoconfiguration for the igb interface: {}", e);
+                }
                 continue;
             }
             // here: check for and initialize other ethernet cards
@@ -97,5 +121,10 @@ pub fn init(key_producer: Queue<Event>, mouse_producer: Queue<Event>) -> Result<
         warn!("Note: no network devices found on this system.");
     }
 
+    // The loopback interface is added last, such that a real network device remains the default interface.
+    if let Err(e) = loopback::init() {
+        error!("Failed to initialize the loopback network interface: {}", e);
+    }
+
     Ok(())
 }
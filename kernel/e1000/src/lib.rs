@@ -27,33 +27,39 @@ extern crate intel_ethernet;
 extern crate nic_buffers;
 extern crate nic_queues;
 extern crate nic_initialization;
+extern crate wait_queue;
+extern crate spawn;
+extern crate scheduler;
 
 pub mod test_e1000_driver;
 mod regs;
 
 
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use spin::Once; 
 use alloc::vec::Vec;
 use alloc::collections::VecDeque;
+use alloc::string::ToString;
 use irq_safety::MutexIrqSafe;
 use volatile::{Volatile, ReadOnly};
 use zerocopy::FromBytes;
 use alloc::boxed::Box;
-use memory::{PhysicalAddress, MappedPages, create_contiguous_mapping};
+use memory::{PhysicalAddress, MappedPages};
 use pci::{PciDevice, PCI_INTERRUPT_LINE, PciConfigSpaceAccessMechanism};
 use kernel_config::memory::PAGE_SIZE;
 use owning_ref::BoxRefMut;
 use interrupts::{eoi,register_interrupt};
 use x86_64::structures::idt::{ExceptionStackFrame};
 use network_interface_card:: NetworkInterfaceCard;
-use nic_initialization::{NIC_MAPPING_FLAGS, allocate_device_register_memory, init_rx_buf_pool, init_rx_queue, init_tx_queue};
+use nic_initialization::{allocate_device_register_memory, init_rx_buf_pool, init_rx_queue, init_tx_queue};
 use intel_ethernet::{
-    descriptors::{TxDescriptor, RxDescriptor, LegacyRxDescriptor, LegacyTxDescriptor},
+    descriptors::{TxDescriptor, LegacyRxDescriptor, LegacyTxDescriptor},
     types::*
 };
 use nic_buffers::{TransmitBuffer, ReceiveBuffer, ReceivedFrame};
 use nic_queues::{RxQueue, TxQueue};
 use apic::get_my_apic_id;
+use wait_queue::WaitQueue;
 
 pub const INTEL_VEND:           u16 = 0x8086;  // Vendor ID for Intel 
 pub const E1000_DEV:            u16 = 0x100E;  // Device ID for the e1000 Qemu, Bochs, and VirtualBox emmulated NICs
@@ -89,6 +95,74 @@ lazy_static! {
     static ref RX_BUFFER_POOL: mpmc::Queue<ReceiveBuffer> = mpmc::Queue::with_capacity(RX_BUFFER_POOL_SIZE);
 }
 
+
+/// The default maximum number of frames that are removed from the receive queue in one go,
+/// either in the interrupt handler or in one poll by the receive polling task.
+pub const DEFAULT_RX_POLL_BUDGET: usize = E1000_NUM_RX_DESC / 2;
+
+/// The current maximum number of frames removed from the receive queue in one go, see [`set_rx_poll_budget()`](fn.set_rx_poll_budget.html).
+static RX_POLL_BUDGET: AtomicUsize = AtomicUsize::new(DEFAULT_RX_POLL_BUDGET);
+
+/// Whether the NIC is in polling mode, in which receive interrupts are masked 
+/// and the receive polling task removes frames from the receive queue instead of the interrupt handler.
+static RX_POLLING_MODE: AtomicBool = AtomicBool::new(false);
+
+static RX_INTERRUPTS:           AtomicUsize = AtomicUsize::new(0);
+static RX_POLLS:                AtomicUsize = AtomicUsize::new(0);
+static RX_FRAMES:               AtomicUsize = AtomicUsize::new(0);
+static RX_SWITCHES_TO_POLLING:  AtomicUsize = AtomicUsize::new(0);
+static RX_SWITCHES_TO_INTERRUPT: AtomicUsize = AtomicUsize::new(0);
+
+lazy_static! {
+    /// The queue on which the receive polling task waits until the NIC switches into polling mode.
+    static ref RX_POLL_WAIT_QUEUE: WaitQueue = WaitQueue::new();
+    /// The queue that is notified whenever received frames are made available through `get_received_frame()`.
+    static ref RX_FRAMES_WAIT_QUEUE: WaitQueue = WaitQueue::new();
+}
+
+
+/// Statistics about how received frames were handled, which are useful for tuning the receive poll budget.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RxStats {
+    /// The number of receive interrupts handled.
+    pub interrupts: usize,
+    /// The number of times the receive polling task polled the receive queue.
+    pub polls: usize,
+    /// The total number of frames removed from the receive queue.
+    pub frames: usize,
+    /// The number of times the NIC switched from interrupt mode into polling mode under load.
+    pub switches_to_polling: usize,
+    /// The number of times the NIC switched from polling mode back into interrupt mode once the load subsided.
+    pub switches_to_interrupt: usize,
+    /// Whether the NIC is currently in polling mode.
+    pub polling_mode: bool,
+}
+
+/// Returns the current receive statistics of the e1000 NIC.
+pub fn rx_stats() -> RxStats {
+    RxStats {
+        interrupts:            RX_INTERRUPTS.load(Ordering::Relaxed),
+        polls:                 RX_POLLS.load(Ordering::Relaxed),
+        frames:                RX_FRAMES.load(Ordering::Relaxed),
+        switches_to_polling:   RX_SWITCHES_TO_POLLING.load(Ordering::Relaxed),
+        switches_to_interrupt: RX_SWITCHES_TO_INTERRUPT.load(Ordering::Relaxed),
+        polling_mode:          RX_POLLING_MODE.load(Ordering::SeqCst),
+    }
+}
+
+/// Sets the maximum number of frames removed from the receive queue in one go.
+/// 
+/// If a receive interrupt finds at least this many frames, the NIC switches into polling mode;
+/// once a poll finds fewer than this many frames, it switches back into interrupt mode.
+/// A smaller budget switches into polling mode sooner, and a larger budget reduces the number of polls.
+pub fn set_rx_poll_budget(budget: usize) -> Result<(), &'static str> {
+    if budget == 0 {
+        return Err("e1000: the receive poll budget must be at least one frame");
+    }
+    RX_POLL_BUDGET.store(budget, Ordering::Relaxed);
+    Ok(())
+}
+
 /// The layout in memory of e1000 registers. 
 /// 
 /// Note: the weird padding is a limitation of using the `zerocopy::FromBytes` trait,
@@ -107,7 +181,9 @@ pub struct E1000Registers {
     pub icr:                        ReadOnly<u32>,          // 0xC0   
     _padding2:                      [u8; 12],               // 0xC4 - 0xCF
     pub ims:                        Volatile<u32>,          // 0xD0
-    _padding3:                      [u8; 44],               // 0xD4 - 0xFF 
+    _padding3a:                     [u8; 4],                // 0xD4 - 0xD7
+    pub imc:                        Volatile<u32>,          // 0xD8
+    _padding3b:                     [u8; 36],               // 0xDC - 0xFF 
 
     /// Receive control register
     pub rctl:                       Volatile<u32>,          // 0x100
@@ -240,65 +316,16 @@ impl NetworkInterfaceCard for E1000Nic {
     }
 
     fn poll_receive(&mut self) -> Result<(), &'static str> {
-        let rxq = &mut self.rx_queue;
-        let rx_buffer_size = E1000_RX_BUFFER_SIZE_IN_BYTES;
-        let num_descs = E1000_NUM_RX_DESC as u16;
-        
-        let mut cur = rxq.rx_cur as usize;
-       
-        let mut receive_buffers_in_frame: Vec<ReceiveBuffer> = Vec::new();
-        let mut _total_packet_length: u16 = 0;
-
-        while rxq.rx_descs[cur].descriptor_done() {
-            // get information about the current receive buffer
-            let length = rxq.rx_descs[cur].length();
-            _total_packet_length += length as u16;
-            // debug!("remove_frames_from_queue: received descriptor of length {}", length);
-            
-            // Now that we are "removing" the current receive buffer from the list of receive buffers that the NIC can use,
-            // (because we're saving it for higher layers to use),
-            // we need to obtain a new `ReceiveBuffer` and set it up such that the NIC will use it for future receivals.
-            let new_receive_buf = match RX_BUFFER_POOL.pop() {
-                Some(rx_buf) => rx_buf,
-                None => {
-                    warn!("NIC RX BUF POOL WAS EMPTY.... reallocating! This means that no task is consuming the accumulated received ethernet frames.");
-                    // if the pool was empty, then we allocate a new receive buffer
-                    let len = rx_buffer_size;
-                    let (mp, phys_addr) = create_contiguous_mapping(len as usize, NIC_MAPPING_FLAGS)?;
-                    ReceiveBuffer::new(mp, phys_addr, len, &RX_BUFFER_POOL)
-                }
-            };
-
-            // actually tell the NIC about the new receive buffer, and that it's ready for use now
-            rxq.rx_descs[cur].set_packet_address(new_receive_buf.phys_addr);
-
-            // Swap in the new receive buffer at the index corresponding to this current rx_desc's receive buffer,
-            // getting back the receive buffer that is part of the received ethernet frame
-            rxq.rx_bufs_in_use.push(new_receive_buf);
-            let mut current_rx_buf = rxq.rx_bufs_in_use.swap_remove(cur); 
-            current_rx_buf.length = length as u16; // set the ReceiveBuffer's length to the size of the actual packet received
-            receive_buffers_in_frame.push(current_rx_buf);
-
-            // move on to the next receive buffer to see if it's ready for us to take
-            rxq.rx_cur = (cur as u16 + 1) % num_descs;
-            self.regs.rx_regs.rdt.write(cur as u32); 
-
-            if rxq.rx_descs[cur].end_of_packet() {
-                let buffers = core::mem::replace(&mut receive_buffers_in_frame, Vec::new());
-                rxq.received_frames.push_back(ReceivedFrame(buffers));
-            } else {
-                warn!("NIC::remove_frames_from_queue(): Received multi-rxbuffer frame, this scenario not fully tested!");
-            }
-            rxq.rx_descs[cur].reset_status();
-            cur = rxq.rx_cur as usize;
-        }
-
-        Ok(())     
+        self.receive_frames(usize::max_value()).map(|_| ())
     }
 
     fn mac_address(&self) -> [u8; 6] {
         self.mac_spoofed.unwrap_or(self.mac_hardware)
     }
+
+    fn receive_wait_queue(&self) -> Option<&'static WaitQueue> {
+        Some(&RX_FRAMES_WAIT_QUEUE)
+    }
 }
 
 
@@ -376,6 +403,11 @@ impl E1000Nic {
         };
         
         let nic_ref = E1000_NIC.call_once(|| MutexIrqSafe::new(e1000_nic));
+
+        spawn::new_task_builder(rx_poll_task, nic_ref)
+            .name("e1000_rx_poll".to_string())
+            .spawn()?;
+
         Ok(nic_ref)
     }
     
@@ -460,7 +492,9 @@ impl E1000Nic {
         // This doesn't prevent all of the rx buffers from being used, they will still all be used fully.
         regs.rx_regs.rdt.write((E1000_NUM_RX_DESC - 1) as u32); 
         // TODO: document these various e1000 flags and why we're setting them
-        regs.rctl.write(regs::RCTL_EN| regs::RCTL_SBP | regs::RCTL_LBM_NONE | regs::RTCL_RDMTS_HALF | regs::RCTL_BAM | regs::RCTL_SECRC  | regs::RCTL_BSIZE_2048);
+        // Multicast promiscuous mode (MPE) accepts all multicast frames without programming the multicast table,
+        // which IPv6 requires for neighbor discovery and router advertisements.
+        regs.rctl.write(regs::RCTL_EN| regs::RCTL_SBP | regs::RCTL_MPE | regs::RCTL_LBM_NONE | regs::RTCL_RDMTS_HALF | regs::RCTL_BAM | regs::RCTL_SECRC  | regs::RCTL_BSIZE_2048);
 
         Ok((rx_descs, rx_bufs_in_use))
     }           
@@ -489,12 +523,43 @@ impl E1000Nic {
         self.regs.icr.read()
     }
 
+    /// Removes up to `budget` received frames from the receive queue,
+    /// making them available through `get_received_frame()`. 
+    /// Returns the number of frames that were removed.
+    fn receive_frames(&mut self, budget: usize) -> Result<usize, &'static str> {
+        let received = self.rx_queue.remove_frames_from_queue(budget, &RX_BUFFER_POOL, E1000_RX_BUFFER_SIZE_IN_BYTES, &mut self.regs.rx_regs.rdt)?;
+        RX_FRAMES.fetch_add(received, Ordering::Relaxed);
+        if received > 0 {
+            // This is safe while the NIC lock is held, because waking up a waiting task or future doesn't acquire it.
+            RX_FRAMES_WAIT_QUEUE.notify_all();
+        }
+        Ok(received)
+    }
+
+    /// Masks receive interrupts so that the receive polling task handles received frames instead.
+    fn switch_to_polling_mode(&mut self) {
+        self.regs.imc.write(INT_RX);
+        RX_POLLING_MODE.store(true, Ordering::SeqCst);
+        RX_SWITCHES_TO_POLLING.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Unmasks receive interrupts so that the interrupt handler handles received frames again.
+    /// If frames arrived since the last poll, the NIC raises an interrupt as soon as they are unmasked.
+    fn switch_to_interrupt_mode(&mut self) {
+        RX_POLLING_MODE.store(false, Ordering::SeqCst);
+        RX_SWITCHES_TO_INTERRUPT.fetch_add(1, Ordering::Relaxed);
+        self.regs.ims.write(INT_LSC | INT_RX);
+    }
+
 
     /// The main interrupt handling routine for the e1000 NIC.
     /// This should be invoked from the actual interrupt handler entry point.
-    fn handle_interrupt(&mut self) -> Result<(), &'static str> {
+    /// 
+    /// Returns true if the NIC switched into polling mode, in which case the receive polling task must be woken up.
+    fn handle_interrupt(&mut self) -> Result<bool, &'static str> {
         let status = self.clear_interrupt_status();        
         let mut handled = false;
+        let mut start_polling = false;
 
         // a link status change
         if (status & INT_LSC) == INT_LSC {
@@ -506,7 +571,14 @@ impl E1000Nic {
         // receiver timer interrupt
         if (status & INT_RX) == INT_RX {
             // debug!("e1000::handle_interrupt(): receive interrupt");
-            self.poll_receive()?;
+            RX_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+            let budget = RX_POLL_BUDGET.load(Ordering::Relaxed);
+            // If the whole budget was used, more frames are likely arriving,
+            // so stop taking an interrupt per frame and poll for them instead.
+            if self.receive_frames(budget)? >= budget && !RX_POLLING_MODE.load(Ordering::SeqCst) {
+                self.switch_to_polling_mode();
+                start_polling = true;
+            }
             handled = true;
         }
 
@@ -514,19 +586,65 @@ impl E1000Nic {
             error!("e1000::handle_interrupt(): unhandled interrupt!  status: {:#X}", status);
         }
         //regs.icr.read(); //clear interrupt
-        Ok(())
+        Ok(start_polling)
     }
 }
 
 extern "x86-interrupt" fn e1000_handler(_stack_frame: &mut ExceptionStackFrame) {
     if let Some(ref e1000_nic_ref) = E1000_NIC.try() {
-        let mut e1000_nic = e1000_nic_ref.lock();
-        if let Err(e) = e1000_nic.handle_interrupt() {
-            error!("e1000_handler(): error handling interrupt: {:?}", e);
+        let (result, interrupt_num) = {
+            let mut e1000_nic = e1000_nic_ref.lock();
+            (e1000_nic.handle_interrupt(), e1000_nic.interrupt_num)
+        };
+        match result {
+            // The NIC lock must be released before notifying, as the polling task acquires it once woken up.
+            Ok(true) => { RX_POLL_WAIT_QUEUE.notify_one(); }
+            Ok(false) => { }
+            Err(e) => error!("e1000_handler(): error handling interrupt: {:?}", e),
         }
-        eoi(Some(e1000_nic.interrupt_num));
+        eoi(Some(interrupt_num));
     } else {
         error!("BUG: e1000_handler(): E1000 NIC hasn't yet been initialized!");
     }
 
 }
+
+
+/// The receive polling task, which sleeps on a wait queue while the NIC is in interrupt mode.
+/// 
+/// Once a receive interrupt switches the NIC into polling mode, this task repeatedly removes up to a budget of frames
+/// from the receive queue, yielding between polls, until a poll finds fewer frames than the budget.
+/// It then switches the NIC back into interrupt mode and goes back to sleep.
+/// 
+/// Errors are logged rather than ending this task, and the NIC is switched back into interrupt mode,
+/// such that receive interrupts are never left masked without a task to poll for frames.
+fn rx_poll_task(nic_ref: &'static MutexIrqSafe<E1000Nic>) -> Result<(), &'static str> {
+    loop {
+        if let Err(_e) = RX_POLL_WAIT_QUEUE.wait_until(&|| if RX_POLLING_MODE.load(Ordering::SeqCst) { Some(()) } else { None }) {
+            error!("e1000::rx_poll_task(): failed to wait on the receive polling wait queue: {:?}", _e);
+            nic_ref.lock().switch_to_interrupt_mode();
+            continue;
+        }
+
+        loop {
+            let budget = RX_POLL_BUDGET.load(Ordering::Relaxed);
+            {
+                let mut nic = nic_ref.lock();
+                RX_POLLS.fetch_add(1, Ordering::Relaxed);
+                match nic.receive_frames(budget) {
+                    Ok(received) if received < budget => {
+                        nic.switch_to_interrupt_mode();
+                        break;
+                    }
+                    Ok(_) => { }
+                    Err(e) => {
+                        error!("e1000::rx_poll_task(): error receiving frames: {}", e);
+                        nic.switch_to_interrupt_mode();
+                        break;
+                    }
+                }
+            }
+            scheduler::schedule();
+        }
+    }
+}
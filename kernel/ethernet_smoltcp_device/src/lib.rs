@@ -10,11 +10,14 @@ extern crate nic_buffers;
 extern crate irq_safety;
 extern crate owning_ref;
 extern crate network_manager;
+extern crate packet_capture;
 
 
 use alloc::{
     boxed::Box,
     collections::BTreeMap,
+    sync::Arc,
+    vec::Vec,
 };
 use irq_safety::MutexIrqSafe;
 use smoltcp::{
@@ -27,7 +30,8 @@ use smoltcp::{
 use network_interface_card::NetworkInterfaceCard;
 use nic_buffers::{TransmitBuffer, ReceivedFrame};
 use owning_ref::BoxRefMut;
-use network_manager::NetworkInterface;
+use network_manager::{NetworkInterface, InterfaceCounters, InterfaceStats};
+use packet_capture::Direction;
 use core::str::FromStr;
 
 /// standard MTU for ethernet cards
@@ -38,6 +42,8 @@ const DEFAULT_MTU: usize = 1500;
 /// There should be one instance of this struct per interface, i.e., an Ethernet port on the NIC.
 pub struct EthernetNetworkInterface<N: NetworkInterfaceCard + 'static> {
     pub iface: EthernetInterface<'static, 'static, 'static, EthernetDevice<N>>,
+    /// The traffic counters of this interface, which are shared with its `EthernetDevice`.
+    counters: Arc<InterfaceCounters>,
 }
 
 impl<N: NetworkInterfaceCard + 'static> NetworkInterface for EthernetNetworkInterface<N> { 
@@ -68,6 +74,22 @@ impl<N: NetworkInterfaceCard + 'static> NetworkInterface for EthernetNetworkInte
     fn routes_mut(&mut self) -> &mut Routes<'static> {
         self.iface.routes_mut()
     }
+
+    fn add_ip_addr(&mut self, cidr: IpCidr) -> Result<(), &'static str> {
+        let new_addrs = network_manager::ip_addrs_with(self.iface.ip_addrs(), cidr)?;
+        self.iface.update_ip_addrs(|addrs| *addrs = new_addrs.into());
+        Ok(())
+    }
+
+    fn remove_ip_addr(&mut self, cidr: IpCidr) -> Result<(), &'static str> {
+        let new_addrs = network_manager::ip_addrs_without(self.iface.ip_addrs(), cidr)?;
+        self.iface.update_ip_addrs(|addrs| *addrs = new_addrs.into());
+        Ok(())
+    }
+
+    fn stats(&self) -> InterfaceStats {
+        self.counters.snapshot()
+    }
 }
 
 impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
@@ -104,7 +126,17 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
             "couldn't set default gateway IP address"
         })?;
 
-        let device = EthernetDevice::new(nic);
+        Ok(Self::build(nic, ip_addrs, routes))
+    }
+
+    /// Creates the smoltcp interface for the given `nic` with the given IP addresses and routes.
+    fn build(
+        nic: &'static MutexIrqSafe<N>,
+        ip_addrs: Vec<IpCidr>,
+        routes: Routes<'static>,
+    ) -> EthernetNetworkInterface<N> {
+        let counters = Arc::new(InterfaceCounters::new());
+        let device = EthernetDevice::new(nic, counters.clone());
         let hardware_mac_addr = EthernetAddress(nic.lock().mac_address());
         // When creating an EthernetInterface, only the `ethernet_addr` and `neighbor_cache` are required.
         let iface = EthernetInterfaceBuilder::new(device)
@@ -114,9 +146,7 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
             .routes(routes)
             .finalize();
 
-        Ok(
-            EthernetNetworkInterface { iface }
-        )
+        EthernetNetworkInterface { iface, counters }
     }
 
     /// Creates a new ethernet network interface with an ipv4 gateway address.
@@ -145,12 +175,15 @@ impl<N: NetworkInterfaceCard + 'static > EthernetNetworkInterface<N> {
 /// An instance of this `EthernetDevice` can be used in smoltcp's `EthernetInterface`.
 pub struct EthernetDevice<N: NetworkInterfaceCard + 'static> { 
     nic_ref: &'static MutexIrqSafe<N>,
+    counters: Arc<InterfaceCounters>,
 }
 impl<N: NetworkInterfaceCard + 'static> EthernetDevice<N> {
-    /// Create a new instance of the `EthernetDevice`.
-    pub fn new(nic_ref: &'static MutexIrqSafe<N>) -> EthernetDevice<N> {
+    /// Create a new instance of the `EthernetDevice`, 
+    /// which records the frames it sends and receives in the given `counters`.
+    pub fn new(nic_ref: &'static MutexIrqSafe<N>, counters: Arc<InterfaceCounters>) -> EthernetDevice<N> {
         EthernetDevice {
             nic_ref: nic_ref,
+            counters: counters,
         }
     }
 }
@@ -179,8 +212,10 @@ impl<'d, N: NetworkInterfaceCard + 'static> smoltcp::phy::Device<'d> for Etherne
         // Otherwise, if no new packets have arrived, return None.
         let received_frame = {
             let mut nic = self.nic_ref.lock();
+            let counters = &self.counters;
             nic.poll_receive().map_err(|_e| {
                 error!("EthernetDevice::receive(): error returned from poll_receive(): {}", _e);
+                counters.record_rx_error();
                 _e
             }).ok()?;
             nic.get_received_frame()?
@@ -193,10 +228,12 @@ impl<'d, N: NetworkInterfaceCard + 'static> smoltcp::phy::Device<'d> for Etherne
         }
 
         let first_buf_len = received_frame.0[0].length;
+        let counters = &self.counters;
         let rxbuf_byte_slice = BoxRefMut::new(Box::new(received_frame))
             .try_map_mut(|rxframe| rxframe.0[0].as_slice_mut::<u8>(0, first_buf_len as usize))
             .map_err(|e| {
                 error!("EthernetDevice::receive(): couldn't convert receive buffer of length {} into byte slice, error {:?}", first_buf_len, e);
+                counters.record_rx_error();
                 e
             })
             .ok()?;
@@ -204,9 +241,10 @@ impl<'d, N: NetworkInterfaceCard + 'static> smoltcp::phy::Device<'d> for Etherne
         // Just create and return a pair of (receive token, transmit token), 
         // the actual rx buffer handling is done in the RxToken::consume() function
         Some((
-            RxToken(rxbuf_byte_slice),
+            RxToken(rxbuf_byte_slice, self.counters.clone()),
             TxToken {
                 nic_ref: self.nic_ref,
+                counters: self.counters.clone(),
             },
         ))
     }
@@ -218,6 +256,7 @@ impl<'d, N: NetworkInterfaceCard + 'static> smoltcp::phy::Device<'d> for Etherne
         // because we don't yet know its required length.
         Some(TxToken {
             nic_ref: self.nic_ref,
+            counters: self.counters.clone(),
         })
     }
 }
@@ -227,6 +266,7 @@ impl<'d, N: NetworkInterfaceCard + 'static> smoltcp::phy::Device<'d> for Etherne
 /// because the actual transmit buffer is allocated lazily only when it needs to be consumed.
 pub struct TxToken<N: NetworkInterfaceCard + 'static> {
     nic_ref: &'static MutexIrqSafe<N>,
+    counters: Arc<InterfaceCounters>,
 }
 impl<N: NetworkInterfaceCard + 'static> smoltcp::phy::TxToken for TxToken<N> {
     fn consume<R, F>(self, _timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
@@ -237,13 +277,16 @@ impl<N: NetworkInterfaceCard + 'static> smoltcp::phy::TxToken for TxToken<N> {
         // Because we can dynamically allocate transmit buffers, we just do that here.
         if len > (u16::max_value() as usize) {
             error!("EthernetDevice::transmit(): requested tx buffer size {} exceeds the max size of u16!", len);
+            self.counters.record_tx_error();
             return Err(smoltcp::Error::Exhausted)
         }
 
         // debug!("EthernetDevice::transmit(): creating new TransmitBuffer of {} bytes, timestamp: {}", len, _timestamp);
         // create a new TransmitBuffer, cast it as a slice of bytes, call the passed `f` closure, and then send it!
+        let counters = &self.counters;
         let mut txbuf = TransmitBuffer::new(len as u16).map_err(|e| {
             error!("EthernetDevice::transmit(): couldn't allocate TransmitBuffer of length {}, error {:?}", len, e);
+            counters.record_tx_error();
             smoltcp::Error::Exhausted
         })?;
 
@@ -252,28 +295,35 @@ impl<N: NetworkInterfaceCard + 'static> smoltcp::phy::TxToken for TxToken<N> {
                 error!("EthernetDevice::transmit(): couldn't convert TransmitBuffer of length {} into byte slice, error {:?}", len, e);
                 smoltcp::Error::Exhausted
             })?;
-            f(txbuf_byte_slice)?
+            let retval = f(txbuf_byte_slice)?;
+            packet_capture::capture_frame(Direction::Transmitted, txbuf_byte_slice);
+            retval
         };
         self.nic_ref.lock()
             .send_packet(txbuf)
             .map_err(|e| {
                 error!("EthernetDevice::transmit(): error sending Ethernet packet: {:?}", e);
+                counters.record_tx_error();
                 smoltcp::Error::Exhausted
             })?;
         
+        counters.record_tx(len);
         Ok(closure_retval)
     }
 }
 
 
 /// The receive token type used by smoltcp, 
-/// which contains only a `ReceivedFrame` to be consumed later.
-pub struct RxToken(BoxRefMut<ReceivedFrame, [u8]>);
+/// which contains only a `ReceivedFrame` to be consumed later
+/// and the counters of the interface it was received on.
+pub struct RxToken(BoxRefMut<ReceivedFrame, [u8]>, Arc<InterfaceCounters>);
 
 impl smoltcp::phy::RxToken for RxToken {
     fn consume<R, F>(mut self, _timestamp: Instant, f: F) -> smoltcp::Result<R>
         where F: FnOnce(&mut [u8]) -> smoltcp::Result<R>
     {
+        self.1.record_rx(self.0.len());
+        packet_capture::capture_frame(Direction::Received, &self.0);
         f(self.0.as_mut())
     }
 }
\ No newline at end of file
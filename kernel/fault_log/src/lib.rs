@@ -23,7 +23,10 @@ use alloc::{
 use memory::VirtualAddress;
 use apic::get_my_apic_id;
 use irq_safety::MutexIrqSafe;
-use core::panic::PanicInfo;
+use core::{
+    panic::PanicInfo,
+    time::Duration,
+};
 
 /// The possible faults (panics and exceptions) encountered 
 /// during operations.
@@ -41,6 +44,9 @@ pub enum FaultType {
     NMI,
     DivideByZero,
     Panic,
+    /// A real-time task's job didn't finish before its deadline.
+    /// The duration is how late the job was when the miss was detected.
+    DeadlineMiss(Duration),
     UnknownException(u8)
 }
 
@@ -216,10 +222,25 @@ pub fn log_panic_entry(panic_info: &PanicInfo) {
     update_and_insert_fault_entry_internal(fe, None);
 }
 
+/// Add a new deadline miss of a real-time task to the fault log.
+/// Because the scheduler detects deadline misses on behalf of other tasks,
+/// the name of the task and the core it runs on must be given explicitly.
+pub fn log_deadline_miss(task_name: String, core: u8, lateness: Duration) {
+    warn!("Real-time task {:?} on core {} missed its deadline by {:?}", task_name, core, lateness);
+    let mut fe = FaultEntry::new(FaultType::DeadlineMiss(lateness));
+    fe.core = Some(core);
+    fe.running_task = Some(task_name);
+    FAULT_LIST.lock().push(fe);
+}
+
 /// Removes the unhandled faults from the fault log and returns. 
 /// Is useful when we update the recovery detail about unhandled exceptions. 
+/// Deadline misses are not exceptions that a task recovers from, so they are left in the log.
 pub fn remove_unhandled_exceptions() -> Vec<FaultEntry> {
-    FAULT_LIST.lock().drain_filter(|fe| fe.action_taken == RecoveryAction::None).collect::<Vec<_>>()
+    FAULT_LIST.lock().drain_filter(|fe| match fe.fault_type {
+        FaultType::DeadlineMiss(_) => false,
+        _ => fe.action_taken == RecoveryAction::None,
+    }).collect::<Vec<_>>()
 }
 
 /// calls println!() and then println_raw!()
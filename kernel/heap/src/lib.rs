@@ -1,6 +1,14 @@
 //! The global allocator for the system. 
 //! It starts off as a single fixed size allocator.
 //! When a more complex heap is set up, it is set as the default allocator.
+//!
+//! Allocations from the default allocator can be charged to a [`HeapQuota`](struct.HeapQuota.html),
+//! which limits how many bytes a task (or a group of tasks sharing the same quota) can allocate.
+//! Once the functions that find the current task's quota have been registered with
+//! [`set_heap_quota_funcs()`](fn.set_heap_quota_funcs.html), an allocation that would exceed that quota fails
+//! instead of exhausting the heap that is shared by the whole system.
+//! The quota that each charged allocation belongs to is kept in a side table,
+//! such that allocations that aren't charged to any quota are left exactly as they are.
 
 #![feature(const_fn)]
 #![feature(allocator_api)]
@@ -14,7 +22,10 @@ extern crate memory;
 extern crate kernel_config;
 extern crate block_allocator;
 
+use core::{mem, ptr};
+use core::sync::atomic::{AtomicUsize, Ordering};
 use alloc::alloc::{GlobalAlloc, Layout};
+use alloc::sync::Arc;
 use memory::EntryFlags;
 use kernel_config::memory::{KERNEL_HEAP_START, KERNEL_HEAP_INITIAL_SIZE};
 use irq_safety::MutexIrqSafe;
@@ -44,6 +55,13 @@ pub const HEAP_FLAGS: EntryFlags = EntryFlags::WRITABLE;
 const INITIAL_HEAP_END_ADDR: usize = KERNEL_HEAP_START + KERNEL_HEAP_INITIAL_SIZE;
 
 
+/// The function that returns the heap quota of the current task, if it has one.
+static CURRENT_HEAP_QUOTA_FUNC: Once<fn() -> Option<Arc<HeapQuota>>> = Once::new();
+
+/// The function that is invoked when an allocation fails because it would exceed the current task's heap quota.
+static HEAP_QUOTA_EXCEEDED_FUNC: Once<fn(&HeapQuota, Layout)> = Once::new();
+
+
 /// Initializes the single heap, which is the first heap used by the system.
 pub fn init_single_heap(start_virt_addr: usize, size_in_bytes: usize) {
     unsafe { GLOBAL_ALLOCATOR.initial_allocator.lock().init(start_virt_addr, size_in_bytes); }
@@ -56,6 +74,199 @@ pub fn set_allocator(allocator: Box<dyn GlobalAlloc + Send + Sync>) {
 }
 
 
+/// Registers the functions that the global allocator uses to charge allocations to the current task's heap quota.
+/// 
+/// * `current`: returns the heap quota of the current task, if it has one.
+///   It is invoked upon every allocation, so it must not allocate or acquire any locks.
+/// * `exceeded`: is invoked when an allocation fails because it would exceed the current task's heap quota,
+///   right before the allocator returns a null pointer.
+/// 
+/// These can only be set once; later calls have no effect.
+pub fn set_heap_quota_funcs(current: fn() -> Option<Arc<HeapQuota>>, exceeded: fn(&HeapQuota, Layout)) {
+    CURRENT_HEAP_QUOTA_FUNC.call_once(|| current);
+    HEAP_QUOTA_EXCEEDED_FUNC.call_once(|| exceeded);
+}
+
+
+/// A limit on the number of bytes that can be allocated from the heap by the tasks that share this quota,
+/// along with the number of bytes they currently have allocated.
+/// 
+/// A quota can be given to a single task or shared among several tasks, e.g., all tasks of an application.
+/// Memory is charged to the quota of the task that allocates it, even if another task later frees it.
+#[derive(Debug)]
+pub struct HeapQuota {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl HeapQuota {
+    /// Creates a new quota that allows up to `limit` bytes to be allocated.
+    pub fn new(limit: usize) -> HeapQuota {
+        HeapQuota {
+            limit: AtomicUsize::new(limit),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Returns the maximum number of bytes that can be allocated under this quota.
+    pub fn limit(&self) -> usize {
+        self.limit.load(Ordering::SeqCst)
+    }
+
+    /// Changes the maximum number of bytes that can be allocated under this quota.
+    /// 
+    /// Lowering the limit below the number of bytes already in use does not free anything,
+    /// it just causes all further allocations to fail until enough memory has been freed.
+    pub fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::SeqCst);
+    }
+
+    /// Returns the number of bytes currently allocated under this quota.
+    pub fn used(&self) -> usize {
+        self.used.load(Ordering::SeqCst)
+    }
+
+    /// Charges `size` bytes to this quota, returning `false` if that would exceed its limit.
+    fn try_charge(&self, size: usize) -> bool {
+        let limit = self.limit();
+        let mut used = self.used();
+        loop {
+            let new_used = match used.checked_add(size) {
+                Some(n) if n <= limit => n,
+                _ => return false,
+            };
+            let previous = self.used.compare_and_swap(used, new_used, Ordering::SeqCst);
+            if previous == used {
+                return true;
+            }
+            used = previous;
+        }
+    }
+
+    /// Returns `size` previously-charged bytes to this quota.
+    fn uncharge(&self, size: usize) {
+        self.used.fetch_sub(size, Ordering::SeqCst);
+    }
+}
+
+
+/// The heap quota that each charged allocation was charged to, keyed by the allocation's address.
+static QUOTA_OWNERS: MutexIrqSafe<QuotaOwners> = MutexIrqSafe::new(QuotaOwners::empty());
+
+/// The number of allocations in `QUOTA_OWNERS`, such that deallocating doesn't need to look there if it's empty.
+static CHARGED_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+/// The minimum number of entries in the `QUOTA_OWNERS` table once it has been allocated.
+const MIN_QUOTA_OWNERS_CAPACITY: usize = 64;
+
+/// An open-addressing hash table that maps the address of each charged allocation
+/// to the heap quota it was charged to, which is a reference obtained from `Arc::into_raw()`.
+/// 
+/// The table's memory comes directly from the default allocator, because the global allocator
+/// can't allocate memory for it while it's in the middle of an allocation.
+struct QuotaOwners {
+    entries: *mut QuotaOwner,
+    capacity: usize,
+    /// The number of entries that hold an allocation.
+    len: usize,
+    /// The number of entries whose allocation was removed, which can't be reused until the table is rebuilt.
+    removed: usize,
+}
+
+// SAFE: the entries are only accessed while holding the lock around `QUOTA_OWNERS`.
+unsafe impl Send for QuotaOwners {}
+
+#[derive(Clone, Copy)]
+struct QuotaOwner {
+    /// The address of the allocation, or one of `EMPTY_ENTRY` and `REMOVED_ENTRY`.
+    addr: usize,
+    quota: usize,
+}
+
+/// Allocations never start at address 0 or 1, so those mark table entries that don't hold an allocation.
+const EMPTY_ENTRY: usize = 0;
+const REMOVED_ENTRY: usize = 1;
+
+impl QuotaOwners {
+    const fn empty() -> QuotaOwners {
+        QuotaOwners { entries: ptr::null_mut(), capacity: 0, len: 0, removed: 0 }
+    }
+
+    fn layout(capacity: usize) -> Layout {
+        // cannot overflow, since a table of this capacity was already allocated or is small
+        Layout::from_size_align(capacity * mem::size_of::<QuotaOwner>(), mem::align_of::<QuotaOwner>()).unwrap()
+    }
+
+    /// Returns the index of the first entry to look at for the given address; `capacity` must be a power of two.
+    fn start_index(addr: usize, capacity: usize) -> usize {
+        (addr >> 3).wrapping_mul(0x9E37_79B9_7F4A_7C15) >> (64 - capacity.trailing_zeros())
+    }
+
+    /// Records that the allocation at `addr` was charged to `quota`,
+    /// allocating a larger table from the given `allocator` if necessary.
+    /// Returns `false` if the table couldn't be grown.
+    unsafe fn insert(&mut self, addr: usize, quota: usize, allocator: &dyn GlobalAlloc) -> bool {
+        if (self.len + self.removed + 1) * 2 > self.capacity {
+            let new_capacity = ((self.len + 1) * 4).max(MIN_QUOTA_OWNERS_CAPACITY).next_power_of_two();
+            if !self.rebuild(new_capacity, allocator) {
+                return false;
+            }
+        }
+        let mut index = Self::start_index(addr, self.capacity);
+        loop {
+            let entry = &mut *self.entries.add(index);
+            if entry.addr == EMPTY_ENTRY {
+                *entry = QuotaOwner { addr, quota };
+                self.len += 1;
+                return true;
+            }
+            index = (index + 1) & (self.capacity - 1);
+        }
+    }
+
+    /// Removes the allocation at `addr` from the table, returning the quota it was charged to.
+    unsafe fn remove(&mut self, addr: usize) -> Option<usize> {
+        if self.capacity == 0 {
+            return None;
+        }
+        let mut index = Self::start_index(addr, self.capacity);
+        loop {
+            let entry = &mut *self.entries.add(index);
+            if entry.addr == addr {
+                entry.addr = REMOVED_ENTRY;
+                self.len -= 1;
+                self.removed += 1;
+                return Some(entry.quota);
+            }
+            if entry.addr == EMPTY_ENTRY {
+                return None;
+            }
+            index = (index + 1) & (self.capacity - 1);
+        }
+    }
+
+    /// Moves all entries into a new table with the given capacity, which must be a power of two.
+    unsafe fn rebuild(&mut self, new_capacity: usize, allocator: &dyn GlobalAlloc) -> bool {
+        let new_entries = allocator.alloc_zeroed(Self::layout(new_capacity)) as *mut QuotaOwner;
+        if new_entries.is_null() {
+            return false;
+        }
+        let old = mem::replace(self, QuotaOwners { entries: new_entries, capacity: new_capacity, len: 0, removed: 0 });
+        for i in 0 .. old.capacity {
+            let entry = *old.entries.add(i);
+            if entry.addr != EMPTY_ENTRY && entry.addr != REMOVED_ENTRY {
+                // there is always room, so this never needs to allocate
+                self.insert(entry.addr, entry.quota, allocator);
+            }
+        }
+        if !old.entries.is_null() {
+            allocator.dealloc(old.entries as *mut u8, Self::layout(old.capacity));
+        }
+        true
+    }
+}
+
+
 /// The heap which is used as a global allocator for the system.
 /// It starts off with one basic fixed size allocator, the `initial allocator`. 
 /// When a more complex heap is created and set as the `DEFAULT_ALLOCATOR`, then it is used.
@@ -78,7 +289,32 @@ unsafe impl GlobalAlloc for Heap {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         match DEFAULT_ALLOCATOR.try() {
             Some(allocator) => {
-                allocator.alloc(layout)
+                let quota = match CURRENT_HEAP_QUOTA_FUNC.try().and_then(|current| current()) {
+                    Some(q) => q,
+                    None => return allocator.alloc(layout),
+                };
+                if !quota.try_charge(layout.size()) {
+                    if let Some(exceeded) = HEAP_QUOTA_EXCEEDED_FUNC.try() {
+                        exceeded(&quota, layout);
+                    }
+                    return ptr::null_mut();
+                }
+
+                let ptr = allocator.alloc(layout);
+                if ptr.is_null() {
+                    quota.uncharge(layout.size());
+                    return ptr;
+                }
+                // The table entry owns one reference to the quota, which is released when the allocation is freed.
+                let owner = Arc::into_raw(quota);
+                if !QUOTA_OWNERS.lock().insert(ptr as usize, owner as usize, &**allocator) {
+                    let quota = Arc::from_raw(owner);
+                    quota.uncharge(layout.size());
+                    allocator.dealloc(ptr, layout);
+                    return ptr::null_mut();
+                }
+                CHARGED_ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+                ptr
             }
             None => {       
                 self.initial_allocator.lock().allocate(layout)
@@ -91,9 +327,22 @@ unsafe impl GlobalAlloc for Heap {
             self.initial_allocator.lock().deallocate(ptr, layout);
         }
         else {
-            DEFAULT_ALLOCATOR.try()
-                .expect("Ptr passed to dealloc is not within the initial allocator's range, and another allocator has not been set up")
-                .dealloc(ptr, layout);
+            let allocator = DEFAULT_ALLOCATOR.try()
+                .expect("Ptr passed to dealloc is not within the initial allocator's range, and another allocator has not been set up");
+            // The entry must be removed before the memory is freed, after which it could be allocated and charged again.
+            let owner = if CHARGED_ALLOCATIONS.load(Ordering::SeqCst) != 0 {
+                QUOTA_OWNERS.lock().remove(ptr as usize)
+            } else {
+                None
+            };
+            allocator.dealloc(ptr, layout);
+            if let Some(owner) = owner {
+                CHARGED_ALLOCATIONS.fetch_sub(1, Ordering::SeqCst);
+                let quota = Arc::from_raw(owner as *const HeapQuota);
+                quota.uncharge(layout.size());
+                // If this was the last reference to the quota, dropping it here deallocates the quota itself.
+                drop(quota);
+            }
         }
     }
 
@@ -0,0 +1,801 @@
+//! Support for the Intel 82576 ethernet controller, which QEMU emulates as `-device igb`.
+//!
+//! Unlike the e1000, the 82576 has multiple receive and transmit queues.
+//! This driver sets up one pair of queues per core (up to `IGB_MAX_QUEUES`),
+//! and uses Receive Side Scaling (RSS) to spread incoming flows across the receive queues.
+//! Each receive queue has its own MSI-X vector, which is steered to the core that the queue belongs to,
+//! so received frames are handled on that core.
+//!
+//! Packets can also be steered to a specific queue, either by changing the RSS redirection table
+//! through [`set_redirection_entry()`](struct.IgbNic.html#method.set_redirection_entry)
+//! or by adding an ethertype filter through [`add_ethertype_filter()`](struct.IgbNic.html#method.add_ethertype_filter).
+//! The frames received on a given queue can be taken through
+//! [`get_received_frame_from_queue()`](struct.IgbNic.html#method.get_received_frame_from_queue).
+//!
+//! Each receive and transmit queue has its own lock, and only the registers shared by all queues
+//! are behind a common lock, such that the queue interrupt handlers and senders on different cores don't contend.
+//! Only one 82576 device is supported; any further devices are ignored.
+
+#![no_std]
+#![feature(abi_x86_interrupt)]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate static_assertions;
+extern crate volatile;
+extern crate zerocopy;
+extern crate alloc;
+extern crate spin;
+extern crate irq_safety;
+extern crate kernel_config;
+extern crate memory;
+extern crate pci;
+extern crate owning_ref;
+extern crate interrupts;
+extern crate x86_64;
+extern crate mpmc;
+extern crate network_interface_card;
+extern crate apic;
+extern crate intel_ethernet;
+extern crate nic_buffers;
+extern crate nic_queues;
+extern crate nic_initialization;
+
+pub mod regs;
+
+use core::sync::atomic::{AtomicU8, Ordering};
+use spin::Once;
+use alloc::vec::Vec;
+use alloc::collections::VecDeque;
+use alloc::boxed::Box;
+use irq_safety::MutexIrqSafe;
+use memory::{PhysicalAddress, MappedPages};
+use pci::{PciDevice, PciConfigSpaceAccessMechanism, MSIX_CAPABILITY};
+use kernel_config::memory::PAGE_SIZE;
+use owning_ref::BoxRefMut;
+use interrupts::{eoi, register_msi_interrupt};
+use x86_64::structures::idt::{ExceptionStackFrame, HandlerFunc};
+use network_interface_card::NetworkInterfaceCard;
+use nic_initialization::{allocate_memory, init_rx_buf_pool, init_rx_queue, init_tx_queue};
+use intel_ethernet::descriptors::{TxDescriptor, AdvancedRxDescriptor, LegacyTxDescriptor};
+use nic_buffers::{TransmitBuffer, ReceiveBuffer, ReceivedFrame};
+use nic_queues::{RxQueue, TxQueue};
+use apic::{get_lapics, get_my_apic_id};
+use regs::*;
+
+pub const INTEL_VEND:           u16 = 0x8086;  // Vendor ID for Intel
+pub const IGB_DEV:              u16 = 0x10C9;  // Device ID for the 82576 NIC emulated by QEMU's igb device
+
+/// The maximum number of receive and transmit queue pairs,
+/// which is the number of queues that RSS can spread flows across on the 82576.
+pub const IGB_MAX_QUEUES:       usize = 8;
+
+/// The number of entries in the RSS redirection table.
+pub const IGB_RETA_ENTRIES:     usize = 128;
+
+/// The number of ethertype filters.
+pub const IGB_NUM_ETQF:         usize = 8;
+
+const IGB_NUM_RX_DESC:          usize = 64;
+const IGB_NUM_TX_DESC:          usize = 64;
+
+/// Currently, each receive buffer is a single page.
+const IGB_RX_BUFFER_SIZE_IN_BYTES:     u16 = PAGE_SIZE as u16;
+
+/// The key used by the RSS hash function, which is the default key from Microsoft's RSS specification.
+const RSS_KEY: [u32; 10] = [
+    0xda565a6d, 0xc20e5b25, 0x3d256741, 0xb08fa343, 0xcb2bcad0,
+    0xb4307bae, 0xa32dcb77, 0x0cf23080, 0x3bb7426a, 0xfa01acbe,
+];
+
+
+/// The single instance of the 82576 NIC.
+static IGB_NIC: Once<MutexIrqSafe<IgbNic>> = Once::new();
+
+/// The queues and registers of the single 82576 NIC, which the queue interrupt handlers use
+/// without taking the lock around `IGB_NIC`.
+static IGB_QUEUES: Once<IgbQueues> = Once::new();
+
+/// Returns a reference to the IgbNic wrapped in a MutexIrqSafe,
+/// if it exists and has been initialized.
+pub fn get_igb_nic() -> Option<&'static MutexIrqSafe<IgbNic>> {
+    IGB_NIC.try()
+}
+
+/// How many ReceiveBuffers are preallocated for this driver to use.
+const RX_BUFFER_POOL_SIZE: usize = 1024;
+lazy_static! {
+    /// The pool of pre-allocated receive buffers that are used by all of the 82576's receive queues
+    /// and temporarily given to higher layers in the networking stack.
+    static ref RX_BUFFER_POOL: mpmc::Queue<ReceiveBuffer> = mpmc::Queue::with_capacity(RX_BUFFER_POOL_SIZE);
+}
+
+
+/// Statistics about one pair of receive and transmit queues.
+#[derive(Debug, Clone, Copy)]
+pub struct QueueStats {
+    /// The number of the queue.
+    pub id: u8,
+    /// The APIC ID of the core that the queue's interrupt is steered to.
+    pub cpu_id: u8,
+    /// The interrupt number of the queue's MSI-X vector.
+    pub interrupt_num: u8,
+    /// The number of interrupts handled for this queue.
+    pub interrupts: usize,
+    /// The number of frames received on this queue.
+    pub rx_frames: usize,
+    /// The number of received frames waiting to be taken from this queue.
+    pub pending_frames: usize,
+    /// The number of frames sent on this queue.
+    pub tx_frames: usize,
+}
+
+
+/// struct representing an 82576 network interface card.
+pub struct IgbNic {
+    /// The actual MAC address burnt into the hardware of this NIC.
+    mac_hardware: [u8; 6],
+    /// The optional spoofed MAC address to use in place of `mac_hardware` when transmitting.
+    mac_spoofed: Option<[u8; 6]>,
+    /// The receive queue that `get_received_frame()` looks at first, which rotates for fairness
+    next_rx_queue: usize,
+    /// The queues and registers of this NIC, which are shared with the interrupt handlers
+    queues: &'static IgbQueues,
+}
+
+/// The queues and registers of an 82576 NIC.
+/// Each queue is locked separately, so a core only contends with others over the registers shared by all queues.
+struct IgbQueues {
+    /// Receive queues, one per core
+    rx_queues: Vec<MutexIrqSafe<IgbRxQueue>>,
+    /// Transmit queues, one per core
+    tx_queues: Vec<MutexIrqSafe<IgbTxQueue>>,
+    /// The APIC ID of the core that each queue pair is steered to,
+    /// which lets a sender find its core's transmit queue without locking the other queues
+    queue_cpu_ids: Vec<AtomicU8>,
+    /// The interrupt number of each receive queue's MSI-X vector
+    queue_interrupt_nums: Vec<u8>,
+    /// The interrupt number of the MSI-X vector for causes that aren't mapped to a queue, e.g., link status changes
+    other_interrupt_num: u8,
+    /// The registers that are shared by all queues
+    shared: MutexIrqSafe<IgbSharedRegisters>,
+}
+
+/// A receive queue along with its registers and statistics.
+struct IgbRxQueue {
+    queue: RxQueue<AdvancedRxDescriptor>,
+    /// memory-mapped registers of this queue
+    regs: &'static mut IgbRxQueueRegisters,
+    /// The number of interrupts handled for this queue
+    interrupts: usize,
+    /// The number of frames received on this queue
+    rx_frames: usize,
+}
+
+/// A transmit queue along with its registers and statistics.
+struct IgbTxQueue {
+    queue: TxQueue<LegacyTxDescriptor>,
+    /// memory-mapped registers of this queue
+    regs: &'static mut IgbTxQueueRegisters,
+    /// The number of frames sent on this queue
+    tx_frames: usize,
+}
+
+/// The registers that are shared by all queues, and the state kept in them.
+struct IgbSharedRegisters {
+    /// memory-mapped general and interrupt control registers
+    regs1: BoxRefMut<MappedPages, IgbRegisters1>,
+    /// memory-mapped receive filtering and RSS registers
+    regs2: BoxRefMut<MappedPages, IgbRegisters2>,
+    /// memory-mapped MSI-X table
+    msix_vectors: BoxRefMut<MappedPages, [MsixVectorEntry]>,
+    /// The ethertype that each ethertype filter matches, if the filter is in use
+    ethertype_filters: [Option<(u16, u8)>; IGB_NUM_ETQF],
+}
+
+
+impl NetworkInterfaceCard for IgbNic {
+
+    fn send_packet(&mut self, transmit_buffer: TransmitBuffer) -> Result<(), &'static str> {
+        // send on the current core's queue, such that cores don't contend over the same transmit queue
+        let my_apic_id = get_my_apic_id();
+        let qid = self.queues.queue_cpu_ids.iter().position(|cpu_id| cpu_id.load(Ordering::Relaxed) == my_apic_id).unwrap_or(0);
+
+        let mut txq = self.queues.tx_queues[qid].lock();
+        let txq = &mut *txq;
+        let queue = &mut txq.queue;
+        queue.tx_descs[queue.tx_cur as usize].send(transmit_buffer.phys_addr, transmit_buffer.length);
+        // update the tx_cur value to hold the next free descriptor
+        let old_cur = queue.tx_cur;
+        queue.tx_cur = (queue.tx_cur + 1) % IGB_NUM_TX_DESC as u16;
+        // update the tdt register by 1 so that it knows the previous descriptor has been used
+        // and has a packet to be sent
+        txq.regs.tdt.write(queue.tx_cur as u32);
+        // Wait for the packet to be sent
+        queue.tx_descs[old_cur as usize].wait_for_packet_tx();
+        txq.tx_frames += 1;
+        Ok(())
+    }
+
+    fn get_received_frame(&mut self) -> Option<ReceivedFrame> {
+        // take frames from each queue in turn, such that a busy queue doesn't starve the others
+        let num_queues = self.queues.rx_queues.len();
+        for i in 0..num_queues {
+            let qid = (self.next_rx_queue + i) % num_queues;
+            if let Some(frame) = self.queues.rx_queues[qid].lock().queue.received_frames.pop_front() {
+                self.next_rx_queue = (qid + 1) % num_queues;
+                return Some(frame);
+            }
+        }
+        None
+    }
+
+    fn poll_receive(&mut self) -> Result<(), &'static str> {
+        for rxq in self.queues.rx_queues.iter() {
+            rxq.lock().receive_frames()?;
+        }
+        Ok(())
+    }
+
+    fn mac_address(&self) -> [u8; 6] {
+        self.mac_spoofed.unwrap_or(self.mac_hardware)
+    }
+}
+
+
+
+/// functions that setup the NIC struct and handle the sending and receiving of packets
+impl IgbNic {
+    /// Initializes the new 82576 network interface card that is connected as the given PciDevice.
+    /// Only one 82576 device is supported, so this returns an error if one has already been initialized.
+    pub fn init(igb_pci_dev: &PciDevice) -> Result<&'static MutexIrqSafe<IgbNic>, &'static str> {
+        if IGB_NIC.try().is_some() {
+            return Err("igb::init(): only one igb device is supported, and one has already been initialized");
+        }
+
+        let bar0 = igb_pci_dev.bars[0];
+        // Determine the access mechanism from the base address register's bit 0
+        let bar_type = (bar0 as u8) & 0x1;
+
+        // If the base address is not memory mapped then exit
+        if bar_type == PciConfigSpaceAccessMechanism::IoPort as u8 {
+            error!("igb::init(): BAR0 is of I/O type");
+            return Err("igb::init(): BAR0 is of I/O type")
+        }
+
+        // memory mapped base address
+        let mem_base = igb_pci_dev.determine_mem_base()?;
+
+        // set the bus mastering bit for this PciDevice, which allows it to use DMA
+        igb_pci_dev.pci_set_command_bus_master_bit();
+
+        let (mut regs1, mut regs2, rx_regs, tx_regs) = Self::map_igb_regs(mem_base)?;
+        let mut msix_vectors = Self::map_msix_table(igb_pci_dev)?;
+        if msix_vectors.len() < 2 {
+            error!("igb::init(): the device has {} MSI-X vectors, but at least 2 are needed", msix_vectors.len());
+            return Err("igb::init(): the device has too few MSI-X vectors for a queue and the other causes");
+        }
+
+        Self::reset(&mut regs1);
+        Self::start_link(&mut regs1);
+        let mac_addr_hardware = Self::read_mac_address_from_nic(&mut regs2);
+
+        // one queue pair per core, limited by the number of queues RSS supports and the number of MSI-X vectors,
+        // one of which is reserved for causes that aren't mapped to a queue.
+        let mut apic_ids: Vec<u8> = get_lapics().iter().map(|(apic_id, _lapic)| *apic_id).collect();
+        apic_ids.sort();
+        let num_queues = apic_ids.len().min(IGB_MAX_QUEUES).min(msix_vectors.len() - 1);
+        if num_queues == 0 {
+            return Err("igb::init(): couldn't find any cores to use the device's queues on");
+        }
+        apic_ids.truncate(num_queues);
+        info!("igb: using {} queue pairs on cores {:?}", num_queues, apic_ids);
+
+        // initialize the buffer pool
+        init_rx_buf_pool(RX_BUFFER_POOL_SIZE, IGB_RX_BUFFER_SIZE_IN_BYTES, &RX_BUFFER_POOL)?;
+
+        // Each queue's registers are handed to that queue, so they can be used under the queue's own lock.
+        // They are never unmapped, because the NIC is used for as long as the system runs.
+        let rx_regs: &'static mut [IgbRxQueueRegisters] = &mut **Box::leak(Box::new(rx_regs));
+        let tx_regs: &'static mut [IgbTxQueueRegisters] = &mut **Box::leak(Box::new(tx_regs));
+
+        let rx_queues = Self::rx_init(&mut regs1, &mut regs2, rx_regs, &apic_ids)?;
+        let tx_queues = Self::tx_init(&mut regs1, tx_regs, &apic_ids)?;
+        let (queue_interrupt_nums, other_interrupt_num) = Self::enable_msix_interrupts(igb_pci_dev, &mut regs1, &mut msix_vectors, &apic_ids)?;
+
+        let queues = IGB_QUEUES.call_once(|| IgbQueues {
+            rx_queues: rx_queues.into_iter().map(MutexIrqSafe::new).collect(),
+            tx_queues: tx_queues.into_iter().map(MutexIrqSafe::new).collect(),
+            queue_cpu_ids: apic_ids.iter().map(|apic_id| AtomicU8::new(*apic_id)).collect(),
+            queue_interrupt_nums: queue_interrupt_nums,
+            other_interrupt_num: other_interrupt_num,
+            shared: MutexIrqSafe::new(IgbSharedRegisters {
+                regs1: regs1,
+                regs2: regs2,
+                msix_vectors: msix_vectors,
+                ethertype_filters: [None; IGB_NUM_ETQF],
+            }),
+        });
+
+        let igb_nic = IgbNic {
+            mac_hardware: mac_addr_hardware,
+            mac_spoofed: None,
+            next_rx_queue: 0,
+            queues: queues,
+        };
+
+        let nic_ref = IGB_NIC.call_once(|| MutexIrqSafe::new(igb_nic));
+        Ok(nic_ref)
+    }
+
+    /// Allocates memory for the NIC and maps each of the register regions used by this driver to that memory area.
+    /// Returns references to the registers, tied to their backing `MappedPages`.
+    ///
+    /// # Arguments
+    /// * `mem_base`: the physical address where the NIC's memory starts.
+    fn map_igb_regs(mem_base: PhysicalAddress) -> Result<(
+        BoxRefMut<MappedPages, IgbRegisters1>,
+        BoxRefMut<MappedPages, IgbRegisters2>,
+        BoxRefMut<MappedPages, [IgbRxQueueRegisters]>,
+        BoxRefMut<MappedPages, [IgbTxQueueRegisters]>
+    ), &'static str> {
+        // the queue registers are mapped for all queues the hardware supports, which is 16 of each
+        const NUM_HW_QUEUES: usize = 16;
+
+        let mp1 = allocate_memory(mem_base + REGISTERS1_OFFSET, core::mem::size_of::<IgbRegisters1>())?;
+        let mp2 = allocate_memory(mem_base + REGISTERS2_OFFSET, core::mem::size_of::<IgbRegisters2>())?;
+        let mp_rx = allocate_memory(mem_base + RX_QUEUE_REGISTERS_OFFSET, NUM_HW_QUEUES * core::mem::size_of::<IgbRxQueueRegisters>())?;
+        let mp_tx = allocate_memory(mem_base + TX_QUEUE_REGISTERS_OFFSET, NUM_HW_QUEUES * core::mem::size_of::<IgbTxQueueRegisters>())?;
+
+        let regs1 = BoxRefMut::new(Box::new(mp1)).try_map_mut(|mp| mp.as_type_mut::<IgbRegisters1>(0))?;
+        let regs2 = BoxRefMut::new(Box::new(mp2)).try_map_mut(|mp| mp.as_type_mut::<IgbRegisters2>(0))?;
+        let rx_regs = BoxRefMut::new(Box::new(mp_rx)).try_map_mut(|mp| mp.as_slice_mut::<IgbRxQueueRegisters>(0, NUM_HW_QUEUES))?;
+        let tx_regs = BoxRefMut::new(Box::new(mp_tx)).try_map_mut(|mp| mp.as_slice_mut::<IgbTxQueueRegisters>(0, NUM_HW_QUEUES))?;
+        Ok((regs1, regs2, rx_regs, tx_regs))
+    }
+
+    /// Finds the MSI-X table through the device's MSI-X capability and maps it.
+    fn map_msix_table(dev: &PciDevice) -> Result<BoxRefMut<MappedPages, [MsixVectorEntry]>, &'static str> {
+        // offset in the capability space of the message control register, whose lower 11 bits hold the table size minus one
+        const MESSAGE_CONTROL_REGISTER_OFFSET: u16 = 2;
+        // offset in the capability space of the register that holds the table's offset and BAR index (BIR)
+        const TABLE_OFFSET_REGISTER_OFFSET: u16 = 4;
+
+        let cap_addr = dev.find_pci_capability(MSIX_CAPABILITY).ok_or("igb: device not MSI-X capable")?;
+        let num_vectors = (dev.pci_read_16(cap_addr + MESSAGE_CONTROL_REGISTER_OFFSET) & 0x7FF) as usize + 1;
+        let table_offset_and_bir = dev.pci_read_32(cap_addr + TABLE_OFFSET_REGISTER_OFFSET);
+        let bir = (table_offset_and_bir & 0x7) as usize;
+        let table_offset = (table_offset_and_bir & !0x7) as usize;
+
+        // the BAR holding the MSI-X table may be 64-bit, in which case the next BAR holds the upper 32 bits
+        let bar = dev.bars[bir];
+        let bar_is_64_bit = (bar >> 1) & 0x3 == 0x2;
+        let upper_bits = if bar_is_64_bit && bir < 5 { (dev.bars[bir + 1] as usize) << 32 } else { 0 };
+        let table_addr = ((bar & !0xF) as usize | upper_bits) + table_offset;
+        let page_offset = table_addr % PAGE_SIZE;
+
+        let mp = allocate_memory(PhysicalAddress::new(table_addr - page_offset)?, page_offset + num_vectors * core::mem::size_of::<MsixVectorEntry>())?;
+        let msix_vectors = BoxRefMut::new(Box::new(mp)).try_map_mut(|mp| mp.as_slice_mut::<MsixVectorEntry>(page_offset, num_vectors))?;
+        debug!("igb: MSI-X table with {} vectors at {:#X}", num_vectors, table_addr);
+        Ok(msix_vectors)
+    }
+
+    pub fn spoof_mac(&mut self, spoofed_mac_addr: [u8; 6]) {
+        self.mac_spoofed = Some(spoofed_mac_addr);
+    }
+
+    /// Reads the actual MAC address burned into the NIC hardware.
+    fn read_mac_address_from_nic(regs: &mut IgbRegisters2) -> [u8; 6] {
+        let mac_32_low = regs.ral.read();
+        let mac_32_high = regs.rah.read();
+
+        let mut mac_addr = [0; 6];
+        mac_addr[0] =  mac_32_low as u8;
+        mac_addr[1] = (mac_32_low >> 8) as u8;
+        mac_addr[2] = (mac_32_low >> 16) as u8;
+        mac_addr[3] = (mac_32_low >> 24) as u8;
+        mac_addr[4] =  mac_32_high as u8;
+        mac_addr[5] = (mac_32_high >> 8) as u8;
+
+        debug!("igb: read hardware MAC address: {:02x?}", mac_addr);
+        mac_addr
+    }
+
+    /// Resets the NIC with all interrupts disabled.
+    fn reset(regs: &mut IgbRegisters1) {
+        Self::disable_interrupts(regs);
+        let val = regs.ctrl.read();
+        regs.ctrl.write(val | CTRL_RST);
+        // the reset bit is cleared by the hardware once the reset completes
+        while regs.ctrl.read() & CTRL_RST == CTRL_RST { }
+        // interrupts must be disabled again after a reset
+        Self::disable_interrupts(regs);
+    }
+
+    /// Masks all interrupts and clears any pending interrupt causes.
+    fn disable_interrupts(regs: &mut IgbRegisters1) {
+        regs.imc.write(0xFFFF_FFFF);
+        regs.eimc.write(0xFFFF_FFFF);
+        regs.icr.read();
+        regs.eicr.read();
+    }
+
+    /// Start up the network
+    fn start_link(regs: &mut IgbRegisters1) {
+        let val = regs.ctrl.read();
+        regs.ctrl.write(val | CTRL_SLU);
+        // tell the firmware that a driver has taken control of the NIC
+        let val = regs.ctrl_ext.read();
+        regs.ctrl_ext.write(val | CTRL_EXT_DRV_LOAD);
+
+        debug!("igb::start_link(): REG_CTRL: {:#X}", regs.ctrl.read());
+    }
+
+    /// Initializes one receive queue per core, each with its array of receive descriptors and their corresponding receive buffers,
+    /// then configures RSS to spread received flows across those queues.
+    fn rx_init(
+        regs1: &mut IgbRegisters1,
+        regs2: &mut IgbRegisters2,
+        rx_regs: &'static mut [IgbRxQueueRegisters],
+        apic_ids: &[u8]
+    ) -> Result<Vec<IgbRxQueue>, &'static str> {
+        let mut rx_queues = Vec::with_capacity(apic_ids.len());
+
+        for ((qid, apic_id), rxq_regs) in apic_ids.iter().enumerate().zip(rx_regs.iter_mut()) {
+            let (rx_descs, rx_bufs_in_use) = init_rx_queue(IGB_NUM_RX_DESC, &RX_BUFFER_POOL, IGB_RX_BUFFER_SIZE_IN_BYTES as usize, &mut rxq_regs.rdbal,
+                                                &mut rxq_regs.rdbah, &mut rxq_regs.rdlen, &mut rxq_regs.rdh, &mut rxq_regs.rdt)?;
+
+            // use advanced descriptors, with the buffer size given in units of 1 KiB
+            let bsize_packet = IGB_RX_BUFFER_SIZE_IN_BYTES as u32 >> SRRCTL_BSIZEPACKET_SHIFT;
+            rxq_regs.srrctl.write(SRRCTL_DESCTYPE_ADV_ONEBUF | SRRCTL_DROP_EN | bsize_packet);
+
+            let val = rxq_regs.rxdctl.read();
+            rxq_regs.rxdctl.write(val | DCTL_QUEUE_ENABLE);
+            // the enable bit reads as set once the queue has actually been enabled
+            while rxq_regs.rxdctl.read() & DCTL_QUEUE_ENABLE == 0 { }
+
+            // Write the tail index, see the e1000 driver for why this is one less than the number of descriptors.
+            rxq_regs.rdt.write((IGB_NUM_RX_DESC - 1) as u32);
+
+            rx_queues.push(IgbRxQueue {
+                queue: RxQueue {
+                    id: qid as u8,
+                    rx_descs: rx_descs,
+                    rx_cur: 0,
+                    rx_bufs_in_use: rx_bufs_in_use,
+                    received_frames: VecDeque::new(),
+                    cpu_id: *apic_id,
+                },
+                regs: rxq_regs,
+                interrupts: 0,
+                rx_frames: 0,
+            });
+        }
+
+        Self::enable_rss(regs2, apic_ids.len());
+
+        // Multicast promiscuous mode (MPE) accepts all multicast frames without programming the multicast table,
+        // which IPv6 requires for neighbor discovery and router advertisements.
+        regs1.rctl.write(RCTL_EN | RCTL_MPE | RCTL_BAM | RCTL_SECRC);
+
+        Ok(rx_queues)
+    }
+
+    /// Enables RSS, which hashes the addresses and ports of each received packet
+    /// and uses the hash to pick a receive queue from the redirection table.
+    /// The redirection table initially spreads its entries evenly across all `num_queues` queues.
+    fn enable_rss(regs2: &mut IgbRegisters2, num_queues: usize) {
+        for (i, key) in RSS_KEY.iter().enumerate() {
+            regs2.rssrk[i].write(*key);
+        }
+
+        // each redirection table register holds 4 entries of one byte each
+        for i in 0..(IGB_RETA_ENTRIES / 4) {
+            let mut reta = 0;
+            for j in 0..4 {
+                let queue = ((i * 4 + j) % num_queues) as u32;
+                reta |= queue << (j * 8);
+            }
+            regs2.reta[i].write(reta);
+        }
+
+        regs2.mrqc.write(MRQC_ENABLE_RSS
+            | MRQC_RSS_FIELD_IPV4 | MRQC_RSS_FIELD_IPV4_TCP | MRQC_RSS_FIELD_IPV4_UDP
+            | MRQC_RSS_FIELD_IPV6 | MRQC_RSS_FIELD_IPV6_TCP | MRQC_RSS_FIELD_IPV6_UDP
+        );
+
+        // the RSS hash is reported in the same descriptor field as the packet checksum, so the checksum is disabled
+        let val = regs2.rxcsum.read();
+        regs2.rxcsum.write(val | RXCSUM_PCSD);
+    }
+
+    /// Initializes one transmit queue per core and returns them.
+    fn tx_init(
+        regs1: &mut IgbRegisters1,
+        tx_regs: &'static mut [IgbTxQueueRegisters],
+        apic_ids: &[u8]
+    ) -> Result<Vec<IgbTxQueue>, &'static str> {
+        let mut tx_queues = Vec::with_capacity(apic_ids.len());
+
+        for ((qid, apic_id), txq_regs) in apic_ids.iter().enumerate().zip(tx_regs.iter_mut()) {
+            let tx_descs = init_tx_queue(IGB_NUM_TX_DESC, &mut txq_regs.tdbal, &mut txq_regs.tdbah, &mut txq_regs.tdlen, &mut txq_regs.tdh, &mut txq_regs.tdt)?;
+
+            let val = txq_regs.txdctl.read();
+            txq_regs.txdctl.write(val | DCTL_QUEUE_ENABLE);
+            while txq_regs.txdctl.read() & DCTL_QUEUE_ENABLE == 0 { }
+
+            tx_queues.push(IgbTxQueue {
+                queue: TxQueue {
+                    id: qid as u8,
+                    tx_descs: tx_descs,
+                    tx_cur: 0,
+                    cpu_id: *apic_id,
+                },
+                regs: txq_regs,
+                tx_frames: 0,
+            });
+        }
+
+        regs1.tctl.write(TCTL_EN | TCTL_PSP);
+
+        Ok(tx_queues)
+    }
+
+    /// Enables MSI-X interrupts, with one vector per receive queue that is steered to that queue's core,
+    /// plus one vector on the current core for causes that aren't mapped to a queue, e.g., link status changes.
+    ///
+    /// Returns the interrupt number of each queue's vector and the interrupt number of the other vector.
+    fn enable_msix_interrupts(
+        dev: &PciDevice,
+        regs1: &mut IgbRegisters1,
+        msix_vectors: &mut [MsixVectorEntry],
+        apic_ids: &[u8]
+    ) -> Result<(Vec<u8>, u8), &'static str> {
+        dev.pci_enable_msix()?;
+        // legacy interrupts must be disabled while MSI-X is in use
+        dev.pci_set_interrupt_disable_bit();
+
+        // queue vectors are automatically masked when they fire and automatically cleared,
+        // and the handler re-enables them once the queue has been processed.
+        regs1.gpie.write(GPIE_NSICR | GPIE_MSIX_MODE | GPIE_EIAME | GPIE_PBA_SUPPORT);
+
+        let mut interrupt_nums = Vec::with_capacity(apic_ids.len());
+        let mut queue_mask = 0;
+        for (qid, apic_id) in apic_ids.iter().enumerate() {
+            let interrupt_num = register_msi_interrupt(QUEUE_INTERRUPT_HANDLERS[qid])?;
+            Self::set_msix_vector(&mut msix_vectors[qid], *apic_id, interrupt_num);
+
+            // Each IVAR register maps the causes of queues n and n + 8:
+            // byte 0 is receive queue n and byte 2 is receive queue n + 8.
+            let shift = if qid < 8 { 0 } else { 16 };
+            let ivar = &mut regs1.ivar[qid % 8];
+            let val = ivar.read() & !(0xFF << shift);
+            ivar.write(val | ((qid as u32 | IVAR_VALID) << shift));
+
+            queue_mask |= 1 << qid;
+            interrupt_nums.push(interrupt_num);
+        }
+
+        let other_vector = apic_ids.len();
+        let other_interrupt_num = register_msi_interrupt(igb_other_handler)?;
+        Self::set_msix_vector(&mut msix_vectors[other_vector], get_my_apic_id(), other_interrupt_num);
+        // byte 1 of the misc IVAR register maps the "other" causes
+        regs1.ivar_misc.write((other_vector as u32 | IVAR_VALID) << 8);
+
+        regs1.eiac.write(queue_mask);
+        regs1.eiam.write(queue_mask);
+        regs1.eims.write(queue_mask | (1 << other_vector));
+        regs1.ims.write(INT_LSC);
+
+        Ok((interrupt_nums, other_interrupt_num))
+    }
+
+    /// Steers the given MSI-X vector to the core with the given APIC ID, and unmasks it.
+    fn set_msix_vector(vector: &mut MsixVectorEntry, apic_id: u8, interrupt_num: u8) {
+        // the memory region is a constant defined for Intel cpus where MSI messages are written,
+        // and the destination core's APIC ID goes in bits 12 to 19 of the message address (Intel Arch SDM, vol3, 10.11)
+        const MEMORY_REGION: u32 = 0xFEE0_0000;
+        vector.msg_lower_addr.write(MEMORY_REGION | ((apic_id as u32) << 12));
+        vector.msg_upper_addr.write(0);
+        vector.msg_data.write(interrupt_num as u32);
+        vector.vector_control.write(0);
+    }
+
+    /// Returns the number of receive and transmit queue pairs in use.
+    pub fn num_queues(&self) -> usize {
+        self.queues.rx_queues.len()
+    }
+
+    /// Returns the earliest frame received on the given queue, without looking at the other queues.
+    /// This allows a task to handle only the traffic steered to one queue, e.g., the queue of the core it runs on.
+    pub fn get_received_frame_from_queue(&mut self, qid: usize) -> Option<ReceivedFrame> {
+        self.queues.rx_queues.get(qid).and_then(|rxq| rxq.lock().queue.received_frames.pop_front())
+    }
+
+    /// Polls the given queue for received frames, without waiting for its interrupt.
+    pub fn poll_queue(&mut self, qid: usize) -> Result<usize, &'static str> {
+        self.queues.rx_queues.get(qid).ok_or("igb: invalid queue number")?.lock().receive_frames()
+    }
+
+    /// Returns statistics about each pair of receive and transmit queues.
+    pub fn queue_stats(&self) -> Vec<QueueStats> {
+        self.queues.rx_queues.iter().zip(self.queues.tx_queues.iter()).enumerate().map(|(qid, (rxq, txq))| {
+            let rxq = rxq.lock();
+            QueueStats {
+                id: rxq.queue.id,
+                cpu_id: rxq.queue.cpu_id,
+                interrupt_num: self.queues.queue_interrupt_nums[qid],
+                interrupts: rxq.interrupts,
+                rx_frames: rxq.rx_frames,
+                pending_frames: rxq.queue.received_frames.len(),
+                tx_frames: txq.lock().tx_frames,
+            }
+        }).collect()
+    }
+
+    /// Returns the receive queue that each entry of the RSS redirection table points to.
+    /// The lower 7 bits of a packet's RSS hash select the entry.
+    pub fn redirection_table(&self) -> [u8; IGB_RETA_ENTRIES] {
+        let mut table = [0; IGB_RETA_ENTRIES];
+        for (i, reta) in self.queues.shared.lock().regs2.reta.iter().enumerate() {
+            let val = reta.read();
+            for j in 0..4 {
+                table[i * 4 + j] = (val >> (j * 8)) as u8;
+            }
+        }
+        table
+    }
+
+    /// Steers packets whose RSS hash selects the given redirection table `entry` to the given receive queue.
+    pub fn set_redirection_entry(&mut self, entry: usize, queue: u8) -> Result<(), &'static str> {
+        if entry >= IGB_RETA_ENTRIES {
+            return Err("igb: invalid redirection table entry");
+        }
+        if queue as usize >= self.num_queues() {
+            return Err("igb: invalid queue number");
+        }
+        let shift = (entry % 4) * 8;
+        let mut shared = self.queues.shared.lock();
+        let reta = &mut shared.regs2.reta[entry / 4];
+        let val = reta.read() & !(0xFF << shift);
+        reta.write(val | ((queue as u32) << shift));
+        Ok(())
+    }
+
+    /// Steers all received packets with the given ethertype to the given receive queue,
+    /// regardless of RSS. Returns the index of the ethertype filter that was used.
+    pub fn add_ethertype_filter(&mut self, ethertype: u16, queue: u8) -> Result<usize, &'static str> {
+        if queue as usize >= self.num_queues() {
+            return Err("igb: invalid queue number");
+        }
+        let mut shared = self.queues.shared.lock();
+        if shared.ethertype_filters.iter().any(|f| f.map(|(etype, _)| etype) == Some(ethertype)) {
+            return Err("igb: an ethertype filter for that ethertype already exists");
+        }
+        let index = shared.ethertype_filters.iter().position(|f| f.is_none()).ok_or("igb: all ethertype filters are in use")?;
+        shared.regs2.etqf[index].write(ethertype as u32 | ((queue as u32) << ETQF_QUEUE_SHIFT) | ETQF_FILTER_ENABLE | ETQF_QUEUE_ENABLE);
+        shared.ethertype_filters[index] = Some((ethertype, queue));
+        Ok(index)
+    }
+
+    /// Removes the ethertype filter with the given index, which was returned by `add_ethertype_filter()`.
+    pub fn remove_ethertype_filter(&mut self, index: usize) -> Result<(), &'static str> {
+        let mut shared = self.queues.shared.lock();
+        if shared.ethertype_filters.get(index).cloned().and_then(|f| f).is_none() {
+            return Err("igb: no ethertype filter with that index");
+        }
+        shared.regs2.etqf[index].write(0);
+        shared.ethertype_filters[index] = None;
+        Ok(())
+    }
+
+    /// Steers the interrupt of the given receive queue to the core with the given APIC ID,
+    /// such that the queue's received frames are handled on that core.
+    /// Frames sent from that core will also use the queue's transmit queue.
+    pub fn steer_queue_interrupt(&mut self, qid: usize, apic_id: u8) -> Result<(), &'static str> {
+        if qid >= self.num_queues() {
+            return Err("igb: invalid queue number");
+        }
+        if get_lapics().get(&apic_id).is_none() {
+            return Err("igb: no core with that APIC ID");
+        }
+        // each lock is released before the next one is taken, because the interrupt handlers take them in a different order
+        Self::set_msix_vector(&mut self.queues.shared.lock().msix_vectors[qid], apic_id, self.queues.queue_interrupt_nums[qid]);
+        self.queues.rx_queues[qid].lock().queue.cpu_id = apic_id;
+        self.queues.tx_queues[qid].lock().queue.cpu_id = apic_id;
+        self.queues.queue_cpu_ids[qid].store(apic_id, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns the ethertype and receive queue of each ethertype filter in use, along with its index.
+    pub fn ethertype_filters(&self) -> Vec<(usize, u16, u8)> {
+        self.queues.shared.lock().ethertype_filters.iter().enumerate()
+            .filter_map(|(i, f)| f.map(|(ethertype, queue)| (i, ethertype, queue)))
+            .collect()
+    }
+}
+
+impl IgbRxQueue {
+    /// Removes all received frames from this receive queue,
+    /// making them available through `get_received_frame()` and `get_received_frame_from_queue()`.
+    fn receive_frames(&mut self) -> Result<usize, &'static str> {
+        let received = self.queue.remove_frames_from_queue(usize::max_value(), &RX_BUFFER_POOL, IGB_RX_BUFFER_SIZE_IN_BYTES, &mut self.regs.rdt)?;
+        self.rx_frames += received;
+        Ok(received)
+    }
+}
+
+impl IgbQueues {
+    /// Handles the interrupt of the given receive queue's MSI-X vector.
+    /// Only that queue's lock is held while its frames are received.
+    fn handle_queue_interrupt(&self, qid: usize) -> Result<(), &'static str> {
+        let result = {
+            let mut rxq = self.rx_queues[qid].lock();
+            rxq.interrupts += 1;
+            rxq.receive_frames()
+        };
+        // the vector was automatically masked when it fired, so re-enable it
+        self.shared.lock().regs1.eims.write(1 << qid);
+        result.map(|_| ())
+    }
+
+    /// Handles the interrupt of the MSI-X vector for causes that aren't mapped to a queue.
+    fn handle_other_interrupt(&self) {
+        let mut shared = self.shared.lock();
+        let status = shared.regs1.icr.read();
+        if status & INT_LSC == INT_LSC {
+            debug!("igb::handle_other_interrupt(): link status changed, status: {:#X}", shared.regs1.status.read());
+            IgbNic::start_link(&mut shared.regs1);
+        } else {
+            error!("igb::handle_other_interrupt(): unhandled interrupt!  status: {:#X}", status);
+        }
+        shared.regs1.eims.write(1 << self.rx_queues.len());
+    }
+}
+
+
+/// Handles the interrupt of the given receive queue, which runs on the core that the queue is steered to.
+fn queue_interrupt(qid: usize) {
+    if let Some(queues) = IGB_QUEUES.try() {
+        if let Err(e) = queues.handle_queue_interrupt(qid) {
+            error!("igb: error handling interrupt of queue {}: {:?}", qid, e);
+        }
+        eoi(Some(queues.queue_interrupt_nums[qid]));
+    } else {
+        error!("BUG: igb queue {} interrupt: IGB NIC hasn't yet been initialized!", qid);
+    }
+}
+
+/// Defines one interrupt handler per receive queue, because an interrupt handler can't tell which vector invoked it.
+macro_rules! queue_interrupt_handlers {
+    ($($name:ident => $qid:expr),*) => {
+        $(
+            extern "x86-interrupt" fn $name(_stack_frame: &mut ExceptionStackFrame) {
+                queue_interrupt($qid);
+            }
+        )*
+
+        /// The interrupt handler of each receive queue, indexed by queue number.
+        static QUEUE_INTERRUPT_HANDLERS: [HandlerFunc; IGB_MAX_QUEUES] = [$($name),*];
+    };
+}
+
+queue_interrupt_handlers!(
+    igb_queue0_handler => 0,
+    igb_queue1_handler => 1,
+    igb_queue2_handler => 2,
+    igb_queue3_handler => 3,
+    igb_queue4_handler => 4,
+    igb_queue5_handler => 5,
+    igb_queue6_handler => 6,
+    igb_queue7_handler => 7
+);
+
+extern "x86-interrupt" fn igb_other_handler(_stack_frame: &mut ExceptionStackFrame) {
+    if let Some(queues) = IGB_QUEUES.try() {
+        queues.handle_other_interrupt();
+        eoi(Some(queues.other_interrupt_num));
+    } else {
+        error!("BUG: igb_other_handler(): IGB NIC hasn't yet been initialized!");
+    }
+}
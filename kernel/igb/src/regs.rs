@@ -0,0 +1,269 @@
+//! The memory-mapped registers of the 82576 NIC and the values written into them.
+//!
+//! The registers are split into several structs that are each mapped separately,
+//! because the 128 KiB register space is sparse and only a few regions of it are used.
+//! More information can be found in the 82576 datasheet.
+
+use volatile::{Volatile, ReadOnly};
+use zerocopy::FromBytes;
+use intel_ethernet::types::*;
+
+
+/// The general, interrupt and receive/transmit control registers, from 0x0 to 0x1FFF.
+///
+/// Note: the weird padding is a limitation of using the `zerocopy::FromBytes` trait,
+/// which in the absence of const generics, only implements its trais for arrays of [T: N]
+/// where N is a power of two or is less than 256.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct IgbRegisters1 {
+    pub ctrl:                       Volatile<u32>,          // 0x0
+    _padding0:                      [u8; 4],                // 0x4 - 0x7
+    pub status:                     ReadOnly<u32>,          // 0x8
+    _padding1:                      [u8; 12],               // 0xC - 0x17
+    pub ctrl_ext:                   Volatile<u32>,          // 0x18
+    _padding2:                      [u8; 164],              // 0x1C - 0xBF
+
+    /// Interrupt cause read register, used for causes that aren't mapped to a queue ("other" causes)
+    pub icr:                        ReadOnly<u32>,          // 0xC0
+    _padding3:                      [u8; 12],               // 0xC4 - 0xCF
+    /// Interrupt mask set register
+    pub ims:                        Volatile<u32>,          // 0xD0
+    _padding4:                      [u8; 4],                // 0xD4 - 0xD7
+    /// Interrupt mask clear register
+    pub imc:                        Volatile<u32>,          // 0xD8
+    _padding5:                      [u8; 36],               // 0xDC - 0xFF
+
+    /// Receive control register
+    pub rctl:                       Volatile<u32>,          // 0x100
+    _padding6a:                     [u8; 512],              // 0x104 - 0x3FF,  764 bytes
+    _padding6b:                     [u8; 236],
+    _padding6c:                     [u8;  16],
+
+    /// Transmit control register
+    pub tctl:                       Volatile<u32>,          // 0x400
+    _padding7a:                     [u8; 4096],             // 0x404 - 0x1513,  4368 bytes
+    _padding7b:                     [u8;  256],
+    _padding7c:                     [u8;   16],
+
+    /// General purpose interrupt enable register
+    pub gpie:                       Volatile<u32>,          // 0x1514
+    _padding8:                      [u8; 8],                // 0x1518 - 0x151F
+    /// Extended interrupt cause set register
+    pub eics:                       Volatile<u32>,          // 0x1520
+    /// Extended interrupt mask set register, one bit per MSI-X vector
+    pub eims:                       Volatile<u32>,          // 0x1524
+    /// Extended interrupt mask clear register
+    pub eimc:                       Volatile<u32>,          // 0x1528
+    /// Extended interrupt auto clear register
+    pub eiac:                       Volatile<u32>,          // 0x152C
+    /// Extended interrupt auto mask register
+    pub eiam:                       Volatile<u32>,          // 0x1530
+    _padding9:                      [u8; 76],               // 0x1534 - 0x157F
+    /// Extended interrupt cause read register
+    pub eicr:                       Volatile<u32>,          // 0x1580
+    _padding10:                     [u8; 252],              // 0x1584 - 0x167F
+
+    /// Extended interrupt throttle registers, one per MSI-X vector
+    pub eitr:                       [Volatile<u32>; 25],    // 0x1680 - 0x16E3
+    _padding11:                     [u8; 28],               // 0x16E4 - 0x16FF
+    /// Interrupt vector allocation registers, which map queue interrupt causes to MSI-X vectors
+    pub ivar:                       [Volatile<u32>; 8],     // 0x1700 - 0x171F
+    _padding12:                     [u8; 32],               // 0x1720 - 0x173F
+    /// Interrupt vector allocation register for the causes that aren't mapped to a queue
+    pub ivar_misc:                  Volatile<u32>,          // 0x1740
+    _padding13a:                    [u8; 2048],             // 0x1744 - 0x1FFF,  2236 bytes
+    _padding13b:                    [u8;  128],
+    _padding13c:                    [u8;   60],
+
+    // End of struct should be at offset 0x2000 (8 KiB in total size).
+}
+const_assert_eq!(core::mem::size_of::<IgbRegisters1>(), 0x2000);
+
+
+/// The receive filtering and RSS registers, from 0x5000 to 0x5FFF.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct IgbRegisters2 {
+    /// Receive checksum control register
+    pub rxcsum:                     Volatile<u32>,          // 0x5000
+    _padding0a:                     [u8; 512],              // 0x5004 - 0x53FF,  1020 bytes
+    _padding0b:                     [u8; 256],
+    _padding0c:                     [u8; 252],
+
+    /// The lower (least significant) 32 bits of the NIC's MAC hardware address.
+    pub ral:                        Volatile<u32>,          // 0x5400
+    /// The higher (most significant) 32 bits of the NIC's MAC hardware address.
+    pub rah:                        Volatile<u32>,          // 0x5404
+    _padding1a:                     [u8; 1024],             // 0x5408 - 0x5817,  1040 bytes
+    _padding1b:                     [u8;   16],
+
+    /// Multiple receive queues command register
+    pub mrqc:                       Volatile<u32>,          // 0x5818
+    _padding2a:                     [u8; 512],              // 0x581C - 0x5BFF,  996 bytes
+    _padding2b:                     [u8; 256],
+    _padding2c:                     [u8; 228],
+
+    /// RSS redirection table, in which each byte maps a hash bucket to a receive queue
+    pub reta:                       [Volatile<u32>; 32],    // 0x5C00 - 0x5C7F
+    /// RSS random key registers
+    pub rssrk:                      [Volatile<u32>; 10],    // 0x5C80 - 0x5CA7
+    _padding3:                      [u8; 8],                // 0x5CA8 - 0x5CAF
+    /// Ethertype queue filters
+    pub etqf:                       [Volatile<u32>; 8],     // 0x5CB0 - 0x5CCF
+    _padding4a:                     [u8; 512],              // 0x5CD0 - 0x5FFF,  816 bytes
+    _padding4b:                     [u8; 256],
+    _padding4c:                     [u8;  48],
+
+    // End of struct should be at offset 0x1000 (4 KiB in total size).
+}
+const_assert_eq!(core::mem::size_of::<IgbRegisters2>(), 0x1000);
+
+
+/// The registers of one receive queue, starting at 0xC000 + 0x100 * queue number.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct IgbRxQueueRegisters {
+    /// The lower (least significant) 32 bits of the physical address of the array of receive descriptors.
+    pub rdbal:                      Volatile<Rdbal>,        // 0x00
+    /// The higher (most significant) 32 bits of the physical address of the array of receive descriptors.
+    pub rdbah:                      Volatile<Rdbah>,        // 0x04
+    /// The length in bytes of the array of receive descriptors.
+    pub rdlen:                      Volatile<Rdlen>,        // 0x08
+    /// Split and replication receive control register, which selects the descriptor type and buffer size.
+    pub srrctl:                     Volatile<u32>,          // 0x0C
+    /// The receive descriptor head index, which points to the next available receive descriptor.
+    pub rdh:                        Volatile<Rdh>,          // 0x10
+    _padding0:                      [u8; 4],                // 0x14 - 0x17
+    /// The receive descriptor tail index, which points to the last available receive descriptor.
+    pub rdt:                        Volatile<Rdt>,          // 0x18
+    _padding1:                      [u8; 12],               // 0x1C - 0x27
+    /// Receive descriptor control register, which enables the queue.
+    pub rxdctl:                     Volatile<u32>,          // 0x28
+    _padding2:                      [u8; 212],              // 0x2C - 0xFF
+}
+const_assert_eq!(core::mem::size_of::<IgbRxQueueRegisters>(), 0x100);
+
+
+/// The registers of one transmit queue, starting at 0xE000 + 0x100 * queue number.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct IgbTxQueueRegisters {
+    /// The lower (least significant) 32 bits of the physical address of the array of transmit descriptors.
+    pub tdbal:                      Volatile<Tdbal>,        // 0x00
+    /// The higher (most significant) 32 bits of the physical address of the array of transmit descriptors.
+    pub tdbah:                      Volatile<Tdbah>,        // 0x04
+    /// The length in bytes of the array of transmit descriptors.
+    pub tdlen:                      Volatile<Tdlen>,        // 0x08
+    _padding0:                      [u8; 4],                // 0x0C - 0x0F
+    /// The transmit descriptor head index, which points to the next available transmit descriptor.
+    pub tdh:                        Volatile<Tdh>,          // 0x10
+    _padding1:                      [u8; 4],                // 0x14 - 0x17
+    /// The transmit descriptor tail index, which points to the last available transmit descriptor.
+    pub tdt:                        Volatile<Tdt>,          // 0x18
+    _padding2:                      [u8; 12],               // 0x1C - 0x27
+    /// Transmit descriptor control register, which enables the queue.
+    pub txdctl:                     Volatile<u32>,          // 0x28
+    _padding3:                      [u8; 212],              // 0x2C - 0xFF
+}
+const_assert_eq!(core::mem::size_of::<IgbTxQueueRegisters>(), 0x100);
+
+
+/// One entry of the MSI-X table, which determines the core and interrupt number of an MSI-X vector.
+#[derive(FromBytes)]
+#[repr(C)]
+pub struct MsixVectorEntry {
+    /// The lower 32 bits of the message address, which holds the destination core's APIC ID.
+    pub msg_lower_addr:             Volatile<u32>,
+    /// The upper 32 bits of the message address.
+    pub msg_upper_addr:             Volatile<u32>,
+    /// The message data, which holds the interrupt number.
+    pub msg_data:                   Volatile<u32>,
+    /// Bit 0 masks the vector.
+    pub vector_control:             Volatile<u32>,
+}
+
+
+/// Offset of the first general register region
+pub const REGISTERS1_OFFSET:        usize = 0x0;
+/// Offset of the receive filtering and RSS register region
+pub const REGISTERS2_OFFSET:        usize = 0x5000;
+/// Offset of the registers of receive queue 0
+pub const RX_QUEUE_REGISTERS_OFFSET: usize = 0xC000;
+/// Offset of the registers of transmit queue 0
+pub const TX_QUEUE_REGISTERS_OFFSET: usize = 0xE000;
+
+// CTRL commands
+/// Set Link Up
+pub const CTRL_SLU:                 u32 = 1 << 6;
+/// Device Reset
+pub const CTRL_RST:                 u32 = 1 << 26;
+
+// CTRL_EXT commands
+/// Driver Loaded
+pub const CTRL_EXT_DRV_LOAD:        u32 = 1 << 28;
+
+// RCTL commands
+/// Receiver Enable
+pub const RCTL_EN:                  u32 = 1 << 1;
+/// Multicast Promiscuous Enabled
+pub const RCTL_MPE:                 u32 = 1 << 4;
+/// Broadcast Accept Mode
+pub const RCTL_BAM:                 u32 = 1 << 15;
+/// Strip Ethernet CRC
+pub const RCTL_SECRC:               u32 = 1 << 26;
+
+// TCTL commands
+/// Transmit Enable
+pub const TCTL_EN:                  u32 = 1 << 1;
+/// Pad Short Packets
+pub const TCTL_PSP:                 u32 = 1 << 3;
+
+// SRRCTL values
+/// Use advanced descriptors with a single packet buffer
+pub const SRRCTL_DESCTYPE_ADV_ONEBUF: u32 = 1 << 25;
+/// Drop packets when no receive descriptors are available, so one full queue doesn't stall the others
+pub const SRRCTL_DROP_EN:           u32 = 1 << 31;
+/// The receive buffer size field is in units of 1 KiB
+pub const SRRCTL_BSIZEPACKET_SHIFT: u32 = 10;
+
+/// Enables a receive or transmit queue in RXDCTL/TXDCTL
+pub const DCTL_QUEUE_ENABLE:        u32 = 1 << 25;
+
+// RXCSUM values
+/// Packet checksum disable, which makes the NIC report the RSS hash in the receive descriptor instead
+pub const RXCSUM_PCSD:              u32 = 1 << 13;
+
+// MRQC values
+/// Enable RSS on multiple receive queues
+pub const MRQC_ENABLE_RSS:          u32 = 0x2;
+pub const MRQC_RSS_FIELD_IPV4_TCP:  u32 = 1 << 16;
+pub const MRQC_RSS_FIELD_IPV4:      u32 = 1 << 17;
+pub const MRQC_RSS_FIELD_IPV6:      u32 = 1 << 20;
+pub const MRQC_RSS_FIELD_IPV6_TCP:  u32 = 1 << 21;
+pub const MRQC_RSS_FIELD_IPV4_UDP:  u32 = 1 << 22;
+pub const MRQC_RSS_FIELD_IPV6_UDP:  u32 = 1 << 23;
+
+// ETQF values
+/// The receive queue field of an ethertype filter
+pub const ETQF_QUEUE_SHIFT:         u32 = 16;
+/// Enables the ethertype filter
+pub const ETQF_FILTER_ENABLE:       u32 = 1 << 26;
+/// Steers packets that match the ethertype filter to its receive queue
+pub const ETQF_QUEUE_ENABLE:        u32 = 1 << 31;
+
+// GPIE values
+/// Non-selective interrupt clear on read of ICR
+pub const GPIE_NSICR:               u32 = 1 << 0;
+/// Use MSI-X mode, in which interrupt causes are mapped to vectors through the IVAR registers
+pub const GPIE_MSIX_MODE:           u32 = 1 << 4;
+/// Enable auto-masking of vectors in EIAM
+pub const GPIE_EIAME:               u32 = 1 << 30;
+/// Report pending MSI-X interrupts in the PBA table
+pub const GPIE_PBA_SUPPORT:         u32 = 1 << 31;
+
+/// Marks an IVAR entry as valid
+pub const IVAR_VALID:               u32 = 0x80;
+
+/// Interrupt cause: Link Status Change
+pub const INT_LSC:                  u32 = 1 << 2;
@@ -20,6 +20,7 @@ extern crate gdt;
 extern crate exceptions_early;
 extern crate pic;
 extern crate scheduler;
+extern crate sleep;
 extern crate keyboard;
 extern crate mouse;
 extern crate ps2;
@@ -337,7 +338,10 @@ extern "x86-interrupt" fn lapic_timer_handler(_stack_frame: &mut ExceptionStackF
     
     // we must acknowledge the interrupt first before handling it because we switch tasks here, which doesn't return
     eoi(None); // None, because 0x22 IRQ cannot possibly be a PIC interrupt
-    
+
+    // wake up any tasks whose sleep has ended so that they can be picked by the scheduler below
+    sleep::handle_timer_interrupt();
+
     scheduler::schedule();
 }
 
@@ -0,0 +1,356 @@
+//! IPv6 stateless address autoconfiguration (SLAAC), as specified in RFC 4862.
+//!
+//! Autoconfiguring an interface consists of the following steps:
+//! 1. A link-local address is derived from the interface's MAC address (modified EUI-64)
+//!    and assigned once duplicate address detection (DAD) finds no other host using it.
+//! 2. Router solicitations are sent to the all-routers multicast group until a router advertisement arrives.
+//! 3. If the advertisement carries an autonomous /64 prefix, a global address is formed from that prefix,
+//!    checked with DAD and assigned, and the advertising router becomes the interface's default IPv6 route.
+//!
+//! Neighbor solicitations and advertisements for address resolution are handled by smoltcp itself
+//! once the addresses are assigned; this crate only sends and receives the NDP messages needed for autoconfiguration,
+//! using a raw ICMPv6 socket.
+//!
+//! Prefix and router lifetimes are not tracked, so addresses and routes remain assigned
+//! after they expire; autoconfiguration can be re-run to pick up a changed prefix or router.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+extern crate spawn;
+extern crate scheduler;
+extern crate network_manager;
+extern crate hpet;
+extern crate smoltcp;
+#[macro_use] extern crate smoltcp_helper;
+
+use alloc::{
+    string::ToString,
+    vec::Vec,
+};
+use hpet::get_hpet;
+use network_manager::NetworkInterfaceRef;
+use smoltcp::{
+    phy::ChecksumCapabilities,
+    socket::{SocketSet, SocketHandle, RawSocket, RawSocketBuffer, RawPacketMetadata},
+    wire::{
+        EthernetAddress, IpAddress, IpCidr, IpProtocol, IpVersion,
+        Ipv6Address, Ipv6Cidr, Ipv6Packet, Ipv6Repr,
+        Icmpv6Packet, Icmpv6Repr, NdiscRepr, NdiscPrefixInfoFlags,
+    },
+};
+use smoltcp_helper::{millis_since, poll_iface};
+
+
+/// The length of an IPv6 header without any extension headers.
+const IPV6_HEADER_LEN: usize = 40;
+
+/// NDP messages must be sent with, and are only accepted with, the maximum hop limit,
+/// which guarantees that they originated on the local link.
+const NDP_HOP_LIMIT: u8 = 255;
+
+/// The length of the prefix that SLAAC forms addresses from, which leaves 64 bits for the interface identifier.
+const SLAAC_PREFIX_LEN: u8 = 64;
+
+/// The number of packets that can be buffered in each direction of the raw socket.
+const SOCKET_PACKETS: usize = 4;
+
+/// The maximum size of a received NDP packet.
+const MAX_PACKET_LEN: usize = 1280;
+
+/// The link-local all-routers multicast address, `ff02::2`.
+const ALL_ROUTERS: Ipv6Address = Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x02]);
+
+
+/// The settings used for autoconfiguring an interface, whose defaults are the constants from RFC 4861.
+#[derive(Debug, Clone, Copy)]
+pub struct AutoconfConfig {
+    /// How long to wait for a reply to a duplicate address detection probe before assuming the address is unique.
+    pub dad_timeout_millis: u64,
+    /// How many router solicitations are sent before giving up on finding a router.
+    pub router_solicitations: usize,
+    /// How long to wait for a router advertisement after each router solicitation.
+    pub solicitation_interval_millis: u64,
+}
+
+impl Default for AutoconfConfig {
+    fn default() -> AutoconfConfig {
+        AutoconfConfig {
+            dad_timeout_millis: 1000,
+            router_solicitations: 3,
+            solicitation_interval_millis: 4000,
+        }
+    }
+}
+
+/// The IPv6 configuration acquired by autoconfiguring an interface.
+#[derive(Debug, Clone)]
+pub struct AutoconfResult {
+    /// The interface's link-local address.
+    pub link_local: Ipv6Address,
+    /// The global addresses formed from the prefixes advertised by the router.
+    pub global_addrs: Vec<Ipv6Cidr>,
+    /// The router that was added as the interface's default IPv6 route, if any.
+    pub router: Option<Ipv6Address>,
+    /// The link MTU advertised by the router, if any.
+    pub mtu: Option<u32>,
+}
+
+
+/// Spawns a task that autoconfigures the given interface with the default settings,
+/// such that booting doesn't wait for duplicate address detection or a router to reply.
+pub fn start(iface: NetworkInterfaceRef) -> Result<(), &'static str> {
+    spawn::new_task_builder(autoconf_task, iface)
+        .name("ipv6_autoconf".to_string())
+        .spawn()
+        .map(|handle| handle.detach())
+}
+
+fn autoconf_task(iface: NetworkInterfaceRef) -> Result<(), &'static str> {
+    let result = autoconfigure(&iface, &AutoconfConfig::default()).map_err(|e| {
+        error!("ipv6_autoconf: failed to autoconfigure interface: {}", e);
+        e
+    })?;
+    info!("ipv6_autoconf: link-local address {}, global addresses {:?}, default router {:?}",
+        result.link_local, result.global_addrs, result.router
+    );
+    Ok(())
+}
+
+
+/// Autoconfigures the IPv6 addresses and default route of the given interface,
+/// as described in the crate-level documentation.
+///
+/// Returns an error if the link-local address is already in use by another host.
+/// If no router replies, the interface is left with only its link-local address.
+pub fn autoconfigure(iface: &NetworkInterfaceRef, config: &AutoconfConfig) -> Result<AutoconfResult, &'static str> {
+    let mac = iface.lock().ethernet_addr();
+    let mut ndp = NdpSocket::new(iface)?;
+
+    let link_local = network_manager::ipv6_link_local_addr(mac);
+    let link_local_cidr = IpCidr::Ipv6(Ipv6Cidr::new(link_local, SLAAC_PREFIX_LEN));
+    if !iface.lock().ip_addrs().contains(&link_local_cidr) {
+        if ndp.is_duplicate(link_local, config)? {
+            error!("ipv6_autoconf: link-local address {} is already in use on the link", link_local);
+            return Err("ipv6_autoconf: duplicate link-local address detected");
+        }
+        iface.lock().add_ip_addr(link_local_cidr)?;
+    }
+
+    let mut result = AutoconfResult {
+        link_local,
+        global_addrs: Vec::new(),
+        router: None,
+        mtu: None,
+    };
+
+    let advert = match ndp.solicit_router(link_local, mac, config)? {
+        Some(advert) => advert,
+        None => {
+            warn!("ipv6_autoconf: no router advertisement received, only the link-local address is configured");
+            return Ok(result);
+        }
+    };
+    debug!("ipv6_autoconf: received {:?}", advert);
+    result.mtu = advert.mtu;
+
+    if let Some(prefix) = advert.prefix {
+        if prefix.autonomous && prefix.prefix_len == SLAAC_PREFIX_LEN && prefix.valid_lifetime_millis > 0
+            && !network_manager::is_ipv6_link_local(&prefix.prefix)
+        {
+            let addr = network_manager::ipv6_addr_from_prefix(prefix.prefix, mac);
+            let cidr = Ipv6Cidr::new(addr, SLAAC_PREFIX_LEN);
+            if iface.lock().ip_addrs().contains(&IpCidr::Ipv6(cidr)) {
+                result.global_addrs.push(cidr);
+            } else if ndp.is_duplicate(addr, config)? {
+                warn!("ipv6_autoconf: address {} is already in use on the link, not assigning it", addr);
+            } else {
+                add_global_addr(iface, cidr, link_local_cidr)?;
+                result.global_addrs.push(cidr);
+            }
+        }
+    }
+
+    if advert.router_lifetime_millis > 0 {
+        iface.lock().routes_mut().add_default_ipv6_route(advert.router).map_err(|_e| {
+            error!("ipv6_autoconf: couldn't add default route via {}: {:?}", advert.router, _e);
+            "ipv6_autoconf: couldn't add default IPv6 route"
+        })?;
+        result.router = Some(advert.router);
+    }
+
+    Ok(result)
+}
+
+/// Assigns the given global address to the interface ahead of its link-local address,
+/// because smoltcp uses an interface's first IPv6 address as the source of outgoing IPv6 packets.
+fn add_global_addr(iface: &NetworkInterfaceRef, cidr: Ipv6Cidr, link_local_cidr: IpCidr) -> Result<(), &'static str> {
+    let mut locked_iface = iface.lock();
+    locked_iface.remove_ip_addr(link_local_cidr)?;
+    locked_iface.add_ip_addr(IpCidr::Ipv6(cidr))?;
+    locked_iface.add_ip_addr(link_local_cidr)
+}
+
+/// Returns the solicited-node multicast address of the given address, i.e., `ff02::1:ffXX:XXXX`,
+/// to which neighbor solicitations for that address are sent.
+fn solicited_node_addr(addr: Ipv6Address) -> Ipv6Address {
+    let b = addr.as_bytes();
+    Ipv6Address([0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x01, 0xff, b[13], b[14], b[15]])
+}
+
+
+/// The relevant contents of a received router advertisement.
+#[derive(Debug, Clone, Copy)]
+struct RouterAdvertisement {
+    router: Ipv6Address,
+    router_lifetime_millis: u64,
+    mtu: Option<u32>,
+    prefix: Option<PrefixInfo>,
+}
+
+/// The contents of a prefix information option in a router advertisement.
+#[derive(Debug, Clone, Copy)]
+struct PrefixInfo {
+    prefix: Ipv6Address,
+    prefix_len: u8,
+    /// Whether the prefix can be used for stateless address autoconfiguration.
+    autonomous: bool,
+    valid_lifetime_millis: u64,
+}
+
+
+/// A raw ICMPv6 socket used for sending and receiving NDP messages on an interface.
+struct NdpSocket<'i> {
+    iface: &'i NetworkInterfaceRef,
+    sockets: SocketSet<'static, 'static, 'static>,
+    handle: SocketHandle,
+    startup_time: u64,
+}
+
+impl<'i> NdpSocket<'i> {
+    fn new(iface: &'i NetworkInterfaceRef) -> Result<NdpSocket<'i>, &'static str> {
+        let startup_time = hpet_ticks!();
+        let rx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; SOCKET_PACKETS], vec![0; SOCKET_PACKETS * MAX_PACKET_LEN]);
+        let tx_buffer = RawSocketBuffer::new(vec![RawPacketMetadata::EMPTY; SOCKET_PACKETS], vec![0; SOCKET_PACKETS * MAX_PACKET_LEN]);
+        let socket = RawSocket::new(IpVersion::Ipv6, IpProtocol::Icmpv6, rx_buffer, tx_buffer);
+        let mut sockets = SocketSet::new(Vec::with_capacity(1));
+        let handle = sockets.add(socket);
+        Ok(NdpSocket { iface, sockets, handle, startup_time })
+    }
+
+    /// Performs duplicate address detection for the given tentative address.
+    /// Returns true if another host on the link replied that it is already using the address.
+    fn is_duplicate(&mut self, tentative: Ipv6Address, config: &AutoconfConfig) -> Result<bool, &'static str> {
+        // DAD probes are sent from the unspecified address, as the tentative address can't be used yet.
+        let probe = NdiscRepr::NeighborSolicit { target_addr: tentative, lladdr: None };
+        self.send(Ipv6Address::UNSPECIFIED, solicited_node_addr(tentative), probe)?;
+        let reply = self.receive(config.dad_timeout_millis, |_src, ndisc| match *ndisc {
+            NdiscRepr::NeighborAdvert { target_addr, .. } if target_addr == tentative => Some(()),
+            _ => None,
+        })?;
+        Ok(reply.is_some())
+    }
+
+    /// Sends router solicitations until a router advertisement is received,
+    /// up to the configured number of solicitations.
+    fn solicit_router(
+        &mut self,
+        link_local: Ipv6Address,
+        mac: EthernetAddress,
+        config: &AutoconfConfig,
+    ) -> Result<Option<RouterAdvertisement>, &'static str> {
+        for _attempt in 0 .. config.router_solicitations {
+            self.send(link_local, ALL_ROUTERS, NdiscRepr::RouterSolicit { lladdr: Some(mac) })?;
+            let advert = self.receive(config.solicitation_interval_millis, |src, ndisc| match *ndisc {
+                NdiscRepr::RouterAdvert { router_lifetime, mtu, ref prefix_info, .. } => Some(RouterAdvertisement {
+                    router: src,
+                    router_lifetime_millis: router_lifetime.total_millis(),
+                    mtu,
+                    prefix: prefix_info.as_ref().map(|info| PrefixInfo {
+                        prefix: info.prefix,
+                        prefix_len: info.prefix_len,
+                        autonomous: info.flags.contains(NdiscPrefixInfoFlags::ADDRCONF),
+                        valid_lifetime_millis: info.valid_lifetime.total_millis(),
+                    }),
+                }),
+                _ => None,
+            })?;
+            if advert.is_some() {
+                return Ok(advert);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sends the given NDP message from `src_addr` to `dst_addr`.
+    fn send(&mut self, src_addr: Ipv6Address, dst_addr: Ipv6Address, ndisc: NdiscRepr) -> Result<(), &'static str> {
+        let icmp_repr = Icmpv6Repr::Ndisc(ndisc);
+        let ip_repr = Ipv6Repr {
+            src_addr,
+            dst_addr,
+            next_header: IpProtocol::Icmpv6,
+            payload_len: icmp_repr.buffer_len(),
+            hop_limit: NDP_HOP_LIMIT,
+        };
+        let mut packet = vec![0u8; IPV6_HEADER_LEN + icmp_repr.buffer_len()];
+        ip_repr.emit(&mut Ipv6Packet::new_unchecked(&mut packet[..]));
+        icmp_repr.emit(
+            &IpAddress::Ipv6(src_addr),
+            &IpAddress::Ipv6(dst_addr),
+            &mut Icmpv6Packet::new_unchecked(&mut packet[IPV6_HEADER_LEN ..]),
+            &ChecksumCapabilities::default(),
+        );
+
+        self.sockets.get::<RawSocket>(self.handle).send_slice(&packet)
+            .map_err(|_e| "ipv6_autoconf: couldn't send NDP packet")?;
+        poll_iface(self.iface, &mut self.sockets, self.startup_time)?;
+        Ok(())
+    }
+
+    /// Waits up to `timeout_millis` for a received NDP message that `accept` turns into a value.
+    /// Returns `None` if no such message arrived before the timeout.
+    fn receive<F, R>(&mut self, timeout_millis: u64, mut accept: F) -> Result<Option<R>, &'static str>
+        where F: FnMut(Ipv6Address, &NdiscRepr) -> Option<R>
+    {
+        let start = hpet_ticks!();
+        while millis_since(start)? <= timeout_millis {
+            let packet_io_occurred = poll_iface(self.iface, &mut self.sockets, self.startup_time)?;
+            {
+                let mut socket = self.sockets.get::<RawSocket>(self.handle);
+                while let Ok(packet) = socket.recv() {
+                    if let Some(value) = parse_ndisc(packet, &mut accept) {
+                        return Ok(Some(value));
+                    }
+                }
+            }
+            if !packet_io_occurred {
+                scheduler::schedule(); // yield the CPU while waiting for packets to arrive
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Parses the given raw IPv6 packet as an NDP message and passes it and its source address to `accept`.
+/// Packets that aren't valid NDP messages are ignored.
+fn parse_ndisc<F, R>(packet: &[u8], accept: &mut F) -> Option<R>
+    where F: FnMut(Ipv6Address, &NdiscRepr) -> Option<R>
+{
+    let ipv6_packet = Ipv6Packet::new_checked(packet).ok()?;
+    let ipv6_repr = Ipv6Repr::parse(&ipv6_packet).ok()?;
+    if ipv6_repr.next_header != IpProtocol::Icmpv6 || ipv6_repr.hop_limit != NDP_HOP_LIMIT {
+        return None;
+    }
+    let icmp_packet = Icmpv6Packet::new_checked(ipv6_packet.payload()).ok()?;
+    let icmp_repr = Icmpv6Repr::parse(
+        &IpAddress::Ipv6(ipv6_repr.src_addr),
+        &IpAddress::Ipv6(ipv6_repr.dst_addr),
+        &icmp_packet,
+        &ChecksumCapabilities::default(),
+    ).ok()?;
+    match icmp_repr {
+        Icmpv6Repr::Ndisc(ref ndisc) => accept(ipv6_repr.src_addr, ndisc),
+        _ => None,
+    }
+}
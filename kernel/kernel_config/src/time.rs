@@ -12,4 +12,10 @@ pub const CONFIG_RTC_FREQUENCY_HZ: usize = 128;
 pub const CONFIG_TIMESLICE_PERIOD_MICROSECONDS: u32 = 8000; // 8ms
 
 /// the heartbeat period in milliseconds
-pub const CONFIG_HEARTBEAT_PERIOD_MS: usize = 10000;
\ No newline at end of file
+pub const CONFIG_HEARTBEAT_PERIOD_MS: usize = 10000;
+
+/// the period in milliseconds at which the load balancer evens out the runqueues of all cores
+pub const CONFIG_LOAD_BALANCING_PERIOD_MS: u64 = 100;
+
+/// the period in microseconds at which an idle core tries to steal a task from a busier core
+pub const CONFIG_WORK_STEALING_PERIOD_MICROSECONDS: u64 = 8000;
\ No newline at end of file
@@ -0,0 +1,39 @@
+//! A kernel task that periodically balances the load across all cores
+//! by migrating runnable tasks from busy cores' runqueues to less busy ones.
+//!
+//! This complements the work stealing done by the idle task of each core,
+//! which only kicks in once a core has nothing left to run.
+//! The balancing period is set by `CONFIG_LOAD_BALANCING_PERIOD_MS`.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate alloc;
+extern crate kernel_config;
+extern crate runqueue;
+extern crate sleep;
+extern crate spawn;
+
+use core::time::Duration;
+use alloc::string::ToString;
+use kernel_config::time::CONFIG_LOAD_BALANCING_PERIOD_MS;
+
+
+/// Spawns the load balancer task.
+pub fn init() -> Result<(), &'static str> {
+    spawn::new_task_builder(load_balancer_task, ())
+        .name("load_balancer".to_string())
+        .spawn()
+        .map(|_taskref| ())
+}
+
+fn load_balancer_task(_: ()) -> Result<(), &'static str> {
+    let period = Duration::from_millis(CONFIG_LOAD_BALANCING_PERIOD_MS);
+    loop {
+        sleep::sleep(period)?;
+        let migrated = runqueue::balance();
+        if migrated > 0 {
+            trace!("load_balancer: migrated {} tasks, {} migrations in total", migrated, runqueue::total_migrations());
+        }
+    }
+}
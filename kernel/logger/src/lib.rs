@@ -6,6 +6,7 @@ extern crate spin;
 
 use log::{Record, Level, SetLoggerError, Metadata, Log};
 use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
 use spin::Once;
 
 
@@ -18,6 +19,21 @@ const DEFAULT_LOG_LEVEL: Level = Level::Trace;
 pub type LogOutputFunc = fn(fmt::Arguments);
 static MIRROR_VGA_FUNC: Once<LogOutputFunc> = Once::new();
 
+/// The signature of an additional log output, which receives every log record that is enabled.
+/// 
+/// These functions may be invoked from any context, including interrupt handlers,
+/// so they must not block for long and must never use the logging macros themselves.
+pub type LogRecordFunc = fn(&Record);
+
+/// The maximum number of additional log outputs that can be registered at once.
+const MAX_LOG_OUTPUTS: usize = 4;
+
+/// The additional log outputs, stored as `LogRecordFunc` pointers cast to `usize`, in which `0` denotes an empty slot.
+/// These are atomics rather than a lock so that logging can never deadlock, e.g., if an interrupt handler logs a message.
+static LOG_OUTPUTS: [AtomicUsize; MAX_LOG_OUTPUTS] = [
+    AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0), AtomicUsize::new(0),
+];
+
 /// See ANSI terminal formatting schemes
 #[allow(dead_code)]
 pub enum LogColor {
@@ -54,6 +70,26 @@ pub fn mirror_to_vga(func: LogOutputFunc) {
     MIRROR_VGA_FUNC.call_once(|| func);
 }
 
+/// Registers an additional log output, which will receive every subsequent log record
+/// in addition to the serial port (and VGA mirror, if enabled).
+/// 
+/// Returns an error if the maximum number of log outputs has already been registered.
+pub fn add_log_output(func: LogRecordFunc) -> Result<(), &'static str> {
+    for slot in LOG_OUTPUTS.iter() {
+        if slot.compare_and_swap(0, func as usize, Ordering::SeqCst) == 0 {
+            return Ok(());
+        }
+    }
+    Err("logger: the maximum number of additional log outputs has already been registered")
+}
+
+/// Removes a log output that was previously registered with [`add_log_output()`](fn.add_log_output.html).
+/// 
+/// Returns `true` if the given `func` was found and removed.
+pub fn remove_log_output(func: LogRecordFunc) -> bool {
+    LOG_OUTPUTS.iter().any(|slot| slot.compare_and_swap(func as usize, 0, Ordering::SeqCst) == func as usize)
+}
+
 /// A dummy struct that exists so we can implement the Log trait's methods.
 struct Logger { }
 
@@ -98,6 +134,15 @@ impl Log for Logger {
                 record.args(),
             ));
         }
+
+        for slot in LOG_OUTPUTS.iter() {
+            let func_addr = slot.load(Ordering::Acquire);
+            if func_addr != 0 {
+                // SAFE: only valid `LogRecordFunc` pointers are ever stored into `LOG_OUTPUTS`.
+                let func: LogRecordFunc = unsafe { core::mem::transmute(func_addr) };
+                func(record);
+            }
+        }
     }
 
     fn flush(&self) {
@@ -0,0 +1,199 @@
+//! A software loopback network interface that handles `127.0.0.0/8` and `::1`.
+//!
+//! Every frame transmitted on this interface is queued in memory and received again on the next poll,
+//! which allows local clients and servers to talk to each other over TCP/IP
+//! and networking code to be tested even when no network device exists.
+//!
+//! The loopback interface is added to the list of `NETWORK_INTERFACES` by [`init()`](fn.init.html),
+//! and `network_manager::route_iface()` chooses it for all loopback destinations.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+extern crate smoltcp;
+extern crate network_manager;
+
+use alloc::{
+    collections::BTreeMap,
+    sync::Arc,
+};
+use smoltcp::{
+    socket::SocketSet,
+    time::Instant,
+    phy::{self, Device, DeviceCapabilities, Loopback},
+    wire::{EthernetAddress, IpAddress, IpCidr, Ipv4Address, Ipv6Address},
+    iface::{EthernetInterface, EthernetInterfaceBuilder, NeighborCache, Routes},
+};
+use network_manager::{NetworkInterface, InterfaceCounters, InterfaceStats, add_to_network_interfaces};
+
+
+/// The IPv4 address of the loopback interface and the prefix length of its subnet.
+const LOOPBACK_IPV4_CIDR: (Ipv4Address, u8) = (Ipv4Address([127, 0, 0, 1]), 8);
+/// The IPv6 address of the loopback interface and the prefix length of its subnet.
+const LOOPBACK_IPV6_CIDR: (Ipv6Address, u8) = (Ipv6Address::LOOPBACK, 128);
+
+/// The loopback interface doesn't have a hardware address, so we use an all-zero address like Linux does.
+const LOOPBACK_ETHERNET_ADDR: EthernetAddress = EthernetAddress([0; 6]);
+
+
+/// A struct that implements the `NetworkInterface` trait for the software loopback device.
+/// There should only be one instance of this struct, which is created by [`init()`](fn.init.html).
+pub struct LoopbackInterface {
+    iface: EthernetInterface<'static, 'static, 'static, LoopbackDevice>,
+    /// The traffic counters of this interface, which are shared with its `LoopbackDevice`.
+    counters: Arc<InterfaceCounters>,
+}
+
+impl NetworkInterface for LoopbackInterface {
+    fn ethernet_addr(&self) -> EthernetAddress {
+        self.iface.ethernet_addr()
+    }
+
+    fn set_ethernet_addr(&mut self, addr: EthernetAddress) {
+        self.iface.set_ethernet_addr(addr)
+    }
+
+    fn poll(&mut self, sockets: &mut SocketSet, timestamp: Instant) -> smoltcp::Result<bool> {
+        self.iface.poll(sockets, timestamp)
+    }
+
+    fn ip_addrs(&self) -> &[IpCidr] {
+        self.iface.ip_addrs()
+    }
+
+    fn has_ip_addr(&self, addr: IpAddress) -> bool {
+        self.iface.has_ip_addr(addr)
+    }
+
+    fn routes(&self) -> &Routes<'static> {
+        self.iface.routes()
+    }
+
+    fn routes_mut(&mut self) -> &mut Routes<'static> {
+        self.iface.routes_mut()
+    }
+
+    fn add_ip_addr(&mut self, cidr: IpCidr) -> Result<(), &'static str> {
+        let new_addrs = network_manager::ip_addrs_with(self.iface.ip_addrs(), cidr)?;
+        self.iface.update_ip_addrs(|addrs| *addrs = new_addrs.into());
+        Ok(())
+    }
+
+    fn remove_ip_addr(&mut self, cidr: IpCidr) -> Result<(), &'static str> {
+        let new_addrs = network_manager::ip_addrs_without(self.iface.ip_addrs(), cidr)?;
+        self.iface.update_ip_addrs(|addrs| *addrs = new_addrs.into());
+        Ok(())
+    }
+
+    fn stats(&self) -> InterfaceStats {
+        self.counters.snapshot()
+    }
+
+    fn is_loopback(&self) -> bool {
+        true
+    }
+}
+
+impl LoopbackInterface {
+    /// Creates a new loopback interface with the addresses `127.0.0.1/8` and `::1/128`.
+    pub fn new() -> LoopbackInterface {
+        let ip_addrs = vec![
+            IpCidr::new(LOOPBACK_IPV4_CIDR.0.into(), LOOPBACK_IPV4_CIDR.1),
+            IpCidr::new(LOOPBACK_IPV6_CIDR.0.into(), LOOPBACK_IPV6_CIDR.1),
+        ];
+        // There is no gateway beyond the loopback interface, so it has no routes.
+        let routes = Routes::new(BTreeMap::new());
+
+        let counters = Arc::new(InterfaceCounters::new());
+        let device = LoopbackDevice {
+            inner: Loopback::new(),
+            counters: counters.clone(),
+        };
+        let iface = EthernetInterfaceBuilder::new(device)
+            .ethernet_addr(LOOPBACK_ETHERNET_ADDR)
+            .neighbor_cache(NeighborCache::new(BTreeMap::new()))
+            .ip_addrs(ip_addrs)
+            .routes(routes)
+            .finalize();
+
+        LoopbackInterface { iface, counters }
+    }
+}
+
+
+/// A wrapper around smoltcp's in-memory `Loopback` device that counts the frames passing through it.
+pub struct LoopbackDevice {
+    inner: Loopback,
+    counters: Arc<InterfaceCounters>,
+}
+
+impl<'a> Device<'a> for LoopbackDevice {
+    type RxToken = RxToken<<Loopback as Device<'a>>::RxToken>;
+    type TxToken = TxToken<<Loopback as Device<'a>>::TxToken>;
+
+    fn capabilities(&self) -> DeviceCapabilities {
+        self.inner.capabilities()
+    }
+
+    fn receive(&'a mut self) -> Option<(Self::RxToken, Self::TxToken)> {
+        let counters = &self.counters;
+        self.inner.receive().map(|(rx, tx)| (
+            RxToken { inner: rx, counters: counters.clone() },
+            TxToken { inner: tx, counters: counters.clone() },
+        ))
+    }
+
+    fn transmit(&'a mut self) -> Option<Self::TxToken> {
+        let counters = &self.counters;
+        self.inner.transmit().map(|tx| TxToken { inner: tx, counters: counters.clone() })
+    }
+}
+
+/// The receive token of the `LoopbackDevice`, which counts the frame when it is consumed.
+pub struct RxToken<T: phy::RxToken> {
+    inner: T,
+    counters: Arc<InterfaceCounters>,
+}
+
+impl<T: phy::RxToken> phy::RxToken for RxToken<T> {
+    fn consume<R, F>(self, timestamp: Instant, f: F) -> smoltcp::Result<R>
+        where F: FnOnce(&mut [u8]) -> smoltcp::Result<R>
+    {
+        let counters = self.counters;
+        self.inner.consume(timestamp, |buffer| {
+            counters.record_rx(buffer.len());
+            f(buffer)
+        })
+    }
+}
+
+/// The transmit token of the `LoopbackDevice`, which counts the frame once it has been queued.
+pub struct TxToken<T: phy::TxToken> {
+    inner: T,
+    counters: Arc<InterfaceCounters>,
+}
+
+impl<T: phy::TxToken> phy::TxToken for TxToken<T> {
+    fn consume<R, F>(self, timestamp: Instant, len: usize, f: F) -> smoltcp::Result<R>
+        where F: FnOnce(&mut [u8]) -> smoltcp::Result<R>
+    {
+        let counters = self.counters;
+        let retval = self.inner.consume(timestamp, len, f)?;
+        counters.record_tx(len);
+        Ok(retval)
+    }
+}
+
+
+/// Creates the loopback interface and adds it to the list of `NETWORK_INTERFACES`.
+pub fn init() -> Result<(), &'static str> {
+    if network_manager::NETWORK_INTERFACES.lock().iter().any(|iface| iface.lock().is_loopback()) {
+        return Err("loopback interface was already initialized");
+    }
+    add_to_network_interfaces(LoopbackInterface::new());
+    info!("Initialized loopback network interface: {}/{}, {}/{}", 
+        LOOPBACK_IPV4_CIDR.0, LOOPBACK_IPV4_CIDR.1, LOOPBACK_IPV6_CIDR.0, LOOPBACK_IPV6_CIDR.1,
+    );
+    Ok(())
+}
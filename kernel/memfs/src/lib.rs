@@ -53,6 +53,31 @@ impl MemFile {
         parent.lock().insert(FileOrDir::File(file_ref.clone()))?; // adds the newly created file to the tree
         Ok(file_ref)
     }
+
+    /// Creates a new `MemFile` containing a copy of the given `content`, 
+    /// whose parent is the given `parent` directory, but **does not** insert it into that directory.
+    /// 
+    /// This is useful for directories that populate themselves with new files 
+    /// while their lock is already held, which the caller must then insert into the directory itself.
+    pub fn new_uninserted(name: String, content: &[u8], parent: WeakDirRef) -> Result<FileRef, &'static str> {
+        let memfile = Self::with_content(name, content, parent)?;
+        Ok(Arc::new(Mutex::new(memfile)) as FileRef)
+    }
+
+    /// Creates a new `MemFile` containing a copy of the given `content`, whose parent is the given `parent` directory,
+    /// and returns it directly rather than as a `FileRef`, e.g., such that it can be embedded in another file type.
+    pub fn with_content(name: String, content: &[u8], parent: WeakDirRef) -> Result<MemFile, &'static str> {
+        let mut memfile = MemFile {
+            name: name, 
+            size: 0, 
+            mp: MappedPages::empty(), 
+            parent: parent, 
+        };
+        if !content.is_empty() {
+            memfile.write(content, 0)?;
+        }
+        Ok(memfile)
+    }
 }
 
 impl File for MemFile {
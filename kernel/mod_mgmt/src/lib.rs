@@ -22,6 +22,7 @@ extern crate path;
 extern crate memfs;
 extern crate cstr_core;
 extern crate hashbrown;
+extern crate tls_initializer;
 
 use core::{
     fmt,
@@ -36,7 +37,7 @@ use alloc::{
 use spin::{Mutex, Once};
 use xmas_elf::{
     ElfFile,
-    sections::{SectionData, ShType, SHF_WRITE, SHF_ALLOC, SHF_EXECINSTR},
+    sections::{SectionData, ShType, SHF_WRITE, SHF_ALLOC, SHF_EXECINSTR, SHF_TLS},
 };
 use util::round_up_power_of_two;
 use memory::{MmiRef, get_frame_allocator_ref, MemoryManagementInfo, FrameRange, VirtualAddress, PhysicalAddress, MappedPages, EntryFlags, allocate_pages_by_bytes};
@@ -872,7 +873,7 @@ impl CrateNamespace {
                     relocation_entry, 
                     &mut target_sec_mapped_pages, 
                     target_sec.mapped_pages_offset, 
-                    new_section.start_address(), 
+                    new_section.relocation_address(&relocation_entry), 
                     false
                 )?;
 
@@ -1024,6 +1025,8 @@ impl CrateNamespace {
         const RODATA_PREFIX:         &'static str = ".rodata.";
         const DATA_PREFIX:           &'static str = ".data.";
         const BSS_PREFIX:            &'static str = ".bss.";
+        const TDATA_PREFIX:          &'static str = ".tdata.";
+        const TBSS_PREFIX:           &'static str = ".tbss.";
         const RELRO_PREFIX:          &'static str = "rel.ro.";
         const GCC_EXCEPT_TABLE_NAME: &'static str = ".gcc_except_table";
         const EH_FRAME_NAME:         &'static str = ".eh_frame";
@@ -1146,12 +1149,23 @@ impl CrateNamespace {
                 }
             }
 
-            // Second, if not executable, handle writable .data/.bss sections
+            // Second, if not executable, handle writable .data/.bss sections and thread-local .tdata/.tbss sections
             else if write {
-                // check if this section is .bss or .data
-                let (name, is_bss) = if sec_name.starts_with(BSS_PREFIX) {
+                // check if this section is .bss, .data, .tbss, or .tdata
+                let (name, typ) = if sec_flags & SHF_TLS == SHF_TLS {
+                    let (prefix, typ) = if sec_name.starts_with(TBSS_PREFIX) {
+                        (TBSS_PREFIX, SectionType::TlsBss)
+                    } else if sec_name.starts_with(TDATA_PREFIX) {
+                        (TDATA_PREFIX, SectionType::TlsData)
+                    } else {
+                        error!("Unsupported: found TLS section that wasn't .tdata or .tbss: [{}] {:?}", shndx, sec_name);
+                        return Err("Unsupported: found TLS section that wasn't .tdata or .tbss");
+                    };
+                    let name = sec_name.get(prefix.len() ..).ok_or("Failed to get the TLS section's name after its prefix")?;
+                    (name, typ)
+                } else if sec_name.starts_with(BSS_PREFIX) {
                     if let Some(name) = sec_name.get(BSS_PREFIX.len() ..) {
-                        (name, true) // true means it is .bss
+                        (name, SectionType::Bss)
                     } else {
                         error!("Failed to get the .bss section's name after \".bss.\": {:?}", sec_name);
                         return Err("Failed to get the .bss section's name after \".bss.\"!");
@@ -1164,7 +1178,7 @@ impl CrateNamespace {
                         } else {
                             name
                         };
-                        (name, false) // false means it's not .bss
+                        (name, SectionType::Data)
                     }
                     else {
                         error!("Failed to get the .data section's name after \".data.\": {:?}", sec_name);
@@ -1194,25 +1208,32 @@ impl CrateNamespace {
                         }
                     }
                     
-                    loaded_sections.insert(
-                        shndx,
-                        Arc::new(LoadedSection::new(
-                            if is_bss { SectionType::Bss } else { SectionType::Data },
-                            demangled.clone(),
-                            Arc::clone(dp_ref),
-                            data_offset,
-                            dest_vaddr,
-                            sec_size,
-                            global_sections.contains(&shndx),
-                            new_crate_weak_ref.clone(),
-                        ))
+                    let mut new_sec = LoadedSection::new(
+                        typ,
+                        demangled.clone(),
+                        Arc::clone(dp_ref),
+                        data_offset,
+                        dest_vaddr,
+                        sec_size,
+                        global_sections.contains(&shndx),
+                        new_crate_weak_ref.clone(),
                     );
-                    data_sections.insert(shndx);
+                    if new_sec.typ.is_tls() {
+                        // The loaded .tdata/.tbss section only serves as the initial contents of each task's copy of it,
+                        // so it isn't a data section whose contents must be preserved when swapping its crate.
+                        new_sec.tls_offset = Some(tls_initializer::reserve_section(sec_size, sec_align)?);
+                        let new_sec = Arc::new(new_sec);
+                        tls_initializer::add_section(&new_sec)?;
+                        loaded_sections.insert(shndx, new_sec);
+                    } else {
+                        loaded_sections.insert(shndx, Arc::new(new_sec));
+                        data_sections.insert(shndx);
+                    }
 
                     data_offset += round_up_power_of_two(sec_size, sec_align);
                 }
                 else {
-                    return Err("no data_pages were allocated for .data/.bss section");
+                    return Err("no data_pages were allocated for .data/.bss/.tdata/.tbss section");
                 }
             }
 
@@ -1494,7 +1515,7 @@ impl CrateNamespace {
                         relocation_entry,
                         &mut target_sec_mapped_pages,
                         target_sec.mapped_pages_offset,
-                        source_sec.start_address(),
+                        source_sec.relocation_address(&relocation_entry),
                         verbose_log
                     )?;
 
@@ -2186,7 +2207,7 @@ fn allocate_section_pages(elf_file: &ElfFile, kernel_mmi_ref: &MmiRef) -> Result
                 text_max_offset = core::cmp::max(text_max_offset, (sec.offset() as usize) + addend);
             }
             else if write {
-                // this includes both .bss and .data sections
+                // this includes .bss and .data sections, plus thread-local .tbss and .tdata sections
                 rw_bytes += addend;
             }
             else {
@@ -1,19 +1,35 @@
 //! A mutex that puts tasks to sleep while they wait for the lock. 
+//!
+//! Under a scheduler policy with priorities, the task holding a `MutexSleep`
+//! inherits the priority of higher-priority tasks waiting for it, see the `priority_inheritance` module.
 
 #![no_std]
 
+extern crate alloc;
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate irq_safety;
 extern crate spin;
 extern crate owning_ref;
 extern crate stable_deref_trait;
 extern crate wait_queue;
 extern crate task;
+extern crate sleep;
+extern crate scheduler;
+
+mod priority_inheritance;
 
 use core::fmt;
+use core::mem::ManuallyDrop;
 use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
 use spin::{Mutex, MutexGuard};
 use owning_ref::{OwningRef, OwningRefMut};
 use stable_deref_trait::StableDeref;
-use wait_queue::WaitQueue;
+use wait_queue::{WaitQueue, WaitError};
+use sleep::Instant;
+use priority_inheritance::{LockId, NO_OWNER};
 
 
 /// A mutual exclusion wrapper that puts a `Task` to sleep while waiting for the lock to become available. 
@@ -23,6 +39,10 @@ use wait_queue::WaitQueue;
 /// will be notified (woken up) so they can attempt to acquire the lock again.
 pub struct MutexSleep<T: ?Sized> {
     queue: WaitQueue,
+    /// The ID of the task that currently holds the lock, or `NO_OWNER`.
+    owner: AtomicUsize,
+    /// The number of tasks currently waiting for the lock.
+    waiters: AtomicUsize,
     lock: Mutex<T>,
 }
 
@@ -31,8 +51,8 @@ pub struct MutexSleep<T: ?Sized> {
 /// When the guard falls out of scope, the lock will be automatically released,
 /// which then notifies any `Task`s waiting on the lock.
 pub struct MutexSleepGuard<'a, T: ?Sized + 'a> {
-    guard: MutexGuard<'a, T>,
-    queue: &'a WaitQueue,
+    guard: ManuallyDrop<MutexGuard<'a, T>>,
+    mutex: &'a MutexSleep<T>,
 }
 
 // Same unsafe impls as `std::sync::Mutex`
@@ -50,6 +70,8 @@ impl<T> MutexSleep<T> {
         MutexSleep {
             lock: Mutex::new(data),
             queue: WaitQueue::new(),
+            owner: AtomicUsize::new(NO_OWNER),
+            waiters: AtomicUsize::new(0),
         }
     }
 
@@ -72,21 +94,69 @@ impl<T: ?Sized> MutexSleep<T> {
             return Ok(guard);
         }
         // Slow path if already locked elsewhere: wait until we obtain the lock.
-        self.queue
-            .wait_until(&|| self.try_lock())
-            .map_err(|_| "failed to add current task to waitqueue")
+        let curr_task = task::get_my_current_task().ok_or("failed to get current task")?;
+        self.start_waiting(curr_task);
+        let result = self.queue.wait_until(&|| self.try_lock());
+        self.stop_waiting(curr_task);
+        result.map_err(|_| "failed to add current task to waitqueue")
+    }
+
+    /// Similar to [`lock`](#method.lock), but gives up and returns `WaitError::Timeout`
+    /// if the lock couldn't be acquired within the given `timeout`.
+    pub fn lock_timeout(&self, timeout: Duration) -> Result<MutexSleepGuard<T>, WaitError> {
+        self.lock_deadline(Instant::now() + timeout)
+    }
+
+    /// Similar to [`lock`](#method.lock), but gives up and returns `WaitError::Timeout`
+    /// if the lock couldn't be acquired by the given `deadline`.
+    pub fn lock_deadline(&self, deadline: Instant) -> Result<MutexSleepGuard<T>, WaitError> {
+        // Fast path: check for the uncontended case.
+        if let Some(guard) = self.try_lock() {
+            return Ok(guard);
+        }
+        // Slow path if already locked elsewhere: wait until we obtain the lock or the deadline passes.
+        let curr_task = task::get_my_current_task().ok_or(WaitError::NoCurrentTask)?;
+        self.start_waiting(curr_task);
+        let result = self.queue.wait_until_deadline(&|| self.try_lock(), deadline);
+        self.stop_waiting(curr_task);
+        result
     }
 
     /// Tries to lock the MutexSleep. If it is already locked, it will return `None`.
     /// Otherwise it returns a guard within `Some`.
     pub fn try_lock(&self) -> Option<MutexSleepGuard<T>> {
         self.lock.try_lock().map(|spinlock_guard| {
+            if let Some(curr_task_id) = task::get_my_current_task_id() {
+                self.owner.store(curr_task_id, Ordering::SeqCst);
+                // Only contended locks are tracked for priority inheritance.
+                if self.waiters.load(Ordering::SeqCst) > 0 {
+                    priority_inheritance::acquired(self.lock_id(), curr_task_id);
+                }
+            }
             MutexSleepGuard {
-                guard: spinlock_guard,
-                queue: &self.queue,
+                guard: ManuallyDrop::new(spinlock_guard),
+                mutex: self,
             }
         })
     }
+
+    /// Registers the given task as a waiter for priority inheritance, before it starts waiting for the lock.
+    fn start_waiting(&self, waiter: &task::TaskRef) {
+        // The waiter must be counted before the owner is read, such that a concurrent release sees it.
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        priority_inheritance::start_waiting(self.lock_id(), &self.owner, waiter);
+    }
+
+    /// Unregisters the given task as a waiter for priority inheritance, after it stopped waiting for the lock.
+    fn stop_waiting(&self, waiter: &task::TaskRef) {
+        priority_inheritance::stop_waiting(self.lock_id(), waiter);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Returns the ID that identifies this lock for the purpose of priority inheritance.
+    fn lock_id(&self) -> LockId {
+        &self.queue as *const WaitQueue as LockId
+    }
 }
 
 impl<T: ?Sized + fmt::Debug> fmt::Debug for MutexSleep<T> {
@@ -108,22 +178,37 @@ impl<'a, T: ?Sized> Deref for MutexSleepGuard<'a, T> {
     type Target = T;
 
     fn deref<'b>(&'b self) -> &'b T { 
-        &*(self.guard) 
+        &**(self.guard) 
     }
 }
 
 impl<'a, T: ?Sized> DerefMut for MutexSleepGuard<'a, T> {
     fn deref_mut<'b>(&'b mut self) -> &'b mut T { 
-        &mut *(self.guard)
+        &mut **(self.guard)
     }
 }
 
 
 impl<'a, T: ?Sized> Drop for MutexSleepGuard<'a, T> {
     fn drop(&mut self) {
-        // Notify a task on the waitqueue that the lock is released,
-        // which occurs automatically when the inner `guard` is dropped after this method executes.
-        self.queue.notify_one();
+        // Give up ownership before the inner lock is released, such that it isn't mistaken for the next owner's.
+        self.mutex.owner.store(NO_OWNER, Ordering::SeqCst);
+        if self.mutex.waiters.load(Ordering::SeqCst) > 0 {
+            priority_inheritance::released(self.mutex.lock_id());
+        }
+        // Release the inner lock before notifying a task on the waitqueue,
+        // otherwise the woken task could fail to acquire it and go back to sleep without being notified again.
+        unsafe { ManuallyDrop::drop(&mut self.guard); }
+        self.mutex.queue.notify_one();
+    }
+}
+
+impl<'a, T: ?Sized> MutexSleepGuard<'a, T> {
+    /// Returns the `MutexSleep` that the given guard has locked.
+    ///
+    /// This is an associated function rather than a method, such that it doesn't shadow methods of the locked data.
+    pub fn mutex(guard: &Self) -> &'a MutexSleep<T> {
+        guard.mutex
     }
 }
 
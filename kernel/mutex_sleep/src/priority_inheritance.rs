@@ -0,0 +1,178 @@
+//! Priority inheritance for `MutexSleep`, which prevents unbounded priority inversion
+//! when tasks are scheduled by a scheduler policy with priorities.
+//!
+//! While a task waits for a `MutexSleep`, the task that owns that lock inherits the waiter's priority
+//! if it is higher than its own. If the owner is itself waiting for another lock,
+//! the boosted priority is passed along to that lock's owner, and so on.
+//! Once the owner releases the lock, its priority drops back to the highest priority
+//! among its original priority and the waiters of any other locks it still owns.
+//!
+//! Each `MutexSleep` keeps track of its own owner, and the state in this module
+//! only records the owners of locks that currently have waiters,
+//! such that uncontended locking and unlocking never touches it.
+//!
+//! If the task's core uses a scheduler policy without priorities, none of this has any effect.
+
+use alloc::{
+    collections::BTreeMap,
+    vec::Vec,
+};
+use core::cmp::max;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use irq_safety::MutexIrqSafe;
+use task::{self, TaskRef};
+use scheduler;
+
+
+/// A lock is identified by the address of its waitqueue, which can't move while the lock is held or waited on.
+pub type LockId = usize;
+
+/// The owner ID of a lock that isn't held by any task.
+pub const NO_OWNER: usize = usize::max_value();
+
+/// The maximum number of owners along a chain of nested locks whose priority is updated at once,
+/// which also guards against looping forever when tasks are deadlocked.
+const MAX_CHAIN_LENGTH: usize = 16;
+
+#[derive(Default)]
+struct InheritanceState {
+    /// The task that currently owns each lock that has waiters.
+    owners: BTreeMap<LockId, TaskRef>,
+    /// The tasks waiting for each lock.
+    waiters: BTreeMap<LockId, Vec<TaskRef>>,
+    /// The lock that each waiting task is waiting for, keyed by task ID.
+    waiting_for: BTreeMap<usize, LockId>,
+    /// The original priority of each task whose priority is currently boosted, keyed by task ID.
+    base_priorities: BTreeMap<usize, u8>,
+}
+
+lazy_static! {
+    static ref INHERITANCE_STATE: MutexIrqSafe<InheritanceState> = MutexIrqSafe::new(InheritanceState::default());
+}
+
+
+/// Records that the task with the given ID has acquired the given lock, which other tasks may be waiting for.
+///
+/// If other tasks are already waiting for the lock, the new owner inherits their priority.
+pub fn acquired(lock: LockId, owner_id: usize) {
+    let mut state = INHERITANCE_STATE.lock();
+    if state.waiters.get(&lock).map_or(true, |waiters| waiters.is_empty()) {
+        return;
+    }
+    if let Some(owner) = task::get_task(owner_id) {
+        state.owners.insert(lock, owner.clone());
+        update_priorities(&mut state, &owner);
+    }
+}
+
+/// Records that the given lock has been released by its owner,
+/// whose priority is then restored if it had been boosted.
+pub fn released(lock: LockId) {
+    let mut state = INHERITANCE_STATE.lock();
+    if let Some(owner) = state.owners.remove(&lock) {
+        let owner_id = owner.lock().id;
+        if state.base_priorities.contains_key(&owner_id) {
+            update_priorities(&mut state, &owner);
+        }
+    }
+}
+
+/// Records that the given task is about to wait for the given lock, whose current owner ID is stored in `owner_id`,
+/// and boosts the priority of the lock's owner (and the owners further along the chain) if necessary.
+pub fn start_waiting(lock: LockId, owner_id: &AtomicUsize, waiter: &TaskRef) {
+    let mut state = INHERITANCE_STATE.lock();
+    let waiter_id = waiter.lock().id;
+    state.waiting_for.insert(waiter_id, lock);
+    state.waiters.entry(lock).or_insert_with(Vec::new).push(waiter.clone());
+    // The owner is read while holding the state lock, such that it can't have released the lock without
+    // either clearing its ID first or removing its ownership again in `released()` afterwards.
+    let owner = match owner_id.load(Ordering::SeqCst) {
+        NO_OWNER => None,
+        id => task::get_task(id),
+    };
+    if let Some(owner) = owner {
+        state.owners.insert(lock, owner.clone());
+        update_priorities(&mut state, &owner);
+    }
+}
+
+/// Records that the given task has stopped waiting for the given lock,
+/// either because it acquired the lock or because it gave up.
+///
+/// If the lock is owned by another task, that task may no longer need its boosted priority.
+pub fn stop_waiting(lock: LockId, waiter: &TaskRef) {
+    let mut state = INHERITANCE_STATE.lock();
+    let waiter_id = waiter.lock().id;
+    state.waiting_for.remove(&waiter_id);
+    let no_waiters_left = match state.waiters.get_mut(&lock) {
+        Some(waiters) => {
+            waiters.retain(|t| t != waiter);
+            waiters.is_empty()
+        }
+        None => false,
+    };
+    if no_waiters_left {
+        state.waiters.remove(&lock);
+    }
+    let owner = if no_waiters_left {
+        state.owners.remove(&lock)
+    } else {
+        state.owners.get(&lock).cloned()
+    };
+    match owner {
+        Some(ref owner) if owner != waiter => update_priorities(&mut state, owner),
+        _ => { }
+    }
+}
+
+/// Recalculates the priority of the given task, which is the highest of its original priority
+/// and the priorities of all tasks waiting for locks it owns.
+/// If that changes the task's priority and the task is itself waiting for a lock,
+/// the priority of that lock's owner is recalculated as well, and so on.
+fn update_priorities(state: &mut InheritanceState, task: &TaskRef) {
+    let mut next = Some(task.clone());
+    let mut chain_length = 0;
+    while let Some(task) = next.take() {
+        if chain_length >= MAX_CHAIN_LENGTH {
+            warn!("MutexSleep priority inheritance: chain of nested locks is too long or deadlocked at task {:?}", task);
+            break;
+        }
+        chain_length += 1;
+
+        let task_id = task.lock().id;
+        let current = match scheduler::get_priority(&task) {
+            Some(priority) => priority,
+            // the task's core doesn't use priorities
+            None => {
+                state.base_priorities.remove(&task_id);
+                break;
+            }
+        };
+        let base = state.base_priorities.get(&task_id).cloned().unwrap_or(current);
+        let inherited = state.owners.iter()
+            .filter(|&(_lock, owner)| owner == &task)
+            .filter_map(|(lock, _owner)| state.waiters.get(lock))
+            .flat_map(|waiters| waiters.iter())
+            .filter_map(|waiter| scheduler::get_priority(waiter))
+            .max();
+        let effective = max(base, inherited.unwrap_or(0));
+
+        if effective > base {
+            state.base_priorities.insert(task_id, base);
+        } else {
+            state.base_priorities.remove(&task_id);
+        }
+        if effective == current {
+            // the owners further along the chain aren't affected
+            break;
+        }
+        if let Err(e) = scheduler::set_priority(&task, effective) {
+            warn!("MutexSleep priority inheritance: couldn't change priority of task {:?}: {}", task, e);
+            break;
+        }
+
+        next = state.waiting_for.get(&task_id)
+            .and_then(|lock| state.owners.get(lock))
+            .cloned();
+    }
+}
@@ -1,8 +1,10 @@
 #![no_std]
 
 extern crate nic_buffers;
+extern crate wait_queue;
 
 use nic_buffers::{TransmitBuffer, ReceivedFrame};
+use wait_queue::WaitQueue;
 
 
 /// A trait that defines the necessary minimum functions that all network interface card (NIC) drivers
@@ -20,6 +22,15 @@ pub trait NetworkInterfaceCard {
     /// Can be used as an alternative to interrupts, or as a supplement to interrupts.
     fn poll_receive(&mut self) -> Result<(), &'static str>;
 
+    /// Returns the `WaitQueue` that is notified whenever received frames become available
+    /// through `get_received_frame()`, which allows tasks and futures to wait for received frames
+    /// instead of repeatedly polling for them.
+    ///
+    /// NICs that don't support this return `None`, in which case they must be polled.
+    fn receive_wait_queue(&self) -> Option<&'static WaitQueue> {
+        None
+    }
+
     /// Returns the MAC address that this NIC is configured with.
     /// If spoofed, it will return the spoofed MAC address, 
     /// otherwise it will return the regular MAC address defined by the NIC hardware.
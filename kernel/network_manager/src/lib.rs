@@ -7,13 +7,14 @@ extern crate spin;
 extern crate owning_ref;
 extern crate smoltcp;
 
+use core::sync::atomic::{AtomicUsize, Ordering};
 use alloc::vec::Vec;
 use alloc::sync::Arc;
 use spin::Mutex;
 use smoltcp::{
     socket::SocketSet,
     time::Instant,
-    wire::{EthernetAddress, IpAddress, IpCidr},
+    wire::{EthernetAddress, IpAddress, IpCidr, Ipv6Address},
     iface::Routes,
 };
 
@@ -53,6 +54,88 @@ pub trait NetworkInterface {
     fn routes(&self) -> &Routes<'static>;
 
     fn routes_mut(&mut self) -> &mut Routes<'static>;
+
+    /// Assigns the given IP address (and subnet) to the interface.
+    /// Returns an error if the address is already assigned to it.
+    fn add_ip_addr(&mut self, cidr: IpCidr) -> Result<(), &'static str>;
+
+    /// Removes the given IP address (and subnet) from the interface.
+    /// Returns an error if the address was not assigned to it.
+    fn remove_ip_addr(&mut self, cidr: IpCidr) -> Result<(), &'static str>;
+
+    /// Returns the counters of packets, bytes and errors received and transmitted by the interface.
+    fn stats(&self) -> InterfaceStats;
+
+    /// Returns true if this interface is a software loopback interface,
+    /// i.e., it only handles packets sent from this machine to itself.
+    fn is_loopback(&self) -> bool {
+        false
+    }
+}
+
+/// A snapshot of the traffic counters of a network interface.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InterfaceStats {
+    pub rx_packets: usize,
+    pub rx_bytes: usize,
+    pub rx_errors: usize,
+    pub tx_packets: usize,
+    pub tx_bytes: usize,
+    pub tx_errors: usize,
+}
+
+/// The traffic counters of a network interface, which are updated by its device as packets pass through it.
+/// 
+/// These can be shared (e.g., within an `Arc`) between an interface and its device's transmit and receive tokens.
+#[derive(Debug, Default)]
+pub struct InterfaceCounters {
+    rx_packets: AtomicUsize,
+    rx_bytes: AtomicUsize,
+    rx_errors: AtomicUsize,
+    tx_packets: AtomicUsize,
+    tx_bytes: AtomicUsize,
+    tx_errors: AtomicUsize,
+}
+
+impl InterfaceCounters {
+    /// Creates a new set of counters that are all zero.
+    pub fn new() -> InterfaceCounters {
+        InterfaceCounters::default()
+    }
+
+    /// Records that a packet of `len` bytes was received.
+    pub fn record_rx(&self, len: usize) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    /// Records that a packet could not be received.
+    pub fn record_rx_error(&self) {
+        self.rx_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records that a packet of `len` bytes was transmitted.
+    pub fn record_tx(&self, len: usize) {
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+        self.tx_bytes.fetch_add(len, Ordering::Relaxed);
+    }
+
+    /// Records that a packet could not be transmitted.
+    pub fn record_tx_error(&self) {
+        self.tx_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns a snapshot of the current values of these counters.
+    pub fn snapshot(&self) -> InterfaceStats {
+        InterfaceStats {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_bytes:   self.rx_bytes.load(Ordering::Relaxed),
+            rx_errors:  self.rx_errors.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_bytes:   self.tx_bytes.load(Ordering::Relaxed),
+            tx_errors:  self.tx_errors.load(Ordering::Relaxed),
+        }
+    }
 }
 
 /// A trait object wrapped in an Arc and Mutex that allows 
@@ -61,6 +144,86 @@ pub type NetworkInterfaceRef = Arc<Mutex<dyn NetworkInterface + Send>>;
 
 /// Add a Nic to the global list of network interfaces.
 /// The Nic must implement the NetworkInterface trait.
-pub fn add_to_network_interfaces<T: NetworkInterface + 'static + Send> (iface: T) {
-    NETWORK_INTERFACES.lock().push(Arc::new(Mutex::new(iface)));
+/// Returns a reference to the newly-added interface.
+pub fn add_to_network_interfaces<T: NetworkInterface + 'static + Send> (iface: T) -> NetworkInterfaceRef {
+    let iface_ref: NetworkInterfaceRef = Arc::new(Mutex::new(iface));
+    NETWORK_INTERFACES.lock().push(iface_ref.clone());
+    iface_ref
+}
+
+/// Returns the list of IP addresses that results from adding `cidr` to the given `ip_addrs`,
+/// which is a convenience function for implementing `NetworkInterface::add_ip_addr()`.
+pub fn ip_addrs_with(ip_addrs: &[IpCidr], cidr: IpCidr) -> Result<Vec<IpCidr>, &'static str> {
+    if ip_addrs.contains(&cidr) {
+        return Err("the IP address is already assigned to this interface");
+    }
+    if !cidr.address().is_unicast() {
+        return Err("only unicast IP addresses can be assigned to an interface");
+    }
+    let mut new_addrs = ip_addrs.to_vec();
+    new_addrs.push(cidr);
+    Ok(new_addrs)
+}
+
+/// Returns the list of IP addresses that results from removing `cidr` from the given `ip_addrs`,
+/// which is a convenience function for implementing `NetworkInterface::remove_ip_addr()`.
+pub fn ip_addrs_without(ip_addrs: &[IpCidr], cidr: IpCidr) -> Result<Vec<IpCidr>, &'static str> {
+    if !ip_addrs.contains(&cidr) {
+        return Err("the IP address is not assigned to this interface");
+    }
+    Ok(ip_addrs.iter().filter(|&&c| c != cidr).cloned().collect())
+}
+
+/// Returns true if the given address is a loopback address, i.e., in `127.0.0.0/8` or `::1`.
+pub fn is_loopback_addr(addr: &IpAddress) -> bool {
+    match *addr {
+        IpAddress::Ipv4(ipv4) => ipv4.is_loopback(),
+        IpAddress::Ipv6(ipv6) => ipv6.is_loopback(),
+        _ => false,
+    }
+}
+
+/// Returns the IPv6 address formed from the 64-bit prefix of `prefix` 
+/// and the modified EUI-64 interface identifier derived from the given MAC address, as per RFC 4291 Appendix A.
+pub fn ipv6_addr_from_prefix(prefix: Ipv6Address, mac: EthernetAddress) -> Ipv6Address {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&prefix.as_bytes()[..8]);
+    let mac = mac.as_bytes();
+    // The universal/local bit of the MAC address is inverted, and 0xFFFE is inserted in its middle.
+    bytes[8..16].copy_from_slice(&[mac[0] ^ 0x02, mac[1], mac[2], 0xFF, 0xFE, mac[3], mac[4], mac[5]]);
+    Ipv6Address::from_bytes(&bytes)
+}
+
+/// Returns the link-local IPv6 address (within `fe80::/64`) derived from the given MAC address.
+pub fn ipv6_link_local_addr(mac: EthernetAddress) -> Ipv6Address {
+    ipv6_addr_from_prefix(Ipv6Address::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac)
+}
+
+/// Returns true if the given IPv6 address is a link-local address, i.e., within `fe80::/10`.
+pub fn is_ipv6_link_local(addr: &Ipv6Address) -> bool {
+    addr.as_bytes()[0] == 0xfe && (addr.as_bytes()[1] & 0xc0) == 0x80
+}
+
+/// Chooses the network interface that should be used to reach the given `destination` address.
+///
+/// The routing rules are, in order:
+/// 1. Loopback destinations are routed to the loopback interface.
+/// 2. Destinations within the subnet of an interface's IP address are routed to that interface.
+/// 3. All other destinations are routed to the first non-loopback interface, 
+///    which is expected to have a default gateway.
+///
+/// Returns `None` if no interface can reach the `destination`.
+pub fn route_iface(destination: IpAddress) -> Option<NetworkInterfaceRef> {
+    let ifaces = NETWORK_INTERFACES.lock();
+    if is_loopback_addr(&destination) {
+        return ifaces.iter().find(|iface| iface.lock().is_loopback()).cloned();
+    }
+
+    ifaces.iter()
+        .find(|iface| {
+            let locked_iface = iface.lock();
+            !locked_iface.is_loopback() && locked_iface.ip_addrs().iter().any(|cidr| cidr.contains_addr(&destination))
+        })
+        .or_else(|| ifaces.iter().find(|iface| !iface.lock().is_loopback()))
+        .cloned()
 }
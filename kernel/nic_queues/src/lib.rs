@@ -7,20 +7,28 @@
 #![no_std]
 
 extern crate alloc;
-extern crate log;
+#[macro_use] extern crate log;
 extern crate memory;
 extern crate intel_ethernet;
 extern crate nic_buffers;
+extern crate nic_initialization;
 extern crate owning_ref;
+extern crate volatile;
+extern crate mpmc;
 
 use owning_ref::BoxRefMut;
 use alloc::{
     vec::Vec,
     collections::VecDeque
 };
-use memory::MappedPages;
-use intel_ethernet::descriptors::{RxDescriptor, TxDescriptor};
+use volatile::Volatile;
+use memory::{MappedPages, create_contiguous_mapping};
+use intel_ethernet::{
+    descriptors::{RxDescriptor, TxDescriptor},
+    types::Rdt,
+};
 use nic_buffers::{ReceiveBuffer, ReceivedFrame};
+use nic_initialization::NIC_MAPPING_FLAGS;
 
 
 /// A struct that holds all information for one receive queue.
@@ -48,6 +56,89 @@ pub struct RxQueue<T: RxDescriptor> {
 
 
 
+impl<T: RxDescriptor> RxQueue<T> {
+    /// Removes up to `budget` received frames from the ring of receive descriptors
+    /// and pushes them onto the back of `received_frames`. 
+    /// Each receive buffer taken from the ring is replaced with a new one from the `rx_buffer_pool`,
+    /// and the tail register `rdt` is advanced so the NIC can reuse the descriptor.
+    /// 
+    /// Returns the number of frames that were received.
+    /// 
+    /// # Arguments
+    /// * `budget`: the maximum number of frames to remove, which allows the caller to bound the time spent here.
+    /// * `rx_buffer_pool`: the pool from which new receive buffers are taken.
+    /// * `rx_buffer_size`: the size of a new receive buffer, if the pool is empty and one must be allocated.
+    /// * `rdt`: the receive descriptor tail register of this queue.
+    pub fn remove_frames_from_queue(
+        &mut self,
+        budget: usize,
+        rx_buffer_pool: &'static mpmc::Queue<ReceiveBuffer>,
+        rx_buffer_size: u16,
+        rdt: &mut Volatile<Rdt>,
+    ) -> Result<usize, &'static str> {
+        let num_descs = self.rx_descs.len() as u16;
+        let mut cur = self.rx_cur as usize;
+        let mut frames_received = 0;
+       
+        let mut receive_buffers_in_frame: Vec<ReceiveBuffer> = Vec::new();
+        let mut _total_packet_length: u16 = 0;
+
+        while frames_received < budget && self.rx_descs[cur].descriptor_done() {
+            // get information about the current receive buffer
+            let length = self.rx_descs[cur].length();
+            _total_packet_length += length as u16;
+            // debug!("remove_frames_from_queue: received descriptor of length {}", length);
+            
+            // Now that we are "removing" the current receive buffer from the list of receive buffers that the NIC can use,
+            // (because we're saving it for higher layers to use),
+            // we need to obtain a new `ReceiveBuffer` and set it up such that the NIC will use it for future receivals.
+            let new_receive_buf = match rx_buffer_pool.pop() {
+                Some(rx_buf) => rx_buf,
+                None => {
+                    warn!("NIC RX BUF POOL WAS EMPTY.... reallocating! This means that no task is consuming the accumulated received ethernet frames.");
+                    // if the pool was empty, then we allocate a new receive buffer
+                    let len = rx_buffer_size;
+                    let (mp, phys_addr) = create_contiguous_mapping(len as usize, NIC_MAPPING_FLAGS)?;
+                    ReceiveBuffer::new(mp, phys_addr, len, rx_buffer_pool)
+                }
+            };
+
+            // actually tell the NIC about the new receive buffer, and that it's ready for use now
+            self.rx_descs[cur].set_packet_address(new_receive_buf.phys_addr);
+
+            // Swap in the new receive buffer at the index corresponding to this current rx_desc's receive buffer,
+            // getting back the receive buffer that is part of the received ethernet frame
+            self.rx_bufs_in_use.push(new_receive_buf);
+            let mut current_rx_buf = self.rx_bufs_in_use.swap_remove(cur); 
+            current_rx_buf.length = length as u16; // set the ReceiveBuffer's length to the size of the actual packet received
+            receive_buffers_in_frame.push(current_rx_buf);
+
+            // move on to the next receive buffer to see if it's ready for us to take
+            self.rx_cur = (cur as u16 + 1) % num_descs;
+            rdt.write(cur as u32); 
+
+            if self.rx_descs[cur].end_of_packet() {
+                let buffers = core::mem::replace(&mut receive_buffers_in_frame, Vec::new());
+                self.received_frames.push_back(ReceivedFrame(buffers));
+                frames_received += 1;
+            } else {
+                warn!("NIC::remove_frames_from_queue(): Received multi-rxbuffer frame, this scenario not fully tested!");
+            }
+            self.rx_descs[cur].reset_status();
+            cur = self.rx_cur as usize;
+        }
+
+        Ok(frames_received)
+    }
+
+    /// Returns true if the NIC has written a received packet into the current receive descriptor,
+    /// i.e., if a call to [`remove_frames_from_queue()`](#method.remove_frames_from_queue) would receive a frame.
+    pub fn has_pending_frames(&self) -> bool {
+        self.rx_descs[self.rx_cur as usize].descriptor_done()
+    }
+}
+
+
 /// A struct that holds all information for a transmit queue. 
 /// There should be one such object per queue.
 pub struct TxQueue<T: TxDescriptor> {
@@ -1,5 +1,12 @@
 //! Functions to communicate with a network server that provides over-the-air live update functionality.
 //! 
+//! Each update build contains a manifest file that names that build and lists the SHA3-512 hash 
+//! of every file in it, which is signed by the build server with an Ed25519 private key. 
+//! Because the build name is covered by the signature, an older signed build cannot be
+//! served under the name of a newer one.
+//! The manifest is only trusted if its signature can be verified by one of the public keys
+//! that were baked into this image from the `trusted_keys.txt` file at build time,
+//! which protects against a malicious or spoofed update server. 
 
 #![no_std]
 #![feature(slice_concat_ext)]
@@ -13,6 +20,7 @@ extern crate owning_ref;
 extern crate spawn;
 extern crate task;
 extern crate sha3;
+extern crate ed25519_dalek;
 extern crate percent_encoding;
 extern crate rand;
 extern crate http_client;
@@ -23,7 +31,7 @@ extern crate itertools;
 use core::str;
 use alloc::{
     vec::Vec,
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     string::{String, ToString},
 };
 use itertools::Itertools;
@@ -33,6 +41,7 @@ use smoltcp::{
     socket::{SocketSet, TcpSocket, TcpSocketBuffer, TcpState},
 };
 use sha3::{Digest, Sha3_512};
+use ed25519_dalek::{PublicKey, Signature};
 use percent_encoding::{DEFAULT_ENCODE_SET, utf8_percent_encode};
 use network_manager::{NetworkInterfaceRef};
 use rand::{
@@ -81,6 +90,18 @@ const CHECKSUMS_DIR_NAME: &'static str = "checksums";
 /// The file extension that is appended onto each crate object file's checksum file.
 const CHECKSUM_FILE_EXTENSION: &'static str = ".sha512";
 
+/// The name of the manifest file inside each update build directory,
+/// which contains the SHA3-512 hash and name of every crate object file and the diff file in that update build.
+pub const MANIFEST_FILE_NAME: &'static str = "manifest.txt";
+
+/// The name of the file inside each update build directory that contains 
+/// the raw 64-byte Ed25519 signature of the manifest file.
+pub const MANIFEST_SIGNATURE_FILE_NAME: &'static str = "manifest.txt.sig";
+
+/// The hex-encoded Ed25519 public keys that are trusted to sign update manifests, one per line.
+/// Lines that are empty or start with `#` are ignored.
+const TRUSTED_PUBLIC_KEYS: &'static str = include_str!("../trusted_keys.txt");
+
 
 
 /// A file that has been downloaded over the network, 
@@ -144,6 +165,21 @@ pub fn download_diff(
 }
 
 
+/// Connects to the update server over the given network interface
+/// and downloads the given file in the given update build, returning its raw contents. 
+/// 
+/// This can be used to download the diff file, the manifest file, or the manifest's signature file. 
+pub fn download_update_file(
+    iface: &NetworkInterfaceRef,
+    remote_endpoint: IpEndpoint,
+    update_build: &str,
+    file_name: &str,
+) -> Result<Vec<u8>, &'static str> {
+    let file = download_file(iface, remote_endpoint, &format!("/{}/{}", update_build, file_name))?;
+    file.content.as_result_err_str().map(|content| content.to_vec())
+}
+
+
 /// Convenience function for downloading files and returning their contents as Strings per line. 
 fn download_string_file(
     iface: &NetworkInterfaceRef,
@@ -459,6 +495,118 @@ fn download_files<S: AsRef<str>>(
 // }
 
 
+/// The prefix of the first line of every manifest file, which is followed by the name of the update build.
+const MANIFEST_BUILD_PREFIX: &'static str = "build ";
+
+/// The verified contents of an update build's manifest file, 
+/// which holds the name of the update build and maps the name of each file in it to its SHA3-512 hash.
+pub struct Manifest {
+    build_name: String,
+    hashes: BTreeMap<String, String>,
+}
+impl Manifest {
+    /// Returns the name of the update build that this manifest was signed for.
+    pub fn build_name(&self) -> &str {
+        &self.build_name
+    }
+
+    /// Returns `Ok` if this manifest was signed for the given `update_build`.
+    pub fn verify_build(&self, update_build: &str) -> Result<(), &'static str> {
+        if self.build_name == update_build {
+            Ok(())
+        } else {
+            error!("ota_update_client: update manifest was signed for build {:?}, not {:?}.", self.build_name, update_build);
+            Err("update manifest was signed for a different update build")
+        }
+    }
+
+    /// Returns `Ok` if the given `file_name` is listed in this manifest
+    /// and the hash of the given `content` matches the hash listed for it.
+    pub fn verify_file(&self, file_name: &str, content: &[u8]) -> Result<(), &'static str> {
+        let expected_hash = self.hashes.get(file_name).ok_or_else(|| {
+            error!("ota_update_client: file {:?} is not listed in the signed update manifest.", file_name);
+            "file is not listed in the signed update manifest"
+        })?;
+        if verify_hash(content, expected_hash) {
+            Ok(())
+        } else {
+            error!("ota_update_client: file {:?} does not match the hash in the signed update manifest.", file_name);
+            Err("file does not match the hash in the signed update manifest")
+        }
+    }
+
+    /// Returns an iterator over the names of all files listed in this manifest.
+    pub fn file_names(&self) -> impl Iterator<Item = &String> {
+        self.hashes.keys()
+    }
+}
+
+
+/// Verifies that the given `manifest` file content was signed by one of the trusted public keys
+/// that were built into this image, using the given raw Ed25519 `signature`.
+/// 
+/// Returns the parsed `Manifest` if and only if the signature is valid. 
+pub fn verify_manifest(manifest: &[u8], signature: &[u8]) -> Result<Manifest, &'static str> {
+    let signature = Signature::from_bytes(signature).map_err(|_e| "update manifest signature was malformed")?;
+    let trusted_keys = trusted_public_keys()?;
+    if trusted_keys.is_empty() {
+        return Err("no trusted public keys were built into this image, so no update manifest can be verified");
+    }
+    if !trusted_keys.iter().any(|key| key.verify(manifest, &signature).is_ok()) {
+        return Err("update manifest was not signed by a trusted key");
+    }
+
+    let lines = as_lines(manifest)?;
+    let mut lines = lines.iter();
+    let build_name = match lines.next().map(|line| line.trim()) {
+        Some(line) if line.starts_with(MANIFEST_BUILD_PREFIX) => line[MANIFEST_BUILD_PREFIX.len() ..].trim().to_string(),
+        _ => return Err("update manifest did not begin with the name of its update build"),
+    };
+    if build_name.is_empty() {
+        return Err("update manifest had an empty update build name");
+    }
+    let mut hashes = BTreeMap::new();
+    for line in lines {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next(), parts.next()) {
+            (None, ..) => continue,
+            (Some(hash), Some(file_name), None) => {
+                hashes.insert(file_name.to_string(), hash.to_string());
+            }
+            _ => {
+                error!("ota_update_client: error parsing manifest line: {:?}", line);
+                return Err("error parsing update manifest line");
+            }
+        }
+    }
+    Ok(Manifest { build_name, hashes })
+}
+
+
+/// Parses the list of trusted public keys that were built into this image.
+fn trusted_public_keys() -> Result<Vec<PublicKey>, &'static str> {
+    TRUSTED_PUBLIC_KEYS.lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let bytes = decode_hex(line).ok_or("trusted public key was not a valid hex string")?;
+            PublicKey::from_bytes(&bytes).map_err(|_e| "trusted public key was not a valid Ed25519 public key")
+        })
+        .collect()
+}
+
+
+/// Decodes the given string of hexadecimal digit pairs into bytes.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0 .. hex.len()).step_by(2)
+        .map(|i| hex.get(i .. i + 2).and_then(|byte| u8::from_str_radix(byte, 16).ok()))
+        .collect()
+}
+
+
 /// Returns true if the SHA3 512-bit hash of the given `content` matches the given `hash` string.
 /// The `hash` string must be 64 hexadecimal characters, otherwise `false` will be returned. 
 fn verify_hash(content: &[u8], hash: &str) -> bool {
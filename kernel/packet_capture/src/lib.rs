@@ -0,0 +1,522 @@
+//! Captures Ethernet frames that pass through network devices and records them in the pcap format,
+//! such that they can be copied out of Theseus and opened with Wireshark or `tcpdump -r`.
+//!
+//! Network device glue layers (e.g., `ethernet_smoltcp_device`) call [`capture_frame()`](fn.capture_frame.html)
+//! on every frame they transmit or receive; this is nearly free when no capture is running.
+//! A capture is started with [`start()`](fn.start.html), which specifies a [`CaptureFilter`](struct.CaptureFilter.html)
+//! and a [`CaptureSink`](enum.CaptureSink.html) that determines whether frames are kept in an in-memory ring
+//! or written to a file.
+//! Frames destined for a file are only queued on the network device's path;
+//! a dedicated task writes them to the file, such that file I/O never happens while the capture lock is held.
+//! A capture is ended with [`stop()`](fn.stop.html), which returns the frames in the ring (if any).
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+#[macro_use] extern crate log;
+extern crate irq_safety;
+extern crate fs_node;
+extern crate hpet;
+extern crate wall_clock;
+extern crate spawn;
+extern crate wait_queue;
+extern crate smoltcp;
+
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::{
+    collections::VecDeque,
+    string::String,
+    sync::Arc,
+    vec::Vec,
+};
+use spawn::JoinHandle;
+use wait_queue::WaitQueue;
+use irq_safety::MutexIrqSafe;
+use fs_node::FileRef;
+use hpet::get_hpet;
+use smoltcp::wire::{
+    EthernetFrame, EthernetProtocol, IpAddress, IpProtocol,
+    Ipv4Packet, Ipv6Packet, TcpPacket, UdpPacket,
+};
+
+
+/// The magic number at the beginning of a pcap file with microsecond-resolution timestamps.
+const PCAP_MAGIC: u32 = 0xa1b2_c3d4;
+const PCAP_VERSION_MAJOR: u16 = 2;
+const PCAP_VERSION_MINOR: u16 = 4;
+/// The pcap link-layer header type for Ethernet frames.
+const LINKTYPE_ETHERNET: u32 = 1;
+/// The size of the pcap file header.
+pub const PCAP_FILE_HEADER_SIZE: usize = 24;
+/// The size of the header that precedes each frame in a pcap file.
+pub const PCAP_RECORD_HEADER_SIZE: usize = 16;
+
+/// The default maximum number of bytes captured from each frame.
+pub const DEFAULT_SNAPLEN: usize = 1518;
+/// The default number of frames kept in an in-memory capture ring.
+pub const DEFAULT_RING_FRAMES: usize = 1024;
+/// The maximum number of captured frames that can be waiting to be written to a capture file.
+/// Frames captured while this many are already waiting are dropped.
+pub const MAX_QUEUED_FILE_FRAMES: usize = 1024;
+
+
+/// Whether a capture is currently running. This is checked first to avoid taking the capture lock on every frame.
+static CAPTURING: AtomicBool = AtomicBool::new(false);
+
+/// The currently-running capture, if any.
+static CAPTURE: MutexIrqSafe<Option<Capture>> = MutexIrqSafe::new(None);
+
+
+/// The direction in which a frame was traveling through a network device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The frame was received by the device.
+    Received,
+    /// The frame was transmitted by the device.
+    Transmitted,
+}
+
+/// The criteria that a frame must meet to be captured. Fields that are `None` match every frame.
+#[derive(Debug, Clone, Default)]
+pub struct CaptureFilter {
+    /// Only capture frames traveling in this direction.
+    pub direction: Option<Direction>,
+    /// Only capture frames with this EtherType, e.g., ARP, IPv4 or IPv6.
+    pub ethertype: Option<EthernetProtocol>,
+    /// Only capture IP packets that carry this protocol, e.g., TCP, UDP or ICMP.
+    pub ip_protocol: Option<IpProtocol>,
+    /// Only capture IP packets sent from or to this address.
+    pub host: Option<IpAddress>,
+    /// Only capture TCP or UDP segments sent from or to this port.
+    pub port: Option<u16>,
+}
+
+impl CaptureFilter {
+    /// Returns true if the given `frame`, traveling in the given `direction`, meets all of this filter's criteria.
+    pub fn matches(&self, direction: Direction, frame: &[u8]) -> bool {
+        if self.direction.map_or(false, |d| d != direction) {
+            return false;
+        }
+        let needs_ip_info = self.ip_protocol.is_some() || self.host.is_some() || self.port.is_some();
+        if self.ethertype.is_none() && !needs_ip_info {
+            return true;
+        }
+
+        let eth_frame = match EthernetFrame::new_checked(frame) {
+            Ok(f) => f,
+            Err(_) => return false,
+        };
+        let ethertype = eth_frame.ethertype();
+        if self.ethertype.map_or(false, |e| e != ethertype) {
+            return false;
+        }
+        if !needs_ip_info {
+            return true;
+        }
+
+        let (src, dst, protocol, ip_payload): (IpAddress, IpAddress, IpProtocol, &[u8]) = match ethertype {
+            EthernetProtocol::Ipv4 => match Ipv4Packet::new_checked(eth_frame.payload()) {
+                Ok(p) => (p.src_addr().into(), p.dst_addr().into(), p.protocol(), &eth_frame.payload()[p.header_len() as usize ..]),
+                Err(_) => return false,
+            },
+            EthernetProtocol::Ipv6 => match Ipv6Packet::new_checked(eth_frame.payload()) {
+                Ok(p) => match ipv6_upper_layer(p.next_header(), &eth_frame.payload()[p.header_len() ..]) {
+                    Some((protocol, payload)) => (p.src_addr().into(), p.dst_addr().into(), protocol, payload),
+                    None => return false,
+                },
+                Err(_) => return false,
+            },
+            // non-IP frames can't meet any IP-level criteria
+            _ => return false,
+        };
+
+        if self.ip_protocol.map_or(false, |p| p != protocol) {
+            return false;
+        }
+        if self.host.map_or(false, |h| h != src && h != dst) {
+            return false;
+        }
+        if let Some(port) = self.port {
+            let ports = match protocol {
+                IpProtocol::Tcp => TcpPacket::new_checked(ip_payload).ok().map(|p| (p.src_port(), p.dst_port())),
+                IpProtocol::Udp => UdpPacket::new_checked(ip_payload).ok().map(|p| (p.src_port(), p.dst_port())),
+                _ => None,
+            };
+            match ports {
+                Some((src_port, dst_port)) if src_port == port || dst_port == port => { }
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+
+/// Skips the chain of IPv6 extension headers that starts with the given `next_header`,
+/// and returns the upper-layer protocol along with its header and payload.
+///
+/// The returned payload is empty for a fragment other than the first one, which doesn't contain the upper-layer header.
+/// Returns `None` if an extension header is truncated.
+fn ipv6_upper_layer(mut next_header: IpProtocol, mut payload: &[u8]) -> Option<(IpProtocol, &[u8])> {
+    loop {
+        let header_len = match next_header {
+            // Hop-by-Hop Options, Routing, and Destination Options headers: the length is in 8-byte units, excluding the first 8 bytes.
+            IpProtocol::HopByHop | IpProtocol::Ipv6Route | IpProtocol::Ipv6Opts => {
+                (*payload.get(1)? as usize + 1) * 8
+            }
+            // The Fragment header has a fixed length.
+            IpProtocol::Ipv6Frag => {
+                if payload.len() < 8 {
+                    return None;
+                }
+                let fragment_offset = u16::from_be_bytes([payload[2], payload[3]]) >> 3;
+                if fragment_offset != 0 {
+                    return Some((IpProtocol::from(payload[0]), &payload[.. 0]));
+                }
+                8
+            }
+            // The Authentication header: the length is in 4-byte units, excluding the first 8 bytes.
+            IpProtocol::Unknown(51) => (*payload.get(1)? as usize + 2) * 4,
+            upper_layer => return Some((upper_layer, payload)),
+        };
+        if payload.len() < header_len {
+            return None;
+        }
+        next_header = IpProtocol::from(payload[0]);
+        payload = &payload[header_len ..];
+    }
+}
+
+
+/// Where captured frames are recorded.
+pub enum CaptureSink {
+    /// Frames are kept in an in-memory ring of at most `max_frames` frames,
+    /// in which the oldest frames are overwritten once it is full.
+    Ring { max_frames: usize },
+    /// Frames are appended in pcap format to the given file by a separate task shortly after they are captured.
+    /// Any existing content of the file is overwritten.
+    File(FileRef),
+}
+
+/// The configuration of a capture.
+pub struct CaptureConfig {
+    /// Which frames should be captured.
+    pub filter: CaptureFilter,
+    /// The maximum number of bytes captured from each frame; longer frames are truncated.
+    pub snaplen: usize,
+    /// Where captured frames are recorded.
+    pub sink: CaptureSink,
+}
+
+impl Default for CaptureConfig {
+    fn default() -> CaptureConfig {
+        CaptureConfig {
+            filter: CaptureFilter::default(),
+            snaplen: DEFAULT_SNAPLEN,
+            sink: CaptureSink::Ring { max_frames: DEFAULT_RING_FRAMES },
+        }
+    }
+}
+
+/// Statistics about a capture.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureStats {
+    /// The number of frames that matched the filter and were recorded.
+    pub captured: usize,
+    /// The number of frames that were seen but did not match the filter.
+    pub filtered_out: usize,
+    /// The number of captured frames that were lost, either overwritten in a full ring,
+    /// or not written to the file because too many frames were waiting to be written or the write failed.
+    pub dropped: usize,
+}
+
+/// A single captured frame.
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    /// The time at which this frame was captured, in microseconds since the Unix epoch.
+    pub timestamp_micros: u64,
+    /// The direction in which this frame was traveling.
+    pub direction: Direction,
+    /// The length of the frame as it was sent or received.
+    pub original_len: usize,
+    /// The captured bytes of the frame, at most `snaplen` in length.
+    pub data: Vec<u8>,
+}
+
+/// The result of a finished capture.
+pub struct CaptureResult {
+    /// The frames that were kept in the ring; this is empty if the capture was written to a file.
+    pub frames: Vec<CapturedFrame>,
+    /// The statistics of the capture.
+    pub stats: CaptureStats,
+    /// The snapshot length used for the capture.
+    pub snaplen: usize,
+}
+
+impl CaptureResult {
+    /// Returns the contents of a pcap file containing all of the frames in this capture.
+    pub fn to_pcap_bytes(&self) -> Vec<u8> {
+        let size = PCAP_FILE_HEADER_SIZE + self.frames.iter().map(|f| PCAP_RECORD_HEADER_SIZE + f.data.len()).sum::<usize>();
+        let mut bytes = Vec::with_capacity(size);
+        bytes.extend_from_slice(&pcap_file_header(self.snaplen));
+        for frame in &self.frames {
+            bytes.extend_from_slice(&pcap_record_header(frame));
+            bytes.extend_from_slice(&frame.data);
+        }
+        bytes
+    }
+
+    /// Writes all of the frames in this capture to the given `file` in pcap format,
+    /// overwriting its existing content. Returns the number of bytes written.
+    pub fn write_to_file(&self, file: &FileRef) -> Result<usize, &'static str> {
+        file.lock().write(&self.to_pcap_bytes(), 0)
+    }
+}
+
+
+/// The state of the currently-running capture.
+struct Capture {
+    filter: CaptureFilter,
+    snaplen: usize,
+    sink: SinkState,
+    stats: CaptureStats,
+    /// The HPET counter value when the capture started.
+    start_ticks: u64,
+    /// The HPET counter period.
+    hpet_period_femtoseconds: u64,
+    /// The wall-clock time when the capture started, in microseconds since the Unix epoch.
+    start_micros: u64,
+}
+
+enum SinkState {
+    Ring {
+        frames: VecDeque<CapturedFrame>,
+        max_frames: usize,
+    },
+    File {
+        writer: Arc<FileWriter>,
+        /// The task that writes the queued frames to the file.
+        writer_task: JoinHandle<()>,
+    },
+}
+
+/// The state shared between a capture with a file sink and the task that writes its frames to the file.
+struct FileWriter {
+    /// The frames that have been captured but not yet written to the file.
+    /// This is bounded by `MAX_QUEUED_FILE_FRAMES` and never locked during file I/O.
+    queue: MutexIrqSafe<VecDeque<CapturedFrame>>,
+    /// The writer task waits on this until frames are queued or the capture is stopped.
+    wait_queue: WaitQueue,
+    /// Set when the capture is stopped, after which the writer task writes the remaining frames and exits.
+    stopped: AtomicBool,
+    /// The number of frames that the writer task failed to write.
+    failed_writes: AtomicUsize,
+}
+
+
+/// Starts a new capture with the given configuration.
+///
+/// Returns an error if a capture is already running, or if the pcap file header couldn't be written to the file sink.
+pub fn start(config: CaptureConfig) -> Result<(), &'static str> {
+    let mut capture = CAPTURE.lock();
+    if capture.is_some() {
+        return Err("packet_capture: a capture is already running");
+    }
+    if config.snaplen == 0 {
+        return Err("packet_capture: snaplen must be greater than zero");
+    }
+
+    let (start_ticks, hpet_period_femtoseconds) = {
+        let hpet = get_hpet();
+        let hpet = hpet.as_ref().ok_or("packet_capture: couldn't get HPET timer")?;
+        (hpet.get_counter(), hpet.counter_period_femtoseconds() as u64)
+    };
+    let start_micros = wall_clock::now_micros()?;
+
+    let sink = match config.sink {
+        CaptureSink::Ring { max_frames } => {
+            if max_frames == 0 {
+                return Err("packet_capture: ring must hold at least one frame");
+            }
+            SinkState::Ring { frames: VecDeque::new(), max_frames }
+        }
+        CaptureSink::File(file) => {
+            let offset = file.lock().write(&pcap_file_header(config.snaplen), 0)?;
+            let writer = Arc::new(FileWriter {
+                queue: MutexIrqSafe::new(VecDeque::with_capacity(MAX_QUEUED_FILE_FRAMES)),
+                wait_queue: WaitQueue::new(),
+                stopped: AtomicBool::new(false),
+                failed_writes: AtomicUsize::new(0),
+            });
+            let writer_task = spawn::new_task_builder(file_writer_task, (writer.clone(), file, offset))
+                .name(String::from("packet_capture_writer"))
+                .spawn()?;
+            SinkState::File { writer, writer_task }
+        }
+    };
+
+    *capture = Some(Capture {
+        filter: config.filter,
+        snaplen: config.snaplen,
+        sink,
+        stats: CaptureStats::default(),
+        start_ticks,
+        hpet_period_femtoseconds,
+        start_micros,
+    });
+    CAPTURING.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Stops the currently-running capture and returns its results.
+///
+/// With a file sink, this waits for all captured frames to be written to the file.
+/// Returns `None` if no capture was running.
+pub fn stop() -> Option<CaptureResult> {
+    CAPTURING.store(false, Ordering::SeqCst);
+    let mut capture = CAPTURE.lock().take()?;
+    let frames = match capture.sink {
+        SinkState::Ring { frames, .. } => frames.into_iter().collect(),
+        SinkState::File { writer, writer_task } => {
+            writer.stopped.store(true, Ordering::SeqCst);
+            writer.wait_queue.notify_one();
+            if let Err(e) = writer_task.join() {
+                error!("packet_capture: couldn't join the capture file writer task: {}", e);
+            }
+            capture.stats.dropped += writer.failed_writes.load(Ordering::SeqCst);
+            Vec::new()
+        }
+    };
+    Some(CaptureResult {
+        frames,
+        stats: capture.stats,
+        snaplen: capture.snaplen,
+    })
+}
+
+/// Returns whether a capture is currently running.
+pub fn is_capturing() -> bool {
+    CAPTURING.load(Ordering::Relaxed)
+}
+
+/// Returns the statistics of the currently-running capture, if any.
+pub fn stats() -> Option<CaptureStats> {
+    CAPTURE.lock().as_ref().map(|c| c.stats)
+}
+
+
+/// Records the given Ethernet `frame` in the current capture, if one is running and the frame matches its filter.
+///
+/// This is invoked by network device glue layers for every frame that is transmitted or received.
+pub fn capture_frame(direction: Direction, frame: &[u8]) {
+    if !CAPTURING.load(Ordering::Relaxed) {
+        return;
+    }
+
+    let mut locked_capture = CAPTURE.lock();
+    let capture = match locked_capture.as_mut() {
+        Some(c) => c,
+        None => return,
+    };
+
+    if !capture.filter.matches(direction, frame) {
+        capture.stats.filtered_out += 1;
+        return;
+    }
+
+    let now_ticks = get_hpet().as_ref().map(|h| h.get_counter()).unwrap_or(capture.start_ticks);
+    let elapsed_ticks = now_ticks.wrapping_sub(capture.start_ticks) as u128;
+    let elapsed_micros = (elapsed_ticks * capture.hpet_period_femtoseconds as u128 / 1_000_000_000) as u64;
+    let captured_frame = CapturedFrame {
+        timestamp_micros: capture.start_micros + elapsed_micros,
+        direction,
+        original_len: frame.len(),
+        data: frame[.. core::cmp::min(frame.len(), capture.snaplen)].to_vec(),
+    };
+    capture.stats.captured += 1;
+
+    match capture.sink {
+        SinkState::Ring { ref mut frames, max_frames } => {
+            if frames.len() >= max_frames {
+                frames.pop_front();
+                capture.stats.dropped += 1;
+            }
+            frames.push_back(captured_frame);
+        }
+        SinkState::File { ref writer, .. } => {
+            let mut queue = writer.queue.lock();
+            if queue.len() >= MAX_QUEUED_FILE_FRAMES {
+                capture.stats.dropped += 1;
+            } else {
+                queue.push_back(captured_frame);
+                drop(queue);
+                writer.wait_queue.notify_one();
+            }
+        }
+    }
+}
+
+
+/// The task that writes the frames queued by a capture with a file sink to that file,
+/// starting at the given offset, until the capture is stopped.
+fn file_writer_task((writer, file, mut offset): (Arc<FileWriter>, FileRef, usize)) {
+    loop {
+        let frames = writer.wait_queue.wait_until(&|| {
+            let mut queue = writer.queue.lock();
+            if !queue.is_empty() {
+                Some(queue.drain(..).collect::<Vec<_>>())
+            } else if writer.stopped.load(Ordering::SeqCst) {
+                Some(Vec::new())
+            } else {
+                None
+            }
+        });
+        let frames = match frames {
+            Ok(frames) => frames,
+            Err(_e) => {
+                error!("packet_capture: capture file writer failed to wait for frames: {:?}", _e);
+                return;
+            }
+        };
+        if frames.is_empty() {
+            // The capture was stopped and all of its frames have been written.
+            return;
+        }
+
+        let mut records = Vec::with_capacity(frames.iter().map(|f| PCAP_RECORD_HEADER_SIZE + f.data.len()).sum());
+        for frame in &frames {
+            records.extend_from_slice(&pcap_record_header(frame));
+            records.extend_from_slice(&frame.data);
+        }
+        match file.lock().write(&records, offset) {
+            Ok(written) => offset += written,
+            Err(_e) => {
+                writer.failed_writes.fetch_add(frames.len(), Ordering::SeqCst);
+                error!("packet_capture: failed to write {} frames to capture file: {}", frames.len(), _e);
+            }
+        }
+    }
+}
+
+
+/// Returns the pcap file header for a capture of Ethernet frames with the given `snaplen`.
+fn pcap_file_header(snaplen: usize) -> [u8; PCAP_FILE_HEADER_SIZE] {
+    let mut header = [0u8; PCAP_FILE_HEADER_SIZE];
+    header[0..4].copy_from_slice(&PCAP_MAGIC.to_le_bytes());
+    header[4..6].copy_from_slice(&PCAP_VERSION_MAJOR.to_le_bytes());
+    header[6..8].copy_from_slice(&PCAP_VERSION_MINOR.to_le_bytes());
+    // bytes 8..16 are the timezone offset and timestamp accuracy, which are always zero
+    header[16..20].copy_from_slice(&(snaplen as u32).to_le_bytes());
+    header[20..24].copy_from_slice(&LINKTYPE_ETHERNET.to_le_bytes());
+    header
+}
+
+/// Returns the pcap record header that precedes the given `frame`'s data.
+fn pcap_record_header(frame: &CapturedFrame) -> [u8; PCAP_RECORD_HEADER_SIZE] {
+    let mut header = [0u8; PCAP_RECORD_HEADER_SIZE];
+    header[0..4].copy_from_slice(&((frame.timestamp_micros / 1_000_000) as u32).to_le_bytes());
+    header[4..8].copy_from_slice(&((frame.timestamp_micros % 1_000_000) as u32).to_le_bytes());
+    header[8..12].copy_from_slice(&(frame.data.len() as u32).to_le_bytes());
+    header[12..16].copy_from_slice(&(frame.original_len as u32).to_le_bytes());
+    header
+}
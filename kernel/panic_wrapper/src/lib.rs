@@ -89,12 +89,17 @@ pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
     }
     error!("------------------------------------------------------------------");
 
+    // A task that ran out of its heap quota panics in the allocation error handler,
+    // but it's killed for exceeding its quota rather than for a regular panic.
+    let cause = task::heap_quota_exceeded_reason()
+        .unwrap_or_else(|| KillReason::Panic(PanicInfoOwned::from(panic_info)));
+
     // Call this task's kill handler, if it has one.
     {
         let kill_handler = task::get_my_current_task().and_then(|t| t.take_kill_handler());
         if let Some(ref kh_func) = kill_handler {
             debug!("Found kill handler callback to invoke in Task {:?}", task::get_my_current_task());
-            kh_func(&KillReason::Panic(PanicInfoOwned::from(panic_info)));
+            kh_func(&cause);
         }
         else {
             debug!("No kill handler callback in Task {:?}", task::get_my_current_task());
@@ -103,7 +108,6 @@ pub fn panic_wrapper(panic_info: &PanicInfo) -> Result<(), &'static str> {
 
     // Start the unwinding process
     {
-        let cause = KillReason::Panic(PanicInfoOwned::from(panic_info));
         match unwind::start_unwinding(cause, 5) {
             Ok(_) => {
                 warn!("BUG: start_unwinding() returned an Ok() value, which is unexpected because it means no unwinding actually occurred. Task: {:?}.", task::get_my_current_task());
@@ -9,19 +9,24 @@
 extern crate spin;
 extern crate dfqueue;
 extern crate event_types;
+extern crate wait_queue;
 
 use core::fmt;
+use alloc::sync::Arc;
 use spin::Once;
 use dfqueue::DFQueueProducer;
 use event_types::Event;
+use wait_queue::WaitQueue;
 
 
-/// The kernel's default destination for print/println invocations
-static DEFAULT_PRINT_OUTPUT: Once<DFQueueProducer<Event>> = Once::new();
+/// The kernel's default destination for print/println invocations,
+/// along with the wait queue that is notified after each message is enqueued.
+static DEFAULT_PRINT_OUTPUT: Once<(DFQueueProducer<Event>, Arc<WaitQueue>)> = Once::new();
 
-/// Gives the kernel an endpoint (queue producer) to which it can send messages to be printed
-pub fn set_default_print_output(producer: DFQueueProducer<Event>) {
-    DEFAULT_PRINT_OUTPUT.call_once(|| producer);
+/// Gives the kernel an endpoint (queue producer) to which it can send messages to be printed,
+/// and the `wait_queue` that will be notified whenever a message has been sent to that endpoint.
+pub fn set_default_print_output(producer: DFQueueProducer<Event>, wait_queue: Arc<WaitQueue>) {
+    DEFAULT_PRINT_OUTPUT.call_once(|| (producer, wait_queue));
 }
 
 /// Calls `print!()` with an extra newilne `\n` appended to the end. 
@@ -43,7 +48,8 @@ macro_rules! print {
 /// Enqueues the given `fmt_args` as a String onto the default printing output queue,
 /// which is typically the default terminal application's input queue
 pub fn print_to_default_output(fmt_args: fmt::Arguments) {
-    if let Some(q) = DEFAULT_PRINT_OUTPUT.try() {
+    if let Some((q, wait_queue)) = DEFAULT_PRINT_OUTPUT.try() {
         let _ = q.enqueue(Event::new_output_event(format!("{}", fmt_args)));
+        wait_queue.notify_one();
     }
 }
@@ -0,0 +1,333 @@
+//! A directory of crate object files that is lazily populated from a network server,
+//! which can back a `CrateNamespace` via `mod_mgmt::NamespaceDir`.
+//! 
+//! When a `CrateNamespace` looks for a crate object file that isn't yet present in a `RemoteDirectory`,
+//! e.g., when loading a crate or resolving one of its dependencies, 
+//! that file is fetched on demand from a [`RemoteSource`](trait.RemoteSource.html), 
+//! i.e., a TFTP server or an HTTP server with the same layout as the OTA update build server.
+//! The file's content is only fetched once it's first read, not when the file is looked up,
+//! such that the network transfer doesn't happen while the directory is locked.
+//! 
+//! The list of files available on the server is obtained from the `listing.txt` file in the server's base directory
+//! when the directory is created, which contains one crate object file name per line, e.g., "k#keyboard-36be916209949cef.o".
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+extern crate spin;
+extern crate fs_node;
+extern crate memfs;
+extern crate memory;
+extern crate mod_mgmt;
+extern crate smoltcp_helper;
+extern crate tftp_client;
+extern crate ota_update_client;
+extern crate smoltcp;
+
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use spin::{Mutex, Once};
+use fs_node::{DirRef, WeakDirRef, Directory, File, FileOrDir, FileRef, FsNode};
+use memory::MappedPages;
+use memfs::MemFile;
+use mod_mgmt::{CrateNamespace, NamespaceDir};
+use smoltcp::wire::IpEndpoint;
+use tftp_client::TftpConfig;
+
+
+/// The name of the file in a server's base directory that lists all of the available crate object files.
+const LISTING_FILE_NAME: &'static str = "listing.txt";
+
+
+/// A network server from which files can be fetched.
+pub trait RemoteSource {
+    /// Returns the names of all files available from this source.
+    fn list(&self) -> Result<Vec<String>, &'static str>;
+
+    /// Fetches the content of the file with the given name from this source.
+    fn fetch(&self, file_name: &str) -> Result<Vec<u8>, &'static str>;
+}
+
+
+/// A `RemoteSource` that fetches files from a directory on a TFTP server.
+pub struct TftpSource {
+    /// The endpoint of the TFTP server.
+    pub server: IpEndpoint,
+    /// The directory on the server containing the files, which may be empty.
+    pub base_path: String,
+    /// The settings used for each transfer.
+    pub config: TftpConfig,
+}
+
+impl TftpSource {
+    fn path_of(&self, file_name: &str) -> String {
+        if self.base_path.is_empty() {
+            file_name.to_string()
+        } else {
+            format!("{}/{}", self.base_path.trim_end_matches('/'), file_name)
+        }
+    }
+}
+
+impl RemoteSource for TftpSource {
+    fn list(&self) -> Result<Vec<String>, &'static str> {
+        let listing = self.fetch(LISTING_FILE_NAME)?;
+        ota_update_client::as_lines(&listing)
+    }
+
+    fn fetch(&self, file_name: &str) -> Result<Vec<u8>, &'static str> {
+        let iface = smoltcp_helper::get_iface_for(self.server.addr)?;
+        tftp_client::get(&iface, self.server, &self.path_of(file_name), &self.config)
+    }
+}
+
+
+/// A `RemoteSource` that fetches files from an update build on an HTTP server,
+/// e.g., the build server used for OTA updates.
+pub struct HttpSource {
+    /// The endpoint of the HTTP server.
+    pub server: IpEndpoint,
+    /// The name of the update build directory on the server that contains the files.
+    pub update_build: String,
+}
+
+impl RemoteSource for HttpSource {
+    fn list(&self) -> Result<Vec<String>, &'static str> {
+        let iface = smoltcp_helper::get_iface_for(self.server.addr)?;
+        ota_update_client::download_listing(&iface, self.server, &self.update_build)
+    }
+
+    fn fetch(&self, file_name: &str) -> Result<Vec<u8>, &'static str> {
+        let iface = smoltcp_helper::get_iface_for(self.server.addr)?;
+        ota_update_client::download_update_file(&iface, self.server, &self.update_build, file_name)
+    }
+}
+
+
+/// A directory whose files are fetched from a `RemoteSource` the first time they are accessed,
+/// and are then kept in memory as `MemFile`s.
+/// 
+/// Files can also be inserted into and removed from it like a regular directory.
+pub struct RemoteDirectory {
+    /// The name of the directory.
+    name: String,
+    /// A weak reference to the parent directory.
+    parent: WeakDirRef,
+    /// A weak reference to this directory, which is the parent of every file it fetches.
+    self_ref: WeakDirRef,
+    /// The source from which missing files are fetched, which is shared with the files that have yet to be fetched.
+    source: Arc<dyn RemoteSource + Send + Sync>,
+    /// The prefix that is prepended onto a file's name in this directory to form its name on the source,
+    /// e.g., "k#" for a kernel crate namespace whose files are named "k#crate-hash.o" on the server.
+    remote_prefix: String,
+    /// The files and directories that have already been looked up or inserted.
+    /// This is behind a lock because remote files are added lazily from within `get()`.
+    children: Mutex<BTreeMap<String, FileOrDir>>,
+    /// The names (without the `remote_prefix`) of the files available from the source,
+    /// or `None` if the listing couldn't be obtained, in which case any file is assumed to be available.
+    remote_names: Option<BTreeSet<String>>,
+}
+
+impl RemoteDirectory {
+    /// Creates a new directory in the given `parent` directory whose files are lazily fetched from the given `source`.
+    /// 
+    /// Only files on the source whose names start with the given `remote_prefix` appear in this directory, 
+    /// with that prefix removed. 
+    /// 
+    /// This obtains the listing of files from the source, before the new directory is locked or reachable by anyone else.
+    pub fn new(
+        name: String,
+        parent: &DirRef,
+        source: Box<dyn RemoteSource + Send + Sync>,
+        remote_prefix: String,
+    ) -> Result<DirRef, &'static str> {
+        let remote_names = match source.list() {
+            Ok(names) => Some(names.iter()
+                .map(|n| n.trim())
+                .filter(|n| n.starts_with(remote_prefix.as_str()))
+                .map(|n| n[remote_prefix.len() ..].to_string())
+                .collect()
+            ),
+            Err(e) => {
+                warn!("RemoteDirectory {:?}: couldn't obtain the listing of remote files: {}", name, e);
+                None
+            }
+        };
+        let directory = Arc::new(Mutex::new(RemoteDirectory {
+            name,
+            parent: Arc::downgrade(parent),
+            self_ref: Weak::<Mutex<RemoteDirectory>>::new(),
+            source: Arc::from(source),
+            remote_prefix,
+            children: Mutex::new(BTreeMap::new()),
+            remote_names,
+        }));
+        let self_ref: WeakDirRef = Arc::downgrade(&directory);
+        directory.lock().self_ref = self_ref;
+        let dir_ref = directory as DirRef;
+        parent.lock().insert(FileOrDir::Dir(dir_ref.clone()))?;
+        Ok(dir_ref)
+    }
+
+}
+
+impl Directory for RemoteDirectory {
+    /// Returns the file with the given `name`, which is added to this directory as a [`RemoteFile`](struct.RemoteFile.html)
+    /// that fetches its content when it's first read, if the source has it.
+    fn get(&self, name: &str) -> Option<FileOrDir> {
+        let mut children = self.children.lock();
+        if let Some(node) = children.get(name) {
+            return Some(node.clone());
+        }
+        // Don't add files that the source is known not to have. 
+        if let Some(ref remote_names) = self.remote_names {
+            if !remote_names.contains(name) {
+                return None;
+            }
+        }
+        let file = RemoteFile {
+            name: name.to_string(),
+            remote_name: format!("{}{}", self.remote_prefix, name),
+            parent: self.self_ref.clone(),
+            source: self.source.clone(),
+            content: Once::new(),
+        };
+        let node = FileOrDir::File(Arc::new(Mutex::new(file)) as FileRef);
+        children.insert(name.to_string(), node.clone());
+        Some(node)
+    }
+
+    fn insert(&mut self, node: FileOrDir) -> Result<Option<FileOrDir>, &'static str> {
+        let name = node.get_name();
+        if let Some(mut old_node) = self.children.lock().insert(name, node) {
+            old_node.set_parent_dir(Weak::<Mutex<RemoteDirectory>>::new());
+            Ok(Some(old_node))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn remove(&mut self, node: &FileOrDir) -> Option<FileOrDir> {
+        if let Some(mut old_node) = self.children.lock().remove(&node.get_name()) {
+            old_node.set_parent_dir(Weak::<Mutex<RemoteDirectory>>::new());
+            Some(old_node)
+        } else {
+            None
+        }
+    }
+
+    /// Lists both the files already present in this directory and those available from the source.
+    fn list(&self) -> Vec<String> {
+        let mut names: BTreeSet<String> = self.children.lock().keys().cloned().collect();
+        if let Some(ref remote_names) = self.remote_names {
+            names.extend(remote_names.iter().cloned());
+        }
+        names.into_iter().collect()
+    }
+}
+
+impl FsNode for RemoteDirectory {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+}
+
+
+/// A read-only file in a `RemoteDirectory` whose content is fetched from the directory's source
+/// the first time it's accessed, and is then kept in memory.
+/// 
+/// Fetching happens while only this file is locked, not its directory.
+/// If fetching fails, the file's content remains unavailable and every access returns that error.
+pub struct RemoteFile {
+    /// The name of the file.
+    name: String,
+    /// The name of the file on the source, including the directory's `remote_prefix`.
+    remote_name: String,
+    /// A weak reference to the parent directory.
+    parent: WeakDirRef,
+    /// The source from which the content is fetched.
+    source: Arc<dyn RemoteSource + Send + Sync>,
+    /// The fetched content, or the error that occurred while fetching it.
+    content: Once<Result<MemFile, &'static str>>,
+}
+
+impl RemoteFile {
+    /// Returns the content of this file, fetching it from the source if it hasn't been fetched yet.
+    fn content(&self) -> Result<&MemFile, &'static str> {
+        let content = self.content.call_once(|| {
+            let content = self.source.fetch(&self.remote_name)
+                .map_err(|e| { warn!("RemoteFile {:?}: couldn't fetch content: {}", self.name, e); e })?;
+            info!("RemoteFile {:?}: fetched {} bytes", self.name, content.len());
+            MemFile::with_content(self.name.clone(), &content, self.parent.clone())
+        });
+        content.as_ref().map_err(|e| *e)
+    }
+}
+
+impl File for RemoteFile {
+    fn read(&self, buffer: &mut [u8], offset: usize) -> Result<usize, &'static str> {
+        self.content()?.read(buffer, offset)
+    }
+
+    fn write(&mut self, _buffer: &[u8], _offset: usize) -> Result<usize, &'static str> {
+        Err("RemoteFile: files fetched from a remote source are read-only")
+    }
+
+    /// Returns the size of this file, or 0 if its content couldn't be fetched.
+    fn size(&self) -> usize {
+        self.content().map(|c| c.size()).unwrap_or(0)
+    }
+
+    fn as_mapping(&self) -> Result<&MappedPages, &'static str> {
+        self.content()?.as_mapping()
+    }
+}
+
+impl FsNode for RemoteFile {
+    fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn get_parent_dir(&self) -> Option<DirRef> {
+        self.parent.upgrade()
+    }
+
+    fn set_parent_dir(&mut self, new_parent: WeakDirRef) {
+        self.parent = new_parent;
+    }
+}
+
+
+/// Creates a new `CrateNamespace` whose crate object files are lazily fetched from the given `source`.
+/// 
+/// The namespace's `RemoteDirectory` is created within the top-level namespaces directory. 
+/// 
+/// # Arguments
+/// * `name`: the name of the new namespace and its directory.
+/// * `source`: the server from which crate object files are fetched.
+/// * `remote_prefix`: the crate type prefix of the files on the server that belong to this namespace, e.g., "k#" or "a#".
+/// * `recursive_namespace`: the namespace that the new namespace is built atop, if any.
+pub fn create_remote_namespace(
+    name: String,
+    source: Box<dyn RemoteSource + Send + Sync>,
+    remote_prefix: String,
+    recursive_namespace: Option<Arc<CrateNamespace>>,
+) -> Result<Arc<CrateNamespace>, &'static str> {
+    let namespaces_dir = mod_mgmt::get_namespaces_directory().ok_or("couldn't find the top-level namespaces directory")?;
+    let dir = RemoteDirectory::new(name.clone(), &namespaces_dir, source, remote_prefix)?;
+    Ok(Arc::new(CrateNamespace::new(name, NamespaceDir::new(dir), recursive_namespace)))
+}
@@ -26,15 +26,19 @@ extern crate irq_safety;
 extern crate wait_queue;
 extern crate task;
 extern crate scheduler;
+extern crate sleep;
 
 #[cfg(downtime_eval)]
 extern crate hpet;
 
 use core::fmt;
+use core::time::Duration;
 use alloc::sync::Arc;
 use irq_safety::MutexIrqSafe;
 use spin::Mutex;
 use wait_queue::{WaitQueue, WaitGuard, WaitError};
+use task::TaskRef;
+use sleep::{Instant, TimerHandle};
 
 
 /// A wrapper type for an `ExchangeSlot` that is used for sending only.
@@ -118,6 +122,26 @@ impl<T> fmt::Debug for ExchangeState<T> {
     }
 }
 
+/// Errors returned by the `*_timeout` and `*_deadline` variants of receiving a message.
+#[derive(Debug, PartialEq)]
+pub enum TimedReceiveError {
+    /// No sender arrived before the deadline.
+    Timeout,
+    /// Any other error, the same as those returned by [`Receiver::receive()`](struct.Receiver.html#method.receive).
+    Other(&'static str),
+}
+impl From<&'static str> for TimedReceiveError {
+    fn from(e: &'static str) -> TimedReceiveError {
+        TimedReceiveError::Other(e)
+    }
+}
+
+/// Adds a kernel timer that unblocks the given `task` at the given `deadline`.
+fn add_wakeup_timer(task: &TaskRef, deadline: Instant) -> TimerHandle {
+    let task_to_wakeup = task.clone();
+    sleep::add_oneshot_timer(deadline, move || task_to_wakeup.unblock())
+}
+
 // enum RendezvousState<T> {
 //     /// Initial state: we're waiting for either a sender or a receiver.
 //     Init,
@@ -179,15 +203,18 @@ impl<T: Send> Channel<T> {
         res
     }
     
-    /// Obtain a receiver slot, blocking until one is available.
-    fn take_receiver_slot(&self) -> Result<ReceiverSlot<T>, WaitError> {
+    /// Obtain a receiver slot, blocking until one is available or until the optional `deadline`.
+    fn take_receiver_slot(&self, deadline: Option<Instant>) -> Result<ReceiverSlot<T>, WaitError> {
         // Fast path: the uncontended case.
         if let Some(s) = self.try_take_receiver_slot() {
             return Ok(s);
         }
         // Slow path: add ourselves to the waitqueue
         // trace!("waiting to acquire receiver slot...");
-        let res = self.waiting_receivers.wait_until(&|| self.try_take_receiver_slot());
+        let res = match deadline {
+            Some(d) => self.waiting_receivers.wait_until_deadline(&|| self.try_take_receiver_slot(), d),
+            None => self.waiting_receivers.wait_until(&|| self.try_take_receiver_slot()),
+        };
         // trace!("... acquired receiver slot!");
         res
     }
@@ -251,6 +278,10 @@ impl <T: Send> Sender<T> {
                     // Hold interrupts to avoid blocking & descheduling this task until we release the slot lock,
                     // which is currently done automatically because the slot uses a MutexIrqSafe.
                     *exchange_state = ExchangeState::WaitingForReceiver(WaitGuard::new(curr_task.clone()), msg);
+                    // Notify a receiver that may be waiting for any one of several channels (e.g., using `select`),
+                    // which isn't holding this channel's receiver slot and thus can't otherwise know that we arrived.
+                    // This must be done while the slot lock is held, since this task is already blocked.
+                    self.channel.waiting_receivers.notify_one();
                     None
                 }
                 ExchangeState::WaitingForSender(receiver_to_notify) => {
@@ -381,14 +412,38 @@ impl <T: Send> Receiver<T> {
     /// Returns the message if it was received properly,
     /// otherwise returns an error.
     pub fn receive(&self) -> Result<T, &'static str> {
+        self.receive_internal(None).map_err(|e| match e {
+            TimedReceiveError::Other(e) => e,
+            TimedReceiveError::Timeout => "BUG: receive without a deadline timed out",
+        })
+    }
+
+    /// Similar to [`receive`](#method.receive), but gives up and returns `TimedReceiveError::Timeout`
+    /// if no sender arrived within the given `timeout`.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, TimedReceiveError> {
+        self.receive_internal(Some(Instant::now() + timeout))
+    }
+
+    /// Similar to [`receive`](#method.receive), but gives up and returns `TimedReceiveError::Timeout`
+    /// if no sender arrived by the given `deadline`.
+    pub fn receive_deadline(&self, deadline: Instant) -> Result<T, TimedReceiveError> {
+        self.receive_internal(Some(deadline))
+    }
+
+    /// The internal routine for receiving a message, which blocks until the optional `deadline`.
+    fn receive_internal(&self, deadline: Option<Instant>) -> Result<T, TimedReceiveError> {
         // trace!("rendezvous: receive() entry");
         let curr_task = task::get_my_current_task().ok_or("couldn't get current task")?;
         
         // obtain a receiver-side exchange slot, blocking if necessary
-        let receiver_slot = self.channel.take_receiver_slot().map_err(|_| "failed to take_receiver_slot")?;
+        let receiver_slot = self.channel.take_receiver_slot(deadline).map_err(|e| match e {
+            WaitError::Timeout => TimedReceiveError::Timeout,
+            _ => TimedReceiveError::Other("failed to take_receiver_slot"),
+        })?;
 
         // Here, either the receiver (this task) arrived first and needs to wait for a sender,
         // or a sender has already arrived and is waiting for a receiver. 
+        let mut wakeup_timer = None;
         let retval = {
             let mut exchange_state = receiver_slot.0.lock();
             // Temporarily take ownership of the channel's waiting state so we can modify it;
@@ -399,6 +454,8 @@ impl <T: Send> Receiver<T> {
                     // Hold interrupts to avoid blocking & descheduling this task until we release the slot lock,
                     // which is currently done automatically because the slot uses a MutexIrqSafe.
                     *exchange_state = ExchangeState::WaitingForSender(WaitGuard::new(curr_task.clone()));
+                    // For the same reason, the wakeup timer must be added before releasing the slot lock.
+                    wakeup_timer = deadline.map(|d| add_wakeup_timer(curr_task, d));
                     None
                 }
                 ExchangeState::WaitingForReceiver(sender_to_notify, msg) => {
@@ -430,29 +487,58 @@ impl <T: Send> Receiver<T> {
                 // Restore the receiver slot and notify waiting receivers.
                 self.channel.slot.replace_receiver_slot(receiver_slot);
                 self.channel.waiting_receivers.notify_one();
-                return Err(e);
+                return Err(e.into());
             }
             None => {
                 scheduler::schedule();
+                if let Some(timer) = wakeup_timer.take() {
+                    timer.cancel();
+                }
             }
         }
 
         // Here, the receiver (this task) is waiting for a sender
+        let mut timed_out = false;
         loop {
             {
-                let exchange_state = receiver_slot.0.lock();
-                match &*exchange_state {
+                let mut exchange_state = receiver_slot.0.lock();
+                let is_waiting = match &*exchange_state {
                     ExchangeState::WaitingForSender(blocked_receiver) => {
-                        warn!("spurious wakeup while receiver is WaitingForSender... re-blocking task.");
                         if blocked_receiver.task() != curr_task {
-                            return Err("BUG: CURR TASK WAS DIFFERENT THAN BLOCKED RECEIVER");
+                            return Err("BUG: CURR TASK WAS DIFFERENT THAN BLOCKED RECEIVER".into());
                         }
-                        blocked_receiver.block_again();
+                        true
                     }
-                    _ => break,
+                    _ => false,
+                };
+                if !is_waiting {
+                    break;
+                }
+                if deadline.map_or(false, |d| Instant::now() >= d) {
+                    // Give up waiting for a sender. Resetting the state drops the `WaitGuard`, which unblocks this task.
+                    *exchange_state = ExchangeState::Init;
+                    timed_out = true;
+                    break;
+                }
+                if deadline.is_none() {
+                    warn!("spurious wakeup while receiver is WaitingForSender... re-blocking task.");
                 }
+                if let ExchangeState::WaitingForSender(blocked_receiver) = &*exchange_state {
+                    blocked_receiver.block_again();
+                }
+                wakeup_timer = deadline.map(|d| add_wakeup_timer(curr_task, d));
             }
             scheduler::schedule();
+            if let Some(timer) = wakeup_timer.take() {
+                timer.cancel();
+            }
+        }
+
+        if timed_out {
+            // Restore the receiver slot and notify waiting receivers.
+            self.channel.slot.replace_receiver_slot(receiver_slot);
+            self.channel.waiting_receivers.notify_one();
+            return Err(TimedReceiveError::Timeout);
         }
 
 
@@ -489,7 +575,7 @@ impl <T: Send> Receiver<T> {
 
         #[cfg(trace_channel)]
         trace!("rendezvous: received msg: {:?}", debugit!(retval));
-        retval
+        retval.map_err(|e| e.into())
     }
 
     /// Tries to receive a message, only succeeding if a sender is ready and waiting. 
@@ -499,7 +585,47 @@ impl <T: Send> Receiver<T> {
     /// Note that if the non-blocking `try_send` and `try_receive` functions are only ever used,
     /// then the message will never be delivered because the sender and receiver cannot possibly rendezvous. 
     pub fn try_receive(&self) -> Result<T, &'static str> {
-        unimplemented!()
+        let receiver_slot = self.channel.try_take_receiver_slot().ok_or("another receiver is using the channel")?;
+
+        let retval = {
+            let mut exchange_state = receiver_slot.0.lock();
+            // Temporarily take ownership of the channel's waiting state so we can modify it;
+            // the match statement below will advance the waiting state to the proper next state.
+            let current_state = core::mem::replace(&mut *exchange_state, ExchangeState::Init);
+            match current_state {
+                ExchangeState::WaitingForReceiver(sender_to_notify, msg) => {
+                    // The message has been received successfully, just like in `receive()`.
+                    // The sender will restore the receiver slot once it is finished with the slot.
+                    *exchange_state = ExchangeState::ReceiverFinishedFirst;
+                    Ok((sender_to_notify, msg))
+                }
+                state => {
+                    *exchange_state = state;
+                    Err("no sender was ready")
+                }
+            }
+        };
+        match retval {
+            Ok((sender_to_notify, msg)) => {
+                drop(sender_to_notify);
+                #[cfg(trace_channel)]
+                trace!("rendezvous: received msg: {:?}", debugit!(msg));
+                Ok(msg)
+            }
+            Err(e) => {
+                // Restore the receiver slot. We don't notify waiting receivers here,
+                // because only a single receiver is currently supported, 
+                // and notifying would spuriously wake up this task if it's waiting on this channel via `select`.
+                self.channel.slot.replace_receiver_slot(receiver_slot);
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the `WaitQueue` that is notified when a sender arrives or the receiver slot becomes available,
+    /// which allows a task to wait on this and other receivers at once, e.g., using the `select` crate.
+    pub fn receive_wait_queue(&self) -> &WaitQueue {
+        &self.channel.waiting_receivers
     }
 }
 
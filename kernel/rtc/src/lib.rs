@@ -113,6 +113,46 @@ impl fmt::Display for RtcTime {
     }
 }
 
+//register value is entered, the given binary value is converted to bcd and written into that register
+fn write_register(register: u8, value: u8) {
+    write_cmos(register);
+    unsafe{
+        CMOS_WRITE_SETTINGS.lock().write(((value / 10) << 4) | (value % 10));
+    }
+}
+
+/// Sets the RTC's date and time to the given `time`.
+/// 
+/// Like `read_rtc()`, this assumes that the RTC is in BCD mode with a 24-hour clock,
+/// and that `years` is the two-digit year within the current century.
+pub fn write_rtc(time: &RtcTime) {
+    let _held_interrupts = hold_interrupts();
+
+    // set bit 7 (SET) of register B, which stops the RTC from updating while we write the new time
+    write_cmos(0x8B);
+    let prev = read_cmos();
+    write_cmos(0x8B);
+    unsafe{
+        CMOS_WRITE_SETTINGS.lock().write(prev | 0x80);
+    }
+
+    write_register(0x00, time.seconds);
+    write_register(0x02, time.minutes);
+    write_register(0x04, time.hours);
+    write_register(0x07, time.days);
+    write_register(0x08, time.months);
+    write_register(0x09, time.years);
+
+    // clear the SET bit so the RTC resumes updating from the new time
+    write_cmos(0x8B);
+    unsafe{
+        CMOS_WRITE_SETTINGS.lock().write(prev & !0x80);
+    }
+
+    trace!("RTC time set to {}", time);
+    // here: _held_interrupts falls out of scope, re-enabling interrupts if they were previously enabled.
+}
+
 //call this function to print RTC's date and time
 pub fn read_rtc() -> RtcTime {
 
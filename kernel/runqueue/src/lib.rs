@@ -1,56 +1,292 @@
-//! This crate contains the API of the `RunQueue` structure, Runqueue Structure should contain
-//! list of tasks with additional scheduling information depending on the scheduler.
-//! All crates except the scheduler should refer to this crate to access functions on `RunQueue`.
-//! 
+//! This crate contains the API for adding tasks to and removing tasks from runqueues.
+//! Each core has a runqueue for each scheduler policy, but only the runqueue of the policy
+//! that is currently active on that core is used, see the `scheduler_policy` crate.
+//! All crates except the scheduler should refer to this crate to access runqueues.
+//!
+//! This crate also migrates tasks between cores' runqueues in order to balance their load,
+//! either when an idle core steals work from a busy one ([`steal_task()`](fn.steal_task.html))
+//! or when all runqueues are balanced at once ([`balance()`](fn.balance.html)).
+//! Only runnable tasks that are not pinned to a core, not idle tasks, not real-time tasks,
+//! and neither running nor still being switched out can be migrated.
 
 #![no_std]
 
 extern crate alloc;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
 extern crate irq_safety;
 extern crate atomic_linked_list;
 extern crate task;
-#[cfg(priority_scheduler)] extern crate runqueue_priority;
-#[cfg(not(priority_scheduler))] extern crate runqueue_round_robin;
+extern crate scheduler_policy;
 
-#[cfg(single_simd_task_optimization)]
-extern crate single_simd_task_optimization;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::vec::Vec;
+use irq_safety::MutexIrqSafe;
+use atomic_linked_list::atomic_map::AtomicMap;
+use task::TaskRef;
+use scheduler_policy::PolicyRunQueues;
 
-use irq_safety::{RwLockIrqSafe};
-use task::{TaskRef};
-#[cfg(priority_scheduler)] use runqueue_priority::RunQueue;
-#[cfg(not(priority_scheduler))] use runqueue_round_robin::RunQueue;
+
+/// The number of tasks that have been migrated into and out of a core's runqueue.
+#[derive(Default)]
+struct MigrationCounters {
+    migrated_in: AtomicUsize,
+    migrated_out: AtomicUsize,
+}
+
+lazy_static! {
+    /// The migration counters of each core, keyed by the core's APIC ID.
+    static ref MIGRATIONS: AtomicMap<u8, MigrationCounters> = AtomicMap::new();
+}
+
+/// Only one migration may happen at a time, such that two cores never move the same task concurrently,
+/// which would leave that task on more than one runqueue.
+static MIGRATION_LOCK: MutexIrqSafe<()> = MutexIrqSafe::new(());
 
 
-/// Creates a new `RunQueue` for the given core, which is an `apic_id`.
+/// Creates a new runqueue for the given core, which is an `apic_id`,
+/// and activates the default scheduler policy on that core.
 pub fn init(which_core: u8) -> Result<(), &'static str>{
-    RunQueue::init(which_core)
+    task::RUNQUEUE_SUSPEND_FUNCTION.call_once(|| suspend_task);
+    task::RUNQUEUE_RESUME_FUNCTION.call_once(|| resume_task);
+    scheduler_policy::init_core(which_core)?;
+    MIGRATIONS.insert(which_core, MigrationCounters::default());
+    Ok(())
 }
 
-/// Returns the `RunQueue` of the given core, which is an `apic_id`.
-pub fn get_runqueue(which_core: u8) -> Option<&'static RwLockIrqSafe<RunQueue>>{
-    RunQueue::get_runqueue(which_core)
+/// Returns the tasks in the runqueue of the given core, which is an `apic_id`.
+pub fn get_tasks(which_core: u8) -> Option<Vec<TaskRef>> {
+    scheduler_policy::with_active_policy(which_core, |policy| policy.tasks(which_core))
 }
 
-/// Returns the "least busy" core
+/// Returns the "least busy" core, which is currently very simple, based on runqueue size.
 pub fn get_least_busy_core() -> Option<u8>{
-    RunQueue::get_least_busy_core()
+    scheduler_policy::cores().into_iter()
+        .filter_map(|core| scheduler_policy::with_active_policy(core, |policy| (core, policy.runqueue_len(core))))
+        .min_by_key(|&(_core, len)| len)
+        .map(|(core, _len)| core)
 }
 
 /// Chooses the "least busy" core's runqueue
 /// and adds the given `Task` reference to that core's runqueue.
 pub fn add_task_to_any_runqueue(task: TaskRef) -> Result<(), &'static str>{
-    RunQueue::add_task_to_any_runqueue(task)
+    let core = get_least_busy_core().ok_or("couldn't find any runqueues to add the task to!")?;
+    add_task_to_specific_runqueue(core, task)
 }
 
 /// Adds the given `Task` reference to given core's runqueue.
 pub fn add_task_to_specific_runqueue(which_core: u8, task: TaskRef) -> Result<(), &'static str>{
-    RunQueue::add_task_to_specific_runqueue(which_core, task)
+    scheduler_policy::with_active_policy(which_core, |policy| policy.add_task(which_core, task.clone()))
+        .unwrap_or(Err("Couldn't get RunQueue for the given core"))?;
+    scheduler_policy::set_core_of(&task, which_core);
+    Ok(())
+}
+
+/// Removes a `TaskRef` from the given core's runqueue.
+pub fn remove_task(which_core: u8, task: &TaskRef) -> Result<(), &'static str>{
+    scheduler_policy::with_active_policy(which_core, |policy| policy.remove_task(which_core, task))
+        .unwrap_or(Err("Couldn't get RunQueue for the given core"))?;
+    scheduler_policy::clear_core_of(task, which_core);
+    Ok(())
 }
 
 /// Removes a `TaskRef` from all `RunQueue`s that exist on the entire system.
 pub fn remove_task_from_all(task: &TaskRef) -> Result<(), &'static str>{
-    RunQueue::remove_task_from_all(task)
+    for core in scheduler_policy::cores() {
+        remove_task(core, task)?;
+    }
+    Ok(())
+}
+
+/// Takes a suspended task off of every runqueue, see `TaskRef::suspend()`.
+fn suspend_task(task: &TaskRef) -> Result<(), &'static str> {
+    // An ongoing migration of this task must finish first, otherwise it could put the task back onto a runqueue.
+    let _migration_locked = MIGRATION_LOCK.lock();
+    remove_task_from_all(task)
+}
+
+/// Puts a resumed task back onto its pinned core's runqueue, or else the least busy core's runqueue,
+/// see `TaskRef::resume()`.
+fn resume_task(task: TaskRef) -> Result<(), &'static str> {
+    let _migration_locked = MIGRATION_LOCK.lock();
+    let pinned_core = task.lock().pinned_core;
+    match pinned_core {
+        Some(core) => add_task_to_specific_runqueue(core, task),
+        None => add_task_to_any_runqueue(task),
+    }
+}
+
+
+/// The load on a single core and the number of tasks that have been migrated to and from it.
+#[derive(Debug, Clone, Copy)]
+pub struct CoreLoad {
+    /// The APIC ID of the core.
+    pub core: u8,
+    /// The number of tasks on the core's runqueue, including its idle task.
+    pub tasks: usize,
+    /// The number of runnable tasks on the core's runqueue, excluding its idle task.
+    pub runnable: usize,
+    /// The number of tasks that have been migrated to this core.
+    pub migrated_in: usize,
+    /// The number of tasks that have been migrated away from this core.
+    pub migrated_out: usize,
+}
+
+/// Returns the current load of the given core, which is an `apic_id`.
+pub fn get_load(which_core: u8) -> Option<CoreLoad> {
+    let tasks = get_tasks(which_core)?;
+    let counters = MIGRATIONS.get(&which_core);
+    Some(CoreLoad {
+        core: which_core,
+        tasks: tasks.len(),
+        runnable: runnable_load(&tasks),
+        migrated_in: counters.map(|c| c.migrated_in.load(Ordering::Relaxed)).unwrap_or(0),
+        migrated_out: counters.map(|c| c.migrated_out.load(Ordering::Relaxed)).unwrap_or(0),
+    })
+}
+
+/// Returns the current load of every core.
+pub fn load_stats() -> Vec<CoreLoad> {
+    scheduler_policy::cores().into_iter().filter_map(get_load).collect()
+}
+
+/// Returns the total number of task migrations between cores since boot.
+pub fn total_migrations() -> usize {
+    MIGRATIONS.iter().map(|(_core, c)| c.migrated_in.load(Ordering::Relaxed)).sum()
+}
+
+/// Returns the number of runnable non-idle tasks among the given tasks.
+fn runnable_load(tasks: &[TaskRef]) -> usize {
+    tasks.iter().filter(|t| {
+        let t = t.lock();
+        t.is_runnable() && !t.is_an_idle_task
+    }).count()
+}
+
+/// Returns true if the given task could currently be moved to another core:
+/// it must be runnable but neither running nor still being switched out, and must be neither pinned to a core nor an idle task.
+fn is_migratable(task: &TaskRef) -> bool {
+    let t = task.lock();
+    t.is_runnable() && !t.is_running() && t.is_context_saved() && t.pinned_core.is_none() && !t.is_an_idle_task
 }
 
 
+/// Moves the given task from the runqueue of core `from` to the runqueue of core `to`.
+///
+/// Returns an error if the task can't be migrated right now, see the [crate-level docs](index.html).
+/// The task keeps its priority if both cores use a scheduler policy with priorities.
+pub fn migrate_task(task: &TaskRef, from: u8, to: u8) -> Result<(), &'static str> {
+    let _migration_locked = MIGRATION_LOCK.lock();
+    migrate_task_locked(task, from, to)
+}
+
+/// The inner part of [`migrate_task()`](fn.migrate_task.html), which expects the `MIGRATION_LOCK` to be held.
+fn migrate_task_locked(task: &TaskRef, from: u8, to: u8) -> Result<(), &'static str> {
+    if from == to {
+        return Ok(());
+    }
+    if !is_migratable(task) {
+        return Err("the task is pinned, an idle task, not runnable, or currently running");
+    }
+
+    let priority = scheduler_policy::with_active_policy(from, |policy| {
+        if scheduler_policy::core_of(task) != Some(from) {
+            return Err("the task is not on the source core's runqueue");
+        }
+        if policy.get_realtime_params(task).is_some() {
+            return Err("real-time tasks cannot be migrated, as they were admitted on a specific core");
+        }
+        let priority = policy.get_priority(task);
+        policy.remove_task(from, task)?;
+        Ok(priority)
+    }).unwrap_or(Err("Couldn't get RunQueue for the source core"))?;
+
+    // If the source core selected the task right before it was removed, it may run there one more time,
+    // but afterwards only the destination core will schedule it.
+    if let Err(e) = add_task_to_specific_runqueue(to, task.clone()) {
+        // Put the task back such that it isn't lost.
+        add_task_to_specific_runqueue(from, task.clone())?;
+        return Err(e);
+    }
+    if let Some(priority) = priority {
+        // the destination core's policy may not use priorities, which is fine
+        let _ = scheduler_policy::with_active_policy(to, |policy| policy.set_priority(task, priority));
+    }
+
+    // The task may have exited while being migrated, after it had already been removed from all runqueues.
+    if task.lock().has_exited() {
+        let _ = remove_task(to, task);
+    }
 
+    if let Some(c) = MIGRATIONS.get(&from) {
+        c.migrated_out.fetch_add(1, Ordering::Relaxed);
+    }
+    if let Some(c) = MIGRATIONS.get(&to) {
+        c.migrated_in.fetch_add(1, Ordering::Relaxed);
+    }
+    debug!("Migrated task {:?} from core {} to core {}", task, from, to);
+    Ok(())
+}
+
+/// Migrates one migratable task from the given core to the other given core, if there is one.
+/// Returns true if a task was migrated.
+fn migrate_any_task(from: u8, to: u8) -> bool {
+    let tasks = match get_tasks(from) {
+        Some(tasks) => tasks,
+        None => return false,
+    };
+    // Tasks at the back of the runqueue will wait the longest, so they're the best to move.
+    tasks.iter().rev()
+        .filter(|t| is_migratable(t))
+        .any(|t| migrate_task_locked(t, from, to).is_ok())
+}
+
+/// Returns the runnable load of each core that has an active scheduler policy.
+fn runnable_loads() -> Vec<(u8, usize)> {
+    scheduler_policy::cores().into_iter()
+        .filter_map(|core| get_tasks(core).map(|tasks| (core, runnable_load(&tasks))))
+        .collect()
+}
+
+/// Lets the given core, which is an `apic_id`, steal a task from the busiest other core.
+/// This is intended to be called by the idle task of a core that has nothing else to run.
+///
+/// A task is only stolen from a core that has more than one runnable task,
+/// such that the stolen task would otherwise have had to wait.
+/// Returns true if a task was stolen.
+pub fn steal_task(to: u8) -> bool {
+    // Don't wait for another ongoing migration, as the idle task will try again later anyway.
+    let _migration_locked = match MIGRATION_LOCK.try_lock() {
+        Some(guard) => guard,
+        None => return false,
+    };
+    let mut loads = runnable_loads();
+    loads.retain(|&(core, load)| core != to && load > 1);
+    loads.sort_by(|a, b| b.1.cmp(&a.1));
+    loads.into_iter().any(|(from, _load)| migrate_any_task(from, to))
+}
+
+/// Balances the runnable load across all cores by repeatedly migrating tasks
+/// from the busiest core to the least busy core, until their loads differ by at most one task.
+/// Returns the number of migrated tasks.
+pub fn balance() -> usize {
+    let _migration_locked = MIGRATION_LOCK.lock();
+    let max_migrations = scheduler_policy::cores().len() * 4;
+    let mut migrated = 0;
+    while migrated < max_migrations {
+        let loads = runnable_loads();
+        let busiest = loads.iter().max_by_key(|&&(_core, load)| load);
+        let least_busy = loads.iter().min_by_key(|&&(_core, load)| load);
+        match (busiest, least_busy) {
+            (Some(&(from, max)), Some(&(to, min))) if max > min + 1 => {
+                if !migrate_any_task(from, to) {
+                    // none of the busiest core's tasks can be migrated right now
+                    break;
+                }
+                migrated += 1;
+            }
+            _ => break,
+        }
+    }
+    migrated
+}
@@ -0,0 +1,455 @@
+//! This crate contains the `RunQueue` structure for the real-time scheduler,
+//! which is a per-core list of tasks along with the real-time parameters and job state of each task.
+//!
+//! In addition to regular tasks, a runqueue holds real-time tasks that have been admitted
+//! with a period, an execution budget, and a relative deadline (see [`RealtimeParams`](struct.RealtimeParams.html)).
+//! Every period, a real-time task releases a new job, which must finish within its budget before its deadline.
+//! Admission control ensures that the real-time tasks on a core never demand more than that whole core,
+//! which guarantees that an earliest-deadline-first scheduler can meet all of their deadlines.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+extern crate irq_safety;
+extern crate atomic_linked_list;
+extern crate task;
+extern crate scheduler_policy;
+extern crate tsc;
+
+#[cfg(single_simd_task_optimization)]
+extern crate single_simd_task_optimization;
+
+use core::{
+    cmp::min,
+    ops::{Deref, DerefMut},
+    time::Duration,
+};
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use irq_safety::{RwLockIrqSafe, MutexIrqSafeGuardRef};
+use atomic_linked_list::atomic_map::AtomicMap;
+use task::{TaskRef, Task};
+use scheduler_policy::PerCoreRunQueue;
+
+/// The utilization of one whole core, in parts per million.
+/// The sum of the utilizations of all real-time tasks on a core must not exceed this.
+pub const MAX_UTILIZATION: u64 = 1_000_000;
+
+/// Returns the current time in microseconds, as measured by the TSC.
+///
+/// The first invocation calibrates the TSC, which takes several milliseconds,
+/// so it should happen when admitting a real-time task rather than within the scheduler.
+pub fn now_micros() -> Result<u64, &'static str> {
+    let frequency = tsc::get_tsc_frequency()?;
+    Ok((tsc::tsc_ticks().into() as u128 * 1_000_000 / frequency as u128) as u64)
+}
+
+fn duration_to_micros(duration: Duration) -> u64 {
+    duration.as_secs().saturating_mul(1_000_000).saturating_add(duration.subsec_micros() as u64)
+}
+
+
+/// The timing parameters of a real-time task, all in microseconds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RealtimeParams {
+    /// The interval at which the task releases a new job.
+    pub period: u64,
+    /// The maximum execution time that each job may use.
+    pub budget: u64,
+    /// The time after its release by which each job must finish.
+    pub deadline: u64,
+}
+
+impl RealtimeParams {
+    /// Creates a new set of real-time parameters.
+    ///
+    /// Returns an error if the period, budget or deadline is shorter than one microsecond,
+    /// or if the budget is longer than the period or the deadline.
+    pub fn new(period: Duration, budget: Duration, deadline: Duration) -> Result<RealtimeParams, &'static str> {
+        let params = RealtimeParams {
+            period: duration_to_micros(period),
+            budget: duration_to_micros(budget),
+            deadline: duration_to_micros(deadline),
+        };
+        if params.period == 0 || params.budget == 0 || params.deadline == 0 {
+            return Err("the period, budget and deadline of a real-time task must be at least one microsecond");
+        }
+        if params.budget > params.period || params.budget > params.deadline {
+            return Err("the budget of a real-time task must not exceed its period or its deadline");
+        }
+        Ok(params)
+    }
+
+    /// Returns the fraction of a core, in parts per million, that this task may demand in the worst case,
+    /// i.e., its budget divided by the shorter of its period and deadline.
+    pub fn utilization(&self) -> u64 {
+        self.budget.saturating_mul(MAX_UTILIZATION) / min(self.period, self.deadline)
+    }
+}
+
+
+/// A cloneable reference to a `TaskRef` along with the real-time parameters and the state of the current job
+/// of that task, which the real-time scheduler keeps alongside the task rather than in it.
+///
+/// A task without real-time parameters is a regular task, which only runs when no real-time job is eligible.
+/// `RealtimeTaskRef` implements the `Deref` and `DerefMut` traits, which dereference to `TaskRef`.
+#[derive(Debug, Clone)]
+pub struct RealtimeTaskRef {
+    /// `TaskRef` wrapped by `RealtimeTaskRef`
+    taskref: TaskRef,
+
+    /// The real-time parameters of the task, or `None` if it is a regular task.
+    pub params: Option<RealtimeParams>,
+
+    /// The time (in microseconds) at which the current job was released.
+    pub release_time: u64,
+
+    /// The time (in microseconds) by which the current job must finish.
+    pub absolute_deadline: u64,
+
+    /// The execution time (in microseconds) that the current job may still use.
+    pub budget_remaining: u64,
+
+    /// Whether the current job has finished, i.e., the task is waiting for its next period.
+    pub job_completed: bool,
+
+    /// Whether the current job has already been reported as having missed its deadline.
+    pub deadline_miss_reported: bool,
+
+    /// Number of context switches the task has undergone. Not used in scheduling algorithm
+    context_switches: usize,
+}
+
+impl Deref for RealtimeTaskRef {
+    type Target = TaskRef;
+    fn deref(&self) -> &TaskRef {
+        &self.taskref
+    }
+}
+
+impl DerefMut for RealtimeTaskRef {
+    fn deref_mut(&mut self) -> &mut TaskRef {
+        &mut self.taskref
+    }
+}
+
+impl RealtimeTaskRef {
+    /// Creates a new `RealtimeTaskRef` that wraps the given `TaskRef`.
+    /// The task starts out as a regular task without real-time parameters.
+    pub fn new(taskref: TaskRef) -> RealtimeTaskRef {
+        RealtimeTaskRef {
+            taskref: taskref,
+            params: None,
+            release_time: 0,
+            absolute_deadline: 0,
+            budget_remaining: 0,
+            job_completed: false,
+            deadline_miss_reported: false,
+            context_switches: 0,
+        }
+    }
+
+    /// Obtains the lock on the underlying `Task` in a read-only, blocking fashion.
+    pub fn lock(&self) -> MutexIrqSafeGuardRef<Task> {
+        self.taskref.lock()
+    }
+
+    /// Increments the number of times the task has been picked.
+    pub fn increment_context_switches(&mut self) {
+        self.context_switches = self.context_switches.saturating_add(1);
+    }
+
+    /// Returns true if this task has real-time parameters.
+    pub fn is_realtime(&self) -> bool {
+        self.params.is_some()
+    }
+
+    /// Releases a new job of this real-time task at the given time.
+    fn release_job(&mut self, params: RealtimeParams, release_time: u64) {
+        self.release_time = release_time;
+        self.absolute_deadline = release_time.saturating_add(params.deadline);
+        self.budget_remaining = params.budget;
+        self.job_completed = false;
+        self.deadline_miss_reported = false;
+    }
+
+    /// Returns true if the current job of this real-time task may run at the given time `now`,
+    /// i.e., it has been released, hasn't finished, and has budget left.
+    /// Whether the task itself is runnable must be checked separately.
+    pub fn is_eligible(&self, now: u64) -> bool {
+        self.is_realtime() && !self.job_completed && self.budget_remaining > 0 && self.release_time <= now
+    }
+
+    /// Charges the given execution time (in microseconds) against the budget of the current job.
+    pub fn charge(&mut self, elapsed: u64) {
+        self.budget_remaining = self.budget_remaining.saturating_sub(elapsed);
+    }
+
+    /// Brings the job state of this real-time task up to the given time `now`,
+    /// releasing a new job if the current period has ended.
+    ///
+    /// Returns true if the current job has just been found to have missed its deadline,
+    /// which is reported only once per job.
+    pub fn update(&mut self, now: u64) -> bool {
+        let params = match self.params {
+            Some(p) => p,
+            None => return false,
+        };
+        let missed = !self.job_completed && !self.deadline_miss_reported && now > self.absolute_deadline;
+        if missed {
+            self.deadline_miss_reported = true;
+        }
+        let next_release = self.release_time.saturating_add(params.period);
+        if now >= next_release {
+            // Periods that have entirely elapsed, e.g., while the task was blocked, are skipped.
+            let elapsed_periods = (now - self.release_time) / params.period;
+            self.release_job(params, self.release_time + elapsed_periods * params.period);
+        }
+        missed
+    }
+}
+
+
+lazy_static! {
+    /// There is one runqueue per core, each core only accesses its own private runqueue
+    /// and allows the scheduler to select a task from that runqueue to schedule in.
+    static ref RUNQUEUES: AtomicMap<u8, RwLockIrqSafe<RunQueue>> = AtomicMap::new();
+}
+
+
+/// A list of references to `Task`s (`RealtimeTaskRef`s)
+/// that is used to store the `Task`s (and associated scheduler related data)
+/// that are runnable on a given core.
+/// Regular tasks are scheduled round robin, so they are kept in queue order.
+/// `RunQueue` implements the `Deref` and `DerefMut` traits, which dereference to `VecDeque`.
+#[derive(Debug)]
+pub struct RunQueue {
+    core: u8,
+    queue: VecDeque<RealtimeTaskRef>,
+    /// The task that was most recently picked on this core and the time at which it was picked,
+    /// which is used to charge that task's execution time against its budget.
+    current: Option<(TaskRef, u64)>,
+}
+
+impl Deref for RunQueue {
+    type Target = VecDeque<RealtimeTaskRef>;
+    fn deref(&self) -> &VecDeque<RealtimeTaskRef> {
+        &self.queue
+    }
+}
+
+impl DerefMut for RunQueue {
+    fn deref_mut(&mut self) -> &mut VecDeque<RealtimeTaskRef> {
+        &mut self.queue
+    }
+}
+
+impl RunQueue {
+
+    /// Moves the `TaskRef` at the given index in this `RunQueue` to the end (back) of this `RunQueue`,
+    /// and returns a cloned reference to that `TaskRef`. The number of context switches is increased by one.
+    /// This function is used when a regular task is selected by the scheduler.
+    pub fn update_and_move_to_end(&mut self, index: usize) -> Option<TaskRef> {
+        if let Some(mut realtime_task_ref) = self.remove(index) {
+            realtime_task_ref.increment_context_switches();
+            let taskref = realtime_task_ref.taskref.clone();
+            self.push_back(realtime_task_ref);
+            Some(taskref)
+        }
+        else {
+            None
+        }
+    }
+
+    /// Records that the given task was picked to run on this core at the given time `now`.
+    pub fn set_current(&mut self, task: TaskRef, now: u64) {
+        self.current = Some((task, now));
+    }
+
+    /// Returns the task that was most recently picked on this core along with the time at which it was picked,
+    /// and clears that record.
+    pub fn take_current(&mut self) -> Option<(TaskRef, u64)> {
+        self.current.take()
+    }
+
+    /// Returns true if any task on this runqueue has real-time parameters.
+    pub fn has_realtime_tasks(&self) -> bool {
+        self.iter().any(|t| t.is_realtime())
+    }
+
+    /// Returns the sum of the utilizations of all real-time tasks on this runqueue, in parts per million.
+    pub fn realtime_utilization(&self) -> u64 {
+        self.iter()
+            .filter_map(|t| t.params)
+            .fold(0, |sum, params| sum.saturating_add(params.utilization()))
+    }
+
+    /// Creates a new `RunQueue` for the given core, which is an `apic_id`
+    pub fn init(which_core: u8) -> Result<(), &'static str> {
+        #[cfg(not(loscd_eval))]
+        trace!("Created runqueue (realtime) for core {}", which_core);
+        let new_rq = RwLockIrqSafe::new(RunQueue {
+            core: which_core,
+            queue: VecDeque::new(),
+            current: None,
+        });
+
+        if RUNQUEUES.insert(which_core, new_rq).is_some() {
+            error!("BUG: RunQueue::init(): runqueue already exists for core {}!", which_core);
+            Err("runqueue already exists for this core")
+        }
+        else {
+            // there shouldn't already be a RunQueue for this core
+            Ok(())
+        }
+    }
+
+    /// Returns `RunQueue` for the given core, which is an `apic_id`.
+    pub fn get_runqueue(which_core: u8) -> Option<&'static RwLockIrqSafe<RunQueue>> {
+        RUNQUEUES.get(&which_core)
+    }
+
+
+    /// Convenience method that adds the given `Task` reference to given core's runqueue.
+    pub fn add_task_to_specific_runqueue(which_core: u8, task: TaskRef) -> Result<(), &'static str> {
+        RunQueue::get_runqueue(which_core)
+            .ok_or("Couldn't get RunQueue for the given core")?
+            .write()
+            .add_task(task)
+    }
+
+    /// Adds a `TaskRef` to this RunQueue.
+    fn add_task(&mut self, task: TaskRef) -> Result<(), &'static str> {
+        #[cfg(single_simd_task_optimization)]
+        let is_simd = task.lock().simd;
+
+        #[cfg(not(loscd_eval))]
+        debug!("Adding task to runqueue_realtime {}, {:?}", self.core, task);
+        let realtime_task_ref = RealtimeTaskRef::new(task);
+        self.push_back(realtime_task_ref);
+
+        #[cfg(single_simd_task_optimization)]
+        {
+            warn!("USING SINGLE_SIMD_TASK_OPTIMIZATION VERSION OF RUNQUEUE::ADD_TASK");
+            // notify simd_personality crate about runqueue change, but only for SIMD tasks
+            if is_simd {
+                single_simd_task_optimization::simd_tasks_added_to_core(self.iter(), self.core);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Removes a `TaskRef` from this RunQueue.
+    pub fn remove_task(&mut self, task: &TaskRef) -> Result<(), &'static str> {
+        debug!("Removing task from runqueue_realtime {}, {:?}", self.core, task);
+        self.retain(|x| &x.taskref != task);
+
+        #[cfg(single_simd_task_optimization)]
+        {
+            let is_simd = { task.lock().simd };
+            warn!("USING SINGLE_SIMD_TASK_OPTIMIZATION VERSION OF RUNQUEUE::REMOVE_TASK");
+            // notify simd_personality crate about runqueue change, but only for SIMD tasks
+            if is_simd {
+                single_simd_task_optimization::simd_tasks_removed_from_core(self.iter(), self.core);
+            }
+        }
+
+        Ok(())
+    }
+
+
+    /// Sets the real-time parameters of the given `Task`, or makes it a regular task if `params` is `None`.
+    ///
+    /// This performs admission control: the task is rejected if the total utilization of the real-time tasks
+    /// on its core, including this task with its new parameters, would exceed that whole core.
+    /// If admitted, the task's first job is released immediately.
+    pub fn set_realtime_params(task: &TaskRef, params: Option<RealtimeParams>) -> Result<(), &'static str> {
+        // Calibrate the clock (if needed) before acquiring any runqueue lock.
+        let now = if params.is_some() { now_micros()? } else { 0 };
+
+        for (_core, rq) in RUNQUEUES.iter() {
+            let mut rq_locked = rq.write();
+            let index = match rq_locked.iter().position(|x| &x.taskref == task) {
+                Some(i) => i,
+                None => continue,
+            };
+
+            if let Some(new_params) = params {
+                let others_utilization = rq_locked.iter()
+                    .filter(|x| &x.taskref != task)
+                    .filter_map(|x| x.params)
+                    .fold(0u64, |sum, p| sum.saturating_add(p.utilization()));
+                if others_utilization.saturating_add(new_params.utilization()) > MAX_UTILIZATION {
+                    warn!("Rejected real-time task {:?} on core {}: utilization {} + {} exceeds {}",
+                        task, rq_locked.core, others_utilization, new_params.utilization(), MAX_UTILIZATION
+                    );
+                    return Err("admission control rejected the real-time task, as its core would be overloaded");
+                }
+            }
+
+            let realtime_task_ref = &mut rq_locked[index];
+            realtime_task_ref.params = params;
+            if let Some(new_params) = params {
+                realtime_task_ref.release_job(new_params, now);
+            }
+            return Ok(());
+        }
+        Err("couldn't find the given task in any runqueue")
+    }
+
+    /// Returns the real-time parameters of the given task,
+    /// or `None` if it is a regular task or is not found in any of the runqueues.
+    pub fn get_realtime_params(task: &TaskRef) -> Option<RealtimeParams> {
+        for (_core, rq) in RUNQUEUES.iter() {
+            if let Some(x) = rq.read().iter().find(|x| &x.taskref == task) {
+                return x.params;
+            }
+        }
+        None
+    }
+
+    /// Marks the current job of the given real-time task as finished,
+    /// such that the task isn't scheduled again until its next job is released.
+    pub fn complete_job(task: &TaskRef) -> Result<(), &'static str> {
+        for (_core, rq) in RUNQUEUES.iter() {
+            let mut rq_locked = rq.write();
+            if let Some(x) = rq_locked.iter_mut().find(|x| &x.taskref == task) {
+                if !x.is_realtime() {
+                    return Err("the given task is not a real-time task");
+                }
+                x.job_completed = true;
+                return Ok(());
+            }
+        }
+        Err("couldn't find the given task in any runqueue")
+    }
+}
+
+impl PerCoreRunQueue for RunQueue {
+    fn init(core: u8) -> Result<(), &'static str> {
+        RunQueue::init(core)
+    }
+
+    fn get_runqueue(core: u8) -> Option<&'static RwLockIrqSafe<RunQueue>> {
+        RunQueue::get_runqueue(core)
+    }
+
+    fn add_task_to_specific_runqueue(core: u8, task: TaskRef) -> Result<(), &'static str> {
+        RunQueue::add_task_to_specific_runqueue(core, task)
+    }
+
+    fn remove_task(&mut self, task: &TaskRef) -> Result<(), &'static str> {
+        RunQueue::remove_task(self, task)
+    }
+
+    fn tasks(&self) -> Vec<TaskRef> {
+        self.iter().map(|t| TaskRef::clone(t)).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
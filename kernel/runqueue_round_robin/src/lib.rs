@@ -11,14 +11,17 @@ extern crate alloc;
 extern crate irq_safety;
 extern crate atomic_linked_list;
 extern crate task;
+extern crate scheduler_policy;
 
 #[cfg(single_simd_task_optimization)]
 extern crate single_simd_task_optimization;
 
 use alloc::collections::VecDeque;
+use alloc::vec::Vec;
 use irq_safety::RwLockIrqSafe;
 use atomic_linked_list::atomic_map::AtomicMap;
 use task::TaskRef;
+use scheduler_policy::PerCoreRunQueue;
 use core::ops::{Deref, DerefMut};
 
 /// A cloneable reference to a `Taskref` that exposes more methods
@@ -288,3 +291,29 @@ impl RunQueue {
             })
     }
 }
+
+impl PerCoreRunQueue for RunQueue {
+    fn init(core: u8) -> Result<(), &'static str> {
+        RunQueue::init(core)
+    }
+
+    fn get_runqueue(core: u8) -> Option<&'static RwLockIrqSafe<RunQueue>> {
+        RunQueue::get_runqueue(core)
+    }
+
+    fn add_task_to_specific_runqueue(core: u8, task: TaskRef) -> Result<(), &'static str> {
+        RunQueue::add_task_to_specific_runqueue(core, task)
+    }
+
+    fn remove_task(&mut self, task: &TaskRef) -> Result<(), &'static str> {
+        RunQueue::remove_task(self, task)
+    }
+
+    fn tasks(&self) -> Vec<TaskRef> {
+        self.iter().map(|t| TaskRef::clone(t)).collect()
+    }
+
+    fn len(&self) -> usize {
+        self.queue.len()
+    }
+}
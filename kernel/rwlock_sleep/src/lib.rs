@@ -0,0 +1,200 @@
+//! A readers-writer lock that puts tasks to sleep while they wait for the lock.
+
+#![no_std]
+
+extern crate spin;
+extern crate owning_ref;
+extern crate stable_deref_trait;
+extern crate wait_queue;
+
+use core::fmt;
+use core::mem::ManuallyDrop;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use owning_ref::{OwningRef, OwningRefMut};
+use stable_deref_trait::StableDeref;
+use wait_queue::WaitQueue;
+
+
+/// A readers-writer lock that puts a `Task` to sleep while waiting for the lock to become available.
+///
+/// Any number of readers or at most one writer can hold the lock at a time.
+/// Writers are preferred: once a writer is waiting for the lock, no new readers can acquire it,
+/// such that a continuous stream of readers can't starve writers.
+///
+/// Once the lock is released, all `Task`s that are sleeping while waiting for the lock
+/// will be notified (woken up) so they can attempt to acquire the lock again.
+pub struct RwLockSleep<T: ?Sized> {
+    queue: WaitQueue,
+    /// The number of writers that are currently waiting to acquire the lock.
+    waiting_writers: AtomicUsize,
+    lock: RwLock<T>,
+}
+
+/// A guard that allows the locked data to be immutably accessed, during which shared access is guaranteed.
+///
+/// When the guard falls out of scope, it will release the lock,
+/// which then notifies any `Task`s waiting on the lock.
+pub struct RwLockSleepReadGuard<'a, T: ?Sized + 'a> {
+    guard: ManuallyDrop<RwLockReadGuard<'a, T>>,
+    queue: &'a WaitQueue,
+}
+
+/// A guard that allows the locked data to be mutably accessed, during which mutual exclusion is guaranteed.
+///
+/// When the guard falls out of scope, it will release the lock,
+/// which then notifies any `Task`s waiting on the lock.
+pub struct RwLockSleepWriteGuard<'a, T: ?Sized + 'a> {
+    guard: ManuallyDrop<RwLockWriteGuard<'a, T>>,
+    queue: &'a WaitQueue,
+}
+
+// Same unsafe impls as `std::sync::RwLock`
+unsafe impl<T: ?Sized + Send> Send for RwLockSleep<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for RwLockSleep<T> {}
+
+impl<T> RwLockSleep<T> {
+    /// Creates a new lock wrapping the supplied data.
+    pub fn new(data: T) -> RwLockSleep<T> {
+        RwLockSleep {
+            queue: WaitQueue::new(),
+            waiting_writers: AtomicUsize::new(0),
+            lock: RwLock::new(data),
+        }
+    }
+
+    /// Consumes this `RwLockSleep`, returning the underlying data.
+    pub fn into_inner(self) -> T {
+        self.lock.into_inner()
+    }
+}
+
+impl<T: ?Sized> RwLockSleep<T> {
+    /// Blocks until the lock is acquired for shared read access by putting this `Task` to sleep
+    /// until all writers, including writers that are waiting for the lock, have released it.
+    ///
+    /// The returned guard may be dereferenced to access the protected data;
+    /// the lock will be released when the returned guard falls out of scope and is dropped.
+    pub fn read(&self) -> Result<RwLockSleepReadGuard<T>, &'static str> {
+        // Fast path: check for the uncontended case.
+        if let Some(guard) = self.try_read() {
+            return Ok(guard);
+        }
+        // Slow path if a writer holds or is waiting for the lock: wait until we obtain the lock.
+        self.queue
+            .wait_until(&|| self.try_read())
+            .map_err(|_| "failed to add current task to waitqueue")
+    }
+
+    /// Blocks until the lock is acquired for exclusive write access by putting this `Task` to sleep
+    /// until all other readers and writers have released it.
+    ///
+    /// The returned guard may be dereferenced to access the protected data;
+    /// the lock will be released when the returned guard falls out of scope and is dropped.
+    pub fn write(&self) -> Result<RwLockSleepWriteGuard<T>, &'static str> {
+        // Fast path: check for the uncontended case.
+        if let Some(guard) = self.try_write() {
+            return Ok(guard);
+        }
+        // Slow path if already locked elsewhere: keep new readers out while we wait until we obtain the lock.
+        self.waiting_writers.fetch_add(1, Ordering::SeqCst);
+        let result = self.queue.wait_until(&|| self.try_write());
+        self.waiting_writers.fetch_sub(1, Ordering::SeqCst);
+        if result.is_err() {
+            // Readers that were held back by this writer may be able to proceed now.
+            self.queue.notify_all();
+        }
+        result.map_err(|_| "failed to add current task to waitqueue")
+    }
+
+    /// Tries to lock the `RwLockSleep` for shared read access.
+    /// If it is locked by a writer or a writer is waiting for it, it will return `None`.
+    /// Otherwise it returns a guard within `Some`.
+    pub fn try_read(&self) -> Option<RwLockSleepReadGuard<T>> {
+        if self.waiting_writers.load(Ordering::SeqCst) != 0 {
+            return None;
+        }
+        self.lock.try_read().map(|spinlock_guard| {
+            RwLockSleepReadGuard {
+                guard: ManuallyDrop::new(spinlock_guard),
+                queue: &self.queue,
+            }
+        })
+    }
+
+    /// Tries to lock the `RwLockSleep` for exclusive write access.
+    /// If it is already locked, it will return `None`.
+    /// Otherwise it returns a guard within `Some`.
+    pub fn try_write(&self) -> Option<RwLockSleepWriteGuard<T>> {
+        self.lock.try_write().map(|spinlock_guard| {
+            RwLockSleepWriteGuard {
+                guard: ManuallyDrop::new(spinlock_guard),
+                queue: &self.queue,
+            }
+        })
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for RwLockSleep<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.lock.try_read() {
+            Some(guard) => write!(f, "RwLockSleep {{ data: {:?} }}", &*guard),
+            None => write!(f, "RwLockSleep {{ <locked> }}"),
+        }
+    }
+}
+
+impl<T: ?Sized + Default> Default for RwLockSleep<T> {
+    fn default() -> RwLockSleep<T> {
+        RwLockSleep::new(Default::default())
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockSleepReadGuard<'a, T> {
+    type Target = T;
+
+    fn deref<'b>(&'b self) -> &'b T {
+        &**(self.guard)
+    }
+}
+
+impl<'a, T: ?Sized> Deref for RwLockSleepWriteGuard<'a, T> {
+    type Target = T;
+
+    fn deref<'b>(&'b self) -> &'b T {
+        &**(self.guard)
+    }
+}
+
+impl<'a, T: ?Sized> DerefMut for RwLockSleepWriteGuard<'a, T> {
+    fn deref_mut<'b>(&'b mut self) -> &'b mut T {
+        &mut **(self.guard)
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockSleepReadGuard<'a, T> {
+    fn drop(&mut self) {
+        // Release the inner lock before notifying the tasks on the waitqueue, such that a writer can acquire it.
+        unsafe { ManuallyDrop::drop(&mut self.guard); }
+        self.queue.notify_all();
+    }
+}
+
+impl<'a, T: ?Sized> Drop for RwLockSleepWriteGuard<'a, T> {
+    fn drop(&mut self) {
+        // Release the inner lock before notifying the tasks on the waitqueue.
+        // All of them are woken up, because either all waiting readers or one waiting writer can acquire it next.
+        unsafe { ManuallyDrop::drop(&mut self.guard); }
+        self.queue.notify_all();
+    }
+}
+
+// Implement the StableDeref trait for RwLockSleep guards, just like it's implemented for RwLock guards
+unsafe impl<'a, T: ?Sized> StableDeref for RwLockSleepReadGuard<'a, T> {}
+unsafe impl<'a, T: ?Sized> StableDeref for RwLockSleepWriteGuard<'a, T> {}
+
+/// Typedef of a owning reference that uses a `RwLockSleepReadGuard` as the owner.
+pub type RwLockSleepReadGuardRef<'a, T, U = T> = OwningRef<RwLockSleepReadGuard<'a, T>, U>;
+/// Typedef of a mutable owning reference that uses a `RwLockSleepWriteGuard` as the owner.
+pub type RwLockSleepWriteGuardRefMut<'a, T, U = T> = OwningRefMut<RwLockSleepWriteGuard<'a, T>, U>;
@@ -1,21 +1,67 @@
+//! This crate performs task switches, and lets the scheduler policy that decides which task runs next
+//! be chosen separately for each core at runtime.
+//!
+//! The built-in policies are round robin, priority and real-time (EDF) scheduling.
+//! Each core starts out with the policy chosen by the `priority_scheduler` or `realtime_scheduler` config options,
+//! or round robin if neither is given, and can be switched to another policy with [`set_policy()`](fn.set_policy.html).
+
 #![no_std]
 
 extern crate alloc;
 // #[macro_use] extern crate log;
+extern crate spin;
 extern crate irq_safety;
 extern crate apic;
 extern crate task;
 extern crate runqueue;
-#[cfg(priority_scheduler)] extern crate scheduler_priority;
-#[cfg(not(priority_scheduler))] extern crate scheduler_round_robin;
-
-
-use core::ops::Deref;
+extern crate scheduler_policy;
+extern crate scheduler_round_robin;
+extern crate scheduler_priority;
+extern crate scheduler_realtime;
+
+
+use core::{
+    ops::Deref,
+    time::Duration,
+};
+use alloc::vec::Vec;
+use spin::Once;
 use irq_safety::hold_interrupts;
 use apic::get_my_apic_id;
 use task::{Task, get_my_current_task, TaskRef};
-#[cfg(priority_scheduler)] use scheduler_priority::select_next_task;
-#[cfg(not(priority_scheduler))] use scheduler_round_robin::select_next_task;
+use scheduler_policy::SchedulerPolicy;
+
+
+/// Registers the built-in scheduler policies and sets the default one, which only happens once.
+fn register_builtin_policies() -> Result<(), &'static str> {
+    static REGISTERED: Once<Result<(), &'static str>> = Once::new();
+    *REGISTERED.call_once(|| {
+        scheduler_policy::register_policy(&scheduler_round_robin::POLICY)?;
+        scheduler_policy::register_policy(&scheduler_priority::POLICY)?;
+        scheduler_policy::register_policy(&scheduler_realtime::POLICY)?;
+
+        #[cfg(realtime_scheduler)]
+        scheduler_policy::set_default_policy(&scheduler_realtime::POLICY);
+        #[cfg(all(priority_scheduler, not(realtime_scheduler)))]
+        scheduler_policy::set_default_policy(&scheduler_priority::POLICY);
+        #[cfg(not(any(priority_scheduler, realtime_scheduler)))]
+        scheduler_policy::set_default_policy(&scheduler_round_robin::POLICY);
+        Ok(())
+    })
+}
+
+/// Initializes scheduling on the given core, which is an `apic_id`,
+/// by creating its runqueue and activating the default scheduler policy on it.
+pub fn init(apic_id: u8) -> Result<(), &'static str> {
+    register_builtin_policies()?;
+    runqueue::init(apic_id)
+}
+
+/// Selects the next task to run on the given core using that core's active scheduler policy.
+fn select_next_task(apic_id: u8) -> Option<TaskRef> {
+    scheduler_policy::with_active_policy(apic_id, |policy| policy.select_next_task(apic_id))
+        .and_then(|next| next)
+}
 
 /// Yields the current CPU by selecting a new `Task` to run 
 /// and then performs a task switch to that new `Task`.
@@ -28,8 +74,10 @@ pub fn schedule() -> bool {
     let next_task: *mut Task; 
     let apic_id = get_my_apic_id();
 
+    let selected_next_task = select_next_task(apic_id);
+
     {
-        if let Some(selected_next_task) = select_next_task(apic_id) {
+        if let Some(ref selected_next_task) = selected_next_task {
             next_task = selected_next_task.lock().deref() as *const Task as *mut Task;
         }
         else {
@@ -42,11 +90,11 @@ pub fn schedule() -> bool {
         // keep the same current task
         return false;
     }
-    
+
     // same scoping reasons as above: to release the lock around current_task
     {
         current_task = get_my_current_task().expect("schedule(): get_my_current_task() failed")
-            .lock().deref() as *const Task as *mut Task; 
+            .lock().deref() as *const Task as *mut Task;
     }
 
     if current_task == next_task {
@@ -54,6 +102,13 @@ pub fn schedule() -> bool {
         return false;
     }
 
+    // A task that was just migrated away from another core may still be running or switching out there.
+    if let Some(ref selected_next_task) = selected_next_task {
+        if !selected_next_task.claim_for_core(apic_id) {
+            return false;
+        }
+    }
+
     // we want mutable task references without the locks, and we use unsafe code to obtain those references
     // because the scope-based lock guard won't drop properly after the actual task_switch occurs.
     let (curr, next) = unsafe { (&mut *current_task, &mut *next_task) };
@@ -68,28 +123,78 @@ pub fn schedule() -> bool {
     true
 }
 
+/// Returns the scheduler policy that is active on the core whose runqueue contains the given task.
+fn policy_of(task: &TaskRef) -> Option<&'static dyn SchedulerPolicy> {
+    scheduler_policy::core_of(task).and_then(scheduler_policy::active_policy)
+}
+
+/// Switches the given core to the scheduler policy with the given name,
+/// moving all tasks on that core's runqueue over to the new policy.
+///
+/// The moved tasks lose any state specific to the previous policy, e.g., their priorities or real-time parameters.
+pub fn set_policy(core: u8, policy_name: &str) -> Result<(), &'static str> {
+    let policy = scheduler_policy::get_policy(policy_name).ok_or("no scheduler policy with the given name exists")?;
+    scheduler_policy::switch_policy(core, policy)
+}
+
+/// Returns the name of the scheduler policy that is active on the given core.
+pub fn get_policy(core: u8) -> Option<&'static str> {
+    scheduler_policy::active_policy(core).map(|policy| policy.name())
+}
+
+/// Returns the names of all scheduler policies that can be activated.
+pub fn policy_names() -> Vec<&'static str> {
+    scheduler_policy::policies().iter().map(|policy| policy.name()).collect()
+}
+
 /// Changes the priority of the given task with the given priority level.
 /// Priority values must be between 40 (maximum priority) and 0 (minimum prriority).
-/// This function returns an error when a scheduler without priority is loaded. 
-pub fn set_priority(_task: &TaskRef, _priority: u8) -> Result<(), &'static str> {
-    #[cfg(priority_scheduler)] {
-        scheduler_priority::set_priority(_task, _priority)
-    }
-    #[cfg(not(priority_scheduler))] {
-        Err("no scheduler that uses task priority is currently loaded")
-    }
+/// This function returns an error when the task's core uses a scheduler policy without priority.
+pub fn set_priority(task: &TaskRef, priority: u8) -> Result<(), &'static str> {
+    policy_of(task)
+        .ok_or("couldn't find the given task in any runqueue")?
+        .set_priority(task, priority)
 }
 
 /// Returns the priority of a given task.
-/// This function returns None when a scheduler without priority is loaded.
-pub fn get_priority(_task: &TaskRef) -> Option<u8> {
-    #[cfg(priority_scheduler)] {
-        scheduler_priority::get_priority(_task)
-    }
-    #[cfg(not(priority_scheduler))] {
-        //Err("no scheduler that uses task priority is currently loaded")
-        None
-    }
+/// This function returns None when the task's core uses a scheduler policy without priority.
+pub fn get_priority(task: &TaskRef) -> Option<u8> {
+    policy_of(task).and_then(|policy| policy.get_priority(task))
 }
 
+/// Makes the given task a real-time task that releases a new job every `period`,
+/// where each job may run for at most `budget` and must finish within `deadline` of its release.
+/// The task's first job is released immediately.
+///
+/// This function returns an error if admitting the task would overload its core,
+/// or when the task's core doesn't use the real-time scheduler policy.
+pub fn set_realtime_params(task: &TaskRef, period: Duration, budget: Duration, deadline: Duration) -> Result<(), &'static str> {
+    policy_of(task)
+        .ok_or("couldn't find the given task in any runqueue")?
+        .set_realtime_params(task, period, budget, deadline)
+}
+
+/// Returns the period, budget and relative deadline of the given real-time task.
+/// This function returns None if the task is not a real-time task.
+pub fn get_realtime_params(task: &TaskRef) -> Option<(Duration, Duration, Duration)> {
+    policy_of(task).and_then(|policy| policy.get_realtime_params(task))
+}
 
+/// Finishes the current job of the current real-time task
+/// and yields the CPU until the task's next period begins.
+///
+/// This function returns an error if the current task is not a real-time task.
+pub fn wait_for_next_period() -> Result<(), &'static str> {
+    {
+        // Interrupts are held such that the task isn't preempted in between finishing its job and yielding,
+        // which could otherwise cause it to yield at the start of its next job.
+        let _held_interrupts = hold_interrupts();
+        let curr_task = get_my_current_task().ok_or("wait_for_next_period(): couldn't get current task")?.clone();
+        scheduler_policy::with_active_policy(get_my_apic_id(), |policy| policy.complete_job(&curr_task))
+            .unwrap_or(Err("no scheduler policy is active on this core"))?;
+        schedule();
+    }
+    // The scheduler only records deadline misses, so they're logged here on its behalf.
+    scheduler_realtime::log_deadline_misses();
+    Ok(())
+}
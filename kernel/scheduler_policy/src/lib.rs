@@ -0,0 +1,306 @@
+//! This crate defines the [`SchedulerPolicy`](trait.SchedulerPolicy.html) trait that every scheduler policy implements,
+//! and keeps track of which policy is active on each core.
+//!
+//! Each policy keeps its own per-core runqueues, and a core only uses the runqueue of its active policy.
+//! Switching a core to a different policy at runtime moves all tasks from the old policy's runqueue
+//! into the new policy's runqueue for that core. Policy-specific state of those tasks,
+//! e.g., their priorities or real-time parameters, is not carried over.
+//! A policy whose runqueue type implements [`PerCoreRunQueue`](trait.PerCoreRunQueue.html)
+//! only needs to name that type in its [`RunQueuePolicy`](trait.RunQueuePolicy.html) implementation
+//! in order to manage its runqueues.
+//!
+//! All crates except the scheduler and the runqueue crates should use this crate only to inspect or switch policies;
+//! tasks should be added to and removed from runqueues through the `runqueue` crate.
+
+#![no_std]
+
+extern crate alloc;
+#[macro_use] extern crate lazy_static;
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate irq_safety;
+extern crate atomic_linked_list;
+extern crate task;
+
+use core::time::Duration;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use spin::Once;
+use irq_safety::{MutexIrqSafe, RwLockIrqSafe};
+use atomic_linked_list::atomic_map::AtomicMap;
+use task::TaskRef;
+
+
+/// A scheduler policy, which decides which task runs next on a core
+/// and manages the per-core runqueues that hold the tasks it chooses from.
+pub trait SchedulerPolicy: PolicyRunQueues + Send + Sync {
+    /// Returns the name of this policy, which is used to select it at runtime.
+    fn name(&self) -> &'static str;
+
+    /// Picks the next task to run on the given core.
+    /// Returns None if there is no schedule-able task.
+    fn select_next_task(&self, core: u8) -> Option<TaskRef>;
+
+    /// Sets the priority of the given task, if this policy uses priorities.
+    fn set_priority(&self, _task: &TaskRef, _priority: u8) -> Result<(), &'static str> {
+        Err("this scheduler policy does not use task priorities")
+    }
+
+    /// Returns the priority of the given task, if this policy uses priorities.
+    fn get_priority(&self, _task: &TaskRef) -> Option<u8> {
+        None
+    }
+
+    /// Makes the given task a periodic real-time task, if this policy supports real-time tasks.
+    fn set_realtime_params(&self, _task: &TaskRef, _period: Duration, _budget: Duration, _deadline: Duration) -> Result<(), &'static str> {
+        Err("this scheduler policy does not support real-time tasks")
+    }
+
+    /// Returns the period, budget and relative deadline of the given real-time task, if this policy supports real-time tasks.
+    fn get_realtime_params(&self, _task: &TaskRef) -> Option<(Duration, Duration, Duration)> {
+        None
+    }
+
+    /// Marks the current job of the given real-time task as finished, if this policy supports real-time tasks.
+    fn complete_job(&self, _task: &TaskRef) -> Result<(), &'static str> {
+        Err("this scheduler policy does not support real-time tasks")
+    }
+}
+
+/// The methods through which a `SchedulerPolicy` manages its per-core runqueues.
+///
+/// This is implemented for every policy that implements `RunQueuePolicy`.
+pub trait PolicyRunQueues {
+    /// Creates this policy's runqueue for the given core, if it doesn't already exist.
+    fn init_runqueue(&self, core: u8) -> Result<(), &'static str>;
+
+    /// Adds the given task to this policy's runqueue for the given core.
+    fn add_task(&self, core: u8, task: TaskRef) -> Result<(), &'static str>;
+
+    /// Removes the given task from this policy's runqueue for the given core.
+    fn remove_task(&self, core: u8, task: &TaskRef) -> Result<(), &'static str>;
+
+    /// Returns the tasks in this policy's runqueue for the given core.
+    fn tasks(&self, core: u8) -> Vec<TaskRef>;
+
+    /// Removes all tasks from this policy's runqueue for the given core and returns them in runqueue order.
+    fn take_tasks(&self, core: u8) -> Vec<TaskRef>;
+
+    /// Returns the number of tasks in this policy's runqueue for the given core.
+    fn runqueue_len(&self, core: u8) -> usize;
+}
+
+/// A runqueue that holds the tasks of one core, of which each policy's runqueue crate keeps one per core.
+pub trait PerCoreRunQueue: Sized + Send + Sync + 'static {
+    /// Creates a new runqueue for the given core.
+    fn init(core: u8) -> Result<(), &'static str>;
+
+    /// Returns the runqueue for the given core, if it has been created.
+    fn get_runqueue(core: u8) -> Option<&'static RwLockIrqSafe<Self>>;
+
+    /// Adds the given task to the runqueue for the given core.
+    fn add_task_to_specific_runqueue(core: u8, task: TaskRef) -> Result<(), &'static str>;
+
+    /// Removes the given task from this runqueue.
+    fn remove_task(&mut self, task: &TaskRef) -> Result<(), &'static str>;
+
+    /// Returns the tasks in this runqueue, in runqueue order.
+    fn tasks(&self) -> Vec<TaskRef>;
+
+    /// Returns the number of tasks in this runqueue.
+    fn len(&self) -> usize;
+}
+
+/// A scheduler policy that keeps its tasks in the given type of `PerCoreRunQueue`,
+/// through which it obtains its implementation of `PolicyRunQueues`.
+pub trait RunQueuePolicy {
+    /// The type of this policy's per-core runqueues.
+    type RunQueue: PerCoreRunQueue;
+}
+
+impl<P: RunQueuePolicy> PolicyRunQueues for P {
+    fn init_runqueue(&self, core: u8) -> Result<(), &'static str> {
+        if P::RunQueue::get_runqueue(core).is_some() {
+            return Ok(());
+        }
+        P::RunQueue::init(core)
+    }
+
+    fn add_task(&self, core: u8, task: TaskRef) -> Result<(), &'static str> {
+        P::RunQueue::add_task_to_specific_runqueue(core, task)
+    }
+
+    fn remove_task(&self, core: u8, task: &TaskRef) -> Result<(), &'static str> {
+        P::RunQueue::get_runqueue(core)
+            .ok_or("Couldn't get RunQueue for the given core")?
+            .write()
+            .remove_task(task)
+    }
+
+    fn tasks(&self, core: u8) -> Vec<TaskRef> {
+        P::RunQueue::get_runqueue(core)
+            .map(|rq| rq.read().tasks())
+            .unwrap_or_default()
+    }
+
+    fn take_tasks(&self, core: u8) -> Vec<TaskRef> {
+        P::RunQueue::get_runqueue(core)
+            .map(|rq| {
+                let mut rq_locked = rq.write();
+                let tasks = rq_locked.tasks();
+                // each task is removed through the runqueue, which keeps its bookkeeping about removed tasks up to date
+                for task in &tasks {
+                    if let Err(e) = rq_locked.remove_task(task) {
+                        error!("take_tasks(): couldn't remove task {:?} from the runqueue of core {}: {}", task, core, e);
+                    }
+                }
+                tasks
+            })
+            .unwrap_or_default()
+    }
+
+    fn runqueue_len(&self, core: u8) -> usize {
+        P::RunQueue::get_runqueue(core).map(|rq| rq.read().len()).unwrap_or(0)
+    }
+}
+
+
+lazy_static! {
+    /// All policies that can be activated, in the order they were registered.
+    static ref POLICIES: MutexIrqSafe<Vec<&'static dyn SchedulerPolicy>> = MutexIrqSafe::new(Vec::new());
+
+    /// The policy that is currently active on each core, keyed by the core's APIC ID.
+    /// The lock is held for reading while a policy operates on that core, and for writing while switching policies,
+    /// such that a core never uses a policy whose runqueue is being filled or drained.
+    static ref ACTIVE_POLICIES: AtomicMap<u8, RwLockIrqSafe<&'static dyn SchedulerPolicy>> = AtomicMap::new();
+
+    /// The core whose runqueue each task is on, keyed by task ID,
+    /// such that a task's core can be found without searching every runqueue.
+    static ref TASK_CORES: MutexIrqSafe<BTreeMap<usize, u8>> = MutexIrqSafe::new(BTreeMap::new());
+}
+
+/// The policy that each core starts out with.
+static DEFAULT_POLICY: Once<&'static dyn SchedulerPolicy> = Once::new();
+
+
+/// Registers the given policy such that it can be activated on any core.
+/// Returns an error if a policy with the same name has already been registered.
+pub fn register_policy(policy: &'static dyn SchedulerPolicy) -> Result<(), &'static str> {
+    let mut policies = POLICIES.lock();
+    if policies.iter().any(|p| p.name() == policy.name()) {
+        return Err("a scheduler policy with the same name has already been registered");
+    }
+    policies.push(policy);
+    Ok(())
+}
+
+/// Returns the registered policy with the given name.
+pub fn get_policy(name: &str) -> Option<&'static dyn SchedulerPolicy> {
+    POLICIES.lock().iter().find(|p| p.name() == name).cloned()
+}
+
+/// Returns all registered policies.
+pub fn policies() -> Vec<&'static dyn SchedulerPolicy> {
+    POLICIES.lock().clone()
+}
+
+/// Sets the policy that each core starts out with when it's initialized.
+/// This can only be done once; subsequent calls have no effect.
+pub fn set_default_policy(policy: &'static dyn SchedulerPolicy) {
+    DEFAULT_POLICY.call_once(|| policy);
+}
+
+/// Returns the policy that each core starts out with, if it has been set.
+pub fn default_policy() -> Option<&'static dyn SchedulerPolicy> {
+    DEFAULT_POLICY.try().cloned()
+}
+
+/// Activates the default policy on the given core, which is an `apic_id`, and creates its runqueue.
+pub fn init_core(core: u8) -> Result<(), &'static str> {
+    let policy = default_policy().ok_or("no default scheduler policy has been set")?;
+    policy.init_runqueue(core)?;
+    if ACTIVE_POLICIES.insert(core, RwLockIrqSafe::new(policy)).is_some() {
+        error!("BUG: scheduler_policy::init_core(): a policy was already active on core {}!", core);
+        return Err("a scheduler policy was already active on this core");
+    }
+    Ok(())
+}
+
+/// Returns the APIC IDs of all cores that have an active policy.
+pub fn cores() -> Vec<u8> {
+    ACTIVE_POLICIES.iter().map(|(core, _)| *core).collect()
+}
+
+/// Returns the policy that is currently active on the given core.
+pub fn active_policy(core: u8) -> Option<&'static dyn SchedulerPolicy> {
+    ACTIVE_POLICIES.get(&core).map(|p| *p.read())
+}
+
+/// Invokes the given function with the policy that is active on the given core,
+/// which cannot be switched until the function returns.
+/// Returns `None` if no policy is active on that core.
+pub fn with_active_policy<F, R>(core: u8, func: F) -> Option<R>
+    where F: FnOnce(&'static dyn SchedulerPolicy) -> R
+{
+    ACTIVE_POLICIES.get(&core).map(|p| func(*p.read()))
+}
+
+/// Records that the given task has been added to the runqueue of the given core.
+/// This is invoked by the `runqueue` crate whenever it adds a task to a runqueue.
+pub fn set_core_of(task: &TaskRef, core: u8) {
+    let task_id = task.lock().id;
+    TASK_CORES.lock().insert(task_id, core);
+}
+
+/// Records that the given task has been removed from the runqueue of the given core.
+/// This is invoked by the `runqueue` crate whenever it removes a task from a runqueue.
+pub fn clear_core_of(task: &TaskRef, core: u8) {
+    let task_id = task.lock().id;
+    let mut task_cores = TASK_CORES.lock();
+    // the task may have already been added to another core's runqueue
+    if task_cores.get(&task_id) == Some(&core) {
+        task_cores.remove(&task_id);
+    }
+}
+
+/// Returns the core whose runqueue the given task is on.
+pub fn core_of(task: &TaskRef) -> Option<u8> {
+    let task_id = task.lock().id;
+    TASK_CORES.lock().get(&task_id).cloned()
+}
+
+/// Switches the given core to the given policy,
+/// moving all tasks from the previously active policy's runqueue into the new policy's runqueue.
+///
+/// The tasks keep their order, but lose any state specific to the previous policy, e.g., their priorities.
+pub fn switch_policy(core: u8, new_policy: &'static dyn SchedulerPolicy) -> Result<(), &'static str> {
+    let active = ACTIVE_POLICIES.get(&core).ok_or("no scheduler policy is active on the given core")?;
+    // Holding the write lock prevents the core from scheduling while its tasks are in between runqueues.
+    let mut active_locked = active.write();
+    let old_policy = *active_locked;
+    if old_policy.name() == new_policy.name() {
+        return Ok(());
+    }
+    new_policy.init_runqueue(core)?;
+
+    let tasks = old_policy.take_tasks(core);
+    for (i, task) in tasks.iter().enumerate() {
+        if let Err(e) = new_policy.add_task(core, task.clone()) {
+            error!("Failed to move task {:?} to the {} policy on core {}: {}. Reverting to the {} policy.",
+                task, new_policy.name(), core, e, old_policy.name()
+            );
+            // Put every task back where it was, such that none of them is lost.
+            for t in &tasks[..i] {
+                let _ = new_policy.remove_task(core, t);
+            }
+            for t in tasks {
+                old_policy.add_task(core, t)?;
+            }
+            return Err(e);
+        }
+    }
+
+    *active_locked = new_policy;
+    info!("Switched core {} from the {} scheduler policy to the {} policy", core, old_policy.name(), new_policy.name());
+    Ok(())
+}
@@ -10,12 +10,13 @@
 
 #![no_std]
 
-extern crate alloc;
 #[macro_use] extern crate log;
 extern crate task;
 extern crate runqueue_priority;
+extern crate scheduler_policy;
 
 use task::TaskRef;
+use scheduler_policy::{SchedulerPolicy, RunQueuePolicy};
 use runqueue_priority::{RunQueue, MAX_PRIORITY};
 
 
@@ -234,4 +235,32 @@ fn assign_tokens(apic_id: u8) -> bool  {
     }
 
     return true;
-}
\ No newline at end of file
+}
+
+/// The token-based priority scheduler policy.
+pub struct PriorityPolicy;
+
+/// The instance of the priority policy that is registered with the `scheduler_policy` crate.
+pub static POLICY: PriorityPolicy = PriorityPolicy;
+
+impl RunQueuePolicy for PriorityPolicy {
+    type RunQueue = RunQueue;
+}
+
+impl SchedulerPolicy for PriorityPolicy {
+    fn name(&self) -> &'static str {
+        "priority"
+    }
+
+    fn select_next_task(&self, core: u8) -> Option<TaskRef> {
+        select_next_task(core)
+    }
+
+    fn set_priority(&self, task: &TaskRef, priority: u8) -> Result<(), &'static str> {
+        set_priority(task, priority)
+    }
+
+    fn get_priority(&self, task: &TaskRef) -> Option<u8> {
+        get_priority(task)
+    }
+}
@@ -0,0 +1,222 @@
+//! This crate picks the next task according to the earliest-deadline-first (EDF) real-time scheduling policy.
+//!
+//! Real-time tasks release a new job every period, which may run for at most its budget
+//! and must finish before its deadline (see `runqueue_realtime::RealtimeParams`).
+//! Among the real-time tasks whose current job is eligible to run,
+//! the one with the earliest absolute deadline is always picked.
+//! Regular tasks are picked in round robin fashion only when no real-time job is eligible,
+//! and the idle task only when no other task is runnable.
+//!
+//! Budgets are enforced by the APIC timer: every time the scheduler is invoked,
+//! the time since the previously picked task was scheduled in is charged against its budget,
+//! and a job that has used up its budget isn't picked again until the task's next period.
+//! Therefore, a job may overrun its budget by at most one timeslice.
+//!
+//! A job that hasn't finished by its deadline is recorded as a deadline miss,
+//! which is later reported to the `fault_log` by [`log_deadline_misses()`](fn.log_deadline_misses.html),
+//! because the scheduler itself must not allocate memory or log anything.
+//! A task finishes its current job by invoking [`complete_job()`](fn.complete_job.html).
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate mpmc;
+extern crate task;
+extern crate runqueue_realtime;
+extern crate scheduler_policy;
+extern crate fault_log;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use task::TaskRef;
+use scheduler_policy::{SchedulerPolicy, RunQueuePolicy};
+use runqueue_realtime::{RunQueue, RealtimeParams, now_micros};
+
+
+/// Sets the given task's period, budget (the maximum execution time of each job) and relative deadline,
+/// making it a real-time task.
+///
+/// Returns an error if the parameters are invalid,
+/// or if admitting the task would overload the core whose runqueue it is on.
+pub fn set_realtime_params(task: &TaskRef, period: Duration, budget: Duration, deadline: Duration) -> Result<(), &'static str> {
+    let params = RealtimeParams::new(period, budget, deadline)?;
+    RunQueue::set_realtime_params(task, Some(params))
+}
+
+/// Removes the real-time parameters of the given task, making it a regular task again.
+pub fn clear_realtime_params(task: &TaskRef) -> Result<(), &'static str> {
+    RunQueue::set_realtime_params(task, None)
+}
+
+/// Returns the period, budget and relative deadline of the given task,
+/// or `None` if it is not a real-time task.
+pub fn get_realtime_params(task: &TaskRef) -> Option<(Duration, Duration, Duration)> {
+    RunQueue::get_realtime_params(task).map(|p| (
+        Duration::from_micros(p.period),
+        Duration::from_micros(p.budget),
+        Duration::from_micros(p.deadline),
+    ))
+}
+
+/// Marks the current job of the given real-time task as finished,
+/// such that it won't be picked again until its next period begins.
+///
+/// The task should then yield the CPU by invoking the scheduler.
+pub fn complete_job(task: &TaskRef) -> Result<(), &'static str> {
+    RunQueue::complete_job(task)
+}
+
+/// The maximum number of deadline misses that are recorded until they're logged.
+const MAX_PENDING_DEADLINE_MISSES: usize = 64;
+
+/// A deadline miss detected by the scheduler that hasn't yet been logged.
+struct DeadlineMiss {
+    task: TaskRef,
+    core: u8,
+    /// How late (in microseconds) the job was when the miss was detected
+    lateness: u64,
+}
+
+lazy_static! {
+    /// The deadline misses detected by the scheduler, which are logged later outside of the scheduler.
+    static ref PENDING_DEADLINE_MISSES: mpmc::Queue<DeadlineMiss> = mpmc::Queue::with_capacity(MAX_PENDING_DEADLINE_MISSES);
+}
+
+/// The number of deadline misses that couldn't be recorded because too many were already pending.
+static UNRECORDED_DEADLINE_MISSES: AtomicUsize = AtomicUsize::new(0);
+
+/// Reports the deadline misses that the scheduler has detected since the last time this was invoked to the `fault_log`.
+///
+/// This must be invoked from a regular task context, e.g., by a real-time task after it has finished its job.
+pub fn log_deadline_misses() {
+    while let Some(miss) = PENDING_DEADLINE_MISSES.pop() {
+        let name = miss.task.lock().name.clone();
+        fault_log::log_deadline_miss(name, miss.core, Duration::from_micros(miss.lateness));
+    }
+    let unrecorded = UNRECORDED_DEADLINE_MISSES.swap(0, Ordering::Relaxed);
+    if unrecorded > 0 {
+        warn!("{} more deadline misses of real-time tasks weren't recorded", unrecorded);
+    }
+}
+
+/// This defines the earliest-deadline-first scheduler policy.
+/// Returns None if there is no schedule-able task.
+pub fn select_next_task(apic_id: u8) -> Option<TaskRef> {
+    let mut runqueue_locked = match RunQueue::get_runqueue(apic_id) {
+        Some(rq) => rq.write(),
+        _ => {
+            error!("BUG: select_next_task_realtime(): couldn't get runqueue for core {}", apic_id);
+            return None;
+        }
+    };
+
+    // The clock is only read if there are real-time tasks, which ensures that it has already been calibrated.
+    let now = if runqueue_locked.has_realtime_tasks() {
+        now_micros().ok()
+    } else {
+        None
+    };
+
+    let previous = runqueue_locked.take_current();
+    if let Some(now) = now {
+        // Charge the previously picked task for the time it has run since.
+        if let Some((prev_task, start)) = previous {
+            if let Some(prev) = runqueue_locked.iter_mut().find(|t| t.is_realtime() && ***t == prev_task) {
+                prev.charge(now.saturating_sub(start));
+            }
+        }
+
+        for realtime_taskref in runqueue_locked.iter_mut() {
+            let missed_deadline = realtime_taskref.absolute_deadline;
+            if realtime_taskref.update(now) {
+                let miss = DeadlineMiss {
+                    task: TaskRef::clone(realtime_taskref),
+                    core: apic_id,
+                    lateness: now.saturating_sub(missed_deadline),
+                };
+                if PENDING_DEADLINE_MISSES.push(miss).is_err() {
+                    UNRECORDED_DEADLINE_MISSES.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+
+    let mut idle_task_index: Option<usize> = None;
+    let mut regular_task_index: Option<usize> = None;
+    let mut realtime_task_index: Option<(usize, u64)> = None;
+
+    for (i, realtime_taskref) in runqueue_locked.iter().enumerate() {
+        let t = realtime_taskref.lock();
+
+        // we skip the idle task, and only choose it if no other tasks are runnable
+        if t.is_an_idle_task {
+            idle_task_index = Some(i);
+            continue;
+        }
+
+        // must be runnable
+        if !t.is_runnable() {
+            continue;
+        }
+
+        if realtime_taskref.is_realtime() {
+            // a real-time task may only run while its current job is eligible
+            let eligible = now.map_or(false, |now| realtime_taskref.is_eligible(now));
+            if eligible && realtime_task_index.map_or(true, |(_, d)| realtime_taskref.absolute_deadline < d) {
+                realtime_task_index = Some((i, realtime_taskref.absolute_deadline));
+            }
+        }
+        else if regular_task_index.is_none() {
+            regular_task_index = Some(i);
+        }
+    }
+
+    let next_task = match realtime_task_index {
+        Some((index, _)) => runqueue_locked.get_mut(index).map(|t| {
+            t.increment_context_switches();
+            (**t).clone()
+        }),
+        // regular tasks (and the idle task) are picked in round robin fashion
+        None => regular_task_index
+            .or(idle_task_index)
+            .and_then(|index| runqueue_locked.update_and_move_to_end(index)),
+    };
+
+    if let (Some(now), Some(task)) = (now, next_task.as_ref()) {
+        runqueue_locked.set_current(task.clone(), now);
+    }
+    next_task
+}
+
+/// The earliest-deadline-first real-time scheduler policy.
+pub struct RealtimePolicy;
+
+/// The instance of the realtime policy that is registered with the `scheduler_policy` crate.
+pub static POLICY: RealtimePolicy = RealtimePolicy;
+
+impl RunQueuePolicy for RealtimePolicy {
+    type RunQueue = RunQueue;
+}
+
+impl SchedulerPolicy for RealtimePolicy {
+    fn name(&self) -> &'static str {
+        "realtime"
+    }
+
+    fn select_next_task(&self, core: u8) -> Option<TaskRef> {
+        select_next_task(core)
+    }
+
+    fn set_realtime_params(&self, task: &TaskRef, period: Duration, budget: Duration, deadline: Duration) -> Result<(), &'static str> {
+        set_realtime_params(task, period, budget, deadline)
+    }
+
+    fn get_realtime_params(&self, task: &TaskRef) -> Option<(Duration, Duration, Duration)> {
+        get_realtime_params(task)
+    }
+
+    fn complete_job(&self, task: &TaskRef) -> Result<(), &'static str> {
+        complete_job(task)
+    }
+}
@@ -4,13 +4,13 @@
 
 #![no_std]
 
-extern crate alloc;
 #[macro_use] extern crate log;
 extern crate task;
-extern crate runqueue;
+extern crate scheduler_policy;
 extern crate runqueue_round_robin;
 
 use task::TaskRef;
+use scheduler_policy::{SchedulerPolicy, RunQueuePolicy};
 use runqueue_round_robin::RunQueue;
 
 
@@ -54,3 +54,23 @@ pub fn select_next_task(apic_id: u8) -> Option<TaskRef> {
         .or(idle_task_index)
         .and_then(|index| runqueue_locked.move_to_end(index))
 }
+
+/// The round robin scheduler policy.
+pub struct RoundRobinPolicy;
+
+/// The instance of the round robin policy that is registered with the `scheduler_policy` crate.
+pub static POLICY: RoundRobinPolicy = RoundRobinPolicy;
+
+impl RunQueuePolicy for RoundRobinPolicy {
+    type RunQueue = RunQueue;
+}
+
+impl SchedulerPolicy for RoundRobinPolicy {
+    fn name(&self) -> &'static str {
+        "round_robin"
+    }
+
+    fn select_next_task(&self, core: u8) -> Option<TaskRef> {
+        select_next_task(core)
+    }
+}
@@ -0,0 +1,229 @@
+//! Lets a task block until any one of multiple event sources is ready, e.g., several channel receivers,
+//! optionally giving up after a timeout.
+//!
+//! Each event source is added to a [`Select`](struct.Select.html) as an "arm",
+//! which consists of a non-blocking attempt to obtain an event from that source
+//! and, optionally, the `WaitQueue` that is notified when that source may have become ready.
+//! While waiting, the current task is registered on the `WaitQueue`s of all arms and blocked,
+//! such that it is woken up by whichever source is notified first.
+//! The arms are checked while interrupts are enabled, so they may acquire locks.
+//!
+//! Event sources that have no `WaitQueue`, such as a `DFQueue`, can be added as polled arms,
+//! which are checked again every timeslice while the task is waiting.
+//!
+//! # Example
+//! ```ignore
+//! enum Event { Number(usize), Text(String) }
+//! let mut select = Select::new();
+//! select.recv(&number_receiver, |res| Event::Number(res.unwrap()))
+//!       .recv(&text_receiver, |res| Event::Text(res.unwrap()));
+//! match select.wait_timeout(Duration::from_millis(100)) { ... }
+//! ```
+
+#![no_std]
+
+extern crate alloc;
+extern crate irq_safety;
+extern crate kernel_config;
+extern crate task;
+extern crate scheduler;
+extern crate wait_queue;
+extern crate sleep;
+extern crate async_channel;
+extern crate rendezvous;
+
+use core::{
+    cmp::min,
+    time::Duration,
+};
+use alloc::{
+    boxed::Box,
+    vec::Vec,
+};
+use irq_safety::hold_interrupts;
+use kernel_config::time::CONFIG_TIMESLICE_PERIOD_MICROSECONDS;
+use async_channel::ChannelError;
+use wait_queue::WaitQueue;
+use sleep::Instant;
+
+
+/// Errors that may occur while waiting in a `Select`.
+#[derive(Debug, PartialEq)]
+pub enum SelectError {
+    /// The current task couldn't be obtained.
+    NoCurrentTask,
+    /// The `Select` has no arms and no deadline, so it would block forever.
+    NoEventSources,
+    /// None of the arms became ready before the deadline.
+    Timeout,
+}
+
+/// One event source in a `Select`.
+struct Arm<'a, R> {
+    /// The queue that is notified when this source may have become ready.
+    /// If `None`, this source must be polled.
+    wait_queue: Option<&'a WaitQueue>,
+    /// Obtains an event from this source without blocking, returning `None` if the source isn't ready.
+    try_ready: Box<dyn FnMut() -> Option<R> + 'a>,
+}
+
+/// A set of event sources that a task can wait on at once.
+///
+/// Each arm converts the event it obtained into a common type `R`, which is returned by the various `wait` functions.
+/// If multiple arms are ready at once, the one that was added first is chosen,
+/// so arms should be added in order of decreasing priority.
+pub struct Select<'a, R> {
+    arms: Vec<Arm<'a, R>>,
+}
+
+impl<'a, R> Select<'a, R> {
+    /// Creates a new `Select` without any arms.
+    pub fn new() -> Select<'a, R> {
+        Select { arms: Vec::new() }
+    }
+
+    /// Adds an arm that receives a message from the given asynchronous channel `receiver`
+    /// and passes it into the given `handler`.
+    ///
+    /// The arm is also ready if the channel is disconnected,
+    /// in which case the `handler` is passed `Err(ChannelError::ChannelDisconnected)`.
+    pub fn recv<T, F>(&mut self, receiver: &'a async_channel::Receiver<T>, mut handler: F) -> &mut Select<'a, R>
+        where T: Send, F: FnMut(Result<T, ChannelError>) -> R + 'a
+    {
+        self.arms.push(Arm {
+            wait_queue: Some(receiver.receive_wait_queue()),
+            try_ready: Box::new(move || match receiver.try_receive() {
+                Err(ChannelError::ChannelEmpty) => None,
+                res => Some(handler(res)),
+            }),
+        });
+        self
+    }
+
+    /// Adds an arm that receives a message from the given rendezvous channel `receiver`
+    /// once a sender is waiting, and passes it into the given `handler`.
+    pub fn recv_rendezvous<T, F>(&mut self, receiver: &'a rendezvous::Receiver<T>, mut handler: F) -> &mut Select<'a, R>
+        where T: Send, F: FnMut(T) -> R + 'a
+    {
+        self.arms.push(Arm {
+            wait_queue: Some(receiver.receive_wait_queue()),
+            try_ready: Box::new(move || receiver.try_receive().ok().map(|msg| handler(msg))),
+        });
+        self
+    }
+
+    /// Adds an arm for a custom event source that notifies the given `wait_queue` whenever it may have become ready.
+    ///
+    /// The `try_ready` closure must obtain an event from the source without blocking,
+    /// or return `None` if the source isn't ready.
+    pub fn wait_queue<F>(&mut self, wait_queue: &'a WaitQueue, try_ready: F) -> &mut Select<'a, R>
+        where F: FnMut() -> Option<R> + 'a
+    {
+        self.arms.push(Arm {
+            wait_queue: Some(wait_queue),
+            try_ready: Box::new(try_ready),
+        });
+        self
+    }
+
+    /// Adds an arm for an event source that doesn't notify waiting tasks, e.g., a keyboard `DFQueue`.
+    ///
+    /// The `try_ready` closure must obtain an event from the source without blocking,
+    /// or return `None` if the source isn't ready.
+    /// It is invoked again every timeslice while the task is waiting.
+    pub fn poll<F>(&mut self, try_ready: F) -> &mut Select<'a, R>
+        where F: FnMut() -> Option<R> + 'a
+    {
+        self.arms.push(Arm {
+            wait_queue: None,
+            try_ready: Box::new(try_ready),
+        });
+        self
+    }
+
+    /// Returns the event from the first arm that is ready, without blocking.
+    pub fn try_select(&mut self) -> Option<R> {
+        self.arms.iter_mut().filter_map(|arm| (arm.try_ready)()).next()
+    }
+
+    /// Blocks until one of the arms is ready and returns its event.
+    pub fn wait(&mut self) -> Result<R, SelectError> {
+        self.wait_inner(None)
+    }
+
+    /// Blocks until one of the arms is ready and returns its event,
+    /// or returns `SelectError::Timeout` if none became ready within the given `timeout`.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Result<R, SelectError> {
+        self.wait_inner(Some(Instant::now() + timeout))
+    }
+
+    /// Blocks until one of the arms is ready and returns its event,
+    /// or returns `SelectError::Timeout` if none became ready before the given `deadline`.
+    pub fn wait_deadline(&mut self, deadline: Instant) -> Result<R, SelectError> {
+        self.wait_inner(Some(deadline))
+    }
+
+    fn wait_inner(&mut self, deadline: Option<Instant>) -> Result<R, SelectError> {
+        if self.arms.is_empty() && deadline.is_none() {
+            return Err(SelectError::NoEventSources);
+        }
+        let curr_task = task::get_my_current_task().ok_or(SelectError::NoCurrentTask)?.clone();
+        let poll_interval = Duration::from_micros(CONFIG_TIMESLICE_PERIOD_MICROSECONDS as u64);
+        let has_polled_arms = self.arms.iter().any(|arm| arm.wait_queue.is_none());
+
+        let result = loop {
+            for wq in self.arms.iter().filter_map(|arm| arm.wait_queue) {
+                wq.register(&curr_task);
+            }
+
+            // The arms are checked *after* registering on the wait queues,
+            // such that a source that becomes ready after being checked will notify this task.
+            if let Some(ret) = self.try_select() {
+                break Ok(ret);
+            }
+            let now = Instant::now();
+            if deadline.map_or(false, |d| now >= d) {
+                break Err(SelectError::Timeout);
+            }
+
+            let wakeup_timer = {
+                // Interrupts must be held from blocking this task until it has set up its wakeup timer,
+                // otherwise it could be descheduled while blocked before anything can wake it up.
+                let _held_interrupts = hold_interrupts();
+                curr_task.block();
+
+                // A notification that arrived in between checking the arms and blocking this task
+                // removed it from that wait queue without unblocking it, so it must not go to sleep.
+                if self.arms.iter().filter_map(|arm| arm.wait_queue).any(|wq| !wq.contains(&curr_task)) {
+                    curr_task.unblock();
+                    continue;
+                }
+
+                // Polled arms must be re-checked after one timeslice, even if no wait queue is notified.
+                let wakeup = if has_polled_arms {
+                    Some(deadline.map_or(now + poll_interval, |d| min(d, now + poll_interval)))
+                } else {
+                    deadline
+                };
+                wakeup.map(|w| {
+                    let task_to_wakeup = curr_task.clone();
+                    sleep::add_oneshot_timer(w, move || task_to_wakeup.unblock())
+                })
+            };
+
+            scheduler::schedule();
+
+            // Here, we have been woken up by a notification or the wakeup timer, so check the arms again.
+            if let Some(timer) = wakeup_timer {
+                timer.cancel();
+            }
+        };
+
+        // Remove this task from the wait queues that didn't notify it,
+        // so they won't wake it up after it has stopped waiting.
+        for wq in self.arms.iter().filter_map(|arm| arm.wait_queue) {
+            wq.unregister(&curr_task);
+        }
+        result
+    }
+}
@@ -0,0 +1,112 @@
+//! A counting semaphore that puts tasks to sleep while they wait for a resource to become available.
+
+#![no_std]
+
+extern crate wait_queue;
+extern crate sleep;
+
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
+use core::time::Duration;
+use wait_queue::{WaitQueue, WaitError};
+use sleep::Instant;
+
+
+/// A counting semaphore that puts a `Task` to sleep while waiting for one of its resources to become available.
+///
+/// The semaphore holds a count of available resources.
+/// Acquiring the semaphore decrements the count, and blocks while the count is zero;
+/// releasing the semaphore increments the count and notifies (wakes up) one of the `Task`s waiting to acquire it.
+///
+/// This can be shared across multiple `Task`s by wrapping it in an `Arc`.
+pub struct Semaphore {
+    queue: WaitQueue,
+    count: AtomicUsize,
+}
+
+/// A guard that releases the `Semaphore` it was acquired from when it falls out of scope.
+pub struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    /// Creates a new semaphore with the given number of available resources.
+    pub fn new(count: usize) -> Semaphore {
+        Semaphore {
+            queue: WaitQueue::new(),
+            count: AtomicUsize::new(count),
+        }
+    }
+
+    /// Blocks until one resource is acquired by putting this `Task` to sleep
+    /// until another `Task` releases one.
+    pub fn acquire(&self) -> Result<(), WaitError> {
+        // Fast path: check for the uncontended case.
+        if self.try_acquire() {
+            return Ok(());
+        }
+        // Slow path if no resources are available: wait until we obtain one.
+        self.queue.wait_until(&|| if self.try_acquire() { Some(()) } else { None })
+    }
+
+    /// Similar to [`acquire`](#method.acquire), but gives up and returns `WaitError::Timeout`
+    /// if no resource could be acquired within the given `timeout`.
+    pub fn acquire_timeout(&self, timeout: Duration) -> Result<(), WaitError> {
+        self.acquire_deadline(Instant::now() + timeout)
+    }
+
+    /// Similar to [`acquire`](#method.acquire), but gives up and returns `WaitError::Timeout`
+    /// if no resource could be acquired by the given `deadline`.
+    pub fn acquire_deadline(&self, deadline: Instant) -> Result<(), WaitError> {
+        // Fast path: check for the uncontended case.
+        if self.try_acquire() {
+            return Ok(());
+        }
+        // Slow path if no resources are available: wait until we obtain one or the deadline passes.
+        self.queue.wait_until_deadline(&|| if self.try_acquire() { Some(()) } else { None }, deadline)
+    }
+
+    /// Tries to acquire one resource without blocking.
+    /// Returns `true` if a resource was acquired, or `false` if none were available.
+    pub fn try_acquire(&self) -> bool {
+        let mut count = self.count.load(Ordering::SeqCst);
+        while count > 0 {
+            let previous = self.count.compare_and_swap(count, count - 1, Ordering::SeqCst);
+            if previous == count {
+                return true;
+            }
+            count = previous;
+        }
+        false
+    }
+
+    /// Releases one resource, which notifies a `Task` waiting to acquire it.
+    pub fn release(&self) {
+        self.count.fetch_add(1, Ordering::SeqCst);
+        self.queue.notify_one();
+    }
+
+    /// Blocks until one resource is acquired, see [`acquire`](#method.acquire),
+    /// and returns a guard that releases it when it falls out of scope.
+    pub fn access(&self) -> Result<SemaphoreGuard, WaitError> {
+        self.acquire()?;
+        Ok(SemaphoreGuard { semaphore: self })
+    }
+
+    /// Returns the number of currently available resources.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::SeqCst)
+    }
+}
+
+impl fmt::Debug for Semaphore {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Semaphore {{ count: {} }}", self.count())
+    }
+}
+
+impl<'a> Drop for SemaphoreGuard<'a> {
+    fn drop(&mut self) {
+        self.semaphore.release();
+    }
+}
@@ -0,0 +1,395 @@
+//! Lets tasks sleep for a given duration or until a given instant, and runs one-shot or periodic kernel timers.
+//!
+//! Each core has its own queue of timers, ordered by deadline, which is serviced on every APIC timer interrupt
+//! through [`handle_timer_interrupt()`](fn.handle_timer_interrupt.html).
+//! A sleeping task is blocked and registered in the timer queue of the core it was running on,
+//! such that the scheduler runs other tasks until the timer expires and unblocks it.
+//! Therefore, the resolution of sleeps and timers is one APIC timer period, i.e., one timeslice.
+//!
+//! Time is measured by a monotonic clock, the HPET if available or otherwise the TSC.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate lazy_static;
+extern crate alloc;
+extern crate spin;
+extern crate irq_safety;
+extern crate atomic_linked_list;
+extern crate apic;
+extern crate hpet;
+extern crate tsc;
+extern crate task;
+extern crate scheduler;
+
+use core::{
+    ops::{Add, Sub},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Once;
+use irq_safety::{MutexIrqSafe, hold_interrupts};
+use atomic_linked_list::atomic_map::AtomicMap;
+use apic::get_my_apic_id;
+use hpet::get_hpet;
+use task::TaskRef;
+
+
+/// A point in time as measured by the monotonic clock, with microsecond resolution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant {
+    micros: u64,
+}
+
+impl Instant {
+    /// Returns the current instant.
+    ///
+    /// If the monotonic clock can't be read, e.g., because neither the HPET nor the TSC frequency is available,
+    /// this returns the instant the clock started at.
+    pub fn now() -> Instant {
+        Instant { micros: monotonic_micros().unwrap_or(0) }
+    }
+
+    /// Returns the time elapsed from `earlier` until this instant, or zero if `earlier` is later than this instant.
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        Duration::from_micros(self.micros.saturating_sub(earlier.micros))
+    }
+
+    /// Returns the time elapsed since this instant.
+    pub fn elapsed(&self) -> Duration {
+        Instant::now().duration_since(*self)
+    }
+
+    /// Returns the number of microseconds since the monotonic clock started.
+    pub fn as_micros(&self) -> u64 {
+        self.micros
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+    fn add(self, duration: Duration) -> Instant {
+        Instant { micros: self.micros.saturating_add(duration_to_micros(duration)) }
+    }
+}
+
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+    fn sub(self, duration: Duration) -> Instant {
+        Instant { micros: self.micros.saturating_sub(duration_to_micros(duration)) }
+    }
+}
+
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+    fn sub(self, earlier: Instant) -> Duration {
+        self.duration_since(earlier)
+    }
+}
+
+fn duration_to_micros(duration: Duration) -> u64 {
+    duration.as_secs().saturating_mul(1_000_000).saturating_add(duration.subsec_micros() as u64)
+}
+
+
+/// The counter from which the monotonic clock is derived.
+#[derive(Debug, Clone, Copy)]
+enum ClockSource {
+    Hpet { period_femtoseconds: u64 },
+    Tsc { frequency: u64 },
+}
+
+/// The clock source, chosen upon first use.
+static CLOCK_SOURCE: Once<ClockSource> = Once::new();
+
+/// Returns the current time of the monotonic clock in microseconds.
+fn monotonic_micros() -> Result<u64, &'static str> {
+    let source = match CLOCK_SOURCE.try() {
+        Some(source) => *source,
+        None => {
+            let source = match get_hpet().as_ref() {
+                Some(hpet) => ClockSource::Hpet { period_femtoseconds: hpet.counter_period_femtoseconds() as u64 },
+                None => ClockSource::Tsc { frequency: tsc::get_tsc_frequency()? },
+            };
+            *CLOCK_SOURCE.call_once(|| source)
+        }
+    };
+    match source {
+        ClockSource::Hpet { period_femtoseconds } => {
+            let ticks = get_hpet().as_ref().ok_or("sleep: couldn't get HPET timer")?.get_counter();
+            Ok((ticks as u128 * period_femtoseconds as u128 / 1_000_000_000) as u64)
+        }
+        ClockSource::Tsc { frequency } => {
+            let ticks = tsc::tsc_ticks().into();
+            Ok((ticks as u128 * 1_000_000 / frequency as u128) as u64)
+        }
+    }
+}
+
+
+/// A unique identifier of a timer.
+type TimerId = usize;
+
+static NEXT_TIMER_ID: AtomicUsize = AtomicUsize::new(1);
+
+/// The number of timers that each core's queue has room for beyond its pending timers,
+/// such that timers added by callbacks in the timer interrupt handler usually don't need to allocate memory.
+const SPARE_TIMER_CAPACITY: usize = 32;
+
+/// What happens when a timer expires.
+enum TimerAction {
+    /// Unblocks a sleeping task.
+    Wake(TaskRef),
+    /// Invokes a callback, and re-arms the timer if it has a period and hasn't been cancelled.
+    Callback {
+        callback: Box<dyn FnMut() + Send>,
+        period: Option<Duration>,
+        /// Set when a periodic timer is cancelled, which may happen while it's off the queue for its callback to run.
+        cancelled: Option<Arc<AtomicBool>>,
+    },
+}
+
+/// A pending timer.
+struct Timer {
+    deadline: Instant,
+    id: TimerId,
+    action: TimerAction,
+}
+
+/// The timers of one core, ordered by their deadlines.
+///
+/// Memory is only allocated and freed outside of the timer interrupt handler,
+/// which instead uses the spare capacity reserved beforehand.
+struct TimerQueue {
+    /// The pending timers, ordered by descending deadline and then by descending ID,
+    /// such that the next timer to expire is at the end.
+    timers: Vec<Timer>,
+    /// The actions of timers that were removed in the timer interrupt handler,
+    /// which are dropped later outside of it.
+    retired: Vec<TimerAction>,
+    /// Whether the timer interrupt handler is running on this queue's core.
+    in_interrupt: bool,
+}
+
+impl TimerQueue {
+    fn new() -> TimerQueue {
+        TimerQueue {
+            timers: Vec::with_capacity(SPARE_TIMER_CAPACITY),
+            retired: Vec::with_capacity(SPARE_TIMER_CAPACITY),
+            in_interrupt: false,
+        }
+    }
+
+    /// Drops the actions of retired timers and reserves spare capacity for more timers,
+    /// unless this is invoked from the timer interrupt handler.
+    fn make_room(&mut self) {
+        if self.in_interrupt {
+            return;
+        }
+        self.retired.clear();
+        // every pending timer may be retired before the next time this is invoked
+        let capacity = self.timers.len() + SPARE_TIMER_CAPACITY;
+        self.timers.reserve(capacity - self.timers.len());
+        self.retired.reserve(capacity);
+    }
+
+    fn insert(&mut self, id: TimerId, deadline: Instant, action: TimerAction) {
+        self.make_room();
+        let index = match self.timers.binary_search_by(|t| (deadline, id).cmp(&(t.deadline, t.id))) {
+            Ok(index) | Err(index) => index,
+        };
+        self.timers.insert(index, Timer { deadline, id, action });
+    }
+
+    /// Removes the timer with the given ID, returning whether it was pending.
+    fn remove(&mut self, id: TimerId) -> bool {
+        match self.timers.iter().position(|t| t.id == id) {
+            Some(index) => {
+                let timer = self.timers.remove(index);
+                self.retire(timer.action);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes and returns the next timer if its deadline is at or before `now`.
+    fn pop_expired(&mut self, now: Instant) -> Option<Timer> {
+        match self.timers.last() {
+            Some(t) if t.deadline <= now => self.timers.pop(),
+            _ => None,
+        }
+    }
+
+    /// Drops the given action of a removed timer, or defers dropping it if in the timer interrupt handler.
+    fn retire(&mut self, action: TimerAction) {
+        if self.in_interrupt {
+            self.retired.push(action);
+        } else {
+            drop(action);
+        }
+    }
+}
+
+lazy_static! {
+    /// The timer queue of each core, keyed by the core's APIC ID.
+    static ref TIMER_QUEUES: AtomicMap<u8, MutexIrqSafe<TimerQueue>> = AtomicMap::new();
+}
+
+/// Returns the timer queue of the current core, creating it if it doesn't yet exist.
+fn my_timer_queue() -> (u8, &'static MutexIrqSafe<TimerQueue>) {
+    // Interrupts are held so that this task isn't migrated to another core in between getting the APIC ID and the queue.
+    let _held_interrupts = hold_interrupts();
+    let apic_id = get_my_apic_id();
+    if TIMER_QUEUES.get(&apic_id).is_none() {
+        // only the current core ever inserts its own queue, so this can't race with another insertion
+        TIMER_QUEUES.insert(apic_id, MutexIrqSafe::new(TimerQueue::new()));
+    }
+    (apic_id, TIMER_QUEUES.get(&apic_id).expect("BUG: sleep: timer queue missing right after insertion"))
+}
+
+
+/// Blocks the current task for at least the given `duration`, letting other tasks run in the meantime.
+pub fn sleep(duration: Duration) -> Result<(), &'static str> {
+    sleep_until(Instant::now() + duration)
+}
+
+/// Blocks the current task until at least the given `deadline`, letting other tasks run in the meantime.
+/// Returns immediately if the deadline has already passed.
+pub fn sleep_until(deadline: Instant) -> Result<(), &'static str> {
+    let curr_task = task::get_my_current_task().ok_or("sleep: couldn't get current task")?.clone();
+
+    // The task may be woken up before its deadline, e.g., if another task unblocks it,
+    // in which case it goes back to sleep until the deadline.
+    while Instant::now() < deadline {
+        let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+        let (_apic_id, queue) = my_timer_queue();
+        {
+            // The task must be blocked while holding the queue lock (which disables interrupts),
+            // such that the timer can't expire and unblock the task before it is blocked.
+            let mut queue_locked = queue.lock();
+            queue_locked.insert(id, deadline, TimerAction::Wake(curr_task.clone()));
+            curr_task.block();
+        }
+        scheduler::schedule();
+
+        // If the task was woken up by something other than its timer, the timer must be removed,
+        // otherwise it would later unblock this task while it's blocked on something else.
+        queue.lock().remove(id);
+    }
+    Ok(())
+}
+
+
+/// A handle to a kernel timer, which can be used to cancel it.
+///
+/// Dropping the handle does not cancel the timer.
+#[derive(Debug)]
+pub struct TimerHandle {
+    id: TimerId,
+    apic_id: u8,
+    /// The flag that keeps a periodic timer from being re-armed once cancelled
+    cancelled: Option<Arc<AtomicBool>>,
+}
+
+impl TimerHandle {
+    /// Cancels the timer, such that its callback won't be invoked again.
+    /// Returns false if the timer had already expired (and wasn't periodic) or been cancelled.
+    ///
+    /// If the timer's callback is running on another core at the time, that invocation still completes.
+    pub fn cancel(self) -> bool {
+        // A periodic timer is off the queue while its callback runs, so it's flagged first such that it isn't re-armed.
+        // The flag is set before the queue lock is taken, so either the re-arming sees it or the timer is removed here.
+        if let Some(ref cancelled) = self.cancelled {
+            cancelled.store(true, Ordering::Release);
+        }
+        let removed = match TIMER_QUEUES.get(&self.apic_id) {
+            Some(queue) => queue.lock().remove(self.id),
+            None => false,
+        };
+        // a periodic timer never expires by itself, so cancelling it always succeeds
+        removed || self.cancelled.is_some()
+    }
+}
+
+/// Adds a timer on the current core that invokes the given `callback` once at the given `deadline`.
+///
+/// The callback runs in the APIC timer interrupt handler, so it must be short and must not block;
+/// a task that needs to do more work should be woken up by the callback instead.
+pub fn add_oneshot_timer<F>(deadline: Instant, callback: F) -> TimerHandle
+    where F: FnMut() + Send + 'static
+{
+    add_timer(deadline, Box::new(callback), None)
+}
+
+/// Adds a timer on the current core that invokes the given `callback` every `period`, starting one period from now.
+///
+/// The callback runs in the APIC timer interrupt handler, so it must be short and must not block.
+/// If the timer falls behind by more than one period, the missed invocations are skipped rather than run back to back.
+pub fn add_periodic_timer<F>(period: Duration, callback: F) -> Result<TimerHandle, &'static str>
+    where F: FnMut() + Send + 'static
+{
+    if period == Duration::from_secs(0) {
+        return Err("sleep: the period of a periodic timer must not be zero");
+    }
+    Ok(add_timer(Instant::now() + period, Box::new(callback), Some(period)))
+}
+
+fn add_timer(deadline: Instant, callback: Box<dyn FnMut() + Send>, period: Option<Duration>) -> TimerHandle {
+    let id = NEXT_TIMER_ID.fetch_add(1, Ordering::Relaxed);
+    let cancelled = period.map(|_| Arc::new(AtomicBool::new(false)));
+    let (apic_id, queue) = my_timer_queue();
+    queue.lock().insert(id, deadline, TimerAction::Callback { callback, period, cancelled: cancelled.clone() });
+    TimerHandle { id, apic_id, cancelled }
+}
+
+
+/// Wakes up the tasks and runs the callbacks of all expired timers on the current core.
+///
+/// This must be invoked on every core from the APIC timer interrupt handler, before the scheduler picks the next task,
+/// such that tasks woken up here can be scheduled right away.
+pub fn handle_timer_interrupt() {
+    let queue = match TIMER_QUEUES.get(&get_my_apic_id()) {
+        Some(q) => q,
+        None => return, // no task on this core has used a timer yet
+    };
+    let now = Instant::now();
+    queue.lock().in_interrupt = true;
+    // Each expired timer is removed and handled without the queue lock held,
+    // such that callbacks can add or cancel timers themselves.
+    loop {
+        let timer = match queue.lock().pop_expired(now) {
+            Some(timer) => timer,
+            None => break,
+        };
+        match timer.action {
+            TimerAction::Wake(task) => {
+                task.unblock();
+                queue.lock().retire(TimerAction::Wake(task));
+            }
+            TimerAction::Callback { mut callback, period, cancelled } => {
+                callback();
+                let mut queue_locked = queue.lock();
+                let is_cancelled = cancelled.as_ref().map_or(false, |c| c.load(Ordering::Acquire));
+                match period {
+                    Some(period) if !is_cancelled => {
+                        let mut next_deadline = timer.deadline + period;
+                        if next_deadline <= now {
+                            trace!("sleep: periodic timer {} fell behind by {:?}, skipping missed periods", timer.id, now - next_deadline);
+                            next_deadline = now + period;
+                        }
+                        // this reuses the room the timer had in the queue before it was removed
+                        queue_locked.insert(timer.id, next_deadline, TimerAction::Callback { callback, period: Some(period), cancelled });
+                    }
+                    _ => queue_locked.retire(TimerAction::Callback { callback, period, cancelled }),
+                }
+            }
+        }
+    }
+    queue.lock().in_interrupt = false;
+}
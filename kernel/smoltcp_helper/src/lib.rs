@@ -5,24 +5,47 @@
 
 #[macro_use] extern crate log;
 #[macro_use] extern crate alloc;
+#[macro_use] extern crate lazy_static;
 extern crate smoltcp;
 extern crate network_manager;
 extern crate spin;
 extern crate hpet;
+extern crate task;
+extern crate sleep;
 
 use core::convert::TryInto;
-use spin::Once;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use alloc::{
+    collections::BTreeMap,
+    string::String,
+    vec::Vec,
+};
+use spin::{Mutex, Once};
 use hpet::get_hpet;
 use smoltcp::{
-    wire::IpEndpoint,
-    socket::{SocketSet, TcpSocket, SocketHandle},
+    wire::{IpAddress, IpEndpoint},
+    socket::{Socket, SocketSet, TcpSocket, SocketHandle},
     time::Instant
 };
-use network_manager::{NetworkInterfaceRef, NETWORK_INTERFACES};
+use network_manager::{NetworkInterfaceRef, NETWORK_INTERFACES, route_iface};
 
 /// The starting number for freely-available (non-reserved) standard TCP/UDP ports.
 pub const STARTING_FREE_PORT: u16 = 49152;
 
+/// How long (in milliseconds) a socket snapshot waits for tasks to poll their sockets.
+const SOCKET_SNAPSHOT_WINDOW_MILLIS: u64 = 100;
+
+/// Whether a socket snapshot is being taken, during which `poll_iface()` records the sockets it polls.
+/// This is checked first such that polling doesn't take the snapshot lock otherwise.
+static SNAPSHOT_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// The sockets recorded by `poll_iface()` during the current snapshot,
+    /// keyed by the ID of the polling task and the address of the `SocketSet` that contains them.
+    static ref SOCKET_SNAPSHOT: Mutex<BTreeMap<(usize, usize), Vec<SocketInfo>>> = Mutex::new(BTreeMap::new());
+}
+
 /// A simple macro to get the current HPET clock ticks.
 #[macro_export]
 macro_rules! hpet_ticks {
@@ -52,15 +75,24 @@ pub fn millis_since(start_time: u64) -> Result<u64, &'static str> {
 }
 
 
-/// Returns the first network interface available in the system.
+/// Returns the first network interface available in the system,
+/// preferring a real network device over the loopback interface.
 pub fn get_default_iface() -> Result<NetworkInterfaceRef, &'static str> {
-    NETWORK_INTERFACES.lock()
-        .iter()
-        .next()
+    let ifaces = NETWORK_INTERFACES.lock();
+    ifaces.iter()
+        .find(|iface| !iface.lock().is_loopback())
+        .or_else(|| ifaces.iter().next())
         .cloned()
         .ok_or_else(|| "no network interfaces available")
 }
 
+/// Returns the network interface that should be used to reach the given `destination` address,
+/// e.g., the loopback interface for `127.0.0.1` or `::1`.
+/// See [`network_manager::route_iface()`](../network_manager/fn.route_iface.html).
+pub fn get_iface_for(destination: IpAddress) -> Result<NetworkInterfaceRef, &'static str> {
+    route_iface(destination).ok_or("no network interface can reach the destination address")
+}
+
 /// A convenience function for connecting a socket.
 /// If the given socket is already open, it is forcibly closed immediately and reconnected.
 pub fn connect(
@@ -125,5 +157,79 @@ pub fn poll_iface(iface: &NetworkInterfaceRef, sockets: &mut SocketSet, startup_
             false
         }
     };
+    if SNAPSHOT_REQUESTED.load(Ordering::Relaxed) {
+        record_sockets(sockets);
+    }
     Ok(packets_were_sent_or_received)
 }
+
+
+/// The protocol of a socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketProtocol {
+    Tcp,
+    Udp,
+    Icmp,
+    Raw,
+}
+
+/// A description of a socket that was polled during a snapshot, used for displaying active connections.
+#[derive(Debug, Clone)]
+pub struct SocketInfo {
+    pub protocol: SocketProtocol,
+    /// The local endpoint of the socket, which has an unspecified address if it's bound to all addresses.
+    pub local_endpoint: Option<IpEndpoint>,
+    /// The remote endpoint of a connected TCP socket.
+    pub remote_endpoint: Option<IpEndpoint>,
+    /// The state of a TCP socket, e.g., "LISTEN" or "ESTABLISHED".
+    pub state: Option<String>,
+    /// The ID of the task that polled this socket.
+    pub task_id: usize,
+}
+
+/// Takes a snapshot of the sockets that are currently being polled through `poll_iface()` by any task.
+///
+/// This blocks for a short time, during which every task that polls its sockets records them.
+/// A task that doesn't poll its sockets within that time, e.g., because it's blocked, isn't included.
+pub fn snapshot_sockets() -> Result<Vec<SocketInfo>, &'static str> {
+    SOCKET_SNAPSHOT.lock().clear();
+    SNAPSHOT_REQUESTED.store(true, Ordering::SeqCst);
+    let slept = sleep::sleep(Duration::from_millis(SOCKET_SNAPSHOT_WINDOW_MILLIS));
+    SNAPSHOT_REQUESTED.store(false, Ordering::SeqCst);
+    slept?;
+
+    let snapshot = core::mem::replace(&mut *SOCKET_SNAPSHOT.lock(), BTreeMap::new());
+    Ok(snapshot.into_iter().flat_map(|(_, sockets)| sockets).collect())
+}
+
+/// Records the given `sockets` into the current snapshot, unless they have already been recorded.
+fn record_sockets(sockets: &SocketSet) {
+    let task_id = task::get_my_current_task_id().unwrap_or(0);
+    let key = (task_id, sockets as *const _ as usize);
+    if SOCKET_SNAPSHOT.lock().contains_key(&key) {
+        return;
+    }
+
+    let infos = sockets.iter().filter_map(|socket| {
+        let (protocol, local_endpoint, remote_endpoint, state) = match *socket {
+            Socket::Tcp(ref s) => {
+                if !s.is_open() { return None; }
+                let remote = if s.is_active() { Some(s.remote_endpoint()) } else { None };
+                (SocketProtocol::Tcp, Some(s.local_endpoint()), remote, Some(format!("{}", s.state())))
+            }
+            Socket::Udp(ref s) => {
+                if !s.is_open() { return None; }
+                (SocketProtocol::Udp, Some(s.endpoint()), None, None)
+            }
+            Socket::Icmp(ref s) => {
+                if !s.is_open() { return None; }
+                (SocketProtocol::Icmp, None, None, None)
+            }
+            Socket::Raw(_) => (SocketProtocol::Raw, None, None, None),
+            _ => return None,
+        };
+        Some(SocketInfo { protocol, local_endpoint, remote_endpoint, state, task_id })
+    }).collect();
+
+    SOCKET_SNAPSHOT.lock().insert(key, infos);
+}
@@ -0,0 +1,238 @@
+//! An SNTP client, as specified in RFC 4330, that queries a time server
+//! and corrects the `wall_clock` by the measured offset.
+//!
+//! Each query sends a single client-mode NTPv4 packet and uses the four timestamps of the exchange
+//! (client transmit, server receive, server transmit, client receive) to compute
+//! the offset between the wall clock and the server's clock, as well as the round-trip delay.
+//! The corrected time can optionally be written back into the CMOS RTC.
+//!
+//! # Note
+//! When using QEMU's default user-mode (slirp) networking, the host is reachable at `10.0.2.2`,
+//! which is the default server. Any NTP server running on the host, e.g., `chronyd` with `allow`
+//! or a simple stand-in script bound to UDP port 123, can be used for testing.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+extern crate network_manager;
+extern crate hpet;
+extern crate wall_clock;
+extern crate smoltcp;
+#[macro_use] extern crate smoltcp_helper;
+
+use alloc::vec::Vec;
+use hpet::get_hpet;
+use network_manager::NetworkInterfaceRef;
+use smoltcp::{
+    socket::{SocketSet, UdpSocket, UdpSocketBuffer, UdpPacketMetadata},
+    wire::{IpAddress, IpEndpoint, Ipv4Address},
+};
+use smoltcp_helper::{STARTING_FREE_PORT, get_iface_for, millis_since, poll_iface};
+
+
+/// The default time server: port 123 on the QEMU user-mode networking host.
+pub const DEFAULT_SERVER: IpEndpoint = IpEndpoint {
+    addr: IpAddress::Ipv4(Ipv4Address([10, 0, 2, 2])),
+    port: NTP_PORT,
+};
+
+/// The well-known UDP port on which NTP servers listen.
+pub const NTP_PORT: u16 = 123;
+
+/// The length of an NTP packet without any extension fields or authenticator.
+const NTP_PACKET_LEN: usize = 48;
+
+/// The number of seconds between the NTP epoch (1900-01-01) and the Unix epoch (1970-01-01).
+const NTP_UNIX_EPOCH_DELTA: u64 = 2_208_988_800;
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+
+const NTP_VERSION: u8 = 4;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+/// The leap indicator value that means the server's clock is not synchronized.
+const LEAP_ALARM: u8 = 3;
+
+
+/// The settings used when querying a time server.
+#[derive(Debug, Clone, Copy)]
+pub struct SntpConfig {
+    /// The endpoint of the time server.
+    pub server: IpEndpoint,
+    /// How long to wait for the server's reply before sending the request again.
+    pub timeout_millis: u64,
+    /// How many times the request is resent before giving up.
+    pub max_retries: usize,
+}
+
+impl Default for SntpConfig {
+    fn default() -> SntpConfig {
+        SntpConfig {
+            server: DEFAULT_SERVER,
+            timeout_millis: 1000,
+            max_retries: 3,
+        }
+    }
+}
+
+
+/// The result of a single exchange with a time server.
+#[derive(Debug, Clone, Copy)]
+pub struct SntpSample {
+    /// The amount of microseconds by which the wall clock is behind the server's clock (negative if ahead).
+    pub offset_micros: i64,
+    /// The round-trip network delay of the exchange in microseconds, excluding the server's processing time.
+    pub round_trip_micros: i64,
+    /// The stratum of the server, i.e., its distance from a reference clock.
+    pub stratum: u8,
+    /// The server's transmit timestamp in microseconds since the Unix epoch.
+    pub server_time_micros: u64,
+}
+
+
+/// Queries the time server given in `config` and corrects the wall clock by the measured offset.
+/// If `write_rtc` is true, the corrected time is also written into the CMOS RTC.
+///
+/// The network interface used to reach the server is chosen by the routing rules of the `network_manager`.
+pub fn synchronize(config: &SntpConfig, write_rtc: bool) -> Result<SntpSample, &'static str> {
+    let iface = get_iface_for(config.server.addr)?;
+    let sample = query(&iface, config)?;
+    wall_clock::adjust(sample.offset_micros)?;
+    info!("sntp_client: adjusted wall clock by {} us using {} (stratum {}, round trip {} us)",
+        sample.offset_micros, config.server, sample.stratum, sample.round_trip_micros
+    );
+    if write_rtc {
+        let written = wall_clock::write_to_rtc()?;
+        info!("sntp_client: wrote {} into the RTC", written);
+    }
+    Ok(sample)
+}
+
+
+/// Queries the time server given in `config` through the given network interface,
+/// and returns the measured offset of the wall clock without correcting it.
+pub fn query(iface: &NetworkInterfaceRef, config: &SntpConfig) -> Result<SntpSample, &'static str> {
+    let startup_time = hpet_ticks!();
+    let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 4], vec![0; 4 * NTP_PACKET_LEN]);
+    let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; 1], vec![0; NTP_PACKET_LEN]);
+    let mut socket = UdpSocket::new(rx_buffer, tx_buffer);
+    let local_port = STARTING_FREE_PORT + (startup_time % (u16::max_value() - STARTING_FREE_PORT) as u64) as u16;
+    socket.bind(local_port).map_err(|_e| "sntp_client: couldn't bind UDP socket")?;
+    let mut sockets = SocketSet::new(Vec::with_capacity(1));
+    let handle = sockets.add(socket);
+
+    for _attempt in 0 ..= config.max_retries {
+        // The client's transmit timestamp is echoed back by the server as the originate timestamp,
+        // which is how we match the reply to this request.
+        let transmit_micros = wall_clock::now_micros()?;
+        let request = request_packet(transmit_micros);
+        sockets.get::<UdpSocket>(handle).send_slice(&request, config.server)
+            .map_err(|_e| "sntp_client: couldn't send UDP packet")?;
+
+        let start = hpet_ticks!();
+        while millis_since(start)? <= config.timeout_millis {
+            poll_iface(iface, &mut sockets, startup_time)?;
+            let mut socket = sockets.get::<UdpSocket>(handle);
+            while let Ok((reply, source)) = socket.recv() {
+                let receive_micros = wall_clock::now_micros()?;
+                if source.addr != config.server.addr {
+                    warn!("sntp_client: ignoring packet from unexpected source {}", source);
+                    continue;
+                }
+                match parse_reply(reply, &request, transmit_micros, receive_micros) {
+                    Ok(sample) => return Ok(sample),
+                    Err(e) => warn!("sntp_client: ignoring invalid reply from {}: {}", source, e),
+                }
+            }
+        }
+    }
+    error!("sntp_client: no reply from server {} after {} retries", config.server, config.max_retries);
+    Err("sntp_client: timed out waiting for a reply from the server")
+}
+
+
+/// Builds a client-mode request whose transmit timestamp is the given time.
+fn request_packet(transmit_micros: u64) -> [u8; NTP_PACKET_LEN] {
+    let mut packet = [0u8; NTP_PACKET_LEN];
+    // Leap indicator 0, version 4, mode 3 (client).
+    packet[0] = (NTP_VERSION << 3) | MODE_CLIENT;
+    packet[40..48].copy_from_slice(&unix_micros_to_ntp(transmit_micros).to_be_bytes());
+    packet
+}
+
+/// Validates the server's `reply` to the given `request` and computes the offset and delay of the exchange.
+/// `transmit_micros` and `receive_micros` are the wall-clock times at which the request was sent and the reply was received.
+fn parse_reply(
+    reply: &[u8],
+    request: &[u8; NTP_PACKET_LEN],
+    transmit_micros: u64,
+    receive_micros: u64,
+) -> Result<SntpSample, &'static str> {
+    if reply.len() < NTP_PACKET_LEN {
+        return Err("reply is too short");
+    }
+    let leap = reply[0] >> 6;
+    let version = (reply[0] >> 3) & 0x7;
+    let mode = reply[0] & 0x7;
+    let stratum = reply[1];
+    if mode != MODE_SERVER || version < 3 || version > NTP_VERSION {
+        return Err("reply has an unexpected mode or version");
+    }
+    if stratum == 0 {
+        // A "kiss-o'-death" packet, whose reference ID holds an ASCII code explaining why the server refused.
+        warn!("sntp_client: server sent kiss code {:?}", core::str::from_utf8(&reply[12..16]).unwrap_or("????"));
+        return Err("server refused the request");
+    }
+    if leap == LEAP_ALARM || stratum > 15 {
+        return Err("server's clock is not synchronized");
+    }
+    if reply[24..32] != request[40..48] {
+        return Err("reply's originate timestamp doesn't match the request");
+    }
+
+    let server_receive = ntp_to_unix_micros(read_timestamp(&reply[32..40])) as i64;
+    let server_transmit = ntp_to_unix_micros(read_timestamp(&reply[40..48])) as i64;
+    if server_transmit == 0 {
+        return Err("reply has no transmit timestamp");
+    }
+    let client_transmit = transmit_micros as i64;
+    let client_receive = receive_micros as i64;
+
+    Ok(SntpSample {
+        offset_micros: ((server_receive - client_transmit) + (server_transmit - client_receive)) / 2,
+        round_trip_micros: (client_receive - client_transmit) - (server_transmit - server_receive),
+        stratum,
+        server_time_micros: server_transmit as u64,
+    })
+}
+
+fn read_timestamp(bytes: &[u8]) -> u64 {
+    let mut timestamp = [0u8; 8];
+    timestamp.copy_from_slice(&bytes[..8]);
+    u64::from_be_bytes(timestamp)
+}
+
+/// Converts microseconds since the Unix epoch into a 64-bit NTP timestamp,
+/// i.e., 32 bits of seconds since the NTP epoch and 32 bits of fractional seconds.
+fn unix_micros_to_ntp(unix_micros: u64) -> u64 {
+    // NTP seconds wrap around in 2036 (the start of era 1), so only the lower 32 bits are kept.
+    let seconds = (unix_micros / MICROS_PER_SECOND + NTP_UNIX_EPOCH_DELTA) & 0xFFFF_FFFF;
+    let fraction = ((unix_micros % MICROS_PER_SECOND) << 32) / MICROS_PER_SECOND;
+    (seconds << 32) | fraction
+}
+
+/// Converts a 64-bit NTP timestamp into microseconds since the Unix epoch.
+/// A zero timestamp is converted to zero.
+fn ntp_to_unix_micros(timestamp: u64) -> u64 {
+    if timestamp == 0 {
+        return 0;
+    }
+    let mut seconds = timestamp >> 32;
+    // As recommended by RFC 4330, timestamps with the most significant bit cleared are in era 1, i.e., after 2036.
+    if seconds & 0x8000_0000 == 0 {
+        seconds += 1 << 32;
+    }
+    let fraction = timestamp & 0xFFFF_FFFF;
+    (seconds - NTP_UNIX_EPOCH_DELTA) * MICROS_PER_SECOND + ((fraction * MICROS_PER_SECOND) >> 32)
+}
@@ -28,12 +28,17 @@ extern crate fs_node;
 extern crate catch_unwind;
 extern crate fault_crate_swap;
 extern crate pause;
+extern crate tsc;
+extern crate heap;
+extern crate kernel_config;
 
 
 use core::{
+    fmt,
     mem,
     marker::PhantomData,
     ops::Deref,
+    time::Duration,
 };
 use alloc::{
     vec::Vec,
@@ -41,13 +46,15 @@ use alloc::{
     sync::Arc,
     boxed::Box,
 };
-use irq_safety::{MutexIrqSafe, hold_interrupts, enable_interrupts};
+use irq_safety::{MutexIrqSafe, hold_interrupts, enable_interrupts, interrupts_enabled};
 use memory::{get_kernel_mmi_ref, MemoryManagementInfo, VirtualAddress};
-use task::{Task, TaskRef, get_my_current_task, RunState, RestartInfo, TASKLIST};
+use task::{Task, TaskRef, get_my_current_task, RunState, RestartInfo, TASKLIST, ExitValue, KillReason};
 use mod_mgmt::{CrateNamespace, SectionType, SECTION_HASH_DELIMITER};
 use path::Path;
 use apic::get_my_apic_id;
 use fs_node::FileOrDir;
+use heap::HeapQuota;
+use kernel_config::time::CONFIG_WORK_STEALING_PERIOD_MICROSECONDS;
 
 #[cfg(simd_personality)]
 use task::SimdExt;
@@ -61,7 +68,7 @@ pub fn init(
     stack_bottom: VirtualAddress,
     stack_top: VirtualAddress
 ) -> Result<BootstrapTaskRef, &'static str> {
-    runqueue::init(apic_id)?;
+    scheduler::init(apic_id)?;
     
     let task_ref = task::bootstrap_task(apic_id, stack_bottom, stack_top, kernel_mmi_ref)?;
     runqueue::add_task_to_specific_runqueue(apic_id, task_ref.clone())?;
@@ -207,6 +214,10 @@ pub struct TaskBuilder<F, A, R> {
     blocked: bool,
     idle: bool,
     post_build_function: Option<Box< dyn FnOnce(&mut Task) -> Result<(), &'static str> >>,
+    /// The period, budget and relative deadline of a real-time task.
+    realtime: Option<(Duration, Duration, Duration)>,
+    /// The quota that the new task's heap allocations are charged to, if not inherited from the current task.
+    heap_quota: Option<Arc<HeapQuota>>,
 
     #[cfg(simd_personality)]
     simd: SimdExt,
@@ -229,6 +240,8 @@ impl<F, A, R> TaskBuilder<F, A, R>
             blocked: false,
             idle: false,
             post_build_function: None,
+            realtime: None,
+            heap_quota: None,
 
             #[cfg(simd_personality)]
             simd: SimdExt::None,
@@ -261,6 +274,36 @@ impl<F, A, R> TaskBuilder<F, A, R>
         self
     }
 
+    /// Make the new Task a real-time task that releases a new job every `period`,
+    /// where each job may run for at most `budget` and must finish within `deadline` of its release.
+    /// 
+    /// The task must be admitted by the real-time scheduler, otherwise [`spawn()`](#method.spawn) fails.
+    /// Each job should end by invoking `scheduler::wait_for_next_period()`.
+    pub fn realtime(mut self, period: Duration, budget: Duration, deadline: Duration) -> TaskBuilder<F, A, R> {
+        self.realtime = Some((period, budget, deadline));
+        self
+    }
+
+    /// Limit the new Task to allocating at most `limit_bytes` bytes from the heap at any one time.
+    /// 
+    /// The quota is shared with all tasks that the new Task spawns, unless they are given their own quota.
+    /// An allocation that would exceed the quota fails, which kills only the offending task
+    /// with `KillReason::HeapQuotaExceeded` instead of exhausting the heap for the whole system.
+    /// 
+    /// By default, the new Task shares the heap quota of the current task, if it has one.
+    pub fn heap_limit(self, limit_bytes: usize) -> TaskBuilder<F, A, R> {
+        self.heap_quota(Arc::new(HeapQuota::new(limit_bytes)))
+    }
+
+    /// Charge the new Task's heap allocations to the given `quota`, 
+    /// which can be shared among several tasks, e.g., all tasks of one application.
+    /// 
+    /// See [`heap_limit()`](#method.heap_limit) for more details.
+    pub fn heap_quota(mut self, quota: Arc<HeapQuota>) -> TaskBuilder<F, A, R> {
+        self.heap_quota = Some(quota);
+        self
+    }
+
     /// Set the new Task's `RunState` to be `Blocked` instead of `Runnable` when it is first spawned.
     /// This allows another task to delay the new task's execution arbitrarily, 
     /// e.g., to set up other things for the newly-spawned (but not yet running) task. 
@@ -274,8 +317,16 @@ impl<F, A, R> TaskBuilder<F, A, R>
     /// Finishes this `TaskBuilder` and spawns the new task as described by its builder functions.
     /// 
     /// This merely makes the new task Runnable, it does not switch to it immediately; that will happen on the next scheduler invocation.
+    /// 
+    /// Returns a [`JoinHandle`](struct.JoinHandle.html) that can be used to wait for the new task
+    /// and obtain the value it returned.
+    pub fn spawn(self) -> Result<JoinHandle<R>, &'static str> {
+        self.spawn_internal().map(|task| JoinHandle { task, _return_type: PhantomData })
+    }
+
+    /// The internal routine shared by `spawn` and `spawn_restartable` that actually spawns the new task.
     #[inline(never)]
-    pub fn spawn(self) -> Result<TaskRef, &'static str> {
+    fn spawn_internal(self) -> Result<TaskRef, &'static str> {
         let mut new_task = Task::new(
             None,
             task_cleanup_failure::<F, A, R>,
@@ -287,6 +338,10 @@ impl<F, A, R> TaskBuilder<F, A, R>
             new_task.simd = self.simd;
         }
 
+        if self.heap_quota.is_some() {
+            new_task.heap_quota = self.heap_quota;
+        }
+
         setup_context_trampoline(&mut new_task, task_wrapper::<F, A, R>)?;
 
         // Currently we're using the very bottom of the kstack for kthread arguments. 
@@ -303,10 +358,12 @@ impl<F, A, R> TaskBuilder<F, A, R>
         }
 
         // The new task is ready to be scheduled in, now that its stack trampoline has been set up.
-        if self.blocked {
+        // A real-time task is kept blocked until it has been admitted, such that it never runs as a regular task.
+        if self.blocked || self.realtime.is_some() {
             new_task.runstate = RunState::Blocked;
         } else {
             new_task.runstate = RunState::Runnable;
+            new_task.cpu_times.became_runnable(tsc::tsc_ticks().into());
         }
 
         // The new task is marked as idle
@@ -335,6 +392,18 @@ impl<F, A, R> TaskBuilder<F, A, R>
             runqueue::add_task_to_any_runqueue(task_ref.clone())?;
         }
 
+        if let Some((period, budget, deadline)) = self.realtime {
+            if let Err(e) = scheduler::set_realtime_params(&task_ref, period, budget, deadline) {
+                // The rejected task never ran, so it can simply be discarded.
+                let _ = runqueue::remove_task_from_all(&task_ref);
+                TASKLIST.lock().remove(&new_task_id);
+                return Err(e);
+            }
+            if !self.blocked {
+                task_ref.unblock();
+            }
+        }
+
         Ok(task_ref)
     }
 
@@ -386,7 +455,115 @@ impl<F, A, R> TaskBuilder<F, A, R>
         ));
 
         // Code path is shared between `spawn` and `spawn_restartable` from this point
-        self.spawn()
+        self.spawn_internal()
+    }
+}
+
+
+/// A handle to a spawned `Task` that can be used to wait for it to exit
+/// and obtain the value of type `R` that it returned.
+/// 
+/// This dereferences to the task's `TaskRef`, so it can also be used to, e.g., unblock the task.
+/// 
+/// Dropping a `JoinHandle` does not affect the task, but its exit value will be kept
+/// until it's taken via the task's `TaskRef`; use [`detach()`](#method.detach) to avoid that.
+pub struct JoinHandle<R> {
+    task: TaskRef,
+    _return_type: PhantomData<R>,
+}
+
+impl<R> JoinHandle<R> where R: Send + 'static {
+    /// Returns a reference to the spawned task.
+    pub fn task(&self) -> &TaskRef {
+        &self.task
+    }
+
+    /// Blocks until the spawned task has exited, and then takes its exit value.
+    /// 
+    /// Returns the value that the task's entry function returned if it ran to completion,
+    /// or `JoinError::Killed` with the reason it was killed otherwise, e.g., `KillReason::Panic` with the panic info.
+    /// 
+    /// Returns an error without waiting if the current task is the spawned task itself,
+    /// if interrupts are disabled, or if the task's exit value was already taken via its `TaskRef`.
+    pub fn join(self) -> Result<R, JoinError> {
+        let curr_task = get_my_current_task().ok_or(JoinError::NoCurrentTask)?;
+        if curr_task == &self.task {
+            return Err(JoinError::JoinedSelf);
+        }
+        if !interrupts_enabled() {
+            return Err(JoinError::InterruptsDisabled);
+        }
+        if let Err(e) = self.task.join() {
+            // the above checks cover all the reasons that `TaskRef::join()` can fail for
+            error!("BUG: JoinHandle::join(): couldn't wait for task {:?} to exit: {}", self.task, e);
+            return Err(JoinError::NoCurrentTask);
+        }
+        match self.task.take_exit_value() {
+            Some(ExitValue::Completed(value)) => match value.downcast::<R>() {
+                Ok(value) => Ok(*value),
+                Err(_) => panic!("BUG: JoinHandle::join(): task {:?} returned a value of an unexpected type", self.task),
+            },
+            Some(ExitValue::Killed(reason)) => Err(JoinError::Killed(reason)),
+            None => Err(JoinError::ExitValueTaken),
+        }
+    }
+
+    /// Detaches the spawned task, such that it will be reaped as soon as it exits
+    /// instead of keeping its exit value around for someone to take it.
+    pub fn detach(self) {
+        self.task.detach();
+    }
+}
+
+/// The reasons that [`JoinHandle::join()`](struct.JoinHandle.html#method.join) can fail.
+///
+/// This can be converted into a `&'static str` error, such that `handle.join()??` works in functions
+/// whose spawned tasks return `Result<_, &'static str>` just like they do.
+#[derive(Debug, Clone)]
+pub enum JoinError {
+    /// The spawned task was killed before it ran to completion.
+    Killed(KillReason),
+    /// The current task couldn't be determined, so it couldn't wait for the spawned task.
+    NoCurrentTask,
+    /// The spawned task is the current task, which can't wait for itself to exit.
+    JoinedSelf,
+    /// Interrupts are disabled, so waiting for the spawned task could deadlock.
+    InterruptsDisabled,
+    /// The spawned task's exit value was already taken via its `TaskRef`.
+    ExitValueTaken,
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            JoinError::Killed(reason) => write!(f, "task was killed: {}", reason),
+            other => write!(f, "{}", <&'static str>::from(other.clone())),
+        }
+    }
+}
+
+impl From<JoinError> for &'static str {
+    fn from(e: JoinError) -> &'static str {
+        match e {
+            JoinError::Killed(_) => "task was killed",
+            JoinError::NoCurrentTask => "couldn't get the current task to join a task",
+            JoinError::JoinedSelf => "a task can't join itself",
+            JoinError::InterruptsDisabled => "can't join a task with interrupts disabled",
+            JoinError::ExitValueTaken => "the joined task's exit value was already taken",
+        }
+    }
+}
+
+impl<R> Deref for JoinHandle<R> {
+    type Target = TaskRef;
+    fn deref(&self) -> &TaskRef {
+        &self.task
+    }
+}
+
+impl<R> fmt::Debug for JoinHandle<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "JoinHandle {{ task: {:?} }}", self.task)
     }
 }
 
@@ -480,6 +657,9 @@ fn task_wrapper_internal<F, A, R>() -> Result<R, task::KillReason>
     let (func, arg) = {
         let curr_task_ref = get_my_current_task().expect("BUG: task_wrapper: couldn't get current task (before task func).");
 
+        // A new task doesn't return into `task_switch()` when it's first switched to, so it finishes that switch here.
+        curr_task_ref.lock_mut().finish_task_switch();
+
         // This task's function and argument were placed at the bottom of the stack when this task was spawned.
         let task_func_arg = {
             let t = curr_task_ref.lock();
@@ -761,13 +941,10 @@ fn remove_current_task_from_runqueue(current_task: &TaskRef) {
         }
     }
 
-    // In the regular case, we do not perform task migration between cores,
-    // so we can use the heuristic that the task is only on the current core's runqueue.
+    // In the regular case, the task may have been migrated to another core's runqueue
+    // while it was running on this core, so we remove it from all runqueues.
     #[cfg(not(rq_eval))] {
-        if let Err(e) = runqueue::get_runqueue(apic::get_my_apic_id())
-            .ok_or("couldn't get this core's ID or runqueue to remove exited task from it")
-            .and_then(|rq| rq.write().remove_task(current_task)) 
-        {
+        if let Err(e) = runqueue::remove_task_from_all(current_task) {
             error!("BUG: couldn't remove exited task from runqueue: {}", e);
         }
     }
@@ -790,9 +967,23 @@ pub fn create_idle_task(core: Option<u8>) -> Result<TaskRef, &'static str> {
 /// Note: the current spawn API does not support spawning a task with the return type `!`,
 /// so we use `()` here instead. 
 #[inline(never)]
-fn dummy_idle_task(_apic_id: u8) {
-    info!("Entered idle task loop on core {}: {:?}", _apic_id, task::get_my_current_task());
+fn dummy_idle_task(apic_id: u8) {
+    info!("Entered idle task loop on core {}: {:?}", apic_id, task::get_my_current_task());
+    // An idle core steals tasks from busier cores here, rather than in the scheduler,
+    // such that the scheduler never has to look at other cores' runqueues.
+    let steal_period_ticks = tsc::get_tsc_frequency().ok()
+        .map(|frequency| frequency / 1_000_000 * CONFIG_WORK_STEALING_PERIOD_MICROSECONDS);
+    let mut last_steal_attempt = tsc::tsc_ticks().into();
     loop {
+        if let Some(period) = steal_period_ticks {
+            let now = tsc::tsc_ticks().into();
+            if now.saturating_sub(last_steal_attempt) >= period {
+                last_steal_attempt = now;
+                if runqueue::steal_task(apic_id) {
+                    scheduler::schedule();
+                }
+            }
+        }
         // TODO: put this core into a low-power state
         pause::spin_loop_hint();
     }
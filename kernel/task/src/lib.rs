@@ -41,6 +41,9 @@ extern crate root;
 extern crate x86_64;
 extern crate spin;
 extern crate kernel_config;
+extern crate tsc;
+extern crate tls_initializer;
+extern crate heap;
 
 
 use core::fmt;
@@ -48,6 +51,7 @@ use core::sync::atomic::{Ordering, AtomicUsize, AtomicBool};
 use core::any::Any;
 use core::panic::PanicInfo;
 use core::ops::Deref;
+use core::time::Duration;
 use alloc::{
     boxed::Box,
     collections::BTreeMap,
@@ -65,12 +69,18 @@ use mod_mgmt::{
 use environment::Environment;
 use spin::Mutex;
 use x86_64::registers::msr::{rdmsr, wrmsr, IA32_FS_BASE};
+use tls_initializer::TlsDataImage;
+use heap::HeapQuota;
 
 
 /// The function signature of the callback that will be invoked
 /// when a given Task panics or otherwise fails, e.g., a machine exception occurs.
 pub type KillHandler = Box<dyn Fn(&KillReason) + Send>;
 
+/// The function signature of the callback that will be invoked
+/// when a given Task has exited or has been suspended.
+pub type StateChangeHandler = Arc<dyn Fn() + Send + Sync>;
+
 /// Just like `core::panic::PanicInfo`, but with owned String types instead of &str references.
 #[derive(Debug, Clone)]
 pub struct PanicInfoOwned {
@@ -122,7 +132,7 @@ pub fn set_my_kill_handler(handler: KillHandler) -> Result<(), &'static str> {
 
 
 /// The list of possible reasons that a given `Task` was killed prematurely.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum KillReason {
     /// The user or another task requested that this `Task` be killed. 
     /// For example, the user pressed `Ctrl + C` on the shell window that started a `Task`.
@@ -132,6 +142,12 @@ pub enum KillReason {
     /// A non-language-level problem, such as a Page Fault or some other machine exception.
     /// The number of the exception is included, e.g., 15 (0xE) for a Page Fault.
     Exception(u8),
+    /// This `Task` tried to allocate more heap memory than its [`HeapQuota`](../heap/struct.HeapQuota.html) allows.
+    /// The size of the failed allocation and the quota's limit, both in bytes, are included.
+    HeapQuotaExceeded {
+        requested: usize,
+        limit: usize,
+    },
 }
 impl fmt::Display for KillReason {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
@@ -139,6 +155,7 @@ impl fmt::Display for KillReason {
             &Self::Requested         => write!(f, "Requested"),
             &Self::Panic(panic_info) => write!(f, "Panicked at {}", panic_info),
             &Self::Exception(num)    => write!(f, "Exception {:#X}({})", num, num),
+            &Self::HeapQuotaExceeded { requested, limit } => write!(f, "Exceeded heap quota of {} bytes when allocating {} bytes", limit, requested),
         }
     }
 }
@@ -168,6 +185,9 @@ pub enum RunState {
     Runnable,
     /// blocked on something, like I/O or a wait event
     Blocked,
+    /// suspended, e.g., by shell job control. The `Task` is not on any runqueue
+    /// and won't be scheduled in again until it is resumed, even if whatever it was blocked on is ready.
+    Stopped,
     /// The `Task` has exited and can no longer be run,
     /// either by running to completion or being killed. 
     Exited(ExitValue),
@@ -183,6 +203,14 @@ pub enum RunState {
 /// Should be initialized by the runqueue crate.
 pub static RUNQUEUE_REMOVAL_FUNCTION: spin::Once<fn(&TaskRef, u8) -> Result<(), &'static str>> = spin::Once::new();
 
+/// A callback that will be invoked to take a suspended task off of every runqueue.
+/// Should be initialized by the runqueue crate.
+pub static RUNQUEUE_SUSPEND_FUNCTION: spin::Once<fn(&TaskRef) -> Result<(), &'static str>> = spin::Once::new();
+
+/// A callback that will be invoked to put a resumed task back onto a runqueue.
+/// Should be initialized by the runqueue crate.
+pub static RUNQUEUE_RESUME_FUNCTION: spin::Once<fn(TaskRef) -> Result<(), &'static str>> = spin::Once::new();
+
 
 #[cfg(simd_personality)]
 /// The supported levels of SIMD extensions that a `Task` can use.
@@ -209,6 +237,99 @@ pub struct RestartInfo {
 pub type FailureCleanupFunction = fn(TaskRef, KillReason) -> !;
 
 
+/// The CPU time accounting of a `Task`, measured with the TSC.
+/// 
+/// The run time is the time a task has spent running on a core,
+/// and the wait time is the time it has spent runnable but waiting to be switched to.
+/// Both include the ongoing interval, e.g., the run time of a task that is currently running
+/// includes the time since it was last switched to.
+#[derive(Debug, Clone, Default)]
+pub struct CpuTimes {
+    /// The total TSC ticks of all completed intervals in which the task was running.
+    run_ticks: u64,
+    /// The total TSC ticks of all completed intervals in which the task was runnable but not running.
+    wait_ticks: u64,
+    /// The number of times the task has been switched to.
+    context_switches: u64,
+    /// The TSC value at which the task was last switched to, if it's currently running.
+    running_since: Option<u64>,
+    /// The TSC value at which the task last became runnable, if it's currently runnable but not running.
+    runnable_since: Option<u64>,
+}
+
+impl CpuTimes {
+    /// Returns the total number of TSC ticks that the task has spent running.
+    pub fn run_ticks(&self) -> u64 {
+        let ongoing = self.running_since.map_or(0, |since| tsc::tsc_ticks().into().saturating_sub(since));
+        self.run_ticks.saturating_add(ongoing)
+    }
+
+    /// Returns the total number of TSC ticks that the task has spent runnable but waiting to be switched to.
+    pub fn wait_ticks(&self) -> u64 {
+        let ongoing = self.runnable_since.map_or(0, |since| tsc::tsc_ticks().into().saturating_sub(since));
+        self.wait_ticks.saturating_add(ongoing)
+    }
+
+    /// Returns the number of times the task has been switched to.
+    pub fn context_switches(&self) -> u64 {
+        self.context_switches
+    }
+
+    /// Returns the total time that the task has spent running,
+    /// or `None` if the TSC frequency is unknown.
+    pub fn run_time(&self) -> Option<Duration> {
+        ticks_to_duration(self.run_ticks())
+    }
+
+    /// Returns the total time that the task has spent runnable but waiting to be switched to,
+    /// or `None` if the TSC frequency is unknown.
+    pub fn wait_time(&self) -> Option<Duration> {
+        ticks_to_duration(self.wait_ticks())
+    }
+
+    /// Accounts for the task being switched to at the given TSC value `now`.
+    fn switched_in(&mut self, now: u64) {
+        if let Some(since) = self.runnable_since.take() {
+            self.wait_ticks = self.wait_ticks.saturating_add(now.saturating_sub(since));
+        }
+        self.running_since = Some(now);
+        self.context_switches = self.context_switches.saturating_add(1);
+    }
+
+    /// Accounts for the task being switched away from at the given TSC value `now`.
+    /// If it's still runnable, it starts waiting right away.
+    fn switched_out(&mut self, now: u64, still_runnable: bool) {
+        if let Some(since) = self.running_since.take() {
+            self.run_ticks = self.run_ticks.saturating_add(now.saturating_sub(since));
+        }
+        if still_runnable {
+            self.runnable_since = Some(now);
+        }
+    }
+
+    /// Accounts for the task becoming runnable at the given TSC value `now`, e.g., when it's unblocked or first spawned.
+    pub fn became_runnable(&mut self, now: u64) {
+        if self.running_since.is_none() && self.runnable_since.is_none() {
+            self.runnable_since = Some(now);
+        }
+    }
+
+    /// Accounts for the task no longer being runnable at the given TSC value `now`, e.g., when it's blocked.
+    /// A running task keeps running until it's switched out, so this only ends an ongoing wait.
+    fn became_blocked(&mut self, now: u64) {
+        if let Some(since) = self.runnable_since.take() {
+            self.wait_ticks = self.wait_ticks.saturating_add(now.saturating_sub(since));
+        }
+    }
+}
+
+/// Converts the given number of TSC ticks into a `Duration`, or returns `None` if the TSC frequency is unknown.
+pub fn ticks_to_duration(ticks: u64) -> Option<Duration> {
+    let frequency = tsc::get_tsc_frequency().ok()?;
+    Some(Duration::from_nanos((ticks as u128 * 1_000_000_000 / frequency as u128) as u64))
+}
+
+
 /// A structure that contains contextual information for a thread of execution. 
 pub struct Task {
     /// the unique id of this Task.
@@ -229,8 +350,15 @@ pub struct Task {
     pub saved_sp: usize,
     /// the virtual address of (a pointer to) the `TaskLocalData` struct, which refers back to this `Task` struct.
     task_local_data_ptr: VirtualAddress,
+    /// This task's thread-local storage (TLS) area, whose thread control block also holds the `task_local_data_ptr`.
+    tls_area: TlsDataImage,
     /// Data that should be dropped after a task switch; for example, the previous Task's TaskLocalData.
     drop_after_task_switch: Option<Box<dyn Any + Send>>,
+    /// Whether this task's context has been completely saved by the last task switch away from it.
+    /// Until then, its `saved_sp` is stale, so it must not be switched to on another core.
+    context_saved: Arc<AtomicBool>,
+    /// The `context_saved` flag of the previous task, which this task sets after it has been switched to.
+    prev_context_saved: Option<Arc<AtomicBool>>,
     /// Memory management details: page tables, mappings, allocators, etc.
     /// This is shared among all other tasks in the same address space.
     pub mmi: MmiRef, 
@@ -252,6 +380,9 @@ pub struct Task {
     /// It will be invoked before the task is cleaned up via stack unwinding.
     /// This is similar to Rust's built-in panic hook, but is also called upon a machine exception, not just a panic.
     pub kill_handler: Option<KillHandler>,
+    /// The function that will be called after this `Task` has exited or has been suspended,
+    /// e.g., to notify the task that manages it.
+    state_change_handler: Option<StateChangeHandler>,
     /// The environment of the task, Wrapped in an Arc & Mutex because it is shared among child and parent tasks
     pub env: Arc<Mutex<Environment>>,
     /// The function that should be run as a last-ditch attempt to recover from this task's failure,
@@ -262,6 +393,19 @@ pub struct Task {
     /// Stores the restartable information of the task. 
     /// `Some(RestartInfo)` indicates that the task is restartable.
     pub restart_info: Option<RestartInfo>,
+    /// How much CPU time this Task has used and waited for, and how often it has been switched to.
+    pub cpu_times: CpuTimes,
+    /// Whether this Task should be runnable (rather than blocked) once it's resumed,
+    /// which is only meaningful while it is `RunState::Stopped`.
+    runnable_on_resume: bool,
+    /// Whether this Task has been detached, meaning that no other Task will take its exit value,
+    /// so it should be reaped as soon as it exits.
+    detached: bool,
+    /// The quota that this Task's heap allocations are charged to, if any.
+    /// It is inherited by the Tasks that this Task spawns, such that they all share the same quota.
+    /// 
+    /// This must be set before the Task's `TaskRef` is created, as the allocator reads it from the Task's local data.
+    pub heap_quota: Option<Arc<HeapQuota>>,
     
     #[cfg(simd_personality)]
     /// Whether this Task is SIMD enabled and what level of SIMD extensions it uses.
@@ -278,7 +422,7 @@ impl fmt::Debug for Task {
 impl Task {
     /// Creates a new Task structure and initializes it to be non-Runnable.
     /// By default, the new `Task` will inherit some of the same states from the currently-running `Task`:
-    /// its `Environment`, `MemoryManagementInfo`, `CrateNamespace`, and `app_crate` reference.
+    /// its `Environment`, `MemoryManagementInfo`, `CrateNamespace`, `app_crate` reference, and `heap_quota`.
     /// If needed, those states can be changed by setting them for the returned `Task`.
     /// 
     /// # Arguments
@@ -294,16 +438,18 @@ impl Task {
         failure_cleanup_function: FailureCleanupFunction
     ) -> Result<Task, &'static str> {
         let curr_task = get_my_current_task().ok_or("Task::new(): couldn't get current task (not yet initialized)")?;
-        let (mmi, namespace, env, app_crate) = {
+        let (mmi, namespace, env, app_crate, heap_quota) = {
             let t = curr_task.lock();
-            (Arc::clone(&t.mmi), Arc::clone(&t.namespace), Arc::clone(&t.env), t.app_crate.clone())
+            (Arc::clone(&t.mmi), Arc::clone(&t.namespace), Arc::clone(&t.env), t.app_crate.clone(), t.heap_quota.clone())
         };
 
         let kstack = kstack
             .or_else(|| mmi.lock().alloc_stack(KERNEL_STACK_SIZE_IN_PAGES))
             .ok_or("couldn't allocate kernel stack!")?;
 
-        Ok(Task::new_internal(kstack, mmi, namespace, env, app_crate, failure_cleanup_function))
+        let mut task = Task::new_internal(kstack, mmi, namespace, env, app_crate, failure_cleanup_function);
+        task.heap_quota = heap_quota;
+        Ok(task)
     }
     
     /// The internal routine for creating a `Task`, which does not make assumptions 
@@ -333,7 +479,10 @@ impl Task {
             
             saved_sp: 0,
             task_local_data_ptr: VirtualAddress::zero(),
+            tls_area: tls_initializer::get_data(),
             drop_after_task_switch: None,
+            context_saved: Arc::new(AtomicBool::new(true)),
+            prev_context_saved: None,
             name: format!("task_{}", task_id),
             kstack,
             mmi,
@@ -342,9 +491,14 @@ impl Task {
             app_crate,
             namespace,
             kill_handler: None,
+            state_change_handler: None,
             env,
             failure_cleanup_function,
             restart_info: None,
+            cpu_times: CpuTimes::default(),
+            runnable_on_resume: false,
+            detached: false,
+            heap_quota: None,
             
             #[cfg(simd_personality)]
             simd: SimdExt::None,
@@ -360,6 +514,12 @@ impl Task {
         self.running_on_cpu.is_some()
     }
 
+    /// Returns true if this task's context has been completely saved since it last ran,
+    /// which is false while it's still being switched out on the core it last ran on.
+    pub fn is_context_saved(&self) -> bool {
+        self.context_saved.load(Ordering::Acquire)
+    }
+
     /// Returns true if this `Task` is Runnable, i.e., able to be scheduled in.
     /// # Note
     /// This does *NOT* mean that this `Task` is actually currently running, just that it is *able* to be run.
@@ -370,6 +530,14 @@ impl Task {
         }
     }
 
+    /// Returns true if this `Task` has been suspended, i.e., if its RunState is `Stopped`.
+    pub fn is_suspended(&self) -> bool {
+        match self.runstate {
+            RunState::Stopped => true,
+            _ => false,
+        }
+    }
+
     /// Returns true if this `Task` has been exited, i.e.,
     /// if its RunState is either `Exited` or `Reaped`.
     pub fn has_exited(&self) -> bool {
@@ -443,11 +611,12 @@ impl Task {
 
     /// Sets this `Task` as this core's current task.
     /// 
-    /// Currently this is achieved by writing a pointer to the `TaskLocalData` 
+    /// Currently this is achieved by writing the thread pointer of this `Task`'s TLS area,
+    /// whose thread control block holds a pointer to the `TaskLocalData`,
     /// into the FS segment register base MSR.
     fn set_as_current_task(&self) {
         unsafe {
-            wrmsr(IA32_FS_BASE, self.task_local_data_ptr.value() as u64);
+            wrmsr(IA32_FS_BASE, self.tls_area.thread_pointer() as u64);
         }
     }
 
@@ -458,6 +627,7 @@ impl Task {
         if self.task_local_data_ptr.value() != 0 {
             let tld = unsafe { Box::from_raw(self.task_local_data_ptr.value() as *mut TaskLocalData) };
             self.task_local_data_ptr = VirtualAddress::zero();
+            self.tls_area.set_tcb_data(0);
             Some(tld)
         }
         else {
@@ -507,6 +677,12 @@ impl Task {
         self.running_on_cpu = None; // no longer running
         next.running_on_cpu = Some(apic_id); // now running on this core
 
+        // account for the CPU time used by the previous task and waited for by the next task
+        let now = tsc::tsc_ticks().into();
+        let still_runnable = self.is_runnable();
+        self.cpu_times.switched_out(now, still_runnable);
+        next.cpu_times.switched_in(now);
+
         // Switch page tables. 
         // Since there is only a single address space (as userspace support is currently disabled),
         // we do not need to do this at all.
@@ -540,6 +716,11 @@ impl Task {
             next.drop_after_task_switch = self.take_task_local_data().map(|tld_box| tld_box as Box<dyn Any + Send>);
         }
 
+        // `self` is only completely switched out once the context switch below has saved its stack pointer,
+        // so `next` marks its context as saved right after it has been switched to.
+        self.context_saved.store(false, Ordering::Release);
+        next.prev_context_saved = Some(self.context_saved.clone());
+
         // debug!("task_switch [4]: prev sp: {:#X}, next sp: {:#X}", self.saved_sp, next.saved_sp);
 
         /// A private macro that actually calls the given context switch routine.
@@ -612,10 +793,19 @@ impl Task {
         // and `next` has become some other random task based on a previous task switch operation.
         // Do not make any assumptions about what `next` is now, since it's unknown. 
 
-        // Now, as a final action, we drop any data that the original previous task 
-        // prepared for droppage before the context switch occurred.
-        let _prev_task_data_to_drop = self.drop_after_task_switch.take();
+        self.finish_task_switch();
+    }
 
+    /// Finishes the task switch to this `Task`, which must be invoked by this task right after it has been switched to,
+    /// including when a new task runs for the first time.
+    ///
+    /// This marks the context of the previous task as saved, such that it can be switched to on other cores again,
+    /// and drops any data that the previous task prepared for droppage before the context switch occurred.
+    pub fn finish_task_switch(&mut self) {
+        if let Some(prev_context_saved) = self.prev_context_saved.take() {
+            prev_context_saved.store(true, Ordering::Release);
+        }
+        let _prev_task_data_to_drop = self.drop_after_task_switch.take();
     }
 }
 
@@ -679,13 +869,20 @@ impl TaskRef {
     /// to determine the current `Task` on each processor core.
     pub fn new(task: Task) -> TaskRef {
         let task_id = task.id;
+        let heap_quota = task.heap_quota.clone();
         let taskref = TaskRef(Arc::new((MutexIrqSafe::new(task), AtomicBool::new(false))));
         let tld = TaskLocalData {
             current_taskref: taskref.clone(),
             current_task_id: task_id,
+            heap_quota,
+            heap_quota_exceeded: AtomicUsize::new(0),
         };
         let tld_ptr = Box::into_raw(Box::new(tld));
-        taskref.0.deref().0.lock().task_local_data_ptr = VirtualAddress::new_canonical(tld_ptr as usize);
+        {
+            let mut task = taskref.0.deref().0.lock();
+            task.task_local_data_ptr = VirtualAddress::new_canonical(tld_ptr as usize);
+            task.tls_area.set_tcb_data(tld_ptr as usize);
+        }
         taskref
     }
 
@@ -729,7 +926,7 @@ impl TaskRef {
     /// The internal routine that actually exits or kills a Task.
     /// It also performs select cleanup routines, e.g., removing the task from the task list.
     fn internal_exit(&self, val: ExitValue) -> Result<(), &'static str> {
-        {
+        let state_change_handler = {
             let mut task = self.0.deref().0.lock();
             if let RunState::Exited(_) = task.runstate {
                 return Err("task was already exited! (did not overwrite its existing exit value)");
@@ -743,7 +940,14 @@ impl TaskRef {
                 // trace!("internal_exit(): dropping TaskLocalData for non-running task {}", &*task);
                 let _tld = task.take_task_local_data();
             }
-        }
+
+            // No one will take the exit value of a detached task, so we reap it right away.
+            if task.detached {
+                let _exit_value = task.take_exit_value();
+            }
+
+            task.state_change_handler.clone()
+        };
 
         #[cfg(runqueue_spillful)] 
         {   
@@ -755,6 +959,9 @@ impl TaskRef {
             }
         }
 
+        if let Some(handler) = state_change_handler {
+            handler();
+        }
         Ok(())
     }
 
@@ -843,13 +1050,110 @@ impl TaskRef {
     }
 
     /// Blocks this `Task` by setting its `RunState` to blocked.
+    /// 
+    /// If this `Task` is suspended, it stays suspended and will be blocked once it's resumed.
     pub fn block(&self) {
-        self.0.deref().0.lock().runstate = RunState::Blocked;
+        let mut task = self.0.deref().0.lock();
+        if task.is_suspended() {
+            task.runnable_on_resume = false;
+            return;
+        }
+        task.runstate = RunState::Blocked;
+        task.cpu_times.became_blocked(tsc::tsc_ticks().into());
     }
 
     /// Unblocks this `Task` by setting its `RunState` to runnable.
+    /// 
+    /// If this `Task` is suspended, it stays suspended and will be runnable once it's resumed.
     pub fn unblock(&self) {
-        self.0.deref().0.lock().runstate = RunState::Runnable;
+        let mut task = self.0.deref().0.lock();
+        if task.is_suspended() {
+            task.runnable_on_resume = true;
+            return;
+        }
+        task.runstate = RunState::Runnable;
+        task.cpu_times.became_runnable(tsc::tsc_ticks().into());
+    }
+
+    /// Suspends this `Task` by setting its `RunState` to `Stopped` and taking it off of its runqueue,
+    /// such that it won't be scheduled in again until [`resume()`](#method.resume) is invoked.
+    /// Whether it was runnable or blocked is remembered and restored upon resuming.
+    /// 
+    /// Suspending an already-suspended `Task` has no effect.
+    /// Exited tasks and idle tasks cannot be suspended.
+    /// 
+    /// # Note 
+    /// The `Task` will not be halted immediately -- 
+    /// it will finish running its current timeslice, and then not be run again until resumed.
+    /// When suspending the current task, yield the CPU afterwards to stop right away.
+    pub fn suspend(&self) -> Result<(), &'static str> {
+        let state_change_handler = {
+            let mut task = self.0.deref().0.lock();
+            if task.is_an_idle_task {
+                return Err("cannot suspend an idle task");
+            }
+            task.runnable_on_resume = match task.runstate {
+                RunState::Runnable => true,
+                RunState::Blocked => false,
+                RunState::Stopped => return Ok(()),
+                RunState::Initing => return Err("cannot suspend a task that is still being initialized"),
+                RunState::Exited(_) | RunState::Reaped => return Err("cannot suspend a task that has exited"),
+            };
+            task.runstate = RunState::Stopped;
+            task.cpu_times.became_blocked(tsc::tsc_ticks().into());
+            task.state_change_handler.clone()
+        };
+
+        if let Some(take_off_runqueues) = RUNQUEUE_SUSPEND_FUNCTION.try() {
+            take_off_runqueues(self)?;
+        }
+        if let Some(handler) = state_change_handler {
+            handler();
+        }
+        Ok(())
+    }
+
+    /// Resumes this suspended `Task` by restoring the `RunState` it had before it was suspended,
+    /// or a later one if it was blocked or unblocked in the meantime,
+    /// and putting it back onto a runqueue.
+    /// 
+    /// Returns an error if this `Task` is not suspended.
+    pub fn resume(&self) -> Result<(), &'static str> {
+        {
+            let mut task = self.0.deref().0.lock();
+            if !task.is_suspended() {
+                return Err("cannot resume a task that is not suspended");
+            }
+            if task.runnable_on_resume {
+                task.runstate = RunState::Runnable;
+                task.cpu_times.became_runnable(tsc::tsc_ticks().into());
+            } else {
+                task.runstate = RunState::Blocked;
+            }
+        }
+
+        if let Some(put_on_runqueue) = RUNQUEUE_RESUME_FUNCTION.try() {
+            put_on_runqueue(self.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Claims this `Task` to be run on the given core, unless it is already running on a different core
+    /// or its context hasn't yet been completely saved by the core it last ran on.
+    /// Returns `true` if the claim succeeded.
+    ///
+    /// Because tasks can be migrated between runqueues, two cores may briefly select the same task;
+    /// only the core that claims it first is allowed to switch to it.
+    pub fn claim_for_core(&self, apic_id: u8) -> bool {
+        let mut task = self.0.deref().0.lock();
+        match task.running_on_cpu {
+            Some(core) if core != apic_id => false,
+            _ if !task.is_context_saved() => false,
+            _ => {
+                task.running_on_cpu = Some(apic_id);
+                true
+            }
+        }
     }
 
     /// Registers a function or closure that will be called if this `Task` panics
@@ -870,6 +1174,15 @@ impl TaskRef {
         self.0.deref().0.lock().take_kill_handler()
     }
 
+    /// Registers a function or closure that will be called after this `Task` has exited or has been suspended.
+    /// The given `callback` is invoked without holding this `Task`'s `Mutex`, 
+    /// by whichever task caused the state change.
+    /// # Locking / Deadlock
+    /// Obtains a write lock on the enclosed `Task` in order to mutate its state.
+    pub fn set_state_change_handler(&self, callback: StateChangeHandler) {
+        self.0.deref().0.lock().state_change_handler = Some(callback);
+    }
+
     /// Takes ownership of this `Task`'s exit value and returns it,
     /// if and only if this `Task` was in the `Exited` runstate.
     /// After invoking this, the `Task`'s runstate will be `Reaped`.
@@ -879,6 +1192,22 @@ impl TaskRef {
         self.0.deref().0.lock().take_exit_value()
     }
 
+    /// Detaches this `Task`, which indicates that no other `Task` will take its exit value.
+    /// 
+    /// A detached `Task` is reaped as soon as it exits, i.e., its exit value is dropped
+    /// and it is removed from the system task list.
+    /// If it has already exited, it is reaped immediately.
+    /// # Locking / Deadlock
+    /// Obtains a write lock on the enclosed `Task` in order to mutate its state.
+    pub fn detach(&self) {
+        let mut task = self.0.deref().0.lock();
+        if task.has_exited() {
+            let _exit_value = task.take_exit_value();
+        } else {
+            task.detached = true;
+        }
+    }
+
     /// Sets the `Environment` of this Task.
     pub fn set_env(&self, new_env: Arc<Mutex<Environment>>) {
         self.0.deref().0.lock().set_env(new_env);
@@ -926,6 +1255,9 @@ pub fn bootstrap_task(
     stack_top: VirtualAddress,
     kernel_mmi_ref: MmiRef,
 ) -> Result<TaskRef, &'static str> {
+    // Once tasking is set up, the global allocator can charge allocations to the current task's heap quota.
+    heap::set_heap_quota_funcs(current_heap_quota, heap_quota_exceeded);
+
     // Here, we cannot call `Task::new()` because tasking hasn't yet been set up for this core.
     // Instead, we generate all of the `Task` states manually, and create an initial task directly.
     let kstack = Stack::new( 
@@ -945,6 +1277,7 @@ pub fn bootstrap_task(
     bootstrap_task.runstate = RunState::Runnable;
     bootstrap_task.running_on_cpu = Some(apic_id); 
     bootstrap_task.pinned_core = Some(apic_id); // can only run on this CPU core
+    bootstrap_task.cpu_times.switched_in(tsc::tsc_ticks().into());
     // debug!("IDLE TASK STACK (apic {}) at bottom={:#x} - top={:#x} ", apic_id, stack_bottom, stack_top);
     let bootstrap_task_id = bootstrap_task.id;
     let task_ref = TaskRef::new(bootstrap_task);
@@ -985,20 +1318,31 @@ fn bootstrap_task_cleanup_failure(current_task: TaskRef, kill_reason: KillReason
 
 /// The structure that holds information local to each Task,
 /// effectively a form of thread-local storage (TLS).
-/// A pointer to this structure is stored in the `FS` segment register,
+/// A pointer to this structure is stored in the thread control block of the task's TLS area,
+/// which the `FS` segment register points to,
 /// such that any task can easily and quickly access their local data.
 // #[repr(C)]
 #[derive(Debug)]
 struct TaskLocalData {
     current_taskref: TaskRef,
     current_task_id: usize,
+    /// A copy of the Task's `heap_quota`, which the allocator can access without locking the Task.
+    heap_quota: Option<Arc<HeapQuota>>,
+    /// The size of the allocation that failed due to exceeding `heap_quota`, or 0 if none has failed since that was last reported.
+    heap_quota_exceeded: AtomicUsize,
 }
 
 /// Returns a reference to the current task's `TaskLocalData` 
-/// by using the `TaskLocalData` pointer stored in the FS base MSR register.
+/// by using the `TaskLocalData` pointer stored in the thread control block
+/// that the FS base MSR register points to.
 fn get_task_local_data() -> Option<&'static TaskLocalData> {
     let tld: &'static TaskLocalData = {
-        let tld_ptr = rdmsr(IA32_FS_BASE) as *const TaskLocalData;
+        let thread_pointer = rdmsr(IA32_FS_BASE) as usize;
+        if thread_pointer == 0 {
+            return None;
+        }
+        // SAFE: the FS base always holds the thread pointer of the current task's TLS area, which outlives its execution.
+        let tld_ptr = unsafe { TlsDataImage::tcb_data(thread_pointer) } as *const TaskLocalData;
         if tld_ptr.is_null() {
             return None;
         }
@@ -1015,6 +1359,46 @@ pub fn get_my_current_task() -> Option<&'static TaskRef> {
     get_task_local_data().map(|tld| &tld.current_taskref)
 }
 
+/// Returns the heap quota that the current task's allocations should be charged to, if any.
+/// This is invoked by the global allocator upon every allocation, so it must not allocate or lock anything.
+/// 
+/// Allocations made with interrupts disabled, e.g., in interrupt handlers or by the scheduler,
+/// aren't charged to the interrupted task, as they may not fail just because that task is out of quota.
+/// 
+/// A task that has exceeded its quota isn't charged until that has been reported via `heap_quota_exceeded_reason()`,
+/// because a failed allocation causes it to panic, and the panic handler needs to allocate memory.
+fn current_heap_quota() -> Option<Arc<HeapQuota>> {
+    if !interrupts_enabled() {
+        return None;
+    }
+    get_task_local_data()
+        .filter(|tld| tld.heap_quota_exceeded.load(Ordering::SeqCst) == 0)
+        .and_then(|tld| tld.heap_quota.clone())
+}
+
+/// Records that an allocation of the given `layout` failed because it would have exceeded the current task's heap quota.
+fn heap_quota_exceeded(_quota: &HeapQuota, layout: core::alloc::Layout) {
+    if let Some(tld) = get_task_local_data() {
+        tld.heap_quota_exceeded.store(core::cmp::max(layout.size(), 1), Ordering::SeqCst);
+    }
+}
+
+/// Returns a `KillReason::HeapQuotaExceeded` if the current task has failed to allocate memory
+/// because it would have exceeded its heap quota.
+/// 
+/// This is used by the panic handler to distinguish a task that ran out of its heap quota
+/// from one that panicked for some other reason.
+/// The failure is only reported once, after which the task's allocations are charged to its quota again.
+pub fn heap_quota_exceeded_reason() -> Option<KillReason> {
+    let tld = get_task_local_data()?;
+    let requested = tld.heap_quota_exceeded.swap(0, Ordering::SeqCst);
+    if requested == 0 {
+        return None;
+    }
+    let limit = tld.heap_quota.as_ref().map(|q| q.limit()).unwrap_or(0);
+    Some(KillReason::HeapQuotaExceeded { requested, limit })
+}
+
 /// Returns the current Task's id by using the `TaskLocalData` pointer
 /// stored in the thread-local storage (FS base model-specific register).
 pub fn get_my_current_task_id() -> Option<usize> {
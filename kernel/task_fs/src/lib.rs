@@ -35,6 +35,7 @@ extern crate path;
 extern crate root;
 
 
+use core::time::Duration;
 use alloc::string::{String, ToString};
 use alloc::vec::Vec;
 use spin::Mutex;
@@ -243,6 +244,7 @@ impl TaskFile {
             RunState::Initing    => "Initing",
             RunState::Runnable   => "Runnable",
             RunState::Blocked    => "Blocked",
+            RunState::Stopped    => "Stopped",
             RunState::Exited(_)  => "Exited",
             RunState::Reaped     => "Reaped",
         };
@@ -255,14 +257,21 @@ impl TaskFile {
         } else {
             " "
         };  
+        let cpu_times = self.taskref.lock().cpu_times.clone();
+        let format_time = |time: Option<Duration>| time
+            .map(|t| format!("{}.{:03} ms", t.as_millis(), t.subsec_micros() % 1000))
+            .unwrap_or(String::from("-"));
 
-        format!("{0:<10} {1}\n{2:<10} {3}\n{4:<10} {5}\n{6:<10} {7}\n{8:<10} {9}\n{10:<10} {11:<10}", 
+        format!("{0:<10} {1}\n{2:<10} {3}\n{4:<10} {5}\n{6:<10} {7}\n{8:<10} {9}\n{10:<10} {11:<10}\n{12:<10} {13}\n{14:<10} {15}\n{16:<10} {17}", 
             "name", name,
             "task id", self.taskref.lock().id,
             "runstate", runstate,
             "cpu", cpu,
             "pinned", pinned,
-            "task type", task_type
+            "task type", task_type,
+            "run time", format_time(cpu_times.run_time()),
+            "wait time", format_time(cpu_times.wait_time()),
+            "switches", cpu_times.context_switches()
         )
     }
 }
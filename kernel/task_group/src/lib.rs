@@ -0,0 +1,152 @@
+//! Groups of tasks that are suspended, resumed or killed together, such as all the tasks in a shell pipeline.
+//!
+//! Operations on a group are atomic with respect to other operations on the same group:
+//! when a group is suspended or killed, every member is first taken off its runqueue,
+//! and then the operation waits for members that are still running on another core to finish their timeslice,
+//! such that no member keeps running while the rest of the group is already stopped.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate alloc;
+extern crate spin;
+extern crate task;
+extern crate runqueue;
+extern crate scheduler;
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+use alloc::vec::Vec;
+use spin::Mutex;
+use task::{TaskRef, KillReason, get_my_current_task};
+
+
+/// A group of tasks that can be suspended, resumed or killed as a whole.
+pub struct TaskGroup {
+    /// The unique ID of this group.
+    id: usize,
+    /// The members of this group, in the order they were added.
+    tasks: Mutex<Vec<TaskRef>>,
+}
+
+impl TaskGroup {
+    /// Creates a new group that contains the given tasks.
+    pub fn new(tasks: Vec<TaskRef>) -> TaskGroup {
+        static GROUP_ID_COUNTER: AtomicUsize = AtomicUsize::new(0);
+        TaskGroup {
+            id: GROUP_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+            tasks: Mutex::new(tasks),
+        }
+    }
+
+    /// Returns the unique ID of this group.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Adds the given task to this group.
+    pub fn add(&self, task: TaskRef) {
+        let mut tasks = self.tasks.lock();
+        if !tasks.contains(&task) {
+            tasks.push(task);
+        }
+    }
+
+    /// Returns the members of this group.
+    pub fn tasks(&self) -> Vec<TaskRef> {
+        self.tasks.lock().clone()
+    }
+
+    /// Returns true if every member of this group has exited.
+    pub fn has_exited(&self) -> bool {
+        self.tasks.lock().iter().all(|t| t.lock().has_exited())
+    }
+
+    /// Returns true if any member of this group is suspended.
+    pub fn is_suspended(&self) -> bool {
+        self.tasks.lock().iter().any(|t| t.lock().is_suspended())
+    }
+
+    /// Suspends every member of this group that hasn't exited,
+    /// and waits until none of them is running anymore.
+    ///
+    /// If a member can't be suspended, the others are still suspended and the first error is returned.
+    pub fn suspend(&self) -> Result<(), &'static str> {
+        let tasks = self.tasks.lock();
+        let result = suspend_all(&tasks);
+        wait_until_not_running(&tasks);
+        result
+    }
+
+    /// Resumes every suspended member of this group.
+    ///
+    /// If a member can't be resumed, the others are still resumed and the first error is returned.
+    pub fn resume(&self) -> Result<(), &'static str> {
+        let tasks = self.tasks.lock();
+        let mut result = Ok(());
+        for task in tasks.iter() {
+            if !task.lock().is_suspended() {
+                continue;
+            }
+            if let Err(e) = task.resume() {
+                error!("TaskGroup {}: couldn't resume task {:?}: {}", self.id, task, e);
+                result = result.and(Err(e));
+            }
+        }
+        result
+    }
+
+    /// Kills every member of this group that hasn't exited with the given `reason`.
+    ///
+    /// All members are suspended before any of them is killed, such that none of them
+    /// can observe another member of the same group having been killed while it's still running.
+    pub fn kill(&self, reason: KillReason) -> Result<(), &'static str> {
+        let tasks = self.tasks.lock();
+        // members that can't be suspended, e.g., because they're still initializing, are killed anyway
+        let _ = suspend_all(&tasks);
+        wait_until_not_running(&tasks);
+
+        let mut result = Ok(());
+        for task in tasks.iter() {
+            if task.lock().has_exited() {
+                continue;
+            }
+            if let Err(e) = task.kill(reason.clone()) {
+                error!("TaskGroup {}: couldn't kill task {:?}: {}", self.id, task, e);
+                result = result.and(Err(e));
+            }
+            // A suspended task has already been taken off its runqueue, but one that couldn't be suspended hasn't.
+            if let Err(e) = runqueue::remove_task_from_all(task) {
+                error!("TaskGroup {}: killed task {:?} but couldn't remove it from its runqueue: {}", self.id, task, e);
+            }
+        }
+        result
+    }
+}
+
+/// Suspends all of the given tasks that haven't exited, returning the first error, if any.
+fn suspend_all(tasks: &[TaskRef]) -> Result<(), &'static str> {
+    let mut result = Ok(());
+    for task in tasks {
+        if task.lock().has_exited() {
+            continue;
+        }
+        if let Err(e) = task.suspend() {
+            error!("TaskGroup: couldn't suspend task {:?}: {}", task, e);
+            result = result.and(Err(e));
+        }
+    }
+    result
+}
+
+/// Yields the CPU until none of the given tasks, except the current task, is running on any core.
+fn wait_until_not_running(tasks: &[TaskRef]) {
+    let curr_task = get_my_current_task();
+    for task in tasks {
+        if Some(task) == curr_task {
+            continue;
+        }
+        while task.lock().is_running() {
+            scheduler::schedule();
+        }
+    }
+}
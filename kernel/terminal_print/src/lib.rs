@@ -21,11 +21,14 @@ extern crate task;
 extern crate dfqueue;
 extern crate event_types;
 extern crate spin;
+extern crate wait_queue;
 
 use event_types::Event;
 use dfqueue::DFQueueProducer;
 use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
 use spin::Mutex;
+use wait_queue::WaitQueue;
 
 /// Calls `print!()` with an extra newline ('\n') appended to the end. 
 #[macro_export]
@@ -46,14 +49,26 @@ macro_rules! print {
 
 lazy_static! {
     /// Maps the child application's task ID to its parent terminal print_producer to track parent-child relationships between
-    /// applications so that applications can print to the correct terminal
-    static ref TERMINAL_PRINT_PRODUCERS: Mutex<BTreeMap<usize, DFQueueProducer<Event>>> = Mutex::new(BTreeMap::new());
+    /// applications so that applications can print to the correct terminal.
+    /// Each print_producer comes with the wait queue that is notified after a print event is enqueued, if any.
+    static ref TERMINAL_PRINT_PRODUCERS: Mutex<BTreeMap<usize, (DFQueueProducer<Event>, Option<Arc<WaitQueue>>)>> = Mutex::new(BTreeMap::new());
 }
 
 /// Adds the (child application's task ID, parent terminal print_producer) key-val pair to the map 
 /// Simulates connecting an output stream to the application
 pub fn add_child(child_task_id: usize, print_producer: DFQueueProducer<Event>) -> Result<(), &'static str> {
-    TERMINAL_PRINT_PRODUCERS.lock().insert(child_task_id, print_producer);
+    TERMINAL_PRINT_PRODUCERS.lock().insert(child_task_id, (print_producer, None));
+    Ok(())
+}
+
+/// Same as `add_child()`, but the given `wait_queue` is also notified every time the child application prints,
+/// such that the parent terminal can block until there is output to display.
+pub fn add_child_with_wait_queue(
+    child_task_id: usize,
+    print_producer: DFQueueProducer<Event>,
+    wait_queue: Arc<WaitQueue>,
+) -> Result<(), &'static str> {
+    TERMINAL_PRINT_PRODUCERS.lock().insert(child_task_id, (print_producer, Some(wait_queue)));
     Ok(())
 }
 
@@ -83,7 +98,10 @@ pub fn print_to_stdout_args(fmt_args: fmt::Arguments) {
     // and handled by the infinite terminal instance loop 
     let print_map = TERMINAL_PRINT_PRODUCERS.lock();
     let result = print_map.get(&task_id);
-    if let Some(selected_term_producer) = result {
+    if let Some((selected_term_producer, wait_queue)) = result {
         selected_term_producer.enqueue(Event::new_output_event(format!("{}", fmt_args)));
+        if let Some(wait_queue) = wait_queue {
+            wait_queue.notify_one();
+        }
     }
 }
@@ -0,0 +1,374 @@
+//! A TFTP client, as specified in RFC 1350, for downloading files from and uploading files to a TFTP server.
+//! 
+//! The client requests the `blksize` (RFC 2348) and `tsize` (RFC 2349) options 
+//! using the option extension (RFC 2347), which allows for larger blocks and therefore faster transfers. 
+//! If the server doesn't support these options, the client falls back to the standard 512-byte blocks.
+//! Only the `octet` (binary) transfer mode is supported.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+extern crate network_manager;
+extern crate hpet;
+extern crate smoltcp;
+#[macro_use] extern crate smoltcp_helper;
+
+use core::{cmp::min, str};
+use alloc::vec::Vec;
+use hpet::get_hpet;
+use network_manager::NetworkInterfaceRef;
+use smoltcp::{
+    socket::{SocketSet, SocketHandle, UdpSocket, UdpSocketBuffer, UdpPacketMetadata},
+    wire::IpEndpoint,
+};
+use smoltcp_helper::{STARTING_FREE_PORT, millis_since, poll_iface};
+
+
+/// The well-known UDP port on which TFTP servers listen for requests.
+pub const DEFAULT_SERVER_PORT: u16 = 69;
+
+/// The block size used when the server doesn't support the `blksize` option.
+const DEFAULT_BLOCK_SIZE: usize = 512;
+
+/// The largest block size that avoids IP fragmentation on a standard Ethernet link: 
+/// the 1500-byte MTU minus the IPv4 (20), UDP (8) and TFTP (4) headers.
+pub const MAX_UNFRAGMENTED_BLOCK_SIZE: u16 = 1468;
+
+/// The length of the opcode and block number at the start of DATA and ACK packets.
+const HEADER_LEN: usize = 4;
+
+/// The number of packets that can be buffered in each direction of the UDP socket.
+const SOCKET_PACKETS: usize = 8;
+
+/// The maximum number of bytes preallocated for a download based on the transfer size announced by the server,
+/// beyond which the content grows as blocks are actually received.
+const MAX_PREALLOC: usize = 1024 * 1024;
+
+/// The default maximum size of a downloaded file.
+pub const DEFAULT_MAX_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+const OPCODE_RRQ:   u16 = 1;
+const OPCODE_WRQ:   u16 = 2;
+const OPCODE_DATA:  u16 = 3;
+const OPCODE_ACK:   u16 = 4;
+const OPCODE_ERROR: u16 = 5;
+const OPCODE_OACK:  u16 = 6;
+
+/// The error code sent to a host that sends packets from an unexpected port, i.e., "Unknown transfer ID".
+const ERROR_UNKNOWN_TRANSFER_ID: u16 = 5;
+/// The error code sent to the server when a file is too large to be downloaded, i.e., "Disk full or allocation exceeded".
+const ERROR_DISK_FULL: u16 = 3;
+
+
+/// The settings used for a TFTP transfer.
+#[derive(Debug, Clone, Copy)]
+pub struct TftpConfig {
+    /// The block size to request from the server, between 8 and 65464 bytes.
+    pub block_size: u16,
+    /// How long to wait for a reply from the server before retransmitting the last packet.
+    pub timeout_millis: u64,
+    /// How many times the last packet is retransmitted before the transfer is aborted.
+    pub max_retries: usize,
+    /// The maximum size in bytes of a downloaded file; larger downloads are aborted.
+    pub max_file_size: usize,
+}
+
+impl Default for TftpConfig {
+    fn default() -> TftpConfig {
+        TftpConfig {
+            block_size: MAX_UNFRAGMENTED_BLOCK_SIZE,
+            timeout_millis: 1000,
+            max_retries: 5,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+        }
+    }
+}
+
+
+/// Downloads the file with the given `file_name` from the TFTP server at the given `server` endpoint,
+/// and returns its entire content.
+/// 
+/// The download is aborted if the file is larger than the configured `max_file_size`.
+pub fn get(
+    iface: &NetworkInterfaceRef,
+    server: IpEndpoint,
+    file_name: &str,
+    config: &TftpConfig,
+) -> Result<Vec<u8>, &'static str> {
+    let mut conn = Connection::new(iface, server, config)?;
+    let request = request_packet(OPCODE_RRQ, file_name, config.block_size, 0);
+
+    let mut reply = conn.send_and_receive(&request, |pkt| opcode(pkt) == Some(OPCODE_OACK) || is_block(pkt, OPCODE_DATA, 1))?;
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut content = Vec::new();
+    if opcode(&reply) == Some(OPCODE_OACK) {
+        let (negotiated_block_size, transfer_size) = parse_options(&reply[2..], config.block_size)?;
+        block_size = negotiated_block_size;
+        if let Some(size) = transfer_size {
+            if size > config.max_file_size {
+                let _ = conn.send(&error_packet(ERROR_DISK_FULL, "File too large"));
+                return Err("tftp_client: the file is larger than the maximum file size");
+            }
+            // Don't trust the announced size too much, the content still grows as blocks are received.
+            content.reserve(min(size, MAX_PREALLOC));
+        }
+        reply = conn.send_and_receive(&ack_packet(0), |pkt| is_block(pkt, OPCODE_DATA, 1))?;
+    }
+
+    let mut block: u16 = 1;
+    loop {
+        let data = &reply[HEADER_LEN..];
+        if data.len() > block_size {
+            return Err("tftp_client: server sent a block larger than the negotiated block size");
+        }
+        if content.len() + data.len() > config.max_file_size {
+            let _ = conn.send(&error_packet(ERROR_DISK_FULL, "File too large"));
+            return Err("tftp_client: the file is larger than the maximum file size");
+        }
+        content.extend_from_slice(data);
+        let ack = ack_packet(block);
+
+        // A block shorter than the block size is the last one.
+        if data.len() < block_size {
+            conn.send(&ack)?;
+            break;
+        }
+        // Block numbers wrap around for files larger than 65535 blocks.
+        block = block.wrapping_add(1);
+        reply = conn.send_and_receive(&ack, |pkt| is_block(pkt, OPCODE_DATA, block))?;
+    }
+
+    debug!("tftp_client: downloaded {:?} ({} bytes) from {}", file_name, content.len(), server);
+    Ok(content)
+}
+
+
+/// Uploads the given `content` into a file with the given `file_name` on the TFTP server at the given `server` endpoint.
+pub fn put(
+    iface: &NetworkInterfaceRef,
+    server: IpEndpoint,
+    file_name: &str,
+    content: &[u8],
+    config: &TftpConfig,
+) -> Result<(), &'static str> {
+    let mut conn = Connection::new(iface, server, config)?;
+    let request = request_packet(OPCODE_WRQ, file_name, config.block_size, content.len());
+
+    let reply = conn.send_and_receive(&request, |pkt| opcode(pkt) == Some(OPCODE_OACK) || is_block(pkt, OPCODE_ACK, 0))?;
+    let block_size = if opcode(&reply) == Some(OPCODE_OACK) {
+        parse_options(&reply[2..], config.block_size)?.0
+    } else {
+        DEFAULT_BLOCK_SIZE
+    };
+
+    let mut block: u16 = 1;
+    let mut offset = 0;
+    loop {
+        // If the content is a multiple of the block size, an empty block marks the end of the file.
+        let end = min(offset + block_size, content.len());
+        let mut data = Vec::with_capacity(HEADER_LEN + end - offset);
+        data.extend_from_slice(&OPCODE_DATA.to_be_bytes());
+        data.extend_from_slice(&block.to_be_bytes());
+        data.extend_from_slice(&content[offset .. end]);
+        conn.send_and_receive(&data, |pkt| is_block(pkt, OPCODE_ACK, block))?;
+
+        if end - offset < block_size {
+            break;
+        }
+        offset = end;
+        block = block.wrapping_add(1);
+    }
+
+    debug!("tftp_client: uploaded {:?} ({} bytes) to {}", file_name, content.len(), server);
+    Ok(())
+}
+
+
+/// The UDP socket and state used for a single TFTP transfer.
+struct Connection<'i> {
+    iface: &'i NetworkInterfaceRef,
+    sockets: SocketSet<'static, 'static, 'static>,
+    handle: SocketHandle,
+    startup_time: u64,
+    /// The endpoint that the initial request is sent to.
+    server: IpEndpoint,
+    /// The endpoint that the server uses for the rest of the transfer, i.e., its transfer ID,
+    /// which is only known once the server has replied to the initial request.
+    peer: Option<IpEndpoint>,
+    config: TftpConfig,
+}
+
+impl<'i> Connection<'i> {
+    fn new(iface: &'i NetworkInterfaceRef, server: IpEndpoint, config: &TftpConfig) -> Result<Connection<'i>, &'static str> {
+        if config.block_size < 8 || config.block_size > 65464 {
+            return Err("tftp_client: the block size must be between 8 and 65464 bytes");
+        }
+        let startup_time = hpet_ticks!();
+        let max_packet_len = HEADER_LEN + config.block_size as usize;
+        let rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; SOCKET_PACKETS], vec![0; SOCKET_PACKETS * max_packet_len]);
+        let tx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY; SOCKET_PACKETS], vec![0; SOCKET_PACKETS * max_packet_len]);
+        let mut socket = UdpSocket::new(rx_buffer, tx_buffer);
+
+        // Each transfer uses a new local port as its transfer ID.
+        let local_port = STARTING_FREE_PORT + (startup_time % (u16::max_value() - STARTING_FREE_PORT) as u64) as u16;
+        socket.bind(local_port).map_err(|_e| "tftp_client: couldn't bind UDP socket")?;
+        let mut sockets = SocketSet::new(Vec::with_capacity(1));
+        let handle = sockets.add(socket);
+
+        Ok(Connection {
+            iface,
+            sockets,
+            handle,
+            startup_time,
+            server,
+            peer: None,
+            config: *config,
+        })
+    }
+
+    /// Sends the given `packet` to the server's transfer ID, or to the server itself if that isn't known yet.
+    fn send(&mut self, packet: &[u8]) -> Result<(), &'static str> {
+        let destination = self.peer.unwrap_or(self.server);
+        let start = hpet_ticks!();
+        loop {
+            {
+                let mut socket = self.sockets.get::<UdpSocket>(self.handle);
+                if socket.can_send() {
+                    socket.send_slice(packet, destination).map_err(|_e| "tftp_client: couldn't send UDP packet")?;
+                    break;
+                }
+            }
+            poll_iface(self.iface, &mut self.sockets, self.startup_time)?;
+            if millis_since(start)? > self.config.timeout_millis {
+                return Err("tftp_client: timed out waiting to send UDP packet");
+            }
+        }
+        poll_iface(self.iface, &mut self.sockets, self.startup_time)?;
+        Ok(())
+    }
+
+    /// Sends the given `packet` and waits for a reply that is `accept`ed,
+    /// retransmitting the `packet` each time the configured timeout elapses.
+    /// 
+    /// Replies that aren't accepted, e.g., duplicates of previous packets, are ignored.
+    /// An ERROR packet from the server aborts the transfer.
+    fn send_and_receive<F>(&mut self, packet: &[u8], mut accept: F) -> Result<Vec<u8>, &'static str> 
+        where F: FnMut(&[u8]) -> bool
+    {
+        for _attempt in 0 ..= self.config.max_retries {
+            self.send(packet)?;
+            let start = hpet_ticks!();
+            while millis_since(start)? <= self.config.timeout_millis {
+                poll_iface(self.iface, &mut self.sockets, self.startup_time)?;
+                while let Some((reply, source)) = self.receive() {
+                    // The first reply from the server's address determines the server's transfer ID.
+                    if self.peer.is_none() && source.addr == self.server.addr {
+                        self.peer = Some(source);
+                    }
+                    if self.peer != Some(source) {
+                        warn!("tftp_client: ignoring packet from unknown transfer ID {}", source);
+                        let error = error_packet(ERROR_UNKNOWN_TRANSFER_ID, "Unknown transfer ID");
+                        let _ = self.sockets.get::<UdpSocket>(self.handle).send_slice(&error, source);
+                        continue;
+                    }
+                    if opcode(&reply) == Some(OPCODE_ERROR) {
+                        error!("tftp_client: server {} returned error {:?}", source, parse_error(&reply));
+                        return Err("tftp_client: server returned an error");
+                    }
+                    if accept(&reply) {
+                        return Ok(reply);
+                    }
+                }
+            }
+        }
+        error!("tftp_client: no reply from server {} after {} retries", self.server, self.config.max_retries);
+        Err("tftp_client: timed out waiting for a reply from the server")
+    }
+
+    /// Returns the next packet received by the UDP socket, if any, along with its source endpoint.
+    fn receive(&mut self) -> Option<(Vec<u8>, IpEndpoint)> {
+        let mut socket = self.sockets.get::<UdpSocket>(self.handle);
+        socket.recv().ok().map(|(data, source)| (data.to_vec(), source))
+    }
+}
+
+
+/// Returns the opcode of the given packet.
+fn opcode(packet: &[u8]) -> Option<u16> {
+    if packet.len() < 2 {
+        return None;
+    }
+    Some(u16::from_be_bytes([packet[0], packet[1]]))
+}
+
+/// Returns true if the given packet has the given `opcode` (DATA or ACK) and `block` number.
+fn is_block(packet: &[u8], opcode_expected: u16, block: u16) -> bool {
+    packet.len() >= HEADER_LEN 
+        && opcode(packet) == Some(opcode_expected)
+        && u16::from_be_bytes([packet[2], packet[3]]) == block
+}
+
+/// Creates a read or write request for the given file,
+/// which requests the given block size and announces (or asks for) the transfer size.
+fn request_packet(opcode: u16, file_name: &str, block_size: u16, transfer_size: usize) -> Vec<u8> {
+    let block_size = format!("{}", block_size);
+    let transfer_size = format!("{}", transfer_size);
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&opcode.to_be_bytes());
+    for field in &[file_name, "octet", "blksize", block_size.as_str(), "tsize", transfer_size.as_str()] {
+        packet.extend_from_slice(field.as_bytes());
+        packet.push(0);
+    }
+    packet
+}
+
+fn ack_packet(block: u16) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN);
+    packet.extend_from_slice(&OPCODE_ACK.to_be_bytes());
+    packet.extend_from_slice(&block.to_be_bytes());
+    packet
+}
+
+fn error_packet(code: u16, message: &str) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LEN + message.len() + 1);
+    packet.extend_from_slice(&OPCODE_ERROR.to_be_bytes());
+    packet.extend_from_slice(&code.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    packet
+}
+
+/// Returns the error code and message of the given ERROR packet.
+fn parse_error(packet: &[u8]) -> (u16, &str) {
+    if packet.len() < HEADER_LEN {
+        return (0, "");
+    }
+    let code = u16::from_be_bytes([packet[2], packet[3]]);
+    let message = packet[HEADER_LEN..].split(|&b| b == 0).next()
+        .and_then(|msg| str::from_utf8(msg).ok())
+        .unwrap_or("");
+    (code, message)
+}
+
+/// Parses the options acknowledged by the server in an OACK packet (without its opcode).
+/// 
+/// Returns the negotiated block size and the transfer size, if the server acknowledged it.
+fn parse_options(options: &[u8], requested_block_size: u16) -> Result<(usize, Option<usize>), &'static str> {
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut transfer_size = None;
+    let mut fields = options.split(|&b| b == 0).map(|f| str::from_utf8(f).unwrap_or(""));
+    while let (Some(name), Some(value)) = (fields.next(), fields.next()) {
+        if name.eq_ignore_ascii_case("blksize") {
+            let size = value.parse::<u16>().map_err(|_e| "tftp_client: server acknowledged an invalid block size")?;
+            // The server may only choose a block size no larger than the one requested.
+            if size < 8 || size > requested_block_size {
+                return Err("tftp_client: server acknowledged an invalid block size");
+            }
+            block_size = size as usize;
+        } else if name.eq_ignore_ascii_case("tsize") {
+            transfer_size = value.parse::<usize>().ok();
+        }
+    }
+    Ok((block_size, transfer_size))
+}
@@ -0,0 +1,184 @@
+//! Lays out the thread-local storage (TLS) sections of all loaded crates
+//! and creates the TLS area of each new task from them.
+//!
+//! Theseus uses the x86_64 "variant II" TLS layout, in which a task's TLS area ends at its thread pointer,
+//! which is the FS base and points to a small thread control block (TCB).
+//! Each `.tdata` or `.tbss` section is assigned a fixed offset below the thread pointer when its crate is loaded,
+//! so that thread-pointer-relative relocations can be resolved at load time.
+//! New sections are always placed below all existing ones, so the offsets of existing sections never change
+//! even though crates are loaded at runtime.
+//!
+//! Every task's TLS area has room for up to `MAX_TLS_SIZE` bytes of TLS sections from the start,
+//! and the initial contents of a newly-loaded TLS section are copied into the TLS areas of all existing tasks,
+//! such that tasks created before a crate was loaded can still use that crate's TLS variables.
+//! Loading a crate whose TLS sections don't fit into that space fails.
+//! TLS variables in the statically-linked `nano_core` are not supported.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+#[macro_use] extern crate alloc;
+extern crate spin;
+extern crate util;
+extern crate crate_metadata;
+
+use core::mem::size_of;
+use alloc::{
+    boxed::Box,
+    sync::Arc,
+    vec::Vec,
+};
+use spin::Mutex;
+use util::round_up_power_of_two;
+use crate_metadata::{SectionType, StrongSectionRef, WeakSectionRef};
+
+
+/// The maximum total size of all TLS sections, which every TLS area has room for.
+pub const MAX_TLS_SIZE: usize = 4096;
+
+/// The maximum alignment of a TLS section, which the thread pointer of every TLS area is aligned to.
+pub const MAX_TLS_ALIGN: usize = 64;
+
+/// The layout of all TLS sections that have been loaded so far.
+struct TlsLayout {
+    /// The total size of the area below the thread pointer that is occupied by TLS sections.
+    total_size: usize,
+    /// The loaded TLS sections, whose `tls_offset`s determine where they are placed.
+    sections: Vec<WeakSectionRef>,
+    /// The thread pointers of all existing TLS areas, into which newly-loaded TLS sections are copied.
+    areas: Vec<usize>,
+}
+
+static TLS_LAYOUT: Mutex<TlsLayout> = Mutex::new(TlsLayout {
+    total_size: 0,
+    sections: Vec::new(),
+    areas: Vec::new(),
+});
+
+
+/// Reserves space for a TLS section with the given size and alignment in the TLS areas of all tasks.
+/// Returns the section's offset below the thread pointer, which should be set as the section's `tls_offset`.
+///
+/// Returns an error if the TLS sections loaded so far plus this one would exceed `MAX_TLS_SIZE`.
+pub fn reserve_section(size: usize, align: usize) -> Result<usize, &'static str> {
+    let align = if align == 0 { 1 } else { align };
+    if !align.is_power_of_two() {
+        return Err("TLS section alignment must be a power of two");
+    }
+    if align > MAX_TLS_ALIGN {
+        return Err("TLS section alignment is larger than the maximum TLS alignment");
+    }
+    let mut layout = TLS_LAYOUT.lock();
+    let offset = round_up_power_of_two(layout.total_size + size, align);
+    if offset > MAX_TLS_SIZE {
+        error!("tls_initializer: can't reserve {} bytes for a TLS section, {} of {} bytes are already in use", size, layout.total_size, MAX_TLS_SIZE);
+        return Err("TLS sections of all loaded crates would exceed the maximum TLS size");
+    }
+    layout.total_size = offset;
+    Ok(offset)
+}
+
+/// Adds the given loaded TLS section, whose space must have been reserved with [`reserve_section()`](fn.reserve_section.html),
+/// and copies its initial contents into the TLS area of every existing task and every task created from now on.
+pub fn add_section(section: &StrongSectionRef) -> Result<(), &'static str> {
+    if !section.typ.is_tls() || section.tls_offset.is_none() {
+        return Err("tls_initializer::add_section(): section was not a TLS section with a reserved TLS offset");
+    }
+    let mut layout = TLS_LAYOUT.lock();
+    for thread_pointer in layout.areas.iter() {
+        // SAFE: the area is still alive, as it's removed from `areas` when dropped,
+        // and no task can be using the section's space yet, as its crate is still being loaded.
+        unsafe { copy_initial_contents(section, *thread_pointer); }
+    }
+    layout.sections.push(Arc::downgrade(section));
+    Ok(())
+}
+
+/// Creates a new TLS area that contains the initial contents of all TLS sections loaded so far.
+pub fn get_data() -> TlsDataImage {
+    let mut layout = TLS_LAYOUT.lock();
+    // forget about sections whose crates have been unloaded; their space stays reserved
+    layout.sections.retain(|sec| sec.upgrade().is_some());
+
+    let data = vec![0u8; MAX_TLS_ALIGN + MAX_TLS_SIZE + TCB_SIZE].into_boxed_slice();
+    let start = data.as_ptr() as usize;
+    let thread_pointer = round_up_power_of_two(start + MAX_TLS_SIZE, MAX_TLS_ALIGN);
+    let tp_index = thread_pointer - start;
+
+    for sec in layout.sections.iter().filter_map(|sec| sec.upgrade()) {
+        // SAFE: the area was just allocated with room for `MAX_TLS_SIZE` bytes below its thread pointer.
+        unsafe { copy_initial_contents(&sec, thread_pointer); }
+    }
+    layout.areas.push(thread_pointer);
+
+    let mut image = TlsDataImage { data, tp_index };
+    // The x86_64 ABI requires the first word at the thread pointer to point to itself.
+    image.write_tcb_word(0, thread_pointer);
+    image
+}
+
+/// Copies the initial contents of the given TLS section into the TLS area with the given `thread_pointer`.
+///
+/// # Safety
+/// The given `thread_pointer` must be the thread pointer of a TLS area that is still alive.
+unsafe fn copy_initial_contents(sec: &StrongSectionRef, thread_pointer: usize) {
+    let tls_offset = match sec.tls_offset {
+        Some(offset) => offset,
+        None => return,
+    };
+    // .tbss sections are already zeroed
+    if sec.typ != SectionType::TlsData {
+        return;
+    }
+    match sec.mapped_pages.lock().as_slice::<u8>(sec.mapped_pages_offset, sec.size()) {
+        Ok(src) => core::ptr::copy_nonoverlapping(src.as_ptr(), (thread_pointer - tls_offset) as *mut u8, src.len()),
+        Err(e) => error!("tls_initializer: couldn't get initial contents of TLS section {:?}: {}", sec, e),
+    }
+}
+
+
+/// The size of the thread control block (TCB) at the thread pointer,
+/// which consists of a pointer to itself, as required by the x86_64 ABI,
+/// followed by one word for the owner of the TLS area to use, see [`TlsDataImage::set_tcb_data()`](struct.TlsDataImage.html#method.set_tcb_data).
+const TCB_SIZE: usize = 2 * size_of::<usize>();
+
+/// A task's TLS area: the copies of all TLS sections, followed by the thread control block (TCB).
+pub struct TlsDataImage {
+    data: Box<[u8]>,
+    /// The index into `data` at which the thread pointer points, i.e., where the TCB starts.
+    tp_index: usize,
+}
+
+impl TlsDataImage {
+    /// Returns the thread pointer of this TLS area, which should be written into the FS base
+    /// whenever the task that owns it is switched to.
+    pub fn thread_pointer(&self) -> usize {
+        self.data.as_ptr() as usize + self.tp_index
+    }
+
+    /// Sets the word in the TCB that is reserved for the owner of this TLS area.
+    /// The `task` crate stores a pointer to the task's `TaskLocalData` there.
+    pub fn set_tcb_data(&mut self, value: usize) {
+        self.write_tcb_word(1, value);
+    }
+
+    /// Returns the word in the TCB that is reserved for the owner of the TLS area at the given `thread_pointer`.
+    ///
+    /// # Safety
+    /// The given `thread_pointer` must be the thread pointer of a `TlsDataImage` that is still alive.
+    pub unsafe fn tcb_data(thread_pointer: usize) -> usize {
+        *(thread_pointer as *const usize).offset(1)
+    }
+
+    fn write_tcb_word(&mut self, index: usize, value: usize) {
+        let start = self.tp_index + index * size_of::<usize>();
+        self.data[start .. start + size_of::<usize>()].copy_from_slice(&value.to_ne_bytes());
+    }
+}
+
+impl Drop for TlsDataImage {
+    fn drop(&mut self) {
+        let thread_pointer = self.thread_pointer();
+        TLS_LAYOUT.lock().areas.retain(|tp| *tp != thread_pointer);
+    }
+}
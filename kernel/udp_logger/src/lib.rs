@@ -0,0 +1,359 @@
+//! A log output that streams formatted log records as UDP datagrams to a remote host,
+//! e.g., one running the `tools/receive_udp_messages` program.
+//!
+//! Log records are buffered in a bounded ring from the moment this output is registered with the `logger`,
+//! even before any network interface exists.
+//! A dedicated task waits for networking to come up and then drains the ring into UDP datagrams,
+//! one record per datagram.
+//! If the ring is full, e.g., because the link is saturated or the network is not yet up,
+//! newly-logged records are dropped and counted; see [`stats()`](fn.stats.html).
+//!
+//! # Note
+//! When using QEMU's default user-mode (slirp) networking, the host is reachable at `10.0.2.2`,
+//! which is the default destination.
+
+#![no_std]
+
+#[macro_use] extern crate alloc;
+extern crate log;
+extern crate spin;
+extern crate irq_safety;
+extern crate logger;
+extern crate spawn;
+extern crate scheduler;
+extern crate hpet;
+extern crate network_manager;
+extern crate smoltcp;
+#[macro_use] extern crate smoltcp_helper;
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use alloc::{
+    string::ToString,
+    vec::Vec,
+};
+use irq_safety::MutexIrqSafe;
+use log::{Level, Record};
+use spin::Once;
+use hpet::get_hpet;
+use smoltcp::{
+    socket::{SocketSet, UdpSocket, UdpSocketBuffer, UdpPacketMetadata},
+    wire::{IpAddress, IpEndpoint, Ipv4Address},
+};
+use smoltcp_helper::{get_default_iface, poll_iface};
+
+
+/// The default destination of log datagrams: port 5901 on the QEMU user-mode networking host.
+pub const DEFAULT_DESTINATION: IpEndpoint = IpEndpoint {
+    addr: IpAddress::Ipv4(Ipv4Address([10, 0, 2, 2])),
+    port: 5901,
+};
+
+/// The local UDP port from which log datagrams are sent.
+const LOCAL_PORT: u16 = 5900;
+
+/// The number of bytes of formatted log records that can be buffered while waiting to be sent.
+const RING_SIZE_IN_BYTES: usize = 64 * 1024;
+
+/// The maximum size of a single log datagram; longer records are truncated.
+const MAX_DATAGRAM_SIZE: usize = 1024;
+
+/// The number of bytes that precede each record in the ring, which hold the record's length.
+const RECORD_HEADER_SIZE: usize = 2;
+
+/// The number of datagrams that can be queued in the UDP socket's transmit buffer.
+const SOCKET_TX_PACKETS: usize = 32;
+
+
+/// The ring of formatted log records that have yet to be sent.
+/// This is preallocated so that pushing a record never allocates, even in interrupt context.
+static RING: Once<MutexIrqSafe<LogRing>> = Once::new();
+
+/// The remote endpoint to which log datagrams are sent.
+static DESTINATION: MutexIrqSafe<IpEndpoint> = MutexIrqSafe::new(DEFAULT_DESTINATION);
+
+/// Whether this log output is currently registered with the `logger`.
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static RECORDS_SENT: AtomicUsize = AtomicUsize::new(0);
+static RECORDS_DROPPED: AtomicUsize = AtomicUsize::new(0);
+/// The number of dropped records that have not yet been reported to the remote host.
+static DROPS_UNREPORTED: AtomicUsize = AtomicUsize::new(0);
+
+
+/// Statistics about the log records handled by the UDP log output.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct UdpLoggerStats {
+    /// The number of records currently buffered in the ring, waiting to be sent.
+    pub queued: usize,
+    /// The number of records that have been sent as datagrams.
+    pub sent: usize,
+    /// The number of records that were dropped because the ring was full or they couldn't be sent.
+    pub dropped: usize,
+}
+
+/// Returns the current statistics of the UDP log output.
+pub fn stats() -> UdpLoggerStats {
+    UdpLoggerStats {
+        queued:  RING.try().map(|r| r.lock().records).unwrap_or(0),
+        sent:    RECORDS_SENT.load(Ordering::Relaxed),
+        dropped: RECORDS_DROPPED.load(Ordering::Relaxed),
+    }
+}
+
+/// Sets the remote endpoint to which subsequent log datagrams will be sent.
+pub fn set_destination(destination: IpEndpoint) {
+    *DESTINATION.lock() = destination;
+}
+
+
+/// Starts streaming log records over UDP to the given `destination`.
+///
+/// This registers a new log output with the `logger`, which immediately begins buffering log records,
+/// and spawns a task that sends them once a network interface is available.
+/// It can be called before the network devices have been initialized, but tasking must already be set up.
+pub fn init(destination: IpEndpoint) -> Result<(), &'static str> {
+    if ENABLED.swap(true, Ordering::SeqCst) {
+        return Err("udp_logger: already initialized");
+    }
+    set_destination(destination);
+    RING.call_once(|| MutexIrqSafe::new(LogRing::new(RING_SIZE_IN_BYTES)));
+
+    spawn::new_task_builder(udp_logger_task, ())
+        .name("udp_logger".to_string())
+        .spawn()
+        .map_err(|e| { ENABLED.store(false, Ordering::SeqCst); e })?;
+
+    logger::add_log_output(log_to_ring).map_err(|e| { ENABLED.store(false, Ordering::SeqCst); e })
+}
+
+
+/// A fixed-size ring buffer of variable-length records,
+/// each of which is stored as its length followed by its bytes.
+struct LogRing {
+    buf: Vec<u8>,
+    /// The index of the first byte of the oldest record.
+    head: usize,
+    /// The number of bytes currently used by records and their headers.
+    used: usize,
+    /// The number of records currently in the ring.
+    records: usize,
+}
+
+impl LogRing {
+    fn new(size_in_bytes: usize) -> LogRing {
+        LogRing {
+            buf: vec![0; size_in_bytes],
+            head: 0,
+            used: 0,
+            records: 0,
+        }
+    }
+
+    /// Appends the given `record` to the ring, returning `false` if there isn't enough space for it.
+    fn push(&mut self, record: &[u8]) -> bool {
+        if self.buf.len() - self.used < RECORD_HEADER_SIZE + record.len() {
+            return false;
+        }
+        let tail = self.head + self.used;
+        self.write_at(tail, &(record.len() as u16).to_le_bytes());
+        self.write_at(tail + RECORD_HEADER_SIZE, record);
+        self.used += RECORD_HEADER_SIZE + record.len();
+        self.records += 1;
+        true
+    }
+
+    /// Copies the oldest record into `out` without removing it, returning its length.
+    fn peek(&self, out: &mut [u8; MAX_DATAGRAM_SIZE]) -> Option<usize> {
+        if self.records == 0 {
+            return None;
+        }
+        let len = self.record_len();
+        self.read_at(self.head + RECORD_HEADER_SIZE, &mut out[.. len]);
+        Some(len)
+    }
+
+    /// Removes the oldest record, if any.
+    fn pop(&mut self) {
+        if self.records == 0 {
+            return;
+        }
+        let size = RECORD_HEADER_SIZE + self.record_len();
+        self.head = (self.head + size) % self.buf.len();
+        self.used -= size;
+        self.records -= 1;
+    }
+
+    fn clear(&mut self) {
+        self.head = 0;
+        self.used = 0;
+        self.records = 0;
+    }
+
+    /// Returns the length of the oldest record, which must exist.
+    fn record_len(&self) -> usize {
+        let mut len_bytes = [0u8; RECORD_HEADER_SIZE];
+        self.read_at(self.head, &mut len_bytes);
+        u16::from_le_bytes(len_bytes) as usize
+    }
+
+    /// Copies `bytes` into the ring starting at the given index, wrapping around its end.
+    fn write_at(&mut self, index: usize, bytes: &[u8]) {
+        let cap = self.buf.len();
+        for (i, b) in bytes.iter().enumerate() {
+            self.buf[(index + i) % cap] = *b;
+        }
+    }
+
+    /// Copies bytes from the ring starting at the given index into `out`, wrapping around its end.
+    fn read_at(&self, index: usize, out: &mut [u8]) {
+        let cap = self.buf.len();
+        for (i, b) in out.iter_mut().enumerate() {
+            *b = self.buf[(index + i) % cap];
+        }
+    }
+}
+
+
+/// Formats text into a fixed-size buffer, silently truncating anything that doesn't fit.
+struct TruncatingWriter<'b> {
+    buf: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Write for TruncatingWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = self.buf.len() - self.len;
+        let mut end = core::cmp::min(s.len(), available);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+        self.buf[self.len .. self.len + end].copy_from_slice(&s.as_bytes()[.. end]);
+        self.len += end;
+        Ok(())
+    }
+}
+
+
+/// The log output function registered with the `logger`, which formats the given `record`
+/// and pushes it onto the ring, or counts it as dropped if the ring is full.
+///
+/// The record is formatted into a buffer on the stack, so this never allocates and can be used in interrupt context.
+/// This must not use any logging macros, as that would recursively invoke this function.
+fn log_to_ring(record: &Record) {
+    let ring = match RING.try() {
+        Some(r) => r,
+        None => return,
+    };
+
+    let level_str = match record.level() {
+        Level::Error => "[E] ",
+        Level::Warn  => "[W] ",
+        Level::Info  => "[I] ",
+        Level::Debug => "[D] ",
+        Level::Trace => "[T] ",
+    };
+    let mut buf = [0u8; MAX_DATAGRAM_SIZE];
+    let len = {
+        // Leave room for the trailing newline, which must be present even if the record is truncated.
+        let mut writer = TruncatingWriter { buf: &mut buf[.. MAX_DATAGRAM_SIZE - 1], len: 0 };
+        let _ = write!(writer, "{}{}:{}: {}",
+            level_str,
+            record.file().unwrap_or("??"),
+            record.line().unwrap_or(0),
+            record.args(),
+        );
+        writer.len
+    };
+    buf[len] = b'\n';
+
+    if !ring.lock().push(&buf[.. len + 1]) {
+        RECORDS_DROPPED.fetch_add(1, Ordering::Relaxed);
+        DROPS_UNREPORTED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+
+/// The task that waits for a network interface to become available,
+/// and then sends buffered log records as UDP datagrams.
+fn udp_logger_task(_: ()) -> Result<(), &'static str> {
+    let ring = RING.try().ok_or("udp_logger: ring was not initialized")?;
+
+    // Wait for networking to come up, during which log records accumulate in the ring.
+    let iface = loop {
+        if let Ok(iface) = get_default_iface() {
+            break iface;
+        }
+        scheduler::schedule();
+    };
+
+    let startup_time = hpet_ticks!();
+    let udp_rx_buffer = UdpSocketBuffer::new(vec![UdpPacketMetadata::EMPTY], vec![0; 64]);
+    let udp_tx_buffer = UdpSocketBuffer::new(
+        vec![UdpPacketMetadata::EMPTY; SOCKET_TX_PACKETS],
+        vec![0; SOCKET_TX_PACKETS * MAX_DATAGRAM_SIZE],
+    );
+    let mut udp_socket = UdpSocket::new(udp_rx_buffer, udp_tx_buffer);
+    udp_socket.bind(LOCAL_PORT).map_err(|_e| "udp_logger: couldn't bind UDP socket")?;
+    let mut sockets = SocketSet::new(Vec::with_capacity(1));
+    let udp_handle = sockets.add(udp_socket);
+
+    while ENABLED.load(Ordering::SeqCst) {
+        let destination = *DESTINATION.lock();
+        let mut records_sent = 0;
+        {
+            let mut socket = sockets.get::<UdpSocket>(udp_handle);
+
+            // First, report any records that were dropped since the last report.
+            let unreported = DROPS_UNREPORTED.load(Ordering::Relaxed);
+            if unreported > 0 && socket.can_send() {
+                let notice = format!("[udp_logger] dropped {} log records (total {})\n",
+                    unreported, RECORDS_DROPPED.load(Ordering::Relaxed)
+                );
+                if socket.send_slice(notice.as_bytes(), destination).is_ok() {
+                    DROPS_UNREPORTED.fetch_sub(unreported, Ordering::Relaxed);
+                }
+            }
+
+            let mut record = [0u8; MAX_DATAGRAM_SIZE];
+            while socket.can_send() {
+                // Only hold the ring's lock while copying out the record, not while sending it.
+                let len = match ring.lock().peek(&mut record) {
+                    Some(len) => len,
+                    None => break,
+                };
+                match socket.send_slice(&record[.. len], destination) {
+                    Ok(()) => records_sent += 1,
+                    // The transmit buffer is full, so leave the record in the ring to be sent later.
+                    Err(smoltcp::Error::Exhausted) => break,
+                    Err(_e) => {
+                        RECORDS_DROPPED.fetch_add(1, Ordering::Relaxed);
+                        DROPS_UNREPORTED.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+                // Apart from `stop()`, which clears the ring, this task is the only one that removes records,
+                // so the oldest record is still the one that was just sent.
+                ring.lock().pop();
+            }
+        }
+        RECORDS_SENT.fetch_add(records_sent, Ordering::Relaxed);
+
+        let packet_io_occurred = poll_iface(&iface, &mut sockets, startup_time)?;
+        if records_sent == 0 && !packet_io_occurred {
+            scheduler::schedule(); // yield the CPU if nothing to do
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Stops streaming log records over UDP and unregisters this log output from the `logger`.
+/// Records that are still buffered in the ring will not be sent.
+pub fn stop() {
+    logger::remove_log_output(log_to_ring);
+    ENABLED.store(false, Ordering::SeqCst);
+    if let Some(ring) = RING.try() {
+        ring.lock().clear();
+    }
+}
@@ -5,11 +5,15 @@ extern crate alloc;
 extern crate irq_safety;
 extern crate task;
 extern crate scheduler;
+extern crate sleep;
 
 
+use core::task::Waker;
+use core::time::Duration;
 use alloc::collections::VecDeque;
 use irq_safety::MutexIrqSafe;
 use task::TaskRef;
+use sleep::Instant;
 
 
 /// An object that holds a blocked `Task` 
@@ -55,9 +59,16 @@ pub enum WaitError {
 }
 
 /// A queue in which multiple `Task`s can wait for other `Task`s to notify them.
+///
+/// Futures can also wait on a `WaitQueue` by registering their `Waker`,
+/// see [`register_waker`](#method.register_waker).
 /// 
 /// This can be shared across multiple `Task`s by wrapping it in an `Arc`. 
-pub struct WaitQueue(MutexIrqSafe<VecDeque<TaskRef>>);
+pub struct WaitQueue {
+    tasks: MutexIrqSafe<VecDeque<TaskRef>>,
+    /// The wakers of futures waiting on this queue, which are notified after all waiting `Task`s.
+    wakers: MutexIrqSafe<VecDeque<Waker>>,
+}
 
 // ******************************************************************
 // ************ IMPORTANT IMPLEMENTATION NOTE ***********************
@@ -82,7 +93,10 @@ impl WaitQueue {
 
     /// Create a new empty WaitQueue.
     pub fn with_capacity(initial_capacity: usize) -> WaitQueue {
-        WaitQueue(MutexIrqSafe::new(VecDeque::with_capacity(initial_capacity)))
+        WaitQueue {
+            tasks: MutexIrqSafe::new(VecDeque::with_capacity(initial_capacity)),
+            wakers: MutexIrqSafe::new(VecDeque::new()),
+        }
     }
 
     /// Puts the current `Task` to sleep where it blocks on this `WaitQueue`
@@ -97,6 +111,18 @@ impl WaitQueue {
         self.wait_until(&|/* _ */| Some(()))
     }
 
+    /// Similar to [`wait`](#method.wait), but this function gives up and returns `WaitError::Timeout`
+    /// if the `Task` hasn't been woken up within the given `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> Result<(), WaitError> {
+        self.wait_deadline(Instant::now() + timeout)
+    }
+
+    /// Similar to [`wait`](#method.wait), but this function gives up and returns `WaitError::Timeout`
+    /// if the `Task` hasn't been woken up by the given `deadline`.
+    pub fn wait_deadline(&self, deadline: Instant) -> Result<(), WaitError> {
+        self.wait_until_deadline(&|/* _ */| Some(()), deadline)
+    }
+
     /// Similar to [`wait`](#method.wait), but this function blocks until the given
     /// `condition` closure returns `Some(value)`, and then returns that `value` inside `Ok()`.
     /// 
@@ -108,38 +134,43 @@ impl WaitQueue {
     // /// The `condition` closure is invoked with one argument, an immutable reference to the waitqueue, 
     // /// to allow the closure to examine the condition of the waitqueue if necessary. 
     pub fn wait_until<R>(&self, condition: &dyn Fn(/* &VecDeque<TaskRef> */) -> Option<R>) -> Result<R, WaitError> {
-        let curr_task = task::get_my_current_task().ok_or(WaitError::NoCurrentTask)?;
+        self.wait_until_internal(&mut || condition(), None)
+    }
 
-        // Do the following atomically:
-        // (1) Obtain the waitqueue lock
-        // (2) Add the current task to the waitqueue
-        // (3) Set the current task's runstate to `Blocked`
-        // (4) Release the lock on the waitqueue.
-        loop {
-            {
-                let mut wq_locked = self.0.lock();
-                if let Some(ret) = condition(/* &wq_locked */) {
-                    return Ok(ret);
-                }
-                // This is only necessary because we're using a non-Set waitqueue collection that allows duplicates
-                if !wq_locked.contains(curr_task) {
-                    wq_locked.push_back(curr_task.clone());
-                } else {
-                    warn!("WaitQueue::wait_until():  task was already on waitqueue (potential spurious wakeup?). {:?}", curr_task);
-                }
-                // trace!("WaitQueue::wait_until():  putting task to sleep: {:?}\n    --> WQ: {:?}", curr_task, &*wq_locked);
-                curr_task.block();
-            }
-            scheduler::schedule();
+    /// Similar to [`wait_until`](#method.wait_until), but this function gives up and returns `WaitError::Timeout`
+    /// if the `condition` hasn't been met within the given `timeout`.
+    pub fn wait_until_timeout<R>(&self, condition: &dyn Fn() -> Option<R>, timeout: Duration) -> Result<R, WaitError> {
+        self.wait_until_deadline(condition, Instant::now() + timeout)
+    }
 
-            // Here, we have been woken up, so loop back around and check the condition again
-            // trace!("WaitQueue::wait_until():  woke up!");
-        }
+    /// Similar to [`wait_until`](#method.wait_until), but this function gives up and returns `WaitError::Timeout`
+    /// if the `condition` hasn't been met by the given `deadline`.
+    pub fn wait_until_deadline<R>(&self, condition: &dyn Fn() -> Option<R>, deadline: Instant) -> Result<R, WaitError> {
+        self.wait_until_internal(&mut || condition(), Some(deadline))
     }
 
     /// Similar to [`wait_until`](#method.wait_until), but this function accepts a `condition` closure
     /// that can mutate its environment (a `FnMut`).
     pub fn wait_until_mut<R>(&self, condition: &mut dyn FnMut(/* &VecDeque<TaskRef> */) -> Option<R>) -> Result<R, WaitError> {
+        self.wait_until_internal(condition, None)
+    }
+
+    /// Similar to [`wait_until_mut`](#method.wait_until_mut), but this function gives up and returns `WaitError::Timeout`
+    /// if the `condition` hasn't been met within the given `timeout`.
+    pub fn wait_until_mut_timeout<R>(&self, condition: &mut dyn FnMut() -> Option<R>, timeout: Duration) -> Result<R, WaitError> {
+        self.wait_until_mut_deadline(condition, Instant::now() + timeout)
+    }
+
+    /// Similar to [`wait_until_mut`](#method.wait_until_mut), but this function gives up and returns `WaitError::Timeout`
+    /// if the `condition` hasn't been met by the given `deadline`.
+    pub fn wait_until_mut_deadline<R>(&self, condition: &mut dyn FnMut() -> Option<R>, deadline: Instant) -> Result<R, WaitError> {
+        self.wait_until_internal(condition, Some(deadline))
+    }
+
+    /// The internal routine for all variants of waiting on this queue until the given `condition` is met.
+    /// If a `deadline` is given, a kernel timer wakes up the current `Task` at that time,
+    /// after which it gives up waiting unless the `condition` has been met.
+    fn wait_until_internal<R>(&self, condition: &mut dyn FnMut() -> Option<R>, deadline: Option<Instant>) -> Result<R, WaitError> {
         let curr_task = task::get_my_current_task().ok_or(WaitError::NoCurrentTask)?;
 
         // Do the following atomically:
@@ -148,27 +179,97 @@ impl WaitQueue {
         // (3) Set the current task's runstate to `Blocked`
         // (4) Release the lock on the waitqueue.
         loop {
-            {
-                let mut wq_locked = self.0.lock();
-                if let Some(ret) = condition(/* &wq_locked */) {
-                    return Ok(ret);
+            let wakeup_timer = {
+                let mut wq_locked = self.tasks.lock();
+                let result = match condition(/* &wq_locked */) {
+                    Some(ret) => Some(Ok(ret)),
+                    None if deadline.map_or(false, |d| Instant::now() >= d) => Some(Err(WaitError::Timeout)),
+                    None => None,
+                };
+                if let Some(result) = result {
+                    // The task may still be on the waitqueue if it was woken up by its timer rather than a notification,
+                    // in which case it must be removed so that it doesn't consume a future notification.
+                    if let Some(index) = wq_locked.iter().position(|t| t == curr_task) {
+                        wq_locked.remove(index);
+                    }
+                    return result;
                 }
                 // This is only necessary because we're using a non-Set waitqueue collection that allows duplicates
                 if !wq_locked.contains(curr_task) {
                     wq_locked.push_back(curr_task.clone());
-                } else {
+                } else if deadline.is_none() {
                     warn!("WaitQueue::wait_until():  task was already on waitqueue (potential spurious wakeup?). {:?}", curr_task);
                 }
                 // trace!("WaitQueue::wait_until():  putting task to sleep: {:?}\n    --> WQ: {:?}", curr_task, &*wq_locked);
                 curr_task.block();
-            }
+                deadline.map(|d| {
+                    let task_to_wakeup = curr_task.clone();
+                    sleep::add_oneshot_timer(d, move || task_to_wakeup.unblock())
+                })
+            };
             scheduler::schedule();
 
             // Here, we have been woken up, so loop back around and check the condition again
             // trace!("WaitQueue::wait_until():  woke up!");
+            if let Some(timer) = wakeup_timer {
+                timer.cancel();
+            }
         }
     }
 
+    /// Adds the given `Task` to this queue without blocking it,
+    /// such that it will be woken up by a future notification.
+    ///
+    /// This is useful for a `Task` that waits on multiple queues at once.
+    /// A notification removes the `Task` from this queue, so after blocking itself,
+    /// the `Task` can use [`contains`](#method.contains) to check whether it was notified
+    /// in between registering and blocking, in which case it must not go to sleep.
+    /// It must then [`unregister`](#method.unregister) itself from all queues once it is done waiting.
+    pub fn register(&self, task: &TaskRef) {
+        let mut wq_locked = self.tasks.lock();
+        // This is only necessary because we're using a non-Set waitqueue collection that allows duplicates
+        if !wq_locked.contains(task) {
+            wq_locked.push_back(task.clone());
+        }
+    }
+
+    /// Returns `true` if the given `Task` is waiting on this queue, i.e., it hasn't been notified yet.
+    pub fn contains(&self, task: &TaskRef) -> bool {
+        self.tasks.lock().contains(task)
+    }
+
+    /// Removes the given `Task` from this queue without changing its runstate.
+    /// # Return
+    /// * returns `true` if the given `Task` was on this queue,
+    /// * returns `false` if it wasn't, e.g., because it was already notified.
+    pub fn unregister(&self, task: &TaskRef) -> bool {
+        let mut wq_locked = self.tasks.lock();
+        let index = wq_locked.iter().position(|t| t == task);
+        index.and_then(|i| wq_locked.remove(i)).is_some()
+    }
+
+    /// Adds the given `Waker` to this queue, such that it will be woken by a future notification
+    /// if no `Task` is waiting on this queue at that time.
+    ///
+    /// This allows a future to wait on this queue: when polled, it registers its waker
+    /// and then checks its condition again, such that it doesn't miss a notification in between.
+    /// Once it's done waiting, it must [`unregister_waker`](#method.unregister_waker) itself,
+    /// otherwise a future notification could be consumed by a waker that no longer waits.
+    pub fn register_waker(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push_back(waker.clone());
+        }
+    }
+
+    /// Removes the given `Waker` from this queue.
+    /// Returns `true` if it was on this queue, or `false` if it wasn't, e.g., because it was already notified.
+    pub fn unregister_waker(&self, waker: &Waker) -> bool {
+        let mut wakers = self.wakers.lock();
+        let index = wakers.iter().position(|w| w.will_wake(waker));
+        index.and_then(|i| wakers.remove(i)).is_some()
+    }
+
     /// Wake up one random `Task` that is waiting on this queue.
     /// # Return
     /// * returns `Ok(true)` if a `Task` was successfully woken up,
@@ -184,7 +285,29 @@ impl WaitQueue {
     pub fn notify_specific(&self, task_to_wakeup: &TaskRef) -> bool {
         self.notify(Some(task_to_wakeup))
     }
-    
+
+    /// Wake up all `Task`s and futures that are waiting on this queue.
+    /// # Return
+    /// * returns the number of `Task`s and futures that were woken up.
+    pub fn notify_all(&self) -> usize {
+        // Remove all tasks atomically, such that tasks that re-add themselves after waking up
+        // aren't woken up again by this same notification.
+        let mut count = {
+            let mut wq_locked = self.tasks.lock();
+            let count = wq_locked.len();
+            for t in wq_locked.drain(..) {
+                t.unblock();
+            }
+            count
+        };
+        let wakers: VecDeque<Waker> = core::mem::replace(&mut *self.wakers.lock(), VecDeque::new());
+        count += wakers.len();
+        for w in wakers {
+            w.wake();
+        }
+        count
+    }
+
     /// The internal routine for notifying / waking up tasks that are blocking on the waitqueue. 
     /// If specified, the given `task_to_wakeup` will be notified, 
     /// otherwise the first task on the waitqueue will be notified.
@@ -197,7 +320,7 @@ impl WaitQueue {
         // (3) Set that task's runstate to `Runnable`
         // (4) Release the lock on the waitqueue.
 
-        let mut wq_locked = self.0.lock();
+        let mut wq_locked = self.tasks.lock();
         let tref = if let Some(ttw) = task_to_wakeup {
             // find a specific task to wake up
             let index = wq_locked.iter().position(|t| t == ttw);
@@ -212,6 +335,18 @@ impl WaitQueue {
             // trace!("WaitQueue::notify():  unblocked task on waitqueue\n    --> WQ: {:?}", &*wq_locked);
             t.unblock();
             true
+        } else if task_to_wakeup.is_none() {
+            drop(wq_locked);
+            // No task was waiting, so wake up a future instead.
+            // Wakers are invoked without holding any waitqueue lock, as they may notify other waitqueues.
+            let waker = self.wakers.lock().pop_front();
+            match waker {
+                Some(w) => {
+                    w.wake();
+                    true
+                }
+                None => false,
+            }
         } else {
             // trace!("WaitQueue::notify():  did nothing");
             false
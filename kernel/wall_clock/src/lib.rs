@@ -0,0 +1,233 @@
+//! A wall clock that tracks the current calendar time (UTC) with sub-second resolution.
+//!
+//! The wall-clock time is derived from a monotonic counter, the HPET if available or otherwise the TSC,
+//! plus an offset between that counter and the Unix epoch.
+//! The offset is initially seeded from the CMOS RTC, which only has a resolution of one second,
+//! and can later be corrected, e.g., by the `sntp_client` using a time server.
+//! The corrected time can optionally be written back into the RTC so that it persists across reboots.
+
+#![no_std]
+
+#[macro_use] extern crate log;
+extern crate spin;
+extern crate hpet;
+extern crate tsc;
+extern crate rtc;
+extern crate scheduler;
+
+use core::{
+    fmt,
+    sync::atomic::{AtomicBool, AtomicI64, Ordering},
+};
+use spin::Once;
+use hpet::get_hpet;
+use rtc::RtcTime;
+
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// The monotonic counter from which the wall-clock time is derived.
+#[derive(Debug, Clone, Copy)]
+enum ClockSource {
+    Hpet { period_femtoseconds: u64 },
+    Tsc { frequency: u64 },
+}
+
+/// The clock source, chosen and seeded from the RTC upon first use.
+static CLOCK_SOURCE: Once<ClockSource> = Once::new();
+
+/// The number of microseconds that must be added to the clock source's time to obtain the Unix time.
+static OFFSET_MICROS: AtomicI64 = AtomicI64::new(0);
+
+/// Whether the wall clock has been set or corrected since it was seeded from the RTC.
+static SYNCHRONIZED: AtomicBool = AtomicBool::new(false);
+
+
+/// Initializes the wall clock by choosing a clock source and seeding the current time from the RTC.
+///
+/// This is invoked automatically upon first use, so calling it is only necessary
+/// to ensure the RTC is read at a specific point, e.g., during boot.
+pub fn init() -> Result<(), &'static str> {
+    if CLOCK_SOURCE.try().is_some() {
+        return Ok(());
+    }
+
+    let source = match get_hpet().as_ref() {
+        Some(hpet) => ClockSource::Hpet { period_femtoseconds: hpet.counter_period_femtoseconds() as u64 },
+        None => ClockSource::Tsc { frequency: tsc::get_tsc_frequency()? },
+    };
+    let rtc_micros = rtc_time_to_unix_seconds(&rtc::read_rtc()) * MICROS_PER_SECOND;
+    let monotonic_micros = source_micros(source)?;
+    OFFSET_MICROS.store(rtc_micros as i64 - monotonic_micros as i64, Ordering::SeqCst);
+    CLOCK_SOURCE.call_once(|| source);
+
+    info!("wall_clock: using {:?}, time seeded from RTC: {}", source, WallTime::from_unix_micros(rtc_micros));
+    Ok(())
+}
+
+/// Returns the current time of the given clock source in microseconds.
+fn source_micros(source: ClockSource) -> Result<u64, &'static str> {
+    match source {
+        ClockSource::Hpet { period_femtoseconds } => {
+            let ticks = get_hpet().as_ref().ok_or("wall_clock: couldn't get HPET timer")?.get_counter();
+            Ok((ticks as u128 * period_femtoseconds as u128 / 1_000_000_000) as u64)
+        }
+        ClockSource::Tsc { frequency } => {
+            let ticks = tsc::tsc_ticks().into();
+            Ok((ticks as u128 * MICROS_PER_SECOND as u128 / frequency as u128) as u64)
+        }
+    }
+}
+
+/// Returns the current time of the clock source in microseconds, initializing the wall clock if necessary.
+fn monotonic_micros() -> Result<u64, &'static str> {
+    init()?;
+    let source = CLOCK_SOURCE.try().ok_or("wall_clock: not initialized")?;
+    source_micros(*source)
+}
+
+/// Returns the current wall-clock time in microseconds since the Unix epoch.
+pub fn now_micros() -> Result<u64, &'static str> {
+    let monotonic = monotonic_micros()? as i64;
+    Ok((monotonic + OFFSET_MICROS.load(Ordering::SeqCst)) as u64)
+}
+
+/// Returns the current wall-clock time.
+pub fn now() -> Result<WallTime, &'static str> {
+    now_micros().map(WallTime::from_unix_micros)
+}
+
+/// Sets the current wall-clock time to the given number of microseconds since the Unix epoch.
+pub fn set_micros(unix_micros: u64) -> Result<(), &'static str> {
+    let monotonic = monotonic_micros()? as i64;
+    OFFSET_MICROS.store(unix_micros as i64 - monotonic, Ordering::SeqCst);
+    SYNCHRONIZED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Steps the current wall-clock time forwards (or backwards, if negative) by the given number of microseconds.
+pub fn adjust(offset_micros: i64) -> Result<(), &'static str> {
+    init()?;
+    OFFSET_MICROS.fetch_add(offset_micros, Ordering::SeqCst);
+    SYNCHRONIZED.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Returns true if the wall clock has been set or corrected since it was seeded from the RTC.
+pub fn is_synchronized() -> bool {
+    SYNCHRONIZED.load(Ordering::SeqCst)
+}
+
+/// Writes the current wall-clock time into the RTC, and returns the time that was written.
+///
+/// Since the RTC only has a resolution of one second, the write is delayed
+/// until the start of the next second so that the RTC's sub-second phase matches the wall clock.
+pub fn write_to_rtc() -> Result<WallTime, &'static str> {
+    let next_second = (now_micros()? / MICROS_PER_SECOND + 1) * MICROS_PER_SECOND;
+    while now_micros()? < next_second {
+        scheduler::schedule();
+    }
+    let time = WallTime::from_unix_micros(next_second);
+    if time.year < 2000 || time.year > 2099 {
+        return Err("wall_clock: the RTC can only hold years between 2000 and 2099");
+    }
+    rtc::write_rtc(&time.to_rtc_time());
+    Ok(time)
+}
+
+
+/// A calendar date and time in UTC.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct WallTime {
+    pub year: u16,
+    /// The month, from 1 to 12.
+    pub month: u8,
+    /// The day of the month, from 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    pub microsecond: u32,
+}
+
+impl WallTime {
+    /// Converts the given number of microseconds since the Unix epoch into a calendar date and time.
+    pub fn from_unix_micros(unix_micros: u64) -> WallTime {
+        let seconds = unix_micros / MICROS_PER_SECOND;
+        let days = (seconds / SECONDS_PER_DAY) as i64;
+        let second_of_day = seconds % SECONDS_PER_DAY;
+
+        // Howard Hinnant's `civil_from_days` algorithm, with years starting in March.
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let day_of_era = z - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+        WallTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (second_of_day / 3_600) as u8,
+            minute: (second_of_day % 3_600 / 60) as u8,
+            second: (second_of_day % 60) as u8,
+            microsecond: (unix_micros % MICROS_PER_SECOND) as u32,
+        }
+    }
+
+    /// Converts this calendar date and time into the number of microseconds since the Unix epoch.
+    pub fn to_unix_micros(&self) -> u64 {
+        let seconds = days_from_civil(self.year as i64, self.month as i64, self.day as i64) as u64 * SECONDS_PER_DAY
+            + self.hour as u64 * 3_600
+            + self.minute as u64 * 60
+            + self.second as u64;
+        seconds * MICROS_PER_SECOND + self.microsecond as u64
+    }
+
+    /// Converts this time into the RTC's representation, which has a two-digit year and no sub-second part.
+    pub fn to_rtc_time(&self) -> RtcTime {
+        RtcTime {
+            seconds: self.second,
+            minutes: self.minute,
+            hours: self.hour,
+            days: self.day,
+            months: self.month,
+            years: (self.year % 100) as u8,
+        }
+    }
+}
+
+impl fmt::Display for WallTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02}.{:06} UTC",
+            self.year, self.month, self.day, self.hour, self.minute, self.second, self.microsecond
+        )
+    }
+}
+
+
+/// Converts the given RTC time to the number of seconds since the Unix epoch,
+/// assuming that the RTC's two-digit year is in the 21st century.
+pub fn rtc_time_to_unix_seconds(time: &RtcTime) -> u64 {
+    let days = days_from_civil(2000 + time.years as i64, time.months as i64, time.days as i64);
+    (days as u64) * SECONDS_PER_DAY
+        + (time.hours as u64) * 3_600
+        + (time.minutes as u64) * 60
+        + (time.seconds as u64)
+}
+
+/// Returns the number of days between the Unix epoch and the given date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Howard Hinnant's `days_from_civil` algorithm, with years starting in March.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
@@ -25,6 +25,7 @@ extern crate window_inner;
 extern crate window_manager;
 extern crate shapes;
 extern crate color;
+extern crate wait_queue;
 
 use alloc::sync::Arc;
 use mpmc::Queue;
@@ -36,6 +37,7 @@ use shapes::{Coord, Rectangle};
 use spin::Mutex;
 use window_inner::{WindowInner, WindowMovingStatus, DEFAULT_BORDER_SIZE, DEFAULT_TITLE_BAR_HEIGHT};
 use window_manager::{WINDOW_MANAGER};
+use wait_queue::WaitQueue;
 
 
 // border radius, in number of pixels
@@ -92,6 +94,8 @@ pub struct Window {
     inner: Arc<Mutex<WindowInner>>,
     /// The event queue
     event_consumer: Queue<Event>,
+    /// The wait queue that is notified whenever an event is pushed onto the event queue.
+    event_wait_queue: Arc<WaitQueue>,
     /// last mouse position event, used to judge click and press-moving event
     /// TODO FIXME (kevinaboos): why is mouse-specific stuff here? 
     last_mouse_position_event: MousePositionEvent,
@@ -133,11 +137,13 @@ impl Window {
         // and to allow applications to receive events from this `Window` object itself.
         let event_consumer = Queue::with_capacity(100);
         let event_producer = event_consumer.clone();
+        let event_wait_queue = Arc::new(WaitQueue::new());
 
-        let window_inner = WindowInner::new(coordinate, framebuffer, event_producer);
+        let window_inner = WindowInner::new(coordinate, framebuffer, event_producer, event_wait_queue.clone());
         let mut window = Window {
             inner: Arc::new(Mutex::new(window_inner)),
             event_consumer,
+            event_wait_queue,
             last_mouse_position_event: MousePositionEvent::default(),
             last_is_active: true, // new window is now set as the active window by default 
         };
@@ -167,6 +173,12 @@ impl Window {
     }
 
 
+    /// Returns the wait queue that is notified whenever an `Event` is sent to this `Window`,
+    /// which allows a task to block until `handle_event()` may return a new event.
+    pub fn event_wait_queue(&self) -> &Arc<WaitQueue> {
+        &self.event_wait_queue
+    }
+
     /// Tries to receive an `Event` that has been sent to this `Window`.
     /// If no events exist on the queue, it returns `Ok(None)`. 
     /// 
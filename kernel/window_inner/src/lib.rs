@@ -15,15 +15,19 @@
 
 #![no_std]
 
+extern crate alloc;
 extern crate mpmc;
 extern crate event_types;
 extern crate framebuffer;
 extern crate shapes;
+extern crate wait_queue;
 
+use alloc::sync::Arc;
 use mpmc::Queue;
 use event_types::{Event};
 use framebuffer::{Framebuffer, AlphaPixel};
 use shapes::{Coord, Rectangle};
+use wait_queue::WaitQueue;
 
 
 // The title bar height, in number of pixels
@@ -70,6 +74,8 @@ pub struct WindowInner {
     /// The corresponding consumer for this event queue is found in the `Window` struct
     /// that created and owns this `WindowInner` instance.
     event_producer: Queue<Event>, // event output used by window manager
+    /// The wait queue that is notified whenever an event is pushed onto this window's event queue.
+    event_wait_queue: Arc<WaitQueue>,
     /// The virtual framebuffer that is used exclusively for rendering only this window.
     framebuffer: Framebuffer<AlphaPixel>,
     /// Whether a window is moving or stationary.
@@ -85,12 +91,14 @@ impl WindowInner {
         coordinate: Coord,
         framebuffer: Framebuffer<AlphaPixel>,
         event_producer: Queue<Event>,
+        event_wait_queue: Arc<WaitQueue>,
     ) -> WindowInner {
         WindowInner {
             coordinate,
             border_size: DEFAULT_BORDER_SIZE,
             title_bar_height: DEFAULT_TITLE_BAR_HEIGHT,
             event_producer,
+            event_wait_queue,
             framebuffer,
             moving: WindowMovingStatus::Stationary,
         }
@@ -178,7 +186,10 @@ impl WindowInner {
     /// Sends the given `event` to this window.
     /// 
     /// If the event queue was full, `Err(event)` is returned.
+    /// Otherwise, a task waiting for this window's events is woken up.
     pub fn send_event(&self, event: Event) -> Result<(), Event> {
-        self.event_producer.push(event)
+        self.event_producer.push(event)?;
+        self.event_wait_queue.notify_one();
+        Ok(())
     }
 }
@@ -18,12 +18,18 @@ use core_io::{Read, Write};
 use keycodes_ascii::KeyEvent;
 use core::ops::Deref;
 
+/// A function that is invoked whenever new elements or the EOF mark have been written
+/// to a ring buffer, e.g., to wake up a task that is waiting for them.
+pub type Listener = Arc<dyn Fn() + Send + Sync>;
+
 /// A ring buffer with an EOF mark.
 pub struct RingBufferEof<T> {
     /// The ring buffer.
     queue: VecDeque<T>,
     /// The EOF mark. We meet EOF when it equals `true`.
-    end: bool
+    end: bool,
+    /// Invoked after anything has been written to the ring buffer.
+    listener: Option<Listener>
 }
 
 /// A reference to a ring buffer with an EOF mark with mutex protection.
@@ -74,10 +80,11 @@ pub struct StdioWriteGuard<'a> {
 
 impl<T> RingBufferEof<T> {
     /// Create a new ring buffer.
-    fn new() -> RingBufferEof<T> {
+    fn new(listener: Option<Listener>) -> RingBufferEof<T> {
         RingBufferEof {
             queue: VecDeque::new(),
-            end: false
+            end: false,
+            listener
         }
     }
 }
@@ -85,7 +92,17 @@ impl<T> RingBufferEof<T> {
 impl Stdio {
     /// Create a new stdio buffer.
     pub fn new() -> Stdio {
-        let ring_buffer = Arc::new(Mutex::new(RingBufferEof::new()));
+        Stdio::new_inner(None)
+    }
+
+    /// Create a new stdio buffer. The given `listener` is invoked every time
+    /// bytes or the EOF mark are written to the buffer.
+    pub fn with_listener(listener: Listener) -> Stdio {
+        Stdio::new_inner(Some(listener))
+    }
+
+    fn new_inner(listener: Option<Listener>) -> Stdio {
+        let ring_buffer = Arc::new(Mutex::new(RingBufferEof::new(listener)));
         Stdio {
             read_access: Arc::new(Mutex::new(Arc::clone(&ring_buffer))),
             write_access: Arc::new(Mutex::new(ring_buffer))
@@ -276,14 +293,18 @@ impl<'a> Write for StdioWriteGuard<'a> {
     /// does so. Always check the return value when using this method. Otherwise, use `write_all` to
     /// ensure that all given bytes are written.
     fn write(&mut self, buf: &[u8]) -> Result<usize, core_io::Error> {
-        if self.guard.lock().end {
-            return Err(core_io::Error::new(core_io::ErrorKind::UnexpectedEof,
-                                           "cannot write to a stream with EOF set"));
-        }
-        let mut locked_ring_buf = self.guard.lock();
-        for byte in buf {
-            locked_ring_buf.queue.push_back(*byte)
-        }
+        let listener = {
+            let mut locked_ring_buf = self.guard.lock();
+            if locked_ring_buf.end {
+                return Err(core_io::Error::new(core_io::ErrorKind::UnexpectedEof,
+                                               "cannot write to a stream with EOF set"));
+            }
+            for byte in buf {
+                locked_ring_buf.queue.push_back(*byte)
+            }
+            locked_ring_buf.listener.clone()
+        }; // the lock on the ring buffer is dropped before invoking the listener
+        if let Some(listener) = listener { listener(); }
         Ok(buf.len())
     }
     /// The function required by `Write` trait. Currently it performs nothing,
@@ -303,7 +324,12 @@ impl<'a> StdioReadGuard<'a> {
 impl<'a> StdioWriteGuard<'a> {
     /// Set the EOF flag of the queue to true.
     pub fn set_eof(&mut self) {
-        self.guard.lock().end = true;
+        let listener = {
+            let mut locked_ring_buf = self.guard.lock();
+            locked_ring_buf.end = true;
+            locked_ring_buf.listener.clone()
+        };
+        if let Some(listener) = listener { listener(); }
     }
 }
 
@@ -330,7 +356,15 @@ impl KeyEventQueue {
     /// Create a new ring buffer storing `KeyEvent`.
     pub fn new() -> KeyEventQueue {
         KeyEventQueue {
-            key_event_queue: Arc::new(Mutex::new(RingBufferEof::new()))
+            key_event_queue: Arc::new(Mutex::new(RingBufferEof::new(None)))
+        }
+    }
+
+    /// Create a new ring buffer storing `KeyEvent`. The given `listener` is invoked
+    /// every time a `KeyEvent` is pushed into the ring buffer.
+    pub fn with_listener(listener: Listener) -> KeyEventQueue {
+        KeyEventQueue {
+            key_event_queue: Arc::new(Mutex::new(RingBufferEof::new(Some(listener))))
         }
     }
 
@@ -361,8 +395,12 @@ impl KeyEventQueueReader {
 impl KeyEventQueueWriter {
     /// Push a keyevent into the ring buffer.
     pub fn write_one(&self, key_event: KeyEvent) {
-        let mut locked_queue = self.key_event_queue.lock();
-        locked_queue.queue.push_back(key_event);
+        let listener = {
+            let mut locked_queue = self.key_event_queue.lock();
+            locked_queue.queue.push_back(key_event);
+            locked_queue.listener.clone()
+        };
+        if let Some(listener) = listener { listener(); }
     }
 }
 
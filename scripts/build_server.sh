@@ -17,6 +17,9 @@ fi
 if ! command -v python > /dev/null ; then
   echo "The 'python' program is missing, please install it."
 fi
+if ! command -v openssl > /dev/null ; then
+  echo "The 'openssl' program is missing, please install it."
+fi
 
 
 ### required argument:  directory of where the new modules were just built
@@ -26,6 +29,18 @@ if [ -z $NEW_MODULES_DIR ] ; then
 fi
 NEW_MODULES_DIR=$(readlink -m $NEW_MODULES_DIR)
 
+### required argument:  the Ed25519 private key (PEM file) used to sign the update manifest.
+### Theseus only accepts updates signed by a key whose public half is listed in
+### kernel/ota_update_client/trusted_keys.txt, which ships with no keys. To provision one:
+###   openssl genpkey -algorithm ed25519 -out update_signing_key.pem
+###   openssl pkey -in update_signing_key.pem -pubout -outform DER | tail -c 32 | xxd -p -c 32 >> kernel/ota_update_client/trusted_keys.txt
+### then rebuild the Theseus image and pass SIGNING_KEY=update_signing_key.pem to 'make build_server'.
+if [ -z $SIGNING_KEY ] ; then 
+	echo "Error: missing SIGNING_KEY var: the Ed25519 private key file used to sign the update manifest"
+	exit 1
+fi
+SIGNING_KEY=$(readlink -m $SIGNING_KEY)
+
 ### optional argument:  directory that is being exposed as the root of the HTTP web server
 if [ -z $HTTP_ROOT ] ; then 
   HTTP_ROOT=$THESEUS_BASE_DIR/.theseus_build_server
@@ -75,6 +90,19 @@ fi
 fi
 
 
+### Create a manifest of the update build's name and the hashes of every module file and the diff file, 
+### and sign it such that Theseus can verify that this update came from a trusted build server.
+### Signing the build name prevents an older signed build from being served as a newer one.
+cd $NEW_DIR/
+echo "build $NEW_DIR_NAME" > $NEW_DIR/manifest.txt
+for f in *.o diff.txt ; do
+  if [ -f $f ] ; then
+    rhash --sha3-512 $f >> $NEW_DIR/manifest.txt
+  fi
+done
+openssl pkeyutl -sign -rawin -inkey $SIGNING_KEY -in $NEW_DIR/manifest.txt -out $NEW_DIR/manifest.txt.sig
+
+
 ### Update the root listing to reflect all available update directories.
 ### The root listing sorts directories in reverse chronological order (newest at top, oldest at bottom),
 ### without the trailing slash that usually is appended on directory names.
@@ -0,0 +1,74 @@
+//! A minimal SNTP server that answers client requests with the host's system time,
+//! which is useful for testing Theseus's `sntp_client` without a real NTP daemon.
+//!
+//! With QEMU's user-mode networking, Theseus reaches the host at `10.0.2.2`,
+//! so requests to `10.0.2.2:123` arrive at the host's port 123, which requires root to bind:
+//! `sudo cargo run --manifest-path tools/sntp_server/Cargo.toml`
+//!
+//! Usage: `sntp_server [BIND_ADDR:PORT] [OFFSET_SECONDS]`,
+//! where the optional offset is added to the host's time in every reply in order to test clock corrections.
+
+use std::env;
+use std::net::UdpSocket;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The number of seconds between the NTP epoch (1900-01-01) and the Unix epoch (1970-01-01).
+const NTP_UNIX_EPOCH_DELTA: u64 = 2_208_988_800;
+const NTP_PACKET_LEN: usize = 48;
+const MODE_CLIENT: u8 = 3;
+const MODE_SERVER: u8 = 4;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let bind_addr = args.get(1).map(|s| s.as_str()).unwrap_or("0.0.0.0:123");
+    let offset_seconds: f64 = args.get(2).map(|s| s.parse().expect("couldn't parse offset")).unwrap_or(0.0);
+
+    let socket = UdpSocket::bind(bind_addr).expect("couldn't bind to address");
+    println!("SNTP server listening on {}, offset {} seconds", bind_addr, offset_seconds);
+
+    loop {
+        let mut request = [0u8; 512];
+        let (len, source) = match socket.recv_from(&mut request) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!("Error receiving request: {}", e);
+                continue;
+            }
+        };
+        let receive_timestamp = ntp_now(offset_seconds);
+        if len < NTP_PACKET_LEN || request[0] & 0x7 != MODE_CLIENT {
+            eprintln!("Ignoring invalid request ({} bytes) from {}", len, source);
+            continue;
+        }
+
+        let mut reply = [0u8; NTP_PACKET_LEN];
+        let version = (request[0] >> 3) & 0x7;
+        // Leap indicator 0, the client's version, mode 4 (server).
+        reply[0] = (version << 3) | MODE_SERVER;
+        // Stratum 1, i.e., a primary reference, with the "LOCL" reference ID.
+        reply[1] = 1;
+        // The poll interval is copied from the request, and the precision is about one microsecond (2^-20).
+        reply[2] = request[2];
+        reply[3] = (-20i8) as u8;
+        reply[12..16].copy_from_slice(b"LOCL");
+        reply[16..24].copy_from_slice(&receive_timestamp.to_be_bytes());
+        // The originate timestamp is the client's transmit timestamp.
+        reply[24..32].copy_from_slice(&request[40..48]);
+        reply[32..40].copy_from_slice(&receive_timestamp.to_be_bytes());
+        reply[40..48].copy_from_slice(&ntp_now(offset_seconds).to_be_bytes());
+
+        match socket.send_to(&reply, source) {
+            Ok(_) => println!("Replied to {}", source),
+            Err(e) => eprintln!("Error replying to {}: {}", source, e),
+        }
+    }
+}
+
+/// Returns the host's current time, shifted by the given offset, as a 64-bit NTP timestamp.
+fn ntp_now(offset_seconds: f64) -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("system time is before the Unix epoch");
+    let micros = (now.as_micros() as i128 + (offset_seconds * 1_000_000.0) as i128) as u64;
+    let seconds = (micros / 1_000_000 + NTP_UNIX_EPOCH_DELTA) & 0xFFFF_FFFF;
+    let fraction = ((micros % 1_000_000) << 32) / 1_000_000;
+    (seconds << 32) | fraction
+}